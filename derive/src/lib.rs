@@ -0,0 +1,30 @@
+//! `#[derive(SessionMessage)]` for session-types-ng.
+//!
+//! The derive expands to a `session_types_ng::session_message!` invocation,
+//! so the set of carriers a message is transferable over is defined by the
+//! main crate. Use it through the `derive` feature of `session-types-ng`.
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput};
+
+#[proc_macro_derive(SessionMessage)]
+pub fn derive_session_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let params = input.generics.params.iter();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let predicates = where_clause.map(|clause| {
+        let predicates = clause.predicates.iter();
+        quote!(where #(#predicates),*)
+    });
+
+    let expanded = quote! {
+        ::session_types_ng::session_message!([#(#params),*] #name #ty_generics #predicates);
+    };
+    expanded.into()
+}
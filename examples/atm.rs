@@ -1,3 +1,5 @@
+#![allow(dead_code, clippy::type_complexity)]
+
 extern crate session_types_ng;
 
 use std::thread::spawn;
@@ -0,0 +1,38 @@
+/// broadcast.rs
+///
+/// This example demonstrates broadcasting: a configuration value is pushed
+/// to every subscribed worker with a single send.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+
+use session_types_ng::*;
+use session_types_ng::broadcast::{Bcast, Group};
+use session_types_ng::mpsc::Value;
+
+type Push = Bcast<Value<String>, End>;
+
+fn worker(id: usize, chan: Chan<mpsc::Channel, (), Dual<Push>>) {
+    let (chan, Value(config)) = chan.recv().unwrap();
+    println!("worker {} got config {:?}", id, config);
+    chan.close();
+}
+
+fn main() {
+    let mut group = Group::new();
+    let workers: Vec<_> = (0 .. 3)
+        .map(|id| {
+            let (chan, peer) = mpsc::session_channel::<Push>();
+            group.subscribe(chan);
+            spawn(move || worker(id, peer))
+        })
+        .collect();
+
+    let (group, errors) = group.send(Value("verbose = true".to_string()));
+    assert!(errors.is_empty());
+    group.close();
+
+    for w in workers {
+        w.join().unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+/// delegation.rs
+///
+/// This example demonstrates session delegation: a front end receives the
+/// first message from a client and hands the rest of the in-progress session
+/// over to a worker thread by sending the channel itself.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+
+use session_types_ng::*;
+use session_types_ng::mpsc::Value;
+
+type Greet = Recv<Value<String>, Reply>;
+type Reply = Send<Value<String>, End>;
+
+type Handoff = Send<Value<String>, Send<Chan<mpsc::Channel, (), Reply>, End>>;
+
+fn front(client: Chan<mpsc::Channel, (), Greet>, worker: Chan<mpsc::Channel, (), Handoff>) {
+    let (client, Value(name)) = client.recv().unwrap();
+    println!("front: delegating {}", name);
+    worker
+        .send(Value(name)).unwrap()
+        .send(client).unwrap()
+        .close();
+}
+
+fn worker(front: Chan<mpsc::Channel, (), <Handoff as HasDual>::Dual>) {
+    let (front, Value(name)) = front.recv().unwrap();
+    let (front, client) = front.recv().unwrap();
+    front.close();
+    client.send(Value(format!("hello, {}", name))).unwrap().close();
+}
+
+fn main() {
+    let (front_client, client) = mpsc::session_channel::<Greet>();
+    let (front_worker, worker_chan) = mpsc::session_channel::<Handoff>();
+
+    let worker_thread = spawn(move || worker(worker_chan));
+    let front_thread = spawn(move || front(front_client, front_worker));
+
+    let (client, Value(reply)) = client.send(Value("world".to_string())).unwrap().recv().unwrap();
+    println!("client: {}", reply);
+    client.close();
+
+    front_thread.join().unwrap();
+    worker_thread.join().unwrap();
+}
@@ -0,0 +1,64 @@
+/// derive.rs
+///
+/// This example demonstrates user defined message types: the same enum is
+/// sent over an in-process channel and, being serializable, over a TCP
+/// connection with a deadline, without wrapping it into `mpsc::Value` or
+/// `stream::Value`.
+extern crate session_types_ng;
+#[macro_use]
+extern crate serde_derive;
+
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+use std::net::{TcpListener, TcpStream};
+
+use session_types_ng::*;
+
+#[derive(SessionMessage, Serialize, Deserialize, Debug)]
+enum Shape {
+    Circle { radius: f64 },
+    Rect { width: f64, height: f64 },
+}
+
+#[derive(SessionMessage, Serialize, Deserialize, Debug)]
+struct Area(f64);
+
+type Srv = Recv<Shape, Send<Area, End>>;
+
+fn srv<C>(chan: Chan<C, (), Srv>) where C: Carrier, Shape: ChannelRecv<C>, Area: ChannelSend<C> {
+    let (chan, shape) = match chan.recv() {
+        Ok(received) => received,
+        Err(_) => panic!("failed to receive a shape"),
+    };
+    let area = match shape {
+        Shape::Circle { radius } => std::f64::consts::PI * radius * radius,
+        Shape::Rect { width, height } => width * height,
+    };
+    match chan.send(Area(area)) {
+        Ok(chan) => chan.close(),
+        Err(_) => panic!("failed to send an area"),
+    }
+}
+
+fn main() {
+    let (server, client) = mpsc::session_channel::<Srv>();
+    let server = spawn(move || srv(server));
+    let (client, Area(area)) = client.send(Shape::Rect { width: 2.0, height: 3.0 }).unwrap().recv().unwrap();
+    client.close();
+    server.join().unwrap();
+    println!("in-process: rect area is {}", area);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let server = spawn(move || {
+        let (server_stream, _) = listener.accept().unwrap();
+        srv(stream::session_channel::<Srv, _>(server_stream).with_deadline(deadline))
+    });
+    let client = stream::session_channel::<<Srv as HasDual>::Dual, _>(TcpStream::connect(addr).unwrap())
+        .with_deadline(deadline);
+    let (client, Area(area)) = client.send(Shape::Circle { radius: 1.0 }).unwrap().recv().unwrap();
+    client.close();
+    server.join().unwrap();
+    println!("tcp: circle area is {}", area);
+}
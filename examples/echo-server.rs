@@ -30,7 +30,7 @@ fn srv(chan: Chan<mpsc::Channel, (), Rec<Srv>>) {
     }
 }
 
-type Cli = <Srv as HasDual>::Dual;
+type Cli = Dual<Srv>;
 
 fn cli(chan: Chan<mpsc::Channel, (), Rec<Cli>>) {
     let stdin = std::io::stdin();
@@ -0,0 +1,70 @@
+/// labeled.rs
+///
+/// This example demonstrates labeled choices: branches are selected and
+/// handled by label instead of position in the choose list.
+extern crate session_types_ng;
+
+use session_types_ng::*;
+use session_types_ng::mpsc::Value;
+
+struct Add;
+struct Neg;
+struct Quit;
+
+type Calc = Offer<Labeled<Add, Recv<Value<i64>, Recv<Value<i64>, Send<Value<i64>, Var<Z>>>>>,
+            Offer<Labeled<Neg, Recv<Value<i64>, Send<Value<i64>, Var<Z>>>>,
+            Offer<Labeled<Quit, End>, Nil>>>;
+
+fn server(chan: Chan<mpsc::Channel, (), Rec<Calc>>) {
+    let mut chan = chan.enter();
+    loop {
+        enum Req<A, N, Q> {
+            Add(A),
+            Neg(N),
+            Quit(Q),
+        }
+
+        let req = chan
+            .offer()
+            .on::<Add, _>(Req::Add)
+            .on::<Neg, _>(Req::Neg)
+            .on::<Quit, _>(Req::Quit)
+            .unwrap();
+
+        chan = match req {
+            Req::Add(c) => {
+                let (c, Value(a)) = c.recv().unwrap();
+                let (c, Value(b)) = c.recv().unwrap();
+                c.send(Value(a + b)).unwrap().zero()
+            },
+            Req::Neg(c) => {
+                let (c, Value(a)) = c.recv().unwrap();
+                c.send(Value(-a)).unwrap().zero()
+            },
+            Req::Quit(c) => {
+                c.close();
+                break;
+            },
+        };
+    }
+}
+
+fn client(chan: Chan<mpsc::Channel, (), Rec<dual!(Calc)>>) {
+    let (chan, Value(sum)) = chan
+        .enter()
+        .sel::<Add, _>().unwrap()
+        .send(Value(40)).unwrap()
+        .send(Value(2)).unwrap()
+        .recv().unwrap();
+    let (chan, Value(neg)) = chan
+        .zero()
+        .sel::<Neg, _>().unwrap()
+        .send(Value(sum)).unwrap()
+        .recv().unwrap();
+    println!("sum = {}, neg = {}", sum, neg);
+    chan.zero().sel::<Quit, _>().unwrap().close();
+}
+
+fn main() {
+    mpsc::connect(server, client);
+}
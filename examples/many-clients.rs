@@ -1,6 +1,8 @@
 extern crate session_types_ng;
 extern crate rand;
 
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Receiver};
 use std::thread::spawn;
 use rand::random;
@@ -24,18 +26,15 @@ fn server_handler(chan: Chan<mpsc::Channel, (), Server>) {
 }
 
 fn server(rx: Receiver<Chan<mpsc::Channel, (), Server>>) {
-    let mut count = 0;
-    loop {
-        match rx.recv() {
-            Ok(c) => {
-                spawn(move || server_handler(c));
-                count += 1;
-            },
-            Err(_) =>
-                break,
-        }
-    }
-    println!("Handled {} connections", count);
+    let count = Arc::new(AtomicUsize::new(0));
+    let handled = count.clone();
+    let pool = pool::SessionPool::new(4, move |c| {
+        server_handler(c);
+        handled.fetch_add(1, Ordering::SeqCst);
+    });
+    pool.run(rx);
+    pool.shutdown();
+    println!("Handled {} connections", count.load(Ordering::SeqCst));
 }
 
 fn client_handler(chan: Chan<mpsc::Channel, (), Client>) {
@@ -62,11 +61,7 @@ fn main() {
     println!("Spawning {} clients", n);
     for _ in 0..n {
         let tmp = tx.clone();
-        spawn(move || {
-            let (c1, c2) = mpsc::session_channel();
-            tmp.send(c1).unwrap();
-            client_handler(c2);
-        });
+        spawn(move || client_handler(request(tmp, mpsc::session_channel).unwrap()));
     }
     drop(tx);
 
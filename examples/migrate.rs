@@ -0,0 +1,39 @@
+/// migrate.rs
+///
+/// This example starts a session over an in-process channel and moves it
+/// to a TCP connection in the middle of the protocol.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+use std::net::{TcpListener, TcpStream};
+
+use session_types_ng::*;
+
+type Srv = Recv<mpsc::Value<u64>, Recv<stream::Value<u64>, Send<stream::Value<u64>, End>>>;
+type Cli = <Srv as HasDual>::Dual;
+
+fn srv(chan: Chan<mpsc::Channel, (), Srv>, listener: TcpListener) {
+    let (chan, mpsc::Value(a)) = chan.recv().unwrap();
+    let (socket, _) = listener.accept().unwrap();
+    let (chan, _) = chan.migrate(stream::Channel::<_, wire::Bincode>::new(socket)).unwrap();
+    let (chan, stream::Value(b)) = chan.recv().unwrap();
+    chan.send(stream::Value(a + b)).unwrap().close();
+}
+
+fn cli(chan: Chan<mpsc::Channel, (), Cli>, addr: std::net::SocketAddr) {
+    let chan = chan.send(mpsc::Value(40)).unwrap();
+    let socket = TcpStream::connect(addr).unwrap();
+    let (chan, _) = chan.migrate(stream::Channel::<_, wire::Bincode>::new(socket)).unwrap();
+    let (chan, stream::Value(sum)) = chan.send(stream::Value(2)).unwrap().recv().unwrap();
+    println!("sum over tcp: {}", sum);
+    chan.close();
+}
+
+fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (srv_chan, cli_chan) = mpsc::session_channel();
+    let thread = spawn(move || srv(srv_chan, listener));
+    cli(cli_chan, addr);
+    thread.join().unwrap();
+}
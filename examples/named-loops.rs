@@ -0,0 +1,73 @@
+/// named-loops.rs
+///
+/// This example demonstrates nested recursion with named loops: the inner
+/// loop jumps back to the outer one with `goto` instead of counting
+/// `succ` calls.
+extern crate session_types_ng;
+
+use session_types_ng::*;
+use session_types_ng::mpsc::Value;
+
+struct Batches;
+struct Items;
+
+// a sequence of batches, each batch is a sequence of numbers
+type Srv =
+    Loop<Batches, Offer<End,
+                  Offer<Loop<Items, Offer<Recv<Value<u32>, Goto<Items>>,
+                                    Offer<Send<Value<u32>, Goto<Batches>>, Nil>>>, Nil>>>;
+
+fn srv(chan: Chan<mpsc::Channel, (), Srv>) {
+    let mut batches = chan.enter();
+    loop {
+        let batch = batches
+            .offer()
+            .option(|chan| { chan.close(); None })
+            .option(Some)
+            .unwrap();
+        let mut items = match batch {
+            Some(chan) => chan.enter(),
+            None => break,
+        };
+        let mut sum = 0;
+        batches = loop {
+            enum Req<I, D> {
+                Item(I),
+                Done(D),
+            }
+
+            let req = items
+                .offer()
+                .option(Req::Item)
+                .option(Req::Done)
+                .unwrap();
+            match req {
+                Req::Item(chan) => {
+                    let (chan, Value(n)) = chan.recv().unwrap();
+                    sum += n;
+                    items = chan.goto();
+                },
+                Req::Done(chan) =>
+                    break chan.send(Value(sum)).unwrap().goto(),
+            }
+        };
+    }
+}
+
+fn cli(chan: Chan<mpsc::Channel, (), Dual<Srv>>) {
+    let mut batches = chan.enter();
+    for batch in &[vec![1, 2, 3], vec![10, 20]] {
+        let mut items = batches.second().unwrap().enter();
+        for &n in batch {
+            items = items.first().unwrap().send(Value(n)).unwrap().goto();
+        }
+        let (chan, Value(sum)) = items.second().unwrap().recv().unwrap();
+        println!("batch {:?} sum is {}", batch, sum);
+        batches = chan.goto();
+    }
+    batches.first().unwrap().close();
+}
+
+fn main() {
+    mpsc::connect(srv, cli);
+}
@@ -0,0 +1,65 @@
+/// reconnect.rs
+///
+/// This example resumes a session over a new TCP connection after the
+/// first one breaks in the middle of the protocol.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+use std::net::{Shutdown, TcpListener, TcpStream};
+
+use session_types_ng::*;
+use session_types_ng::stream::{self, Value};
+
+type Srv = Recv<Value<u64>, Send<Value<u64>, End>>;
+type Cli = <Srv as HasDual>::Dual;
+
+fn srv(listener: TcpListener) {
+    let (socket, _) = listener.accept().unwrap();
+    let chan: Chan<_, (), Srv> = stream::session_channel(socket);
+    let (mut chan, Value(n)) = chan.recv().unwrap();
+
+    // simulate a broken connection right before the reply
+    chan.carrier_mut().get_mut().shutdown(Shutdown::Both).unwrap();
+    let mut attempt = chan.send_resumable(Value(n * 2));
+    loop {
+        match attempt {
+            Ok(chan) => {
+                chan.close();
+                break;
+            },
+            Err(mut disconnected) => {
+                println!("server: {:?}, waiting for the client to come back", disconnected.error);
+                let (socket, _) = listener.accept().unwrap();
+                *disconnected.chan.carrier_mut().get_mut() = socket;
+                attempt = disconnected.chan.send_resumable(Value(n * 2));
+            },
+        }
+    }
+}
+
+fn cli(addr: std::net::SocketAddr) {
+    let chan: Chan<_, (), Cli> = stream::session_channel(TcpStream::connect(addr).unwrap());
+    let mut attempt = chan.send(Value(21)).unwrap().recv_resumable();
+    loop {
+        match attempt {
+            Ok((chan, Value(reply))) => {
+                println!("client: got {}", reply);
+                chan.close();
+                break;
+            },
+            Err(mut disconnected) => {
+                println!("client: {:?}, reconnecting", disconnected.error);
+                *disconnected.chan.carrier_mut().get_mut() = TcpStream::connect(addr).unwrap();
+                attempt = disconnected.chan.recv_resumable();
+            },
+        }
+    }
+}
+
+fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let thread = spawn(move || srv(listener));
+    cli(addr);
+    thread.join().unwrap();
+}
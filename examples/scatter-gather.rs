@@ -0,0 +1,42 @@
+/// scatter-gather.rs
+///
+/// This example demonstrates scatter/gather: a coordinator sends a chunk of
+/// work to every worker and then collects all the partial results.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+
+use session_types_ng::*;
+use session_types_ng::broadcast::{Scatter, Gather, Group};
+use session_types_ng::mpsc::Value;
+
+type Job = Scatter<Value<Vec<u64>>, Gather<Value<u64>, End>>;
+
+fn worker(chan: Chan<mpsc::Channel, (), Dual<Job>>) {
+    let (chan, Value(chunk)) = chan.recv().unwrap();
+    chan.send(Value(chunk.iter().sum())).unwrap().close();
+}
+
+fn main() {
+    let mut group = Group::new();
+    let workers: Vec<_> = (0 .. 4)
+        .map(|_| {
+            let (chan, peer) = mpsc::session_channel::<Job>();
+            group.subscribe(chan);
+            spawn(move || worker(peer))
+        })
+        .collect();
+
+    let numbers: Vec<u64> = (1 ..= 100).collect();
+    let (group, errors) = group.send(numbers.chunks(25).map(|c| Value(c.to_vec())));
+    assert!(errors.is_empty());
+    let (group, sums, errors) = group.recv();
+    assert!(errors.is_empty());
+    group.close();
+
+    println!("total = {}", sums.iter().map(|&(_, Value(s))| s).sum::<u64>());
+
+    for w in workers {
+        w.join().unwrap();
+    }
+}
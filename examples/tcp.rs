@@ -0,0 +1,49 @@
+/// tcp.rs
+///
+/// This example runs a session over a TCP connection using the default
+/// (bincode) wire format. Both sides verify protocol fingerprints first.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+
+use session_types_ng::*;
+use session_types_ng::stream::{self, SessionListener, TcpChannel, Value};
+
+type Srv = Recv<Value<String>, Choose<Send<Value<u64>, End>, Choose<End, Nil>>>;
+type Cli = <Srv as HasDual>::Dual;
+
+fn srv(chan: Chan<TcpChannel, (), Srv>) {
+    let (chan, Value(word)) = chan.recv().unwrap();
+    if word.is_empty() {
+        chan.second().unwrap().close();
+    } else {
+        chan.first().unwrap().send(Value(word.len() as u64)).unwrap().close();
+    }
+}
+
+fn cli(chan: Chan<TcpChannel, (), Cli>) {
+    chan
+        .send(Value("hello".to_string())).unwrap()
+        .offer()
+        .option(|chan| {
+            let (chan, Value(len)) = chan.recv().unwrap();
+            println!("server counted {} chars", len);
+            chan.close();
+        })
+        .option(|chan| {
+            println!("server rejected the word");
+            chan.close();
+        })
+        .unwrap();
+}
+
+fn main() {
+    let listener = SessionListener::<Srv>::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let thread = spawn(move || {
+        let (chan, _) = listener.accept().unwrap();
+        srv(chan);
+    });
+    cli(stream::connect(addr).unwrap());
+    thread.join().unwrap();
+}
@@ -0,0 +1,64 @@
+/// two-phase-commit.rs
+///
+/// This example demonstrates a multiparty session: a coordinator runs two
+/// phase commit with two participants under one global protocol.
+extern crate session_types_ng;
+
+use std::thread::spawn;
+
+use session_types_ng::multiparty::*;
+
+type Coordinator = R0;
+type Alice = R1;
+type Bob = R2;
+
+type Decide =
+    Branch<Coordinator, Alice,
+           Branch<Coordinator, Bob, GEnd, GEnd>,
+           Branch<Coordinator, Bob, GEnd, GEnd>>;
+
+type TwoPhaseCommit =
+    Msg<Coordinator, Alice, String,
+    Msg<Coordinator, Bob, String,
+    Msg<Alice, Coordinator, bool,
+    Msg<Bob, Coordinator, bool,
+    Decide>>>>;
+
+// both participants have the same local protocol
+type Participant = RecvFrom<Coordinator, String, SendTo<Coordinator, bool, OfferFrom<Coordinator, GEnd, GEnd>>>;
+
+fn participant<Me>(name: &str, vote: bool, chan: RoleChan<Me, Participant>) {
+    let (chan, tx) = chan.recv().unwrap();
+    println!("{}: voting {} for {}", name, vote, tx);
+    match chan.send(vote).unwrap().offer().unwrap() {
+        Chosen::First(chan) => {
+            println!("{}: commit", name);
+            chan.close();
+        },
+        Chosen::Second(chan) => {
+            println!("{}: abort", name);
+            chan.close();
+        },
+    }
+}
+
+fn main() {
+    let (coordinator, alice, bob) = session3::<TwoPhaseCommit>();
+
+    let alice = spawn(move || participant::<Alice>("alice", true, alice));
+    let bob = spawn(move || participant::<Bob>("bob", false, bob));
+
+    let (coordinator, a) = coordinator
+        .send("tx1".to_string()).unwrap()
+        .send("tx1".to_string()).unwrap()
+        .recv().unwrap();
+    let (coordinator, b) = coordinator.recv().unwrap();
+    if a && b {
+        coordinator.first().unwrap().first().unwrap().close();
+    } else {
+        coordinator.second().unwrap().second().unwrap().close();
+    }
+
+    alice.join().unwrap();
+    bob.join().unwrap();
+}
@@ -0,0 +1,62 @@
+/* C API of session-types-ng sessions (`ffi` feature).
+ *
+ * A session handle is created on the Rust side with `ffi::session_channel`
+ * and passed here. Every call is checked against the next step of the
+ * protocol and returns SESSION_OK or a negative error code. */
+#ifndef SESSION_TYPES_NG_H
+#define SESSION_TYPES_NG_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SESSION_OK 0
+/* The call does not fit the next step of the protocol. */
+#define SESSION_MISMATCH (-1)
+/* The Rust endpoint has hung up. */
+#define SESSION_HUNG_UP (-2)
+/* The session handle or the output pointer is null. */
+#define SESSION_NULL (-3)
+
+typedef struct Session Session;
+
+/* Select the branch number `branch` of the offer of the Rust endpoint. */
+int session_choose(Session *session, int branch);
+/* Learn the number of the branch chosen by the Rust endpoint. */
+int session_offer(Session *session);
+/* Release the session at the end of the protocol. */
+int session_close(Session *session);
+/* Release the session at any step. */
+void session_free(Session *session);
+
+int session_send_bool(Session *session, bool value);
+int session_recv_bool(Session *session, bool *value);
+int session_send_u8(Session *session, uint8_t value);
+int session_recv_u8(Session *session, uint8_t *value);
+int session_send_u16(Session *session, uint16_t value);
+int session_recv_u16(Session *session, uint16_t *value);
+int session_send_u32(Session *session, uint32_t value);
+int session_recv_u32(Session *session, uint32_t *value);
+int session_send_u64(Session *session, uint64_t value);
+int session_recv_u64(Session *session, uint64_t *value);
+int session_send_i8(Session *session, int8_t value);
+int session_recv_i8(Session *session, int8_t *value);
+int session_send_i16(Session *session, int16_t value);
+int session_recv_i16(Session *session, int16_t *value);
+int session_send_i32(Session *session, int32_t value);
+int session_recv_i32(Session *session, int32_t *value);
+int session_send_i64(Session *session, int64_t value);
+int session_recv_i64(Session *session, int64_t *value);
+int session_send_f32(Session *session, float value);
+int session_recv_f32(Session *session, float *value);
+int session_send_f64(Session *session, double value);
+int session_recv_f64(Session *session, double *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,300 @@
+use std::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::{Carrier, HasDual, Then, Chan, End, Send, Recv, Nil, Choose, Offer, Labeled, Rec, Var, Loop, Goto, cast_chan, close_chan};
+use super::reflect::{ProtocolDesc, Desc};
+
+/// Run protocol `P` where either side of every step may abort into the
+/// failure continuation `F` instead, and the other side follows it.
+///
+/// Both sides exchange a mark before each step, so a step waits for the
+/// other side to reach it as well. If both abort at the same step they
+/// both continue with `F`.
+///
+/// Note that `F` is placed under `Rec` layers of `P`, so `Var` in `F`
+/// should not refer to recursion outside of `Abortable`.
+///
+/// ```
+/// use std::thread;
+/// use session_types_ng::{Send, End, mpsc};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::abort::{Abortable, Abort};
+///
+/// type Upload = Abortable<Send<Value<u32>, Send<Value<u32>, End>>, End>;
+///
+/// let (client, server) = mpsc::session_channel::<Upload>();
+/// let server = thread::spawn(move || {
+///     let (chan, Value(first)) = match server.guard().check() {
+///         Ok(chan) => chan.recv().unwrap(),
+///         Err(_) => panic!("the client should proceed"),
+///     };
+///     assert_eq!(first, 1);
+///     // The passive side aborts the rest of the upload.
+///     chan.abort().unwrap().close();
+/// });
+/// let chan = match client.guard().proceed() {
+///     Ok(chan) => chan.send(Value(1)).unwrap(),
+///     Err(_) => panic!("the server should proceed"),
+/// };
+/// match chan.proceed() {
+///     Err(Abort::Peer(chan)) => chan.close(),
+///     _ => panic!("the server should abort"),
+/// }
+/// server.join().unwrap();
+/// ```
+pub struct Abortable<P, F>(PhantomData<(P, F)>);
+
+/// Active step `P` which could be replaced by abort into `F` by either side
+pub struct Guarded<P, F>(PhantomData<(P, F)>);
+
+/// Passive step `P` which could be replaced by abort into `F` by either side
+pub struct Checked<P, F>(PhantomData<(P, F)>);
+
+unsafe impl<P: HasDual, F: HasDual> HasDual for Abortable<P, F> {
+    type Dual = Abortable<P::Dual, F::Dual>;
+}
+
+unsafe impl<P: HasDual, F: HasDual> HasDual for Guarded<P, F> {
+    type Dual = Checked<P::Dual, F::Dual>;
+}
+
+unsafe impl<P: HasDual, F: HasDual> HasDual for Checked<P, F> {
+    type Dual = Guarded<P::Dual, F::Dual>;
+}
+
+impl<P, F: Then<Q>, Q> Then<Q> for Abortable<P, F> where P: Then<Q> {
+    type Output = Abortable<P::Output, F::Output>;
+}
+
+impl<P: Then<Q>, F: Then<Q>, Q> Then<Q> for Guarded<P, F> {
+    type Output = Guarded<P::Output, F::Output>;
+}
+
+impl<P: Then<Q>, F: Then<Q>, Q> Then<Q> for Checked<P, F> {
+    type Output = Checked<P::Output, F::Output>;
+}
+
+/// Protocol `Self` with every step guarded by abort into `F`.
+pub trait Guard<F> {
+    type Output;
+}
+
+/// Same as `Guard`, but for elements of choose and offer lists.
+pub trait GuardList<F> {
+    type Output;
+}
+
+impl<F> Guard<F> for End {
+    type Output = End;
+}
+
+impl<A, P: Guard<F>, F> Guard<F> for Send<A, P> {
+    type Output = Guarded<Send<A, P::Output>, F>;
+}
+
+impl<A, P: Guard<F>, F> Guard<F> for Recv<A, P> {
+    type Output = Checked<Recv<A, P::Output>, F>;
+}
+
+impl<P: Guard<F>, L: GuardList<F>, F> Guard<F> for Choose<P, L> {
+    type Output = Guarded<Choose<P::Output, L::Output>, F>;
+}
+
+impl<P: Guard<F>, L: GuardList<F>, F> Guard<F> for Offer<P, L> {
+    type Output = Checked<Offer<P::Output, L::Output>, F>;
+}
+
+impl<Lbl, P: Guard<F>, F> Guard<F> for Labeled<Lbl, P> {
+    type Output = Labeled<Lbl, P::Output>;
+}
+
+impl<P: Guard<F>, F> Guard<F> for Rec<P> {
+    type Output = Rec<P::Output>;
+}
+
+impl<N, F> Guard<F> for Var<N> {
+    type Output = Var<N>;
+}
+
+impl<Lbl, P: Guard<F>, F> Guard<F> for Loop<Lbl, P> {
+    type Output = Loop<Lbl, P::Output>;
+}
+
+impl<Lbl, F> Guard<F> for Goto<Lbl> {
+    type Output = Goto<Lbl>;
+}
+
+impl<F> GuardList<F> for Nil {
+    type Output = Nil;
+}
+
+impl<P: Guard<F>, L: GuardList<F>, F> GuardList<F> for Choose<P, L> {
+    type Output = Choose<P::Output, L::Output>;
+}
+
+impl<P: Guard<F>, L: GuardList<F>, F> GuardList<F> for Offer<P, L> {
+    type Output = Offer<P::Output, L::Output>;
+}
+
+impl<P: Guard<F>, F> ProtocolDesc for Abortable<P, F> where P::Output: ProtocolDesc {
+    fn desc() -> Desc {
+        P::Output::desc()
+    }
+}
+
+impl<P: ProtocolDesc, F: ProtocolDesc> ProtocolDesc for Guarded<P, F> {
+    fn desc() -> Desc {
+        Desc::Guarded(Box::new(P::desc()), Box::new(F::desc()))
+    }
+}
+
+impl<P: ProtocolDesc, F: ProtocolDesc> ProtocolDesc for Checked<P, F> {
+    fn desc() -> Desc {
+        Desc::Checked(Box::new(P::desc()), Box::new(F::desc()))
+    }
+}
+
+/// The session has moved to the failure continuation.
+#[derive(Debug)]
+pub enum Abort<F, Err> {
+    /// The other side has aborted.
+    Peer(F),
+    /// Carrier has failed, the channel is closed.
+    Carrier(Err),
+}
+
+/// Failure of the carrier while the marks of a step are exchanged.
+#[derive(Debug)]
+pub enum MarkError<S, R> {
+    Send(S),
+    Recv(R),
+}
+
+/// Send the mark of this side and receive the one of the other side, which
+/// tells whether the other side proceeds. The channel is closed on failure.
+fn exchange<SR, E, P>(mut chan: Chan<SR, E, P>, proceed: bool) -> Result<(Chan<SR, E, P>, bool), MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>>
+    where SR: Carrier
+{
+    if let Err(e) = chan.carrier.send_choice(proceed) {
+        close_chan(chan);
+        return Err(MarkError::Send(e));
+    }
+    match chan.carrier.recv_choice() {
+        Ok(peer) =>
+            Ok((chan, peer)),
+        Err(e) => {
+            close_chan(chan);
+            Err(MarkError::Recv(e))
+        },
+    }
+}
+
+fn proceed<SR, E, S, P, F>(chan: Chan<SR, E, S>) -> Result<Chan<SR, E, P>, Abort<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>>>
+    where SR: Carrier
+{
+    match exchange(chan, true) {
+        Ok((chan, true)) =>
+            Ok(cast_chan(chan)),
+        Ok((chan, false)) =>
+            Err(Abort::Peer(cast_chan(chan))),
+        Err(e) =>
+            Err(Abort::Carrier(e)),
+    }
+}
+
+fn abort<SR, E, S, F>(chan: Chan<SR, E, S>) -> Result<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>>
+    where SR: Carrier
+{
+    exchange(chan, false).map(|(chan, _)| cast_chan(chan))
+}
+
+impl<SR, E, P, F> Chan<SR, E, Abortable<P, F>> where SR: Carrier, P: Guard<F> {
+    /// Start the abortable protocol.
+    #[must_use]
+    pub fn guard(self) -> Chan<SR, E, P::Output> {
+        cast_chan(self)
+    }
+}
+
+impl<SR, E, P, F> Chan<SR, E, Guarded<P, F>> where SR: Carrier {
+    /// Continue with the step `P`, unless the other side has aborted.
+    ///
+    /// ```
+    /// use session_types_ng::{Send, End, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    /// use session_types_ng::abort::{Abortable, Abort};
+    ///
+    /// type Upload = Abortable<Send<Value<u32>, End>, End>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Upload>();
+    /// server.poison();
+    /// match client.guard().proceed() {
+    ///     Err(Abort::Carrier(_)) => (),
+    ///     _ => panic!("the carrier should fail"),
+    /// }
+    /// ```
+    #[must_use]
+    pub fn proceed(self) -> Result<Chan<SR, E, P>, Abort<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>>> {
+        proceed(self)
+    }
+
+    /// Abort into the failure continuation `F`.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Send, End, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    /// use session_types_ng::abort::{Abortable, Abort};
+    ///
+    /// type Upload = Abortable<Send<Value<u32>, End>, Send<Value<String>, End>>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Upload>();
+    /// let client = thread::spawn(move || {
+    ///     let chan = client.guard().abort().unwrap();
+    ///     chan.send(Value("nothing to upload".to_string())).unwrap().close();
+    /// });
+    /// match server.guard().check() {
+    ///     Err(Abort::Peer(chan)) => {
+    ///         let (chan, Value(reason)) = chan.recv().unwrap();
+    ///         assert_eq!(reason, "nothing to upload");
+    ///         chan.close();
+    ///     },
+    ///     _ => panic!("the client should abort"),
+    /// }
+    /// client.join().unwrap();
+    /// ```
+    #[must_use]
+    pub fn abort(self) -> Result<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>> {
+        abort(self)
+    }
+
+    /// Abort into the failure continuation `F` and drive it with `handler`.
+    /// If the abort could not be delivered the channel is closed instead.
+    pub fn abort_with<H>(self, handler: H) where H: FnOnce(Chan<SR, E, F>) {
+        if let Ok(chan) = self.abort() {
+            handler(chan);
+        }
+    }
+}
+
+impl<SR, E, P, F> Chan<SR, E, Checked<P, F>> where SR: Carrier {
+    /// Continue with the step `P`, unless the other side has aborted.
+    #[must_use]
+    pub fn check(self) -> Result<Chan<SR, E, P>, Abort<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>>> {
+        proceed(self)
+    }
+
+    /// Abort into the failure continuation `F`.
+    #[must_use]
+    pub fn abort(self) -> Result<Chan<SR, E, F>, MarkError<SR::SendChoiceErr, SR::RecvChoiceErr>> {
+        abort(self)
+    }
+
+    /// Abort into the failure continuation `F` and drive it with `handler`.
+    /// If the abort could not be delivered the channel is closed instead.
+    pub fn abort_with<H>(self, handler: H) where H: FnOnce(Chan<SR, E, F>) {
+        if let Ok(chan) = self.abort() {
+            handler(chan);
+        }
+    }
+}
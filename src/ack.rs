@@ -0,0 +1,279 @@
+//! At-least-once delivery over lossy connections.
+//!
+//! An `AckCarrier` numbers every value and choice it sends and keeps it in
+//! an outbox until the peer acknowledges it. A send never fails because of
+//! the connection: once the underlying carrier fails, messages are only
+//! kept, and a receive fails instead. The channel stays at the failed step
+//! with `recv_resumable` (see `resume` module), so the connection could be
+//! re-established and handed over to `resume`, which retransmits the kept
+//! messages, after which the step is retried.
+//!
+//! Acknowledgments are carried by the frame headers of the messages the
+//! peer sends, so the messages of a one-way stretch of the protocol stay
+//! in the outbox until the peer sends something (or until the next
+//! `resume`). On `resume` both sides tell how many messages they have
+//! received, so the peer retransmits only the ones which have been lost
+//! and nothing is delivered twice. Both sides should use an `AckCarrier`,
+//! and the underlying carrier should transfer `bytes::Bytes`, which the
+//! headers are sent as. Sent values are cloned into the outbox, so only
+//! payloads implementing `Clone` could be sent over an `AckCarrier`.
+//!
+//! ```
+//! use std::net::{Shutdown, TcpListener, TcpStream};
+//! use std::sync::mpsc::channel;
+//! use std::thread;
+//! use session_types_ng::{Chan, Send, Recv, End, Dual};
+//! use session_types_ng::stream::{self, Value};
+//! use session_types_ng::ack::AckCarrier;
+//!
+//! type Srv = Recv<Value<u64>, Send<Value<u64>, End>>;
+//!
+//! fn link(socket: TcpStream) -> stream::TcpChannel {
+//!     stream::Channel::new(socket)
+//! }
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+//! let addr = listener.local_addr().unwrap();
+//! let (broken_tx, broken_rx) = channel();
+//! let server = thread::spawn(move || {
+//!     let (socket, _) = listener.accept().unwrap();
+//!     let chan: Chan<_, (), Srv> = Chan::new(AckCarrier::new(link(socket)));
+//!     let (chan, Value(n)) = chan.recv().unwrap();
+//!     broken_rx.recv().unwrap();
+//!     // the reply is kept until the client acknowledges it
+//!     let mut carrier = chan.send(Value(n * 2)).unwrap().shutdown();
+//!     let (socket, _) = listener.accept().unwrap();
+//!     carrier.resume(link(socket)).unwrap();
+//! });
+//!
+//! let socket = TcpStream::connect(addr).unwrap();
+//! let chan: Chan<_, (), Dual<Srv>> = Chan::new(AckCarrier::new(link(socket)));
+//! let mut chan = chan.send(Value(21)).unwrap();
+//! // the connection breaks before the reply arrives
+//! chan.carrier_mut().get_mut().get_mut().shutdown(Shutdown::Both).unwrap();
+//! broken_tx.send(()).unwrap();
+//! let mut disconnected = match chan.recv_resumable() {
+//!     Err(disconnected) => disconnected,
+//!     Ok(..) => panic!("the connection is broken"),
+//! };
+//! let socket = TcpStream::connect(addr).unwrap();
+//! disconnected.chan.carrier_mut().resume(link(socket)).unwrap();
+//! let (mut chan, Value(reply)) = disconnected.chan.recv_resumable().unwrap();
+//! assert_eq!(chan.carrier_mut().unacknowledged(), 0);
+//! chan.close();
+//! server.join().unwrap();
+//! assert_eq!(reply, 42);
+//! ```
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::convert::Infallible;
+use std::time::Duration;
+use super::{Carrier, Payload, ChannelSend, ChannelRecv};
+use super::bytes::Bytes;
+use super::timeout::TimeoutCarrier;
+
+/// Error of a receive over `AckCarrier`.
+#[derive(Debug)]
+pub enum AckError<E, H> {
+    /// Transferring the frame header has failed.
+    Header(H),
+    /// The frame header is not a valid one.
+    Malformed,
+    /// A message has been received out of order.
+    Sequence { expected: u64, received: u64 },
+    /// Receiving the message itself has failed.
+    Carrier(E),
+}
+
+/// Error of `AckCarrier::resume`.
+#[derive(Debug)]
+pub enum ResumeError<S, R> {
+    /// Sending the count of received messages has failed.
+    Send(S),
+    /// Receiving the count of the peer has failed.
+    Recv(R),
+    /// The count of the peer is not a valid one.
+    Malformed,
+    /// Retransmitting a kept message has failed.
+    Retransmit,
+}
+
+/// Sent message kept until acknowledged.
+enum Frame<C> {
+    Choice(bool),
+    /// Sends a copy of the value, telling whether it has succeeded.
+    Value(Box<dyn Fn(&mut C) -> bool + Send>),
+}
+
+impl<C> Frame<C> where C: Carrier {
+    fn transmit(&self, carrier: &mut C) -> bool {
+        match *self {
+            Frame::Choice(choice) =>
+                carrier.send_choice(choice).is_ok(),
+            Frame::Value(ref send) =>
+                send(carrier),
+        }
+    }
+}
+
+/// Carrier wrapper providing at-least-once delivery, see the module
+/// documentation.
+pub struct AckCarrier<C> {
+    inner: C,
+    /// Sent messages not acknowledged yet, with their sequence numbers.
+    outbox: VecDeque<(u64, Frame<C>)>,
+    /// Count of messages sent so far.
+    sent: u64,
+    /// Count of messages received so far.
+    received: u64,
+    /// The underlying carrier has failed to send.
+    broken: bool,
+}
+
+impl<C> AckCarrier<C> {
+    pub fn new(inner: C) -> AckCarrier<C> {
+        AckCarrier {
+            inner,
+            outbox: VecDeque::new(),
+            sent: 0,
+            received: 0,
+            broken: false,
+        }
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Count of sent messages not acknowledged by the peer yet.
+    pub fn unacknowledged(&self) -> usize {
+        self.outbox.len()
+    }
+
+    /// Whether the underlying carrier has failed to send, so that messages
+    /// are only kept until `resume`.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    fn acknowledge(&mut self, count: u64) {
+        while self.outbox.front().is_some_and(|&(seq, _)| seq <= count) {
+            self.outbox.pop_front();
+        }
+    }
+}
+
+impl<C> AckCarrier<C>
+    where C: Carrier, Bytes: ChannelSend<C> + ChannelRecv<C>
+{
+    /// Replace the failed underlying carrier with a re-established one,
+    /// and retransmit the messages the peer has not received. The peer
+    /// should resume at the same time.
+    pub fn resume(&mut self, carrier: C) -> Result<(), ResumeError<<Bytes as ChannelSend<C>>::Err, <Bytes as ChannelRecv<C>>::Err>> {
+        self.inner = carrier;
+        self.broken = false;
+        Bytes::from(&self.received.to_be_bytes()[..]).send(&mut self.inner).map_err(ResumeError::Send)?;
+        let count = Bytes::recv(&mut self.inner).map_err(ResumeError::Recv)?;
+        let count = count[..].try_into().map_err(|_| ResumeError::Malformed)?;
+        self.acknowledge(u64::from_be_bytes(count));
+        for &(seq, ref frame) in &self.outbox {
+            header(seq, self.received).send(&mut self.inner).map_err(ResumeError::Send)?;
+            if !frame.transmit(&mut self.inner) {
+                return Err(ResumeError::Retransmit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the message and send it unless the carrier has failed.
+    fn transmit(&mut self, frame: Frame<C>) {
+        self.sent += 1;
+        if !self.broken {
+            let sent = header(self.sent, self.received).send(&mut self.inner).is_ok() && frame.transmit(&mut self.inner);
+            self.broken = !sent;
+        }
+        self.outbox.push_back((self.sent, frame));
+    }
+
+    /// Receive the header of the next message.
+    fn next<E>(&mut self) -> Result<(), AckError<E, <Bytes as ChannelRecv<C>>::Err>> {
+        let header = Bytes::recv(&mut self.inner).map_err(AckError::Header)?;
+        if header.len() != 16 {
+            return Err(AckError::Malformed);
+        }
+        let (seq, ack) = header.split_at(8);
+        let seq = u64::from_be_bytes(seq.try_into().unwrap_or_default());
+        let ack = u64::from_be_bytes(ack.try_into().unwrap_or_default());
+        self.acknowledge(ack);
+        if seq != self.received + 1 {
+            return Err(AckError::Sequence { expected: self.received + 1, received: seq });
+        }
+        Ok(())
+    }
+}
+
+/// Header of the message `seq`, acknowledging `ack` messages.
+fn header(seq: u64, ack: u64) -> Bytes {
+    let mut header = Vec::with_capacity(16);
+    header.extend_from_slice(&seq.to_be_bytes());
+    header.extend_from_slice(&ack.to_be_bytes());
+    Bytes::new(header)
+}
+
+impl<C> Carrier for AckCarrier<C>
+    where C: Carrier, Bytes: ChannelSend<C> + ChannelRecv<C>
+{
+    type SendChoiceErr = Infallible;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.transmit(Frame::Choice(choice));
+        Ok(())
+    }
+
+    type RecvChoiceErr = AckError<C::RecvChoiceErr, <Bytes as ChannelRecv<C>>::Err>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.next()?;
+        let choice = self.inner.recv_choice().map_err(AckError::Carrier)?;
+        self.received += 1;
+        Ok(choice)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+impl<C> TimeoutCarrier for AckCarrier<C>
+    where C: TimeoutCarrier, Bytes: ChannelSend<C> + ChannelRecv<C>
+{
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout).map_err(AckError::Carrier)
+    }
+}
+
+/// Cloneable values transferable over a carrier are transferable over its
+/// acknowledging wrapper.
+impl<C, T> ChannelSend<AckCarrier<C>> for T
+    where T: Payload + ChannelSend<C> + Clone + Send + 'static, C: Carrier, Bytes: ChannelSend<C> + ChannelRecv<C>
+{
+    type Err = Infallible;
+
+    fn send(self, carrier: &mut AckCarrier<C>) -> Result<(), Self::Err> {
+        carrier.transmit(Frame::Value(Box::new(move |carrier| self.clone().send(carrier).is_ok())));
+        Ok(())
+    }
+}
+
+impl<C, T> ChannelRecv<AckCarrier<C>> for T
+    where T: Payload + ChannelRecv<C>, C: Carrier, Bytes: ChannelSend<C> + ChannelRecv<C>
+{
+    type Err = AckError<<T as ChannelRecv<C>>::Err, <Bytes as ChannelRecv<C>>::Err>;
+
+    fn recv(carrier: &mut AckCarrier<C>) -> Result<Self, Self::Err> {
+        carrier.next()?;
+        let value = <T>::recv(&mut carrier.inner).map_err(AckError::Carrier)?;
+        carrier.received += 1;
+        Ok(value)
+    }
+}
+
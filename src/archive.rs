@@ -0,0 +1,141 @@
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use rkyv::{self, rancor, Archive, Deserialize, Portable};
+use rkyv::api::high::{HighSerializer, HighValidator, HighDeserializer};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use super::{ChannelSend, ChannelRecv, Payload};
+use super::stream::{self, read_frame_len};
+use super::wire::WireFormat;
+
+/// A payload transferred over `stream::Channel` in `rkyv` archived form,
+/// bypassing the carrier wire format. Received values are validated once
+/// and then accessed in place via `get` without a deserialization copy.
+///
+/// ```
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, HasDual, Send, End, stream};
+/// use session_types_ng::archive::Archived;
+///
+/// type Samples = Send<Archived<Vec<u32>>, End>;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let tx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (rx, _) = listener.accept().unwrap();
+/// let tx: Chan<stream::TcpChannel, (), Samples> = stream::session_channel(tx);
+/// let rx: Chan<stream::TcpChannel, (), <Samples as HasDual>::Dual> = stream::session_channel(rx);
+///
+/// tx.send(Archived::new(&vec![1, 2, 3]).unwrap()).unwrap().close();
+/// let (rx, samples) = rx.recv().unwrap();
+/// rx.close();
+/// assert_eq!(samples.get().len(), 3);
+/// assert_eq!(samples.deserialize().unwrap(), vec![1, 2, 3]);
+/// ```
+///
+/// A received archive which does not validate is refused, so `get` never
+/// reads out of bounds:
+///
+/// ```
+/// use std::io::Write;
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, Recv, End, stream};
+/// use session_types_ng::archive::{self, Archived};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (socket, _) = listener.accept().unwrap();
+/// let rx: Chan<stream::TcpChannel, (), Recv<Archived<Vec<u32>>, End>> = stream::session_channel(socket);
+///
+/// // the length of the vector, which ends the archive, points past its end
+/// let mut bytes = Archived::new(&vec![1u32, 2, 3]).unwrap().as_bytes().to_vec();
+/// let len = bytes.len();
+/// bytes[len - 4 ..].copy_from_slice(&u32::MAX.to_le_bytes());
+/// peer.write_all(&(len as u32).to_be_bytes()).unwrap();
+/// peer.write_all(&bytes).unwrap();
+/// match rx.recv() {
+///     Err(archive::Error::Archive(_)) => (),
+///     _ => panic!("the archive is corrupted"),
+/// }
+/// ```
+pub struct Archived<T> {
+    bytes: AlignedVec,
+    _marker: PhantomData<T>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Archive(rancor::Error),
+}
+
+impl<T> Archived<T> where T: Archive, T::Archived: Portable + for<'a> CheckBytes<HighValidator<'a, rancor::Error>> {
+    /// Archive `value` for sending.
+    pub fn new(value: &T) -> Result<Archived<T>, Error>
+        where T: for<'a> rkyv::Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>
+    {
+        rkyv::to_bytes::<rancor::Error>(value)
+            .map(Archived::from_bytes)
+            .map_err(Error::Archive)
+    }
+
+    fn from_bytes(bytes: AlignedVec) -> Archived<T> {
+        Archived {
+            bytes,
+            _marker: PhantomData,
+        }
+    }
+
+    fn validate(self) -> Result<Archived<T>, Error> {
+        rkyv::access::<T::Archived, rancor::Error>(&self.bytes).map_err(Error::Archive)?;
+        Ok(self)
+    }
+
+    /// Borrow the archived view of the value.
+    pub fn get(&self) -> &T::Archived {
+        // contents are always validated: either produced by `new` or checked in `recv`
+        unsafe { rkyv::access_unchecked::<T::Archived>(&self.bytes) }
+    }
+
+    /// Deserialize into an owned value.
+    pub fn deserialize(&self) -> Result<T, Error> where T::Archived: Deserialize<T, HighDeserializer<rancor::Error>> {
+        rkyv::deserialize::<T, rancor::Error>(self.get()).map_err(Error::Archive)
+    }
+}
+
+impl<T> Payload for Archived<T> { }
+
+impl<T> Archived<T> {
+    /// The archive as it is transferred.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<T, S, F> ChannelSend<stream::Channel<S, F>> for Archived<T> where S: Read + Write, F: WireFormat {
+    type Err = Error;
+
+    fn send(self, carrier: &mut stream::Channel<S, F>) -> Result<(), Self::Err> {
+        carrier.send_frame(&self.bytes).map_err(Error::Io)
+    }
+}
+
+impl<T, S, F> ChannelRecv<stream::Channel<S, F>> for Archived<T>
+    where T: Archive,
+          T::Archived: Portable + for<'a> CheckBytes<HighValidator<'a, rancor::Error>>,
+          S: Read + Write,
+          F: WireFormat
+{
+    type Err = Error;
+
+    fn recv(carrier: &mut stream::Channel<S, F>) -> Result<Self, Self::Err> {
+        let limits = carrier.limits();
+        let mut stream = carrier.reader().map_err(Error::Io)?;
+        let len = read_frame_len(&mut stream).map_err(Error::Io)?;
+        limits.check_message(len).map_err(Error::Io)?;
+        let mut bytes = AlignedVec::with_capacity(len);
+        bytes.resize(len, 0);
+        stream.read_exact(&mut bytes).map_err(Error::Io)?;
+        Archived::from_bytes(bytes).validate()
+    }
+}
@@ -0,0 +1,90 @@
+//! Authentication before a session.
+//!
+//! A connection could carry a credential exchange first and the application
+//! session only once the credentials are accepted. The exchange is an
+//! ordinary protocol `A` of any shape, e.g. a password login or a challenge
+//! and response, run over the carrier to its `End` by a closure of the
+//! client (`authenticated_connect`) and of the server (`authenticate`).
+//! The server then sends its verdict as a choice: on acceptance both sides
+//! get the application channel at `P` (respectively its dual) over the same
+//! carrier, otherwise the carrier is dropped, tearing the connection down,
+//! and both sides get `Error::Rejected`.
+//!
+//! A closure failing the exchange for its own reasons while it holds the
+//! channel should `poison` it, so that the other side fails as well.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{Chan, Send, Recv, End, HasDual, mpsc};
+//! use session_types_ng::auth::{self, authenticated_connect, authenticate};
+//!
+//! type Login = Send<mpsc::Value<(String, String)>, End>;
+//! type Greeting = Recv<mpsc::Value<String>, End>;
+//!
+//! fn login(user: &str, password: &str) -> Result<Chan<mpsc::Channel, (), Greeting>, auth::Error<(), std::sync::mpsc::RecvError>> {
+//!     let (client, server) = mpsc::session_channel::<End>();
+//!     let (client, server) = (client.shutdown(), server.shutdown());
+//!     let server = thread::spawn(move || {
+//!         let verified = authenticate(server, |chan: Chan<_, (), <Login as HasDual>::Dual>| {
+//!             chan.recv().map(|(chan, mpsc::Value((user, password)))| (chan, user == "alice" && password == "secret"))
+//!         });
+//!         if let Ok(chan) = verified {
+//!             let chan: Chan<_, (), <Greeting as HasDual>::Dual> = chan;
+//!             chan.send(mpsc::Value("welcome".to_string())).unwrap().close();
+//!         }
+//!     });
+//!     let credentials = (user.to_string(), password.to_string());
+//!     let chan = authenticated_connect(client, |chan: Chan<_, (), Login>| chan.send(mpsc::Value(credentials)).map_err(|_| ()));
+//!     server.join().unwrap();
+//!     chan
+//! }
+//!
+//! let (chan, mpsc::Value(greeting)) = login("alice", "secret").unwrap().recv().unwrap();
+//! chan.close();
+//! assert_eq!(greeting, "welcome");
+//!
+//! assert!(matches!(login("alice", "guess"), Err(auth::Error::Rejected)));
+//! ```
+use super::{Carrier, Chan, End};
+
+/// Failure of authentication.
+#[derive(Debug)]
+pub enum Error<X, C> {
+    /// The credential exchange has failed.
+    Exchange(X),
+    /// The verdict could not be transferred.
+    Verdict(C),
+    /// The server has rejected the credentials.
+    Rejected,
+}
+
+/// Authenticate to the server over `carrier`: run the credential exchange
+/// `A` with `exchange`, and once the server accepts the credentials start
+/// the application session `P`.
+pub fn authenticated_connect<SR, A, E, P, F, X>(carrier: SR, exchange: F) -> Result<Chan<SR, (), P>, Error<X, SR::RecvChoiceErr>>
+    where SR: Carrier, F: FnOnce(Chan<SR, (), A>) -> Result<Chan<SR, E, End>, X>
+{
+    let mut carrier = exchange(Chan::new(carrier)).map_err(Error::Exchange)?.shutdown();
+    match carrier.recv_choice() {
+        Ok(true) => Ok(Chan::new(carrier)),
+        Ok(false) => Err(Error::Rejected),
+        Err(e) => Err(Error::Verdict(e)),
+    }
+}
+
+/// Authenticate the client over `carrier`: run the credential exchange `A`
+/// with `verify`, which decides whether the credentials are accepted, and
+/// if they are, start the application session `P`. Rejected clients are
+/// disconnected.
+pub fn authenticate<SR, A, E, P, F, X>(carrier: SR, verify: F) -> Result<Chan<SR, (), P>, Error<X, SR::SendChoiceErr>>
+    where SR: Carrier, F: FnOnce(Chan<SR, (), A>) -> Result<(Chan<SR, E, End>, bool), X>
+{
+    let (chan, accepted) = verify(Chan::new(carrier)).map_err(Error::Exchange)?;
+    let mut carrier = chan.shutdown();
+    carrier.send_choice(accepted).map_err(Error::Verdict)?;
+    if accepted {
+        Ok(Chan::new(carrier))
+    } else {
+        Err(Error::Rejected)
+    }
+}
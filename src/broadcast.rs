@@ -0,0 +1,278 @@
+//! One-to-many steps over a `Group` of session channels sharing the same
+//! protocol state.
+//!
+//! A coordinator follows `Bcast` to send a copy of a value to every member,
+//! `Scatter` to send a distinct value to each one and `Gather` to collect a
+//! reply from each one, while every member sees a plain `Recv` or `Send`.
+//! Replies are gathered as they arrive, so a slow member does not hold the
+//! others back. Members whose carrier fails are closed and dropped from the
+//! group.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{End, mpsc};
+//! use session_types_ng::mpsc::Value;
+//! use session_types_ng::broadcast::{Group, Scatter, Gather};
+//!
+//! type Square = Scatter<Value<u32>, Gather<Value<u32>, End>>;
+//!
+//! let mut group = Group::new();
+//! let mut workers = Vec::new();
+//! for _ in 0 .. 3 {
+//!     let (coordinator, worker) = mpsc::session_channel::<Square>();
+//!     group.subscribe(coordinator);
+//!     workers.push(thread::spawn(move || {
+//!         let (worker, Value(x)) = worker.recv().unwrap();
+//!         worker.send(Value(x * x)).unwrap().close();
+//!     }));
+//! }
+//! let (group, errors) = group.send(vec![Value(1), Value(2), Value(3)]);
+//! assert!(errors.is_empty());
+//! let (group, mut replies, errors) = group.recv();
+//! assert!(errors.is_empty());
+//! replies.sort_by_key(|&(index, _)| index);
+//! let squares: Vec<_> = replies.into_iter().map(|(_, Value(y))| y).collect();
+//! assert_eq!(squares, vec![1, 4, 9]);
+//! group.close();
+//! for worker in workers {
+//!     worker.join().unwrap();
+//! }
+//! ```
+use std::any::type_name;
+use std::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::{Carrier, ChannelSend, HasDual, Then, Chan, End, Send, Recv, cast_chan, close_chan};
+use super::reflect::{ProtocolDesc, Desc};
+use super::subtype::SubtypeOf;
+use super::abort::{Guard, Guarded, Checked};
+#[cfg(feature = "std")]
+use super::ChannelRecv;
+#[cfg(feature = "std")]
+use super::select::{ChanSelect, Selectable, carrier_ready};
+#[cfg(feature = "std")]
+use super::timeout::TimeoutCarrier;
+
+/// Broadcast `A` to every member of a `Group`, then `R`. The dual of each
+/// member is a plain `Recv`.
+pub struct Bcast<A, R>(PhantomData<(A, R)>);
+
+unsafe impl<A, R: HasDual> HasDual for Bcast<A, R> {
+    type Dual = Recv<A, R::Dual>;
+}
+
+impl<A, R: Then<Q>, Q> Then<Q> for Bcast<A, R> {
+    type Output = Bcast<A, R::Output>;
+}
+
+impl<A, R: Guard<F>, F> Guard<F> for Bcast<A, R> {
+    type Output = Guarded<Bcast<A, R::Output>, F>;
+}
+
+impl<A, R, Q> SubtypeOf<Bcast<A, Q>> for Bcast<A, R> where R: SubtypeOf<Q> { }
+
+impl<A, R: ProtocolDesc> ProtocolDesc for Bcast<A, R> {
+    fn desc() -> Desc {
+        Desc::Bcast(type_name::<A>(), Box::new(R::desc()))
+    }
+}
+
+/// Send a distinct `A` to every member of a `Group`, then `R`. The dual of
+/// each member is a plain `Recv`.
+pub struct Scatter<A, R>(PhantomData<(A, R)>);
+
+/// Receive `A` from every member of a `Group`, then `R`. The dual of each
+/// member is a plain `Send`.
+pub struct Gather<A, R>(PhantomData<(A, R)>);
+
+unsafe impl<A, R: HasDual> HasDual for Scatter<A, R> {
+    type Dual = Recv<A, R::Dual>;
+}
+
+unsafe impl<A, R: HasDual> HasDual for Gather<A, R> {
+    type Dual = Send<A, R::Dual>;
+}
+
+impl<A, R: Then<Q>, Q> Then<Q> for Scatter<A, R> {
+    type Output = Scatter<A, R::Output>;
+}
+
+impl<A, R: Then<Q>, Q> Then<Q> for Gather<A, R> {
+    type Output = Gather<A, R::Output>;
+}
+
+impl<A, R: Guard<F>, F> Guard<F> for Scatter<A, R> {
+    type Output = Guarded<Scatter<A, R::Output>, F>;
+}
+
+impl<A, R: Guard<F>, F> Guard<F> for Gather<A, R> {
+    type Output = Checked<Gather<A, R::Output>, F>;
+}
+
+impl<A, R, Q> SubtypeOf<Scatter<A, Q>> for Scatter<A, R> where R: SubtypeOf<Q> { }
+
+impl<A, R, Q> SubtypeOf<Gather<A, Q>> for Gather<A, R> where R: SubtypeOf<Q> { }
+
+impl<A, R: ProtocolDesc> ProtocolDesc for Scatter<A, R> {
+    fn desc() -> Desc {
+        Desc::Scatter(type_name::<A>(), Box::new(R::desc()))
+    }
+}
+
+impl<A, R: ProtocolDesc> ProtocolDesc for Gather<A, R> {
+    fn desc() -> Desc {
+        Desc::Gather(type_name::<A>(), Box::new(R::desc()))
+    }
+}
+
+/// A set of session channels sharing the same protocol state, driven together.
+#[must_use]
+pub struct Group<SR, E, P> {
+    members: Vec<Chan<SR, E, P>>,
+}
+
+impl<SR, E, P> Default for Group<SR, E, P> {
+    fn default() -> Group<SR, E, P> {
+        Group::new()
+    }
+}
+
+impl<SR, E, P> Group<SR, E, P> {
+    pub fn new() -> Group<SR, E, P> {
+        Group { members: Vec::new() }
+    }
+
+    /// Add a session channel to the group.
+    pub fn subscribe(&mut self, chan: Chan<SR, E, P>) {
+        self.members.push(chan);
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Take the member channels back to continue with them individually.
+    pub fn into_chans(self) -> Vec<Chan<SR, E, P>> {
+        self.members
+    }
+}
+
+impl<SR, E, A, R> Group<SR, E, Bcast<A, R>> where SR: Carrier, A: ChannelSend<SR> + Clone {
+    /// Send a copy of the value to every member. Members failed to receive
+    /// it are closed and removed from the group, their errors are returned.
+    pub fn send(self, v: A) -> (Group<SR, E, R>, Vec<A::Err>) {
+        let mut members = Vec::with_capacity(self.members.len());
+        let mut errors = Vec::new();
+        for mut chan in self.members {
+            match v.clone().send(&mut chan.carrier) {
+                Ok(()) =>
+                    members.push(cast_chan(chan)),
+                Err(e) => {
+                    close_chan(chan);
+                    errors.push(e);
+                },
+            }
+        }
+        (Group { members }, errors)
+    }
+}
+
+impl<SR, E, A, R> Group<SR, E, Scatter<A, R>> where SR: Carrier, A: ChannelSend<SR> {
+    /// Send `values` to the members, one value for each member in order of
+    /// subscription. Members failed to receive are closed and removed from
+    /// the group, their errors are returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values differs from the number of members.
+    pub fn send<I>(self, values: I) -> (Group<SR, E, R>, Vec<A::Err>) where I: IntoIterator<Item = A> {
+        let mut values = values.into_iter();
+        let mut members = Vec::with_capacity(self.members.len());
+        let mut errors = Vec::new();
+        for mut chan in self.members {
+            let v = values.next().expect("scatter values are fewer than group members");
+            match v.send(&mut chan.carrier) {
+                Ok(()) =>
+                    members.push(cast_chan(chan)),
+                Err(e) => {
+                    close_chan(chan);
+                    errors.push(e);
+                },
+            }
+        }
+        assert!(values.next().is_none(), "scatter values are more than group members");
+        (Group { members }, errors)
+    }
+}
+
+/// A member at a `Gather` step is ready when its reply has arrived.
+#[cfg(feature = "std")]
+impl<SR, E, A, R> Selectable for Chan<SR, E, Gather<A, R>> where SR: TimeoutCarrier {
+    fn ready(&mut self) -> bool {
+        carrier_ready(&mut self.carrier)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<SR, E, A, R> Group<SR, E, Gather<A, R>> where SR: TimeoutCarrier, A: ChannelRecv<SR> {
+    /// Receive a value from every member, in order of arrival. Each value
+    /// comes with the index of its member in order of subscription. Members
+    /// failed to send are closed and removed from the group, their errors
+    /// are returned with their indices as well.
+    pub fn recv(self) -> (Group<SR, E, R>, Vec<(usize, A)>, Vec<(usize, A::Err)>) {
+        let count = self.members.len();
+        let mut pending: Vec<_> = self.members.into_iter().map(Some).collect();
+        let mut members: Vec<Option<Chan<SR, E, R>>> = (0 .. count).map(|_| None).collect();
+        let mut values = Vec::with_capacity(count);
+        let mut errors = Vec::new();
+        for _ in 0 .. count {
+            let mut select = ChanSelect::new();
+            for (index, chan) in pending.iter_mut().enumerate() {
+                if let Some(ref mut chan) = *chan {
+                    select.add(chan, index);
+                }
+            }
+            let index = select.wait();
+            let mut chan = pending[index].take().expect("selected member is pending");
+            match A::recv(&mut chan.carrier) {
+                Ok(v) => {
+                    members[index] = Some(cast_chan(chan));
+                    values.push((index, v));
+                },
+                Err(e) => {
+                    close_chan(chan);
+                    errors.push((index, e));
+                },
+            }
+        }
+        (Group { members: members.into_iter().flatten().collect() }, values, errors)
+    }
+}
+
+impl<SR, E> Group<SR, E, End> {
+    /// Close all the member channels.
+    pub fn close(self) {
+        for chan in self.members {
+            chan.close();
+        }
+    }
+}
+
+impl<SR, E, A, R> Chan<SR, E, Bcast<A, R>> where SR: Carrier, A: ChannelSend<SR> {
+    /// Send a value to the only recipient.
+    #[must_use]
+    pub fn send(mut self, v: A) -> Result<Chan<SR, E, R>, A::Err> {
+        match v.send(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(e)
+            },
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Large binary payloads without extra copies.
+//!
+//! `Bytes` is a reference counted immutable buffer. In-process carriers
+//! move just the reference, while byte stream carriers write it as a frame
+//! directly from the buffer, bypassing the wire format.
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::mpsc::{SendError, RecvError};
+use super::{ChannelSend, ChannelRecv, Payload, mpsc};
+#[cfg(feature = "stream")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "stream")]
+use super::stream;
+#[cfg(feature = "stream")]
+use super::wire::WireFormat;
+
+/// Reference counted immutable byte buffer, cheap to clone.
+///
+/// ```
+/// use session_types_ng::{Send, End, mpsc};
+/// use session_types_ng::bytes::Bytes;
+///
+/// let (tx, rx) = mpsc::session_channel::<Send<Bytes, End>>();
+/// let payload = Bytes::new(vec![7; 1 << 20]);
+/// tx.send(payload.clone()).unwrap().close();
+/// let (rx, received) = rx.recv().unwrap();
+/// rx.close();
+/// assert_eq!(received.as_ptr(), payload.as_ptr());
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Bytes(Arc<Vec<u8>>);
+
+impl Payload for Bytes { }
+
+impl Bytes {
+    pub fn new(bytes: Vec<u8>) -> Bytes {
+        Bytes(Arc::new(bytes))
+    }
+
+    /// Take the buffer back, copying it only if it is still shared.
+    pub fn into_vec(self) -> Vec<u8> {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Bytes {
+        Bytes::new(bytes)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Bytes {
+    fn from(bytes: &'a [u8]) -> Bytes {
+        Bytes::new(bytes.to_vec())
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl ChannelSend<mpsc::Channel> for Bytes {
+    type Err = SendError<Box<Bytes>>;
+
+    fn send(self, carrier: &mut mpsc::Channel) -> Result<(), Self::Err> {
+        mpsc::send_value(carrier, self)
+    }
+}
+
+impl ChannelRecv<mpsc::Channel> for Bytes {
+    type Err = RecvError;
+
+    fn recv(carrier: &mut mpsc::Channel) -> Result<Self, Self::Err> {
+        mpsc::recv_value(carrier)
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<S, F> ChannelSend<stream::Channel<S, F>> for Bytes where S: Read + Write, F: WireFormat {
+    type Err = io::Error;
+
+    fn send(self, carrier: &mut stream::Channel<S, F>) -> Result<(), Self::Err> {
+        carrier.send_frame(&self)
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<S, F> ChannelRecv<stream::Channel<S, F>> for Bytes where S: Read + Write, F: WireFormat {
+    type Err = io::Error;
+
+    fn recv(carrier: &mut stream::Channel<S, F>) -> Result<Self, Self::Err> {
+        carrier.recv_frame().map(Bytes::new)
+    }
+}
@@ -0,0 +1,223 @@
+//! Cooperative cancellation of sessions.
+//!
+//! A `CancelToken` registered on a channel with `Chan::cancel_on` makes
+//! every subsequent operation fail with `CancelError::Cancelled` once the
+//! token fires, including receives blocked waiting for the peer, so
+//! sessions could take part in a graceful shutdown of a server.
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, Chan};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+
+/// How often a blocked receive checks its token.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Cancellation flag shared by all its clones.
+#[derive(Clone, Default, Debug)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Fire the token, cancelling all sessions it is registered on.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Error of an operation over `CancelCarrier`.
+#[derive(Debug)]
+pub enum CancelError<E, W = Infallible> {
+    /// The token has fired.
+    Cancelled,
+    /// Waiting for the peer has failed.
+    Wait(W),
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+/// Carrier wrapper failing every operation once its token has fired.
+/// Receives wait for the peer in short slices, checking the token between them.
+pub struct CancelCarrier<C> {
+    inner: C,
+    token: CancelToken,
+}
+
+impl<C> CancelCarrier<C> where C: TimeoutCarrier {
+    pub fn new(inner: C, token: CancelToken) -> CancelCarrier<C> {
+        CancelCarrier { inner, token }
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn check<E, W>(&self) -> Result<(), CancelError<E, W>> {
+        if self.token.is_cancelled() {
+            Err(CancelError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn wait<E>(&mut self) -> Result<(), CancelError<E, C::RecvChoiceErr>> {
+        loop {
+            self.check()?;
+            if self.inner.wait_timeout(POLL_INTERVAL).map_err(CancelError::Wait)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<C> Carrier for CancelCarrier<C> where C: TimeoutCarrier {
+    type SendChoiceErr = CancelError<C::SendChoiceErr>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.check()?;
+        self.inner.send_choice(choice).map_err(CancelError::Carrier)
+    }
+
+    type RecvChoiceErr = CancelError<C::RecvChoiceErr, C::RecvChoiceErr>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.wait()?;
+        self.inner.recv_choice().map_err(CancelError::Carrier)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+/// Explicit timeouts report a fired token as an error.
+impl<C> TimeoutCarrier for CancelCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        let mut remaining = timeout;
+        loop {
+            self.check()?;
+            let slice = remaining.min(POLL_INTERVAL);
+            if self.inner.wait_timeout(slice).map_err(CancelError::Wait)? {
+                return Ok(true);
+            }
+            remaining -= slice;
+            if remaining == Duration::from_secs(0) {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl<C> BatchCarrier for CancelCarrier<C> where C: TimeoutCarrier + BatchCarrier {
+    type FlushErr = CancelError<C::FlushErr>;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled).map_err(CancelError::Carrier)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.check()?;
+        self.inner.flush().map_err(CancelError::Carrier)
+    }
+}
+
+impl<C> CapacityCarrier for CancelCarrier<C> where C: TimeoutCarrier + CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+/// Polling fails with `Cancelled` once the token has fired.
+impl<C> NonBlockingCarrier for CancelCarrier<C> where C: TimeoutCarrier + NonBlockingCarrier {
+    type PollErr = CancelError<C::PollErr>;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.check().map_err(PollError::Carrier)?;
+        self.inner.poll_send().map_err(|e| e.map(CancelError::Carrier))
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.check().map_err(PollError::Carrier)?;
+        self.inner.poll_recv().map_err(|e| e.map(CancelError::Carrier))
+    }
+}
+
+/// Values transferable over a carrier are transferable until cancellation.
+impl<C, T> ChannelSend<CancelCarrier<C>> for T where T: Payload + ChannelSend<C>, C: TimeoutCarrier {
+    type Err = CancelError<<T as ChannelSend<C>>::Err>;
+
+    fn send(self, carrier: &mut CancelCarrier<C>) -> Result<(), Self::Err> {
+        carrier.check()?;
+        self.send(&mut carrier.inner).map_err(CancelError::Carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<CancelCarrier<C>> for T where T: Payload + ChannelRecv<C>, C: TimeoutCarrier {
+    type Err = CancelError<<T as ChannelRecv<C>>::Err, C::RecvChoiceErr>;
+
+    fn recv(carrier: &mut CancelCarrier<C>) -> Result<Self, Self::Err> {
+        carrier.wait()?;
+        <T>::recv(&mut carrier.inner).map_err(CancelError::Carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: TimeoutCarrier {
+    /// Register a cancellation token on the rest of the session.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::cancel::{CancelToken, CancelError};
+    ///
+    /// let token = CancelToken::new();
+    /// let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let rx = rx.cancel_on(token.clone());
+    /// let server = thread::spawn(move || match rx.recv() {
+    ///     Err(CancelError::Cancelled) => (),
+    ///     _ => panic!("nothing has been sent"),
+    /// });
+    /// token.cancel();
+    /// server.join().unwrap();
+    /// assert!(tx.send(mpsc::Value(1)).is_err());
+    /// ```
+    #[must_use]
+    pub fn cancel_on(self, token: CancelToken) -> Chan<CancelCarrier<SR>, E, P> {
+        Chan {
+            carrier: CancelCarrier::new(self.carrier, token),
+            session: self.session,
+        }
+    }
+}
+
+impl<SR, E, P> Chan<CancelCarrier<SR>, E, P> where SR: TimeoutCarrier {
+    /// Unregister the cancellation token.
+    #[must_use]
+    pub fn without_cancel(self) -> Chan<SR, E, P> {
+        Chan {
+            carrier: self.carrier.inner,
+            session: self.session,
+        }
+    }
+}
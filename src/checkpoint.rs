@@ -0,0 +1,90 @@
+//! Session checkpoints.
+//!
+//! A session could be suspended into a `Checkpoint`, which records its
+//! position (a fingerprint of the current protocol step and environment)
+//! together with a carrier-specific resume token, e.g. a connection id.
+//! Later, possibly in another process, the checkpoint is restored into a
+//! channel of the expected continuation type only if the positions match.
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::{Chan, Session};
+use super::reflect::ProtocolDesc;
+use super::fingerprint::fnv1a;
+
+/// Environment (recursion targets stack) with a runtime description.
+pub trait EnvDesc {
+    fn describe(out: &mut String);
+}
+
+impl EnvDesc for () {
+    fn describe(_out: &mut String) { }
+}
+
+impl<P, E> EnvDesc for (P, E) where P: ProtocolDesc, E: EnvDesc {
+    fn describe(out: &mut String) {
+        out.push(';');
+        P::desc().write_canonical(out);
+        E::describe(out);
+    }
+}
+
+/// Session position identifier for protocol `P` in environment `E`.
+pub fn step_id<E, P>() -> u64 where E: EnvDesc, P: ProtocolDesc {
+    let mut description = String::new();
+    P::desc().write_canonical(&mut description);
+    E::describe(&mut description);
+    fnv1a(&description)
+}
+
+/// Suspended session position with a resume token `T`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checkpoint<T> {
+    pub step: u64,
+    pub token: T,
+}
+
+impl<SR, E, P> Chan<SR, E, P> where E: EnvDesc, P: ProtocolDesc {
+    /// Suspend the session, turning the carrier into a resume token.
+    pub fn checkpoint<T, F>(self, suspend: F) -> Checkpoint<T> where F: FnOnce(SR) -> T {
+        let step = step_id::<E, P>();
+        self.session.finish();
+        Checkpoint { step, token: suspend(self.carrier) }
+    }
+}
+
+impl<T> Checkpoint<T> {
+    /// Restore the session as a channel with protocol `P` in environment
+    /// `E`, making a carrier from the resume token. Fails with the
+    /// checkpoint untouched if it has been made at a different position.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+    ///
+    /// type Proto = Send<mpsc::Value<u8>, Recv<mpsc::Value<u8>, End>>;
+    ///
+    /// let (chan, peer) = mpsc::session_channel::<Proto>();
+    /// let checkpoint = chan.send(mpsc::Value(1)).unwrap().checkpoint(|carrier| carrier);
+    ///
+    /// let checkpoint = match checkpoint.restore::<(), Proto, _, _>(|carrier| carrier) {
+    ///     Ok(_) => panic!("position mismatch is not detected"),
+    ///     Err(checkpoint) => checkpoint,
+    /// };
+    /// let chan = match checkpoint.restore::<(), Recv<mpsc::Value<u8>, End>, _, _>(|carrier| carrier) {
+    ///     Ok(chan) => chan,
+    ///     Err(_) => panic!("position should match"),
+    /// };
+    ///
+    /// let (peer, _) = peer.recv().unwrap();
+    /// peer.send(mpsc::Value(2)).unwrap().close();
+    /// chan.recv().unwrap().0.close();
+    /// ```
+    pub fn restore<E, P, SR, F>(self, resume: F) -> Result<Chan<SR, E, P>, Checkpoint<T>>
+        where E: EnvDesc, P: ProtocolDesc, F: FnOnce(T) -> SR
+    {
+        if self.step == step_id::<E, P>() {
+            Ok(Chan { carrier: resume(self.token), session: Session::new() })
+        } else {
+            Err(self)
+        }
+    }
+}
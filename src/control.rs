@@ -0,0 +1,330 @@
+//! Out-of-band control events.
+//!
+//! Operational concerns, such as checking that the peer is alive, asking it
+//! to stop early or to hold off for a while, should not be spelled out in
+//! every protocol type. Over a `ControlCarrier` the peers could exchange
+//! `Control` events at any point of the session besides its values and
+//! choices: every frame is preceded by a choice bit telling the two apart,
+//! so both sides should use a `ControlCarrier`.
+//!
+//! Events of the peer are taken while receiving (or on `poll_control`) and
+//! kept until `control_events` is called. Some of them are also acted upon:
+//!
+//! * `Ping` is answered with `Pong` right away;
+//! * after `Cancel` all operations fail with `ControlError::Cancelled`;
+//! * `Pause` and `Resume` only switch `is_paused`, it is up to the
+//!   application to hold off its sends meanwhile.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{Send, Recv, End, mpsc};
+//! use session_types_ng::control::{Control, ControlError};
+//!
+//! type Srv = Send<mpsc::Value<u8>, Recv<mpsc::Value<u8>, End>>;
+//!
+//! let (server, client) = mpsc::session_channel::<Srv>();
+//! let (mut server, client) = (server.with_control(), client.with_control());
+//! let peer = thread::spawn(move || {
+//!     let (mut client, mpsc::Value(x)) = client.recv().unwrap();
+//!     assert_eq!(client.control_events(), vec![Control::Ping]);
+//!     client.send_control(Control::Cancel).unwrap();
+//!     client.poison();
+//!     x
+//! });
+//! server.send_control(Control::Ping).unwrap();
+//! let server = server.send(mpsc::Value(1)).unwrap();
+//! assert!(matches!(server.recv(), Err(ControlError::Cancelled)));
+//! assert_eq!(peer.join().unwrap(), 1);
+//! ```
+use std::convert::Infallible;
+use std::mem;
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, Chan};
+use super::timeout::TimeoutCarrier;
+use super::layer::CarrierLayer;
+
+/// Control event, see the module documentation.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Control {
+    Ping,
+    Pong,
+    Cancel,
+    Pause,
+    Resume,
+}
+
+impl Control {
+    /// Code of the event on the wire, three choice bits.
+    fn code(self) -> u8 {
+        match self {
+            Control::Ping => 0,
+            Control::Pong => 1,
+            Control::Cancel => 2,
+            Control::Pause => 3,
+            Control::Resume => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Control> {
+        match code {
+            0 => Some(Control::Ping),
+            1 => Some(Control::Pong),
+            2 => Some(Control::Cancel),
+            3 => Some(Control::Pause),
+            4 => Some(Control::Resume),
+            _ => None,
+        }
+    }
+}
+
+/// Error of an operation over `ControlCarrier`.
+#[derive(Debug)]
+pub enum ControlError<E, F = Infallible> {
+    /// The peer has sent `Control::Cancel`.
+    Cancelled,
+    /// Transferring the frame marker or a control event has failed.
+    Frame(F),
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+/// Carrier wrapper multiplexing control events with the session, see the
+/// module documentation.
+pub struct ControlCarrier<C> {
+    inner: C,
+    events: Vec<Control>,
+    cancelled: bool,
+    paused: bool,
+    /// The marker of the next frame of the session has been received.
+    pending: bool,
+}
+
+impl<C> ControlCarrier<C> {
+    pub fn new(inner: C) -> ControlCarrier<C> {
+        ControlCarrier {
+            inner,
+            events: Vec::new(),
+            cancelled: false,
+            paused: false,
+            pending: false,
+        }
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Take the events received from the peer so far.
+    pub fn events(&mut self) -> Vec<Control> {
+        mem::take(&mut self.events)
+    }
+
+    /// Whether the peer has sent `Control::Cancel`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Whether the peer has sent `Control::Pause` and not `Control::Resume`
+    /// after it.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+impl<C> ControlCarrier<C> where C: Carrier {
+    /// Send a control event to the peer.
+    pub fn send_control(&mut self, event: Control) -> Result<(), C::SendChoiceErr> {
+        self.inner.send_choice(true)?;
+        let code = event.code();
+        for bit in 0 .. 3 {
+            self.inner.send_choice(code & (1 << bit) != 0)?;
+        }
+        Ok(())
+    }
+
+    /// Receive a control event of the peer, its marker has been received.
+    fn recv_control(&mut self) -> Result<(), C::RecvChoiceErr> {
+        let mut code = 0;
+        for bit in 0 .. 3 {
+            if self.inner.recv_choice()? {
+                code |= 1 << bit;
+            }
+        }
+        // unknown events of a newer peer are skipped
+        if let Some(event) = Control::from_code(code) {
+            match event {
+                // the peer notices a failure to answer on its own
+                Control::Ping => { let _ = self.send_control(Control::Pong); },
+                Control::Cancel => self.cancelled = true,
+                Control::Pause => self.paused = true,
+                Control::Resume => self.paused = false,
+                Control::Pong => (),
+            }
+            self.events.push(event);
+        }
+        Ok(())
+    }
+
+    /// Take control events up to the next frame of the session.
+    fn next<E>(&mut self) -> Result<(), ControlError<E, C::RecvChoiceErr>> {
+        loop {
+            if self.cancelled {
+                return Err(ControlError::Cancelled);
+            }
+            if mem::replace(&mut self.pending, false) {
+                return Ok(());
+            }
+            if self.inner.recv_choice().map_err(ControlError::Frame)? {
+                self.recv_control().map_err(ControlError::Frame)?;
+            } else {
+                self.pending = true;
+            }
+        }
+    }
+
+    fn check<E, F>(&self) -> Result<(), ControlError<E, F>> {
+        if self.cancelled {
+            Err(ControlError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<C> ControlCarrier<C> where C: TimeoutCarrier {
+    /// Take the control events which have arrived, without blocking.
+    pub fn poll_control(&mut self) -> Result<(), C::RecvChoiceErr> {
+        while !self.pending && self.inner.wait_timeout(Duration::from_secs(0))? {
+            if self.inner.recv_choice()? {
+                self.recv_control()?;
+            } else {
+                self.pending = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C> Carrier for ControlCarrier<C> where C: Carrier {
+    type SendChoiceErr = ControlError<C::SendChoiceErr>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.check()?;
+        self.inner.send_choice(false).map_err(ControlError::Carrier)?;
+        self.inner.send_choice(choice).map_err(ControlError::Carrier)
+    }
+
+    type RecvChoiceErr = ControlError<C::RecvChoiceErr, C::RecvChoiceErr>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.next()?;
+        self.inner.recv_choice().map_err(ControlError::Carrier)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+/// Control events arriving meanwhile are taken, and do not count as a
+/// frame ready.
+impl<C> TimeoutCarrier for ControlCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        let started = Instant::now();
+        loop {
+            self.check()?;
+            if self.pending {
+                return Ok(true);
+            }
+            let left = timeout.checked_sub(started.elapsed()).unwrap_or_default();
+            if !self.inner.wait_timeout(left).map_err(ControlError::Frame)? {
+                return Ok(false);
+            }
+            if self.inner.recv_choice().map_err(ControlError::Frame)? {
+                self.recv_control().map_err(ControlError::Frame)?;
+            } else {
+                self.pending = true;
+            }
+        }
+    }
+}
+
+impl<C> BatchCarrier for ControlCarrier<C> where C: BatchCarrier {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+/// Layer wrapping carriers into `ControlCarrier`s.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ControlLayer;
+
+impl<C> CarrierLayer<C> for ControlLayer {
+    type Carrier = ControlCarrier<C>;
+
+    fn layer(&self, inner: C) -> ControlCarrier<C> {
+        ControlCarrier::new(inner)
+    }
+}
+
+/// Values transferable over a carrier are transferable over its control
+/// wrapper.
+impl<C, T> ChannelSend<ControlCarrier<C>> for T where T: Payload + ChannelSend<C>, C: Carrier {
+    type Err = ControlError<<T as ChannelSend<C>>::Err, C::SendChoiceErr>;
+
+    fn send(self, carrier: &mut ControlCarrier<C>) -> Result<(), Self::Err> {
+        carrier.check()?;
+        carrier.inner.send_choice(false).map_err(ControlError::Frame)?;
+        self.send(&mut carrier.inner).map_err(ControlError::Carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<ControlCarrier<C>> for T where T: Payload + ChannelRecv<C>, C: Carrier {
+    type Err = ControlError<<T as ChannelRecv<C>>::Err, C::RecvChoiceErr>;
+
+    fn recv(carrier: &mut ControlCarrier<C>) -> Result<Self, Self::Err> {
+        carrier.next()?;
+        <T>::recv(&mut carrier.inner).map_err(ControlError::Carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Multiplex control events with the rest of the session, see the
+    /// `control` module documentation.
+    #[must_use]
+    pub fn with_control(self) -> Chan<ControlCarrier<SR>, E, P> {
+        Chan {
+            carrier: ControlCarrier::new(self.carrier),
+            session: self.session,
+        }
+    }
+}
+
+impl<SR, E, P> Chan<ControlCarrier<SR>, E, P> where SR: Carrier {
+    /// Send a control event to the peer, at any step of the session.
+    pub fn send_control(&mut self, event: Control) -> Result<(), SR::SendChoiceErr> {
+        self.carrier.send_control(event)
+    }
+
+    /// Take the control events received from the peer so far.
+    pub fn control_events(&mut self) -> Vec<Control> {
+        self.carrier.events()
+    }
+}
+
+impl<SR, E, P> Chan<ControlCarrier<SR>, E, P> where SR: TimeoutCarrier {
+    /// Take the control events which have arrived, without blocking.
+    pub fn poll_control(&mut self) -> Result<(), SR::RecvChoiceErr> {
+        self.carrier.poll_control()
+    }
+}
@@ -0,0 +1,133 @@
+//! Deadlock detection for in-process sessions (enabled with `deadlock` feature).
+//!
+//! An endpoint of an `mpsc` session belongs to the thread which has made the
+//! last step on it. A receive blocked for longer than `CHECK_INTERVAL` looks
+//! for a cycle of threads, each one waiting for a message over an endpoint
+//! whose peer belongs to the next one, and panics with the protocol steps of
+//! the cycle instead of hanging silently. The panic drops the channels of the
+//! thread, so that the rest of the cycle fails with an error.
+//!
+//! Note that an endpoint moved to another thread belongs to the old one until
+//! the first step made on it there.
+//!
+//! ```should_panic
+//! use session_types_ng::{Recv, End, mpsc};
+//!
+//! type Proto = Recv<mpsc::Value<u8>, End>;
+//!
+//! let (a, _a_peer) = mpsc::session_channel::<Proto>();
+//! // both endpoints belong to this thread, so it waits for itself
+//! let _ = a.recv();
+//! ```
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+/// How long a receive is blocked before looking for a deadlock.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Endpoint key: session pair and side.
+type Key = (u64, bool);
+
+struct EndpointInfo {
+    holder: ThreadId,
+    step: &'static str,
+}
+
+struct Graph {
+    endpoints: BTreeMap<Key, EndpointInfo>,
+    /// Blocked receives: endpoint and thread waiting on it.
+    waiting: BTreeMap<Key, ThreadId>,
+}
+
+static NEXT_PAIR: AtomicU64 = AtomicU64::new(0);
+static GRAPH: Mutex<Graph> = Mutex::new(Graph { endpoints: BTreeMap::new(), waiting: BTreeMap::new() });
+
+fn with_graph<F, T>(action: F) -> T where F: FnOnce(&mut Graph) -> T {
+    action(&mut GRAPH.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// An endpoint of a session tracked by the detector, unregistered on drop.
+pub(crate) struct Endpoint {
+    key: Key,
+}
+
+/// Register both endpoints of a new session, belonging to the current thread.
+pub(crate) fn pair() -> (Endpoint, Endpoint) {
+    let pair = NEXT_PAIR.fetch_add(1, Ordering::Relaxed);
+    let holder = thread::current().id();
+    with_graph(|graph| for &side in &[false, true] {
+        graph.endpoints.insert((pair, side), EndpointInfo { holder, step: "" });
+    });
+    (Endpoint { key: (pair, false) }, Endpoint { key: (pair, true) })
+}
+
+impl Endpoint {
+    /// The current thread has moved the session to the step `protocol`.
+    pub(crate) fn step(&self, protocol: &'static str) {
+        let holder = thread::current().id();
+        with_graph(|graph| if let Some(info) = graph.endpoints.get_mut(&self.key) {
+            info.holder = holder;
+            info.step = protocol;
+        });
+    }
+
+    /// Blocking receive, checking for a deadlock every `CHECK_INTERVAL`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread takes part in a cycle of blocked receives.
+    pub(crate) fn recv<T>(&self, rx: &Receiver<T>) -> Result<T, RecvError> {
+        let me = thread::current().id();
+        with_graph(|graph| graph.waiting.insert(self.key, me));
+        let result = loop {
+            match rx.recv_timeout(CHECK_INTERVAL) {
+                Ok(value) =>
+                    break Ok(value),
+                Err(RecvTimeoutError::Disconnected) =>
+                    break Err(RecvError),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(report) = with_graph(|graph| graph.cycle(self.key, me)) {
+                        with_graph(|graph| graph.waiting.remove(&self.key));
+                        panic!("session deadlock: {}", report);
+                    }
+                },
+            }
+        };
+        with_graph(|graph| graph.waiting.remove(&self.key));
+        result
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        with_graph(|graph| {
+            graph.endpoints.remove(&self.key);
+            graph.waiting.remove(&self.key);
+        });
+    }
+}
+
+impl Graph {
+    /// Follow the chain of blocked receives from the endpoint `key`, returning
+    /// the description of the cycle if it comes back to `me`.
+    fn cycle(&self, mut key: Key, me: ThreadId) -> Option<String> {
+        let mut links = Vec::new();
+        loop {
+            let waiter = *self.waiting.get(&key)?;
+            let step = self.endpoints.get(&key)?.step;
+            let peer = self.endpoints.get(&(key.0, !key.1))?.holder;
+            links.push(format!("{:?} waits at `{}` on session #{} for {:?}", waiter, step, key.0, peer));
+            if peer == me {
+                return Some(links.join(", "));
+            }
+            if links.len() > self.waiting.len() {
+                return None;
+            }
+            key = *self.waiting.iter().find(|entry| *entry.1 == peer)?.0;
+        }
+    }
+}
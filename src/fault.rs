@@ -0,0 +1,452 @@
+//! Fault injection for robustness testing.
+//!
+//! `FaultyCarrier` wraps a carrier and, according to a seeded `FaultPolicy`,
+//! drops or delays outgoing messages and choices, so error handling paths
+//! (timeouts, retries, aborts) could be exercised deterministically. Choices
+//! could also be duplicated or corrupted. Values are duplicated or corrupted
+//! as frames by `FaultyStream`, a byte stream adapter beneath a
+//! `stream::Channel`, whatever carrier wrappers are stacked above: a frame is
+//! corrupted by flipping a single bit of its payload.
+use std::io::{self, Read, Write};
+use std::thread;
+use std::task::Waker;
+use std::time::Duration;
+use super::{Carrier, BatchCarrier, ChannelSend, ChannelRecv, Payload, Chan, discard};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::layer::CarrierLayer;
+#[cfg(feature = "stream")]
+use super::stream;
+
+/// Probabilities of faults, each one in `[0, 1]`, and the seed of their
+/// pseudo random sequence. The same seed gives the same faults for the same
+/// sequence of messages.
+#[derive(Clone, Debug)]
+pub struct FaultPolicy {
+    seed: u64,
+    drop: f64,
+    delay: f64,
+    delay_for: Duration,
+    duplicate: f64,
+    corrupt: f64,
+}
+
+impl FaultPolicy {
+    /// A policy without faults.
+    pub fn new(seed: u64) -> FaultPolicy {
+        FaultPolicy { seed, drop: 0.0, delay: 0.0, delay_for: Duration::from_secs(0), duplicate: 0.0, corrupt: 0.0 }
+    }
+
+    /// Silently lose a message.
+    pub fn drop(mut self, probability: f64) -> FaultPolicy {
+        self.drop = probability;
+        self
+    }
+
+    /// Sleep for `duration` before sending a message.
+    pub fn delay(mut self, probability: f64, duration: Duration) -> FaultPolicy {
+        self.delay = probability;
+        self.delay_for = duration;
+        self
+    }
+
+    /// Deliver a message twice.
+    pub fn duplicate(mut self, probability: f64) -> FaultPolicy {
+        self.duplicate = probability;
+        self
+    }
+
+    /// Damage a message. Values are damaged as frames by a `FaultyStream`
+    /// beneath the byte stream carrier, whatever wrappers are stacked above.
+    ///
+    /// ```
+    /// use std::net::{TcpListener, TcpStream};
+    /// use std::time::{Duration, Instant};
+    /// use session_types_ng::{Chan, Recv, End, HasDual};
+    /// use session_types_ng::stream::{self, Value};
+    /// use session_types_ng::fault::{FaultPolicy, FaultyStream, Fault};
+    ///
+    /// type Srv = Recv<Value<u32>, End>;
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    /// let (socket, _) = listener.accept().unwrap();
+    /// let server: Chan<stream::TcpChannel, (), Srv> = stream::session_channel(socket);
+    /// let peer = FaultyStream::new(peer, FaultPolicy::new(1).corrupt(1.0));
+    /// let client: Chan<stream::Channel<_>, (), <Srv as HasDual>::Dual> = stream::session_channel(peer);
+    ///
+    /// let mut client = client
+    ///     .with_deadline(Instant::now() + Duration::from_secs(5))
+    ///     .send(Value(0))
+    ///     .unwrap();
+    /// assert_eq!(client.carrier_mut().get_mut().get_ref().faults(), &[Fault::Corrupted(0)]);
+    /// client.close();
+    ///
+    /// let (server, Value(received)) = server.recv().unwrap();
+    /// server.close();
+    /// assert_ne!(received, 0);
+    /// ```
+    pub fn corrupt(mut self, probability: f64) -> FaultPolicy {
+        self.corrupt = probability;
+        self
+    }
+}
+
+/// An injected fault together with the number of the affected message,
+/// counting both values and choices sent from zero, or of the affected
+/// frame for `FaultyStream`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fault {
+    Dropped(u64),
+    Delayed(u64),
+    Duplicated(u64),
+    Corrupted(u64),
+}
+
+/// Faults chosen for a single message.
+struct Plan {
+    drop: bool,
+    duplicate: bool,
+    /// Random bits choosing the damage, if the message should be corrupted.
+    corrupt: Option<u64>,
+}
+
+/// Pseudo random sequence, xorshift64*.
+#[derive(Clone, Copy)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // xorshift state should not be zero
+        Rng((seed ^ 0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// Faults chosen according to a policy, and the ones injected so far.
+struct Injector {
+    policy: FaultPolicy,
+    rng: Rng,
+    sent: u64,
+    faults: Vec<Fault>,
+}
+
+impl Injector {
+    fn new(policy: FaultPolicy) -> Injector {
+        let rng = Rng::new(policy.seed);
+        Injector { policy, rng, sent: 0, faults: Vec::new() }
+    }
+
+    /// Choose faults for the next message, sleeping right away if it should be delayed.
+    /// Duplication and corruption are only chosen if the message supports them.
+    fn plan(&mut self, tamper: bool) -> Plan {
+        let message = self.sent;
+        self.sent += 1;
+        if self.rng.chance(self.policy.delay) {
+            self.faults.push(Fault::Delayed(message));
+            thread::sleep(self.policy.delay_for);
+        }
+        if self.rng.chance(self.policy.drop) {
+            self.faults.push(Fault::Dropped(message));
+            return Plan { drop: true, duplicate: false, corrupt: None };
+        }
+        let corrupt = if tamper && self.rng.chance(self.policy.corrupt) {
+            self.faults.push(Fault::Corrupted(message));
+            Some(self.rng.next())
+        } else {
+            None
+        };
+        let duplicate = tamper && self.rng.chance(self.policy.duplicate);
+        if duplicate {
+            self.faults.push(Fault::Duplicated(message));
+        }
+        Plan { drop: false, duplicate, corrupt }
+    }
+}
+
+/// Carrier wrapper injecting faults into outgoing messages.
+pub struct FaultyCarrier<C> {
+    inner: C,
+    injector: Injector,
+}
+
+impl<C> FaultyCarrier<C> {
+    pub fn new(inner: C, policy: FaultPolicy) -> FaultyCarrier<C> {
+        FaultyCarrier { inner, injector: Injector::new(policy) }
+    }
+
+    /// Faults injected so far.
+    pub fn faults(&self) -> &[Fault] {
+        &self.injector.faults
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+/// Byte stream adapter injecting faults into the frames written to it by a
+/// `stream::Channel` (or any other writer of frames prefixed by their `u32`
+/// big endian length), so that values are duplicated or corrupted whatever
+/// carrier wrappers are stacked above the channel. The policy applies to
+/// every frame, choices included, and faults are numbered by frame. A frame
+/// is only passed on once it has been written completely, so the stream
+/// should be in blocking mode. Reads are passed through.
+pub struct FaultyStream<S> {
+    inner: S,
+    injector: Injector,
+    /// Bytes of the frames not written completely yet.
+    outbound: Vec<u8>,
+}
+
+impl<S> FaultyStream<S> {
+    pub fn new(inner: S, policy: FaultPolicy) -> FaultyStream<S> {
+        FaultyStream { inner, injector: Injector::new(policy), outbound: Vec::new() }
+    }
+
+    /// Faults injected so far.
+    pub fn faults(&self) -> &[Fault] {
+        &self.injector.faults
+    }
+
+    /// Get back underlying byte stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying byte stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Length of the first frame, once it has been written completely. The
+    /// handover mark of `stream::Channel` is a frame without payload.
+    fn frame_len(&self) -> Option<usize> {
+        let header = self.outbound.get(.. 4)?;
+        let len = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            u32::MAX => 0,
+            len => len as usize,
+        };
+        if self.outbound.len() - 4 >= len { Some(len) } else { None }
+    }
+}
+
+impl<S> Read for FaultyStream<S> where S: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S> Write for FaultyStream<S> where S: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outbound.extend_from_slice(buf);
+        while let Some(len) = self.frame_len() {
+            let mut frame: Vec<u8> = self.outbound.drain(.. 4 + len).collect();
+            let plan = self.injector.plan(true);
+            if plan.drop {
+                continue;
+            }
+            if let Some(noise) = plan.corrupt.filter(|_| len > 0) {
+                let bit = (noise % (len as u64 * 8)) as usize;
+                frame[4 + bit / 8] ^= 1 << (bit % 8);
+            }
+            self.inner.write_all(&frame)?;
+            if plan.duplicate {
+                self.inner.write_all(&frame)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<S> stream::ReadTimeout for FaultyStream<S> where S: stream::ReadTimeout {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.inner.read_timeout()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+/// Layer wrapping carriers into `FaultyCarrier`s, each one following the
+/// same policy.
+#[derive(Clone, Debug)]
+pub struct FaultLayer {
+    policy: FaultPolicy,
+}
+
+impl FaultLayer {
+    pub fn new(policy: FaultPolicy) -> FaultLayer {
+        FaultLayer { policy }
+    }
+}
+
+impl<C> CarrierLayer<C> for FaultLayer {
+    type Carrier = FaultyCarrier<C>;
+
+    fn layer(&self, inner: C) -> FaultyCarrier<C> {
+        FaultyCarrier::new(inner, self.policy.clone())
+    }
+}
+
+impl<C> Carrier for FaultyCarrier<C> where C: Carrier {
+    type SendChoiceErr = C::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        let plan = self.injector.plan(true);
+        if plan.drop {
+            return Ok(());
+        }
+        let choice = if plan.corrupt.is_some() { !choice } else { choice };
+        self.inner.send_choice(choice)?;
+        if plan.duplicate {
+            self.inner.send_choice(choice)?;
+        }
+        Ok(())
+    }
+
+    type RecvChoiceErr = C::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.recv_choice()
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+impl<C> TimeoutCarrier for FaultyCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout)
+    }
+}
+
+impl<C> BatchCarrier for FaultyCarrier<C> where C: BatchCarrier {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+impl<C> CapacityCarrier for FaultyCarrier<C> where C: CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+impl<C> NonBlockingCarrier for FaultyCarrier<C> where C: NonBlockingCarrier {
+    type PollErr = C::PollErr;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_send()
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_recv()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.inner.register_waker(waker);
+    }
+}
+
+/// Values transferable over a carrier are transferable over its faulty
+/// wrapper, which could drop or delay them (see `FaultyStream` to have
+/// their frames duplicated or corrupted). A lost value is dropped, so the
+/// sessions it holds are closed and their peers fail instead of waiting.
+impl<C, T> ChannelSend<FaultyCarrier<C>> for T where T: Payload + ChannelSend<C> {
+    type Err = T::Err;
+
+    fn send(self, carrier: &mut FaultyCarrier<C>) -> Result<(), Self::Err> {
+        if carrier.injector.plan(false).drop {
+            discard(self);
+            return Ok(());
+        }
+        <T as ChannelSend<C>>::send(self, &mut carrier.inner)
+    }
+}
+
+impl<C, T> ChannelRecv<FaultyCarrier<C>> for T where T: Payload + ChannelRecv<C> {
+    type Err = T::Err;
+
+    fn recv(carrier: &mut FaultyCarrier<C>) -> Result<Self, Self::Err> {
+        <T as ChannelRecv<C>>::recv(&mut carrier.inner)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Inject faults into the rest of the session according to `policy`.
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use session_types_ng::{Send, End, mpsc};
+    /// use session_types_ng::fault::{FaultPolicy, Fault};
+    /// use session_types_ng::timeout::DeadlineError;
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Send<mpsc::Value<u8>, End>>();
+    /// let tx = tx.with_faults(FaultPolicy::new(7).drop(1.0));
+    /// let tx = tx.send(mpsc::Value(1)).unwrap();
+    /// assert_eq!(tx.faults(), &[Fault::Dropped(0)]);
+    ///
+    /// let rx = rx.with_deadline(Instant::now() + Duration::from_millis(20));
+    /// match rx.recv() {
+    ///     Err(DeadlineError::Expired) => (),
+    ///     _ => panic!("the value has been lost"),
+    /// }
+    /// tx.close();
+    /// ```
+    #[must_use]
+    pub fn with_faults(self, policy: FaultPolicy) -> Chan<FaultyCarrier<SR>, E, P> {
+        Chan {
+            carrier: FaultyCarrier::new(self.carrier, policy),
+            session: self.session,
+        }
+    }
+}
+
+impl<SR, E, P> Chan<FaultyCarrier<SR>, E, P> {
+    /// Faults injected into the session so far.
+    pub fn faults(&self) -> &[Fault] {
+        self.carrier.faults()
+    }
+
+    /// Stop injecting faults.
+    #[must_use]
+    pub fn without_faults(self) -> Chan<SR, E, P> {
+        Chan {
+            carrier: self.carrier.inner,
+            session: self.session,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! C API for sessions with a C or C++ peer (enabled with `ffi` feature).
+//!
+//! The Rust endpoint is an ordinary statically checked `Chan` over `mpsc`,
+//! while its peer is driven from C through an opaque `Session` handle. Every
+//! call on the handle is checked at runtime against the next step of the
+//! protocol, so a C peer going astray gets `SESSION_MISMATCH` and the session
+//! stays intact. The functions are declared in `include/session_types_ng.h`.
+//!
+//! Branches are selected with `session_choose` and learned with
+//! `session_offer`. Values are transferred as `mpsc::Value<T>` by a pair of
+//! functions per payload type: the ones for primitive types are provided
+//! (`session_send_u32`, `session_recv_u32` and so on), others, for example
+//! `#[repr(C)]` structs of the protocol, are generated with `ffi_value!`.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{Send, Recv, Offer, End, Nil};
+//! use session_types_ng::mpsc::Value;
+//! use session_types_ng::ffi::{self, SESSION_OK, SESSION_MISMATCH};
+//! use session_types_ng::ffi::{session_choose, session_send_u8, session_send_u32, session_recv_u32, session_close};
+//!
+//! type Doubler = Offer<Recv<Value<u32>, Send<Value<u32>, End>>, Offer<End, Nil>>;
+//!
+//! let (chan, session) = ffi::session_channel::<Doubler>();
+//! let endpoint = thread::spawn(move || chan.offer()
+//!     .option(|chan| {
+//!         let (chan, Value(x)) = chan.recv().unwrap();
+//!         chan.send(Value(x * 2)).unwrap().close();
+//!     })
+//!     .option(|chan| chan.close())
+//!     .unwrap());
+//!
+//! // as seen from C
+//! let session = Box::into_raw(session);
+//! let mut result = 0;
+//! unsafe {
+//!     assert_eq!(session_choose(session, 0), SESSION_OK);
+//!     // the endpoint expects `u32`
+//!     assert_eq!(session_send_u8(session, 21), SESSION_MISMATCH);
+//!     assert_eq!(session_send_u32(session, 21), SESSION_OK);
+//!     assert_eq!(session_recv_u32(session, &mut result), SESSION_OK);
+//!     assert_eq!(session_close(session), SESSION_OK);
+//! }
+//! endpoint.join().unwrap();
+//! assert_eq!(result, 42);
+//! ```
+use std::any::{TypeId, type_name};
+use std::collections::BTreeMap;
+use std::os::raw::c_int;
+use std::sync::Mutex;
+use super::{Carrier, ChannelSend, ChannelRecv, Chan, HasDual, mpsc};
+use super::reflect::{ProtocolDesc, Desc, Cursor};
+
+pub const SESSION_OK: c_int = 0;
+/// The call does not fit the next step of the protocol.
+pub const SESSION_MISMATCH: c_int = -1;
+/// The Rust endpoint has hung up.
+pub const SESSION_HUNG_UP: c_int = -2;
+/// The session handle or the output pointer is null.
+pub const SESSION_NULL: c_int = -3;
+
+/// Peer of a Rust endpoint driven from C, opaque to C.
+pub struct Session {
+    carrier: mpsc::Channel,
+    cursor: Cursor<'static>,
+    step: &'static Desc,
+}
+
+static PROTOCOLS: Mutex<BTreeMap<TypeId, &'static Desc>> = Mutex::new(BTreeMap::new());
+
+/// Description of the protocol `P`, made once per protocol type and kept
+/// for the lifetime of the program, so that sessions could refer to it.
+fn protocol<P>() -> &'static Desc where P: ProtocolDesc + 'static {
+    let mut protocols = PROTOCOLS.lock().unwrap_or_else(|e| e.into_inner());
+    protocols.entry(TypeId::of::<P>()).or_insert_with(|| Box::leak(Box::new(P::desc())))
+}
+
+/// Returns a Rust endpoint following the protocol `P` and a handle of its
+/// peer session. The handle is passed to C with `Box::into_raw` and
+/// released there with `session_close` or `session_free`.
+pub fn session_channel<P>() -> (Chan<mpsc::Channel, (), P>, Box<Session>) where P: HasDual + ProtocolDesc + 'static {
+    let (chan, Chan { carrier, session }) = mpsc::session_channel::<P>();
+    session.finish();
+    (chan, Box::new(Session { carrier, cursor: Cursor::default(), step: protocol::<P>() }))
+}
+
+impl Session {
+    /// Step of the Rust endpoint the peer should follow next.
+    fn next(&mut self) -> &'static Desc {
+        self.step = self.cursor.settle(self.step);
+        self.step
+    }
+}
+
+unsafe fn with_session<F>(session: *mut Session, action: F) -> c_int where F: FnOnce(&mut Session) -> c_int {
+    match session.as_mut() {
+        Some(session) => action(session),
+        None => SESSION_NULL,
+    }
+}
+
+/// Send `value` to the Rust endpoint, which should be receiving `mpsc::Value<T>`.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released yet.
+pub unsafe fn send<T>(session: *mut Session, value: T) -> c_int where T: Send + 'static {
+    with_session(session, |session| match *session.next() {
+        Desc::Recv(payload, ref next) if payload == type_name::<mpsc::Value<T>>() =>
+            match mpsc::Value(value).send(&mut session.carrier) {
+                Ok(()) => {
+                    session.step = next;
+                    SESSION_OK
+                },
+                Err(_) =>
+                    SESSION_HUNG_UP,
+            },
+        _ =>
+            SESSION_MISMATCH,
+    })
+}
+
+/// Receive a value from the Rust endpoint, which should be sending
+/// `mpsc::Value<T>`, into `value`.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released
+/// yet, `value` should be null or valid for writes.
+pub unsafe fn recv<T>(session: *mut Session, value: *mut T) -> c_int where T: Send + 'static {
+    if value.is_null() {
+        return SESSION_NULL;
+    }
+    with_session(session, |session| match *session.next() {
+        Desc::Send(payload, ref next) if payload == type_name::<mpsc::Value<T>>() =>
+            match mpsc::Value::<T>::recv(&mut session.carrier) {
+                Ok(mpsc::Value(received)) => {
+                    value.write(received);
+                    session.step = next;
+                    SESSION_OK
+                },
+                Err(_) =>
+                    SESSION_HUNG_UP,
+            },
+        _ =>
+            SESSION_MISMATCH,
+    })
+}
+
+/// Select the branch number `branch` of the offer of the Rust endpoint.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn session_choose(session: *mut Session, branch: c_int) -> c_int {
+    with_session(session, |session| match *session.next() {
+        Desc::Offer(ref branches) if branch >= 0 && (branch as usize) < branches.len() => {
+            let branch = branch as usize;
+            for index in 0 ..= branch {
+                if session.carrier.send_choice(index == branch).is_err() {
+                    return SESSION_HUNG_UP;
+                }
+            }
+            session.step = &branches[branch];
+            SESSION_OK
+        },
+        _ =>
+            SESSION_MISMATCH,
+    })
+}
+
+/// Learn the number of the branch chosen by the Rust endpoint, returned
+/// unless negative error code.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn session_offer(session: *mut Session) -> c_int {
+    with_session(session, |session| match *session.next() {
+        Desc::Choose(ref branches) => {
+            let mut branch = 0;
+            loop {
+                match session.carrier.recv_choice() {
+                    Ok(true) => break,
+                    Ok(false) => branch += 1,
+                    Err(_) => return SESSION_HUNG_UP,
+                }
+            }
+            session.step = &branches[branch];
+            branch as c_int
+        },
+        _ =>
+            SESSION_MISMATCH,
+    })
+}
+
+/// Release the session at the end of the protocol. Before the end the
+/// session is kept and `SESSION_MISMATCH` is returned.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn session_close(session: *mut Session) -> c_int {
+    let code = with_session(session, |session| match *session.next() {
+        Desc::End => SESSION_OK,
+        _ => SESSION_MISMATCH,
+    });
+    if code == SESSION_OK {
+        drop(Box::from_raw(session));
+    }
+    code
+}
+
+/// Release the session at any step, so that the Rust endpoint fails to
+/// communicate with it.
+///
+/// # Safety
+///
+/// `session` should be null or a handle from `session_channel` not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn session_free(session: *mut Session) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+ffi_value!(session_send_bool, session_recv_bool, bool);
+ffi_value!(session_send_u8, session_recv_u8, u8);
+ffi_value!(session_send_u16, session_recv_u16, u16);
+ffi_value!(session_send_u32, session_recv_u32, u32);
+ffi_value!(session_send_u64, session_recv_u64, u64);
+ffi_value!(session_send_i8, session_recv_i8, i8);
+ffi_value!(session_send_i16, session_recv_i16, i16);
+ffi_value!(session_send_i32, session_recv_i32, i32);
+ffi_value!(session_send_i64, session_recv_i64, i64);
+ffi_value!(session_send_f32, session_recv_f32, f32);
+ffi_value!(session_send_f64, session_recv_f64, f64);
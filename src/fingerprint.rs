@@ -0,0 +1,33 @@
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::reflect::ProtocolDesc;
+
+/// Structural fingerprint of a protocol type.
+///
+/// Payload types are identified by `std::any::type_name`, whose output is
+/// not guaranteed to be stable: it depends on the compiler version and on
+/// the crate and module paths of the types. Both peers are expected to be
+/// built with the same toolchain from the same protocol definition (e.g. a
+/// common crate), otherwise the fingerprints of equal protocols may differ.
+pub trait Fingerprint: ProtocolDesc {
+    /// Append a textual description of the protocol to `out`.
+    fn describe(out: &mut String) {
+        Self::desc().write_canonical(out);
+    }
+
+    /// 64-bit FNV-1a hash of the description, equal on peers built alike.
+    fn fingerprint() -> u64 {
+        let mut description = String::new();
+        Self::describe(&mut description);
+        fnv1a(&description)
+    }
+}
+
+impl<P> Fingerprint for P where P: ProtocolDesc { }
+
+/// 64-bit FNV-1a hash of a protocol description.
+pub(crate) fn fnv1a(description: &str) -> u64 {
+    description.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
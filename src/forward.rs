@@ -0,0 +1,287 @@
+//! Relaying sessions between two endpoints.
+//!
+//! A proxy holding a channel at protocol `P` towards one peer and a channel
+//! at its dual towards another one could `forward` the session: every
+//! message and choice received from one side is sent to the other, until
+//! both channels reach `End`. The carriers of the channels need not be the
+//! same, so a proxy could bridge e.g. an `mpsc` peer to a TCP one, as long
+//! as every payload could be received from one carrier and sent over the
+//! other.
+//!
+//! Protocols built of `Send`, `Recv`, `Choose`, `Offer`, `Labeled`, `Rec`
+//! and `Var` are supported.
+//!
+//! `inspect` is the same, but also reports every relayed message with its
+//! decoded payload, and every choice, to a callback, so that a live session
+//! could be debugged or audited without modifying either endpoint.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{Rec, Offer, Recv, Send, Var, Z, End, Nil, mpsc};
+//! use session_types_ng::forward::forward;
+//!
+//! type Echo = Rec<Offer<Recv<mpsc::Value<String>, Send<mpsc::Value<String>, Var<Z>>>, Offer<End, Nil>>>;
+//!
+//! let (server, upstream) = mpsc::session_channel::<Echo>();
+//! let (downstream, client) = mpsc::session_channel::<Echo>();
+//! let server = thread::spawn(move || {
+//!     let mut chan = server.enter();
+//!     loop {
+//!         match chan.offer().option(Ok).option(Err).unwrap() {
+//!             Ok(echo) => {
+//!                 let (echo, mpsc::Value(line)) = echo.recv().unwrap();
+//!                 chan = echo.send(mpsc::Value(line.to_uppercase())).unwrap().zero();
+//!             },
+//!             Err(end) => return end.close(),
+//!         }
+//!     }
+//! });
+//! let proxy = thread::spawn(move || forward(downstream, upstream).unwrap());
+//!
+//! let client = client.enter().first().unwrap();
+//! let (client, mpsc::Value(reply)) = client.send(mpsc::Value("hello".to_string())).unwrap().recv().unwrap();
+//! assert_eq!(reply, "HELLO");
+//! client.zero().second().unwrap().close();
+//! proxy.join().unwrap();
+//! server.join().unwrap();
+//! ```
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use std::any::{Any, type_name};
+use std::fmt;
+use std::marker;
+use super::{ChannelSend, ChannelRecv, Carrier, Chan, HasDual, End, Send, Recv, Choose, Offer, Nil, Labeled, Rec, Var, Nat, close_chan};
+
+/// Endpoint of a forwarded session.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    /// The channel at protocol `P`.
+    A,
+    /// The channel at the dual of `P`.
+    B,
+}
+
+/// Failure of forwarding. The cause is the error of the carrier or the
+/// payload, which differs from step to step.
+#[derive(Debug)]
+pub enum Error {
+    /// Receiving a message or a choice from the endpoint has failed.
+    Recv(Side, Box<dyn fmt::Debug + marker::Send>),
+    /// Sending a message or a choice to the endpoint has failed.
+    Send(Side, Box<dyn fmt::Debug + marker::Send>),
+}
+
+fn failure<E>(variant: fn(Side, Box<dyn fmt::Debug + marker::Send>) -> Error, side: Side) -> impl FnOnce(E) -> Error
+    where E: fmt::Debug + marker::Send + 'static
+{
+    move |e| variant(side, Box::new(e))
+}
+
+/// Where relaying of a protocol has stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exit {
+    /// Both endpoints are at `End`.
+    End,
+    /// Both endpoints recurse to the `Rec` at the given depth, counting from
+    /// the innermost one.
+    Continue(usize),
+}
+
+/// Something relayed by `inspect`.
+#[derive(Clone, Copy, Debug)]
+pub enum Event<'a> {
+    /// A message has been relayed from the endpoint `from`. `payload_type`
+    /// is the type name of the payload, which could be downcast from
+    /// `payload`.
+    Message { from: Side, payload_type: &'static str, payload: &'a dyn Any },
+    /// The endpoint `from` has chosen the branch `branch` (counting from
+    /// zero) of a choice.
+    Choice { from: Side, branch: usize },
+}
+
+/// Relaying of protocol `Self` from the carrier `A` of the endpoint at
+/// `Self` and the carrier `B` of the endpoint at its dual, reporting every
+/// relayed message and choice to `inspect`.
+pub trait Forward<A, B> {
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event);
+}
+
+/// Relaying of a choice list, with `skipped` branches passed over so far.
+pub trait ForwardBranch<A, B> {
+    fn forward_branch<F>(a: &mut A, b: &mut B, inspect: &mut F, skipped: usize) -> Result<Exit, Error> where F: FnMut(Event);
+}
+
+impl<A, B> Forward<A, B> for End {
+    fn forward<F>(_a: &mut A, _b: &mut B, _inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        Ok(Exit::End)
+    }
+}
+
+impl<A, B, T, P> Forward<A, B> for Recv<T, P>
+    where T: ChannelRecv<A> + ChannelSend<B> + 'static,
+          <T as ChannelRecv<A>>::Err: fmt::Debug + marker::Send + 'static,
+          <T as ChannelSend<B>>::Err: fmt::Debug + marker::Send + 'static,
+          P: Forward<A, B>
+{
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        let value = <T as ChannelRecv<A>>::recv(a).map_err(failure(Error::Recv, Side::A))?;
+        inspect(Event::Message { from: Side::A, payload_type: type_name::<T>(), payload: &value });
+        value.send(b).map_err(failure(Error::Send, Side::B))?;
+        P::forward(a, b, inspect)
+    }
+}
+
+impl<A, B, T, P> Forward<A, B> for Send<T, P>
+    where T: ChannelRecv<B> + ChannelSend<A> + 'static,
+          <T as ChannelRecv<B>>::Err: fmt::Debug + marker::Send + 'static,
+          <T as ChannelSend<A>>::Err: fmt::Debug + marker::Send + 'static,
+          P: Forward<A, B>
+{
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        let value = <T as ChannelRecv<B>>::recv(b).map_err(failure(Error::Recv, Side::B))?;
+        inspect(Event::Message { from: Side::B, payload_type: type_name::<T>(), payload: &value });
+        value.send(a).map_err(failure(Error::Send, Side::A))?;
+        P::forward(a, b, inspect)
+    }
+}
+
+impl<A, B> ForwardBranch<A, B> for Nil {
+    fn forward_branch<F>(_a: &mut A, _b: &mut B, _inspect: &mut F, _skipped: usize) -> Result<Exit, Error> where F: FnMut(Event) {
+        panic!("session protocol offer list out of range")
+    }
+}
+
+impl<A, B, P, L> ForwardBranch<A, B> for Offer<P, L>
+    where A: Carrier,
+          B: Carrier,
+          A::RecvChoiceErr: fmt::Debug + marker::Send + 'static,
+          B::SendChoiceErr: fmt::Debug + marker::Send + 'static,
+          P: Forward<A, B>,
+          L: ForwardBranch<A, B>
+{
+    fn forward_branch<F>(a: &mut A, b: &mut B, inspect: &mut F, skipped: usize) -> Result<Exit, Error> where F: FnMut(Event) {
+        let choice = a.recv_choice().map_err(failure(Error::Recv, Side::A))?;
+        b.send_choice(choice).map_err(failure(Error::Send, Side::B))?;
+        if choice {
+            inspect(Event::Choice { from: Side::A, branch: skipped });
+            P::forward(a, b, inspect)
+        } else {
+            L::forward_branch(a, b, inspect, skipped + 1)
+        }
+    }
+}
+
+impl<A, B, P, L> Forward<A, B> for Offer<P, L> where Offer<P, L>: ForwardBranch<A, B> {
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        Self::forward_branch(a, b, inspect, 0)
+    }
+}
+
+impl<A, B, P, L> ForwardBranch<A, B> for Choose<P, L>
+    where A: Carrier,
+          B: Carrier,
+          B::RecvChoiceErr: fmt::Debug + marker::Send + 'static,
+          A::SendChoiceErr: fmt::Debug + marker::Send + 'static,
+          P: Forward<A, B>,
+          L: ForwardBranch<A, B>
+{
+    fn forward_branch<F>(a: &mut A, b: &mut B, inspect: &mut F, skipped: usize) -> Result<Exit, Error> where F: FnMut(Event) {
+        let choice = b.recv_choice().map_err(failure(Error::Recv, Side::B))?;
+        a.send_choice(choice).map_err(failure(Error::Send, Side::A))?;
+        if choice {
+            inspect(Event::Choice { from: Side::B, branch: skipped });
+            P::forward(a, b, inspect)
+        } else {
+            L::forward_branch(a, b, inspect, skipped + 1)
+        }
+    }
+}
+
+impl<A, B, P, L> Forward<A, B> for Choose<P, L> where Choose<P, L>: ForwardBranch<A, B> {
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        Self::forward_branch(a, b, inspect, 0)
+    }
+}
+
+impl<A, B, Lbl, P> Forward<A, B> for Labeled<Lbl, P> where P: Forward<A, B> {
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        P::forward(a, b, inspect)
+    }
+}
+
+impl<A, B, P> Forward<A, B> for Rec<P> where P: Forward<A, B> {
+    fn forward<F>(a: &mut A, b: &mut B, inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        loop {
+            match P::forward(a, b, inspect)? {
+                Exit::Continue(0) => continue,
+                Exit::Continue(depth) => return Ok(Exit::Continue(depth - 1)),
+                Exit::End => return Ok(Exit::End),
+            }
+        }
+    }
+}
+
+impl<A, B, N> Forward<A, B> for Var<N> where N: Nat {
+    fn forward<F>(_a: &mut A, _b: &mut B, _inspect: &mut F) -> Result<Exit, Error> where F: FnMut(Event) {
+        Ok(Exit::Continue(N::value()))
+    }
+}
+
+/// Relay the session between `a` and `b` until both of them reach `End`
+/// and are closed. On failure both channels are closed as well, so that
+/// their peers do not wait forever.
+///
+/// `P` should not continue loops entered before the call: the channels
+/// could not be given back at a recursion point.
+pub fn forward<A, B, EA, EB, P>(a: Chan<A, EA, P>, b: Chan<B, EB, P::Dual>) -> Result<(), Error>
+    where P: HasDual + Forward<A, B>
+{
+    inspect(a, b, |_| ())
+}
+
+/// Same as `forward`, but every relayed message and choice is reported to
+/// `callback` before it is passed on.
+///
+/// ```
+/// use std::thread;
+/// use session_types_ng::{Recv, Send, End, mpsc};
+/// use session_types_ng::forward::{inspect, Event};
+///
+/// type Lookup = Recv<mpsc::Value<String>, Send<mpsc::Value<u32>, End>>;
+///
+/// let (server, upstream) = mpsc::session_channel::<Lookup>();
+/// let (downstream, client) = mpsc::session_channel::<Lookup>();
+/// let server = thread::spawn(move || {
+///     let (chan, mpsc::Value(key)) = server.recv().unwrap();
+///     chan.send(mpsc::Value(key.len() as u32)).unwrap().close();
+/// });
+/// let auditor = thread::spawn(move || {
+///     let mut log = Vec::new();
+///     inspect(downstream, upstream, |event| if let Event::Message { from, payload, .. } = event {
+///         if let Some(mpsc::Value(key)) = payload.downcast_ref::<mpsc::Value<String>>() {
+///             log.push(format!("{:?} asked for {}", from, key));
+///         }
+///         if let Some(mpsc::Value(len)) = payload.downcast_ref::<mpsc::Value<u32>>() {
+///             log.push(format!("{:?} answered {}", from, len));
+///         }
+///     }).unwrap();
+///     log
+/// });
+///
+/// let (client, mpsc::Value(len)) = client.call(mpsc::Value("apple".to_string())).unwrap();
+/// client.close();
+/// server.join().unwrap();
+/// assert_eq!(len, 5);
+/// assert_eq!(auditor.join().unwrap(), ["A asked for apple", "B answered 5"]);
+/// ```
+pub fn inspect<A, B, EA, EB, P, F>(mut a: Chan<A, EA, P>, mut b: Chan<B, EB, P::Dual>, mut callback: F) -> Result<(), Error>
+    where P: HasDual + Forward<A, B>, F: FnMut(Event)
+{
+    let result = P::forward(&mut a.carrier, &mut b.carrier, &mut callback);
+    close_chan(a);
+    close_chan(b);
+    match result? {
+        Exit::End => Ok(()),
+        Exit::Continue(_) => panic!("forwarded protocol continues a loop entered before forwarding"),
+    }
+}
@@ -0,0 +1,319 @@
+//! Producer and consumer loops as async `Sink` and `Stream`.
+//!
+//! A loop of the shape `Rec<Choose<Send<A, Var<Z>>, Choose<R, Nil>>>` is
+//! turned into a `SendSink` of the items with `Chan::into_sink`, and its
+//! dual into a `RecvStream` with `Chan::recv_stream`. Both are driven by a
+//! `poll::NonBlockingCarrier`, so they never block.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures_core::Stream;
+use futures_sink::Sink;
+use poll::{NonBlockingCarrier, PollError, PollRecvError};
+use super::{ChannelSend, ChannelRecv, Chan, Rec, Send, Recv, Choose, Offer, Var, Z, Nil, RecvLoop, cast_chan, close_chan};
+
+/// Failure of `RecvStream` or `SendSink`.
+#[derive(Debug)]
+pub enum LoopError<W, C, V> {
+    /// Carrier has failed while polling.
+    Poll(W),
+    /// A choice transmission has failed.
+    Choice(C),
+    /// A value transmission has failed.
+    Value(V),
+}
+
+enum RecvStreamState<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelRecv<SR> {
+    Loop(Chan<SR, (RecvLoop<A, R>, E), RecvLoop<A, R>>),
+    Value(Chan<SR, (RecvLoop<A, R>, E), Recv<A, Var<Z>>>),
+    Leave(Chan<SR, (RecvLoop<A, R>, E), Offer<R, Nil>>),
+    Done(Chan<SR, (RecvLoop<A, R>, E), R>),
+    Failed(LoopError<SR::PollErr, SR::RecvChoiceErr, A::Err>),
+    Empty,
+}
+
+/// Consumer loop as a `futures::Stream`, see `Chan::recv_stream`.
+pub struct RecvStream<SR, E, A, R>(RecvStreamState<SR, E, A, R>) where SR: NonBlockingCarrier, A: ChannelRecv<SR>;
+
+impl<SR, E, A, R> Chan<SR, E, Rec<RecvLoop<A, R>>> where SR: NonBlockingCarrier, A: ChannelRecv<SR> {
+    /// Drive a consumer loop as a `futures::Stream` (requires `futures`
+    /// feature), dual to `into_sink`. The choices and values are awaited
+    /// with `poll_recv`, so `poll_next` never blocks. While the next one is
+    /// pending the task is parked with `register_waker`.
+    ///
+    /// ```
+    /// extern crate futures;
+    /// # extern crate session_types_ng;
+    /// use std::thread;
+    /// use futures::StreamExt;
+    /// use futures::executor::block_on;
+    /// use session_types_ng::{Rec, Choose, Send, Var, Z, End, Nil, mpsc};
+    ///
+    /// type Producer = Rec<Choose<Send<mpsc::Value<u32>, Var<Z>>, Choose<End, Nil>>>;
+    ///
+    /// # fn main() {
+    /// let (tx, rx) = mpsc::session_channel::<Producer>();
+    /// let producer = thread::spawn(move || tx.send_all((1 .. 4).map(mpsc::Value)).unwrap().close());
+    /// let mut values = rx.recv_stream();
+    /// let doubled: Vec<u32> = block_on(values.by_ref().map(|mpsc::Value(v)| v * 2).collect());
+    /// values.finish().unwrap().close();
+    /// producer.join().unwrap();
+    /// assert_eq!(doubled, [2, 4, 6]);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn recv_stream(self) -> RecvStream<SR, E, A, R> {
+        RecvStream(RecvStreamState::Loop(self.enter()))
+    }
+}
+
+impl<SR, E, A, R> RecvStream<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelRecv<SR> {
+    /// Get the channel after the stream has ended, or the failure which has
+    /// ended it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stream has not ended yet.
+    #[must_use]
+    pub fn finish(self) -> Result<Chan<SR, (RecvLoop<A, R>, E), R>, LoopError<SR::PollErr, SR::RecvChoiceErr, A::Err>> {
+        match self.0 {
+            RecvStreamState::Done(chan) =>
+                Ok(chan),
+            RecvStreamState::Failed(e) =>
+                Err(e),
+            _ =>
+                panic!("the stream has not ended"),
+        }
+    }
+}
+
+impl<SR, E, A, R> Stream for RecvStream<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelRecv<SR> {
+    type Item = A;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<A>> {
+        let stream = self.get_mut();
+        let mut registered = false;
+        loop {
+            match std::mem::replace(&mut stream.0, RecvStreamState::Empty) {
+                RecvStreamState::Loop(mut chan) =>
+                    match chan.carrier.poll_recv() {
+                        Ok(()) =>
+                            match chan.carrier.recv_choice() {
+                                Ok(true) =>
+                                    stream.0 = RecvStreamState::Value(cast_chan(chan)),
+                                Ok(false) =>
+                                    stream.0 = RecvStreamState::Leave(cast_chan(chan)),
+                                Err(e) => {
+                                    close_chan(chan);
+                                    stream.0 = RecvStreamState::Failed(LoopError::Choice(e));
+                                },
+                            },
+                        Err(PollError::WouldBlock) if registered => {
+                            stream.0 = RecvStreamState::Loop(chan);
+                            return Poll::Pending;
+                        },
+                        Err(PollError::WouldBlock) => {
+                            // poll once more, it could have become ready meanwhile
+                            chan.register_waker(cx.waker());
+                            registered = true;
+                            stream.0 = RecvStreamState::Loop(chan);
+                        },
+                        Err(PollError::Carrier(e)) => {
+                            close_chan(chan);
+                            stream.0 = RecvStreamState::Failed(LoopError::Poll(e));
+                        },
+                    },
+                RecvStreamState::Value(chan) =>
+                    match chan.poll_recv() {
+                        Ok((chan, v)) => {
+                            stream.0 = RecvStreamState::Loop(chan.zero());
+                            return Poll::Ready(Some(v));
+                        },
+                        Err(PollRecvError::WouldBlock(chan)) if registered => {
+                            stream.0 = RecvStreamState::Value(chan);
+                            return Poll::Pending;
+                        },
+                        Err(PollRecvError::WouldBlock(mut chan)) => {
+                            chan.register_waker(cx.waker());
+                            registered = true;
+                            stream.0 = RecvStreamState::Value(chan);
+                        },
+                        Err(PollRecvError::Poll(e)) =>
+                            stream.0 = RecvStreamState::Failed(LoopError::Poll(e)),
+                        Err(PollRecvError::Value(e)) =>
+                            stream.0 = RecvStreamState::Failed(LoopError::Value(e)),
+                    },
+                RecvStreamState::Leave(chan) =>
+                    match chan.poll_offer() {
+                        Ok(offers) =>
+                            stream.0 = match offers.option(|chan| chan) {
+                                Ok(chan) => RecvStreamState::Done(chan),
+                                Err(e) => RecvStreamState::Failed(LoopError::Choice(e)),
+                            },
+                        Err(PollRecvError::WouldBlock(chan)) if registered => {
+                            stream.0 = RecvStreamState::Leave(chan);
+                            return Poll::Pending;
+                        },
+                        Err(PollRecvError::WouldBlock(mut chan)) => {
+                            chan.register_waker(cx.waker());
+                            registered = true;
+                            stream.0 = RecvStreamState::Leave(chan);
+                        },
+                        Err(PollRecvError::Poll(e)) =>
+                            stream.0 = RecvStreamState::Failed(LoopError::Poll(e)),
+                        Err(PollRecvError::Value(never)) =>
+                            match never { },
+                    },
+                state => {
+                    stream.0 = state;
+                    return Poll::Ready(None);
+                },
+            }
+        }
+    }
+}
+
+// nothing is pinned structurally
+impl<SR, E, A, R> Unpin for RecvStream<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelRecv<SR> { }
+
+type SendLoop<A, R> = Choose<Send<A, Var<Z>>, Choose<R, Nil>>;
+
+enum SendSinkState<SR, E, A, R> {
+    Loop(Chan<SR, (SendLoop<A, R>, E), SendLoop<A, R>>),
+    Done(Chan<SR, (SendLoop<A, R>, E), R>),
+    Empty,
+}
+
+/// Producer loop as a `futures::Sink`, see `Chan::into_sink`.
+pub struct SendSink<SR, E, A, R>(SendSinkState<SR, E, A, R>);
+
+impl<SR, E, A, R> Chan<SR, E, Rec<SendLoop<A, R>>> where SR: NonBlockingCarrier, A: ChannelSend<SR> {
+    /// Drive a producer loop as a `futures::Sink` (requires `futures`
+    /// feature), like `send_all`: every item is sent choosing the first
+    /// branch, and closing the sink chooses the second one to leave the
+    /// loop. Items are handed to the carrier within `start_send`, and the
+    /// sink is ready for the next one, flushed or closed once `poll_send`
+    /// has delivered them, so it never blocks. While the delivery is pending
+    /// the task is parked with `register_waker`. The sink should not be used
+    /// after an error.
+    ///
+    /// ```
+    /// extern crate futures;
+    /// # extern crate session_types_ng;
+    /// use std::thread;
+    /// use futures::{SinkExt, stream};
+    /// use futures::executor::block_on;
+    /// use session_types_ng::{Rec, Choose, Send, Var, Z, End, Nil, mpsc};
+    ///
+    /// type Producer = Rec<Choose<Send<mpsc::Value<u32>, Var<Z>>, Choose<End, Nil>>>;
+    ///
+    /// # fn main() {
+    /// let (tx, rx) = mpsc::session_channel::<Producer>();
+    /// let consumer = thread::spawn(move || {
+    ///     let mut values = rx.recv_iter();
+    ///     let sum: u32 = values.by_ref().map(|mpsc::Value(v)| v).sum();
+    ///     values.finish().unwrap().close();
+    ///     sum
+    /// });
+    /// let mut sink = tx.into_sink();
+    /// block_on(sink.send_all(&mut stream::iter((1 .. 4).map(|v| Ok(mpsc::Value(v)))))).unwrap();
+    /// block_on(sink.close()).unwrap();
+    /// sink.finish().close();
+    /// assert_eq!(consumer.join().unwrap(), 6);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn into_sink(self) -> SendSink<SR, E, A, R> {
+        SendSink(SendSinkState::Loop(self.enter()))
+    }
+}
+
+impl<SR, E, A, R> SendSink<SR, E, A, R> {
+    /// Get the channel after the sink has been closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sink has not been closed successfully.
+    #[must_use]
+    pub fn finish(self) -> Chan<SR, (SendLoop<A, R>, E), R> {
+        match self.0 {
+            SendSinkState::Done(chan) =>
+                chan,
+            SendSinkState::Loop(..) | SendSinkState::Empty =>
+                panic!("the sink has not been closed"),
+        }
+    }
+}
+
+impl<SR, E, A, R> SendSink<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelSend<SR> {
+    /// Deliver the items handed to the carrier so far.
+    fn poll_deliver(&mut self, cx: &mut Context) -> Poll<Result<(), LoopError<SR::PollErr, SR::SendChoiceErr, A::Err>>> {
+        let carrier = match self.0 {
+            SendSinkState::Loop(ref mut chan) =>
+                &mut chan.carrier,
+            SendSinkState::Done(ref mut chan) =>
+                &mut chan.carrier,
+            SendSinkState::Empty =>
+                panic!("the sink has failed"),
+        };
+        let mut delivered = carrier.poll_send();
+        if let Err(PollError::WouldBlock) = delivered {
+            // poll once more, it could have become ready meanwhile
+            carrier.register_waker(cx.waker());
+            delivered = carrier.poll_send();
+        }
+        match delivered {
+            Ok(()) =>
+                Poll::Ready(Ok(())),
+            Err(PollError::WouldBlock) =>
+                Poll::Pending,
+            Err(PollError::Carrier(e)) => {
+                match std::mem::replace(&mut self.0, SendSinkState::Empty) {
+                    SendSinkState::Loop(chan) => close_chan(chan),
+                    SendSinkState::Done(chan) => close_chan(chan),
+                    SendSinkState::Empty => (),
+                }
+                Poll::Ready(Err(LoopError::Poll(e)))
+            },
+        }
+    }
+}
+
+impl<SR, E, A, R> Sink<A> for SendSink<SR, E, A, R> where SR: NonBlockingCarrier, A: ChannelSend<SR> {
+    type Error = LoopError<SR::PollErr, SR::SendChoiceErr, A::Err>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_deliver(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: A) -> Result<(), Self::Error> {
+        let sink = self.get_mut();
+        match std::mem::replace(&mut sink.0, SendSinkState::Empty) {
+            SendSinkState::Loop(chan) => {
+                let chan = chan.first().map_err(LoopError::Choice)?.send(item).map_err(LoopError::Value)?;
+                sink.0 = SendSinkState::Loop(chan.zero());
+                Ok(())
+            },
+            SendSinkState::Done(..) =>
+                panic!("the sink has been closed"),
+            SendSinkState::Empty =>
+                panic!("the sink has failed"),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_deliver(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let sink = self.get_mut();
+        if let SendSinkState::Loop(..) = sink.0 {
+            if let SendSinkState::Loop(chan) = std::mem::replace(&mut sink.0, SendSinkState::Empty) {
+                sink.0 = SendSinkState::Done(chan.second().map_err(LoopError::Choice)?);
+            }
+        }
+        sink.poll_deliver(cx)
+    }
+}
+
+// nothing is pinned structurally
+impl<SR, E, A, R> Unpin for SendSink<SR, E, A, R> { }
@@ -0,0 +1,101 @@
+//! Servers structured as handler objects.
+//!
+//! A server looping over an offer of branches is declared with
+//! `session_handler!` as a list of methods, one per branch. A branch
+//! receives an optional payload and continues with its own protocol, which
+//! either returns to the loop with `Var<Z>` or ends the session. The macro
+//! derives the protocol of the server and a `SessionHandler` trait with a
+//! method per branch, each getting the channel of the branch and the
+//! payload, and returning the channel back at the loop to go on, or `None`
+//! once the session is over. `run` dispatches the branches chosen by the
+//! client to the methods of a handler.
+//!
+//! ```
+//! # #[macro_use] extern crate session_types_ng;
+//! # use session_types_ng::*;
+//! # use session_types_ng::mpsc::Value;
+//! use std::thread;
+//!
+//! session_handler! {
+//!     pub mod bank {
+//!         fn on_login(Value<String>) => Send<Value<bool>, Var<Z>>;
+//!         fn on_deposit(Value<u64>) => Send<Value<u64>, Var<Z>>;
+//!         fn on_quit() => End;
+//!     }
+//! }
+//!
+//! struct Account { user: Option<String>, balance: u64 }
+//!
+//! impl<SR> bank::SessionHandler<SR> for Account where SR: Carrier, Value<bool>: ChannelSend<SR>, Value<u64>: ChannelSend<SR> {
+//!     type Error = &'static str;
+//!
+//!     fn on_login(&mut self, chan: Chan<SR, bank::Env, Send<Value<bool>, Var<Z>>>, Value(user): Value<String>) -> Result<Option<Chan<SR, bank::Env, Var<Z>>>, Self::Error> {
+//!         let ok = user == "alice";
+//!         if ok {
+//!             self.user = Some(user);
+//!         }
+//!         chan.send(Value(ok)).map(Some).map_err(|_| "client is gone")
+//!     }
+//!
+//!     fn on_deposit(&mut self, chan: Chan<SR, bank::Env, Send<Value<u64>, Var<Z>>>, Value(amount): Value<u64>) -> Result<Option<Chan<SR, bank::Env, Var<Z>>>, Self::Error> {
+//!         self.user.as_ref().ok_or("not logged in")?;
+//!         self.balance += amount;
+//!         chan.send(Value(self.balance)).map(Some).map_err(|_| "client is gone")
+//!     }
+//!
+//!     fn on_quit(&mut self, chan: Chan<SR, bank::Env, End>) -> Result<Option<Chan<SR, bank::Env, Var<Z>>>, Self::Error> {
+//!         chan.close();
+//!         Ok(None)
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let (server, client) = mpsc::session_channel::<bank::Server>();
+//! let server = thread::spawn(move || {
+//!     let mut account = Account { user: None, balance: 0 };
+//!     bank::run(server, &mut account).ok().unwrap();
+//!     account.balance
+//! });
+//!
+//! let client = client.enter();
+//! let (client, Value(ok)) = client.nth_send::<Z, _, _>(Value("alice".to_string())).unwrap().recv().unwrap();
+//! assert!(ok);
+//! let (client, Value(balance)) = client.zero().nth_send::<S<Z>, _, _>(Value(10)).unwrap().recv().unwrap();
+//! assert_eq!(balance, 10);
+//! client.zero().third().unwrap().close();
+//! assert_eq!(server.join().unwrap(), 10);
+//! # }
+//! ```
+use super::{Carrier, Chan, Rec, Var, Z, OfferEnum, OptError};
+
+/// Failure of a server loop.
+#[derive(Debug)]
+pub enum Error<C, V, H> {
+    /// Offer of a branch has failed.
+    Choice(C),
+    /// Receiving the payload of a branch has failed.
+    Payload(V),
+    /// The handler of a branch has failed.
+    Handler(H),
+}
+
+/// Loop over the offer `B` of a `Rec` handling every branch chosen by the
+/// other side, as a variant of `O`, with `dispatch`, until it returns
+/// `None`.
+pub fn run<SR, E, B, O, V, H, F>(chan: Chan<SR, E, Rec<B>>, mut dispatch: F) -> Result<(), Error<SR::RecvChoiceErr, V, H>>
+    where SR: Carrier,
+          O: OfferEnum<SR, (B, E), V, Offer = B>,
+          F: FnMut(O) -> Result<Option<Chan<SR, (B, E), Var<Z>>>, H>
+{
+    let mut chan = chan.enter();
+    loop {
+        let branch = chan.offer_enum().map_err(|e| match e {
+            OptError::Choice(e) => Error::Choice(e),
+            OptError::Value(e) => Error::Payload(e),
+        })?;
+        match dispatch(branch).map_err(Error::Handler)? {
+            Some(next) => chan = next.zero(),
+            None => return Ok(()),
+        }
+    }
+}
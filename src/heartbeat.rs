@@ -0,0 +1,299 @@
+//! Detection of dead peers.
+//!
+//! A long-lived session, e.g. a server looping in a `Rec` over requests of a
+//! client, could otherwise block forever on a peer which has hung without
+//! closing its carrier. Over a `HeartbeatCarrier` both sides send heartbeat
+//! frames every `interval` while they have nothing else to send, and a
+//! receive waiting for the peer fails with `HeartbeatError::PeerUnresponsive`
+//! once nothing at all has been heard from it for `timeout`, which should
+//! span a few intervals. Heartbeats are sent by a background thread, so a
+//! side busy with other work between operations is not taken for dead.
+//!
+//! Every frame is preceded by a choice bit telling a heartbeat from a value
+//! or a choice of the session, so both sides should use a
+//! `HeartbeatCarrier`.
+//!
+//! ```
+//! use std::thread;
+//! use std::time::Duration;
+//! use session_types_ng::{Recv, End, mpsc};
+//! use session_types_ng::heartbeat::HeartbeatError;
+//!
+//! let interval = Duration::from_millis(10);
+//! let timeout = Duration::from_millis(100);
+//!
+//! // an idle peer is alive
+//! let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+//! let (rx, tx) = (rx.with_heartbeat(interval, timeout), tx.with_heartbeat(interval, timeout));
+//! let peer = thread::spawn(move || {
+//!     thread::sleep(Duration::from_millis(300));
+//!     tx.send(mpsc::Value(1)).unwrap().close();
+//! });
+//! let (rx, mpsc::Value(x)) = rx.recv().unwrap();
+//! rx.close();
+//! peer.join().unwrap();
+//! assert_eq!(x, 1);
+//!
+//! // a silent one is not
+//! let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+//! let rx = rx.with_heartbeat(interval, timeout);
+//! match rx.recv() {
+//!     Err(HeartbeatError::PeerUnresponsive) => (),
+//!     _ => panic!("the peer has not sent anything"),
+//! }
+//! assert!(tx.send(mpsc::Value(1)).is_err());
+//! ```
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, Chan};
+use super::timeout::TimeoutCarrier;
+use super::layer::CarrierLayer;
+
+/// How long a blocked receive holds the carrier, so that heartbeats could
+/// be sent meanwhile.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Error of an operation over `HeartbeatCarrier`.
+#[derive(Debug)]
+pub enum HeartbeatError<E, F = Infallible> {
+    /// Nothing has been heard from the peer for longer than the timeout, or
+    /// a heartbeat could not be sent to it.
+    PeerUnresponsive,
+    /// Transferring the frame marker, or waiting for the peer, has failed.
+    Frame(F),
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+/// The carrier shared with the heartbeat thread.
+struct Link<C> {
+    carrier: C,
+    last_sent: Instant,
+    failed: bool,
+}
+
+impl<C> Link<C> where C: Carrier {
+    /// Send a heartbeat unless something has been sent within `interval`.
+    fn beat(&mut self, interval: Duration) {
+        if !self.failed && self.last_sent.elapsed() >= interval {
+            match self.carrier.send_choice(true) {
+                Ok(()) => self.last_sent = Instant::now(),
+                Err(_) => self.failed = true,
+            }
+        }
+    }
+}
+
+/// Carrier wrapper exchanging heartbeats with the peer, see the module
+/// documentation.
+pub struct HeartbeatCarrier<C> {
+    link: Arc<Mutex<Link<C>>>,
+    interval: Duration,
+    timeout: Duration,
+    last_heard: Instant,
+    /// The marker of the next frame has been received by `wait_timeout`.
+    pending: bool,
+    stop: Option<Sender<()>>,
+    pulse: Option<JoinHandle<()>>,
+}
+
+impl<C> HeartbeatCarrier<C> where C: TimeoutCarrier + Send + 'static {
+    /// Wrap a carrier sending heartbeats every `interval` and giving up on
+    /// the peer after `timeout` of silence.
+    pub fn new(inner: C, interval: Duration, timeout: Duration) -> HeartbeatCarrier<C> {
+        let now = Instant::now();
+        let link = Arc::new(Mutex::new(Link { carrier: inner, last_sent: now, failed: false }));
+        let (stop, stopped) = channel();
+        let shared = link.clone();
+        let pulse = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                lock(&shared).beat(interval);
+            }
+        });
+        HeartbeatCarrier {
+            link,
+            interval,
+            timeout,
+            last_heard: now,
+            pending: false,
+            stop: Some(stop),
+            pulse: Some(pulse),
+        }
+    }
+
+    /// Stop sending heartbeats and get back underlying carrier.
+    pub fn into_inner(mut self) -> C {
+        self.halt();
+        let link = self.link.clone();
+        drop(self);
+        match Arc::try_unwrap(link) {
+            Ok(link) => link.into_inner().unwrap_or_else(|e| e.into_inner()).carrier,
+            Err(_) => unreachable!("the heartbeat thread has stopped"),
+        }
+    }
+
+    /// Wait for the marker of a frame of the session for at most `timeout`,
+    /// skipping heartbeats.
+    fn wait_for<E>(&mut self, timeout: Duration) -> Result<bool, HeartbeatError<E, C::RecvChoiceErr>> {
+        if self.pending {
+            return Ok(true);
+        }
+        let started = Instant::now();
+        loop {
+            let mut link = check(&self.link)?;
+            if link.carrier.wait_timeout(POLL_INTERVAL).map_err(HeartbeatError::Frame)? {
+                self.last_heard = Instant::now();
+                if !link.carrier.recv_choice().map_err(HeartbeatError::Frame)? {
+                    self.pending = true;
+                    return Ok(true);
+                }
+            } else if self.last_heard.elapsed() >= self.timeout {
+                return Err(HeartbeatError::PeerUnresponsive);
+            }
+            link.beat(self.interval);
+            if started.elapsed() >= timeout {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Wait for the next frame of the session.
+    fn wait<E>(&mut self) -> Result<MutexGuard<'_, Link<C>>, HeartbeatError<E, C::RecvChoiceErr>> {
+        while !self.wait_for(self.timeout)? { }
+        self.pending = false;
+        Ok(lock(&self.link))
+    }
+}
+
+impl<C> HeartbeatCarrier<C> {
+    /// Stop the heartbeat thread.
+    fn halt(&mut self) {
+        self.stop.take();
+        if let Some(pulse) = self.pulse.take() {
+            let _ = pulse.join();
+        }
+    }
+}
+
+impl<C> Drop for HeartbeatCarrier<C> {
+    fn drop(&mut self) {
+        self.halt();
+    }
+}
+
+fn lock<C>(link: &Mutex<Link<C>>) -> MutexGuard<'_, Link<C>> {
+    link.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lock the carrier unless sending heartbeats to the peer has failed.
+fn check<C, E, F>(link: &Mutex<Link<C>>) -> Result<MutexGuard<'_, Link<C>>, HeartbeatError<E, F>> {
+    let link = lock(link);
+    if link.failed {
+        Err(HeartbeatError::PeerUnresponsive)
+    } else {
+        Ok(link)
+    }
+}
+
+/// Layer wrapping carriers into `HeartbeatCarrier`s with the same interval
+/// and timeout.
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatLayer {
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl HeartbeatLayer {
+    pub fn new(interval: Duration, timeout: Duration) -> HeartbeatLayer {
+        HeartbeatLayer { interval, timeout }
+    }
+}
+
+impl<C> CarrierLayer<C> for HeartbeatLayer where C: TimeoutCarrier + Send + 'static {
+    type Carrier = HeartbeatCarrier<C>;
+
+    fn layer(&self, inner: C) -> HeartbeatCarrier<C> {
+        HeartbeatCarrier::new(inner, self.interval, self.timeout)
+    }
+}
+
+impl<C> Carrier for HeartbeatCarrier<C> where C: TimeoutCarrier + Send + 'static {
+    type SendChoiceErr = HeartbeatError<C::SendChoiceErr>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        let mut link = check(&self.link)?;
+        link.carrier.send_choice(false).map_err(HeartbeatError::Carrier)?;
+        link.carrier.send_choice(choice).map_err(HeartbeatError::Carrier)?;
+        link.last_sent = Instant::now();
+        Ok(())
+    }
+
+    type RecvChoiceErr = HeartbeatError<C::RecvChoiceErr, C::RecvChoiceErr>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.wait()?.carrier.recv_choice().map_err(HeartbeatError::Carrier)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        lock(&self.link).carrier.step(protocol);
+    }
+}
+
+/// Explicit timeouts skip heartbeats, and report an unresponsive peer as an
+/// error.
+impl<C> TimeoutCarrier for HeartbeatCarrier<C> where C: TimeoutCarrier + Send + 'static {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.wait_for(timeout)
+    }
+}
+
+impl<C> BatchCarrier for HeartbeatCarrier<C> where C: TimeoutCarrier + BatchCarrier + Send + 'static {
+    type FlushErr = HeartbeatError<C::FlushErr>;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        check(&self.link)?.carrier.set_batching(enabled).map_err(HeartbeatError::Carrier)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        check(&self.link)?.carrier.flush().map_err(HeartbeatError::Carrier)
+    }
+}
+
+/// Values transferable over a carrier are transferable over its heartbeat
+/// wrapper.
+impl<C, T> ChannelSend<HeartbeatCarrier<C>> for T
+    where T: Payload + ChannelSend<C>, C: TimeoutCarrier + Send + 'static
+{
+    type Err = HeartbeatError<<T as ChannelSend<C>>::Err, C::SendChoiceErr>;
+
+    fn send(self, carrier: &mut HeartbeatCarrier<C>) -> Result<(), Self::Err> {
+        let mut link = check(&carrier.link)?;
+        link.carrier.send_choice(false).map_err(HeartbeatError::Frame)?;
+        self.send(&mut link.carrier).map_err(HeartbeatError::Carrier)?;
+        link.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+impl<C, T> ChannelRecv<HeartbeatCarrier<C>> for T
+    where T: Payload + ChannelRecv<C>, C: TimeoutCarrier + Send + 'static
+{
+    type Err = HeartbeatError<<T as ChannelRecv<C>>::Err, C::RecvChoiceErr>;
+
+    fn recv(carrier: &mut HeartbeatCarrier<C>) -> Result<Self, Self::Err> {
+        <T>::recv(&mut carrier.wait()?.carrier).map_err(HeartbeatError::Carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: TimeoutCarrier + Send + 'static {
+    /// Exchange heartbeats with the peer for the rest of the session, see
+    /// the `heartbeat` module documentation.
+    #[must_use]
+    pub fn with_heartbeat(self, interval: Duration, timeout: Duration) -> Chan<HeartbeatCarrier<SR>, E, P> {
+        Chan {
+            carrier: HeartbeatCarrier::new(self.carrier, interval, timeout),
+            session: self.session,
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Interleaving several sessions on one thread.
+//!
+//! A thread holding more than one session, e.g. a proxy between an upstream
+//! and a downstream channel, could not simply drive them one after another:
+//! blocking on one session while the other one waits for it leads to a
+//! deadlock. Instead every session is written as a chain of `Step`s. A step
+//! waits until its channel is ready, in the sense of `select::Selectable`,
+//! and then runs a continuation which performs the next operation on the
+//! channel and returns the following step. `Interleave` runs whichever
+//! session is ready, round robin, passing all of them a shared state to
+//! exchange data through.
+//!
+//! A continuation should perform at most one operation which could block,
+//! the one its channel is ready for: sending over an unbounded carrier is
+//! fine as well. Readiness is polled backing off up to a millisecond, as
+//! `select::ChanSelect` does.
+//!
+//! ```
+//! use std::collections::VecDeque;
+//! use std::thread;
+//! use session_types_ng::{Chan, Rec, Choose, Send, Var, Z, End, Nil, HasDual, mpsc};
+//! use session_types_ng::interleave::{self, Step};
+//!
+//! type Feed = Choose<Send<mpsc::Value<u32>, Var<Z>>, Choose<End, Nil>>;
+//! type Drain = <Feed as HasDual>::Dual;
+//!
+//! #[derive(Default)]
+//! struct Relay { queue: VecDeque<u32>, finished: bool }
+//!
+//! fn upstream(chan: Chan<mpsc::Channel, (Drain, ()), Drain>) -> Step<'static, Relay, ()> {
+//!     Step::wait(chan, |chan, relay: &mut Relay| match chan.offer().option(Ok).option(Err).unwrap() {
+//!         Ok(chan) => {
+//!             let (chan, mpsc::Value(x)) = chan.recv().unwrap();
+//!             relay.queue.push_back(x * 10);
+//!             upstream(chan.zero())
+//!         },
+//!         Err(chan) => {
+//!             chan.close();
+//!             relay.finished = true;
+//!             Step::done(())
+//!         },
+//!     })
+//! }
+//!
+//! fn downstream(chan: Chan<mpsc::Channel, (Feed, ()), Feed>) -> Step<'static, Relay, ()> {
+//!     let ready = |_: &mut _, relay: &Relay| relay.finished || !relay.queue.is_empty();
+//!     Step::wait_for(chan, ready, |chan, relay: &mut Relay| match relay.queue.pop_front() {
+//!         Some(x) => downstream(chan.first().unwrap().send(mpsc::Value(x)).unwrap().zero()),
+//!         None => {
+//!             chan.second().unwrap().close();
+//!             Step::done(())
+//!         },
+//!     })
+//! }
+//!
+//! let (server, up) = mpsc::session_channel::<Rec<Feed>>();
+//! let (down, client) = mpsc::session_channel::<Rec<Feed>>();
+//! let server = thread::spawn(move || server.send_all((1 .. 4).map(mpsc::Value)).unwrap().close());
+//! let client = thread::spawn(move || {
+//!     let mut values = client.recv_iter();
+//!     let received: Vec<u32> = values.by_ref().map(|mpsc::Value(x)| x).collect();
+//!     values.finish().unwrap().close();
+//!     received
+//! });
+//!
+//! let mut relay = Relay::default();
+//! interleave::interleave(&mut relay, upstream(up.enter()), downstream(down.enter()));
+//! server.join().unwrap();
+//! assert_eq!(client.join().unwrap(), [10, 20, 30]);
+//! ```
+use std::thread;
+use std::time::Duration;
+use super::select::Selectable;
+
+const MIN_PAUSE: Duration = Duration::from_micros(10);
+const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+/// A session suspended until its channel is ready.
+trait Suspended<'a, S, T> {
+    fn ready(&mut self, state: &S) -> bool;
+    fn resume(self: Box<Self>, state: &mut S) -> Step<'a, S, T>;
+}
+
+struct Waiting<C, R, F> {
+    chan: C,
+    ready: R,
+    then: F,
+}
+
+impl<'a, S, T, C, R, F> Suspended<'a, S, T> for Waiting<C, R, F>
+    where R: FnMut(&mut C, &S) -> bool, F: FnOnce(C, &mut S) -> Step<'a, S, T>
+{
+    fn ready(&mut self, state: &S) -> bool {
+        (self.ready)(&mut self.chan, state)
+    }
+
+    fn resume(self: Box<Self>, state: &mut S) -> Step<'a, S, T> {
+        (self.then)(self.chan, state)
+    }
+}
+
+enum Progress<'a, S, T> {
+    Waiting(Box<dyn Suspended<'a, S, T> + 'a>),
+    Done(T),
+}
+
+/// Next step of a session run by `Interleave`, sharing the state `S`, and
+/// completing with a `T`.
+pub struct Step<'a, S, T>(Progress<'a, S, T>);
+
+impl<'a, S, T> Step<'a, S, T> {
+    /// The session has completed with `value`.
+    pub fn done(value: T) -> Step<'a, S, T> {
+        Step(Progress::Done(value))
+    }
+
+    /// Continue with `then` once `chan` is ready, i.e. its next `recv` or
+    /// `offer` (or `send` over a bounded carrier) would not block.
+    pub fn wait<C, F>(chan: C, then: F) -> Step<'a, S, T>
+        where C: Selectable + 'a, F: FnOnce(C, &mut S) -> Step<'a, S, T> + 'a, S: 'a, T: 'a
+    {
+        Step::wait_for(chan, |chan: &mut C, _: &S| chan.ready(), then)
+    }
+
+    /// Continue with `then` once `ready` holds for the channel and the
+    /// shared state, e.g. when the other session has left something to
+    /// send.
+    pub fn wait_for<C, R, F>(chan: C, ready: R, then: F) -> Step<'a, S, T>
+        where C: 'a, R: FnMut(&mut C, &S) -> bool + 'a, F: FnOnce(C, &mut S) -> Step<'a, S, T> + 'a, S: 'a, T: 'a
+    {
+        Step(Progress::Waiting(Box::new(Waiting { chan, ready, then })))
+    }
+}
+
+/// A set of sessions run on the current thread, see the module
+/// documentation.
+pub struct Interleave<'a, S, T> {
+    sessions: Vec<Option<Progress<'a, S, T>>>,
+}
+
+impl<'a, S, T> Default for Interleave<'a, S, T> {
+    fn default() -> Interleave<'a, S, T> {
+        Interleave { sessions: Vec::new() }
+    }
+}
+
+impl<'a, S, T> Interleave<'a, S, T> {
+    pub fn new() -> Interleave<'a, S, T> {
+        Interleave::default()
+    }
+
+    /// Add a session starting with `step`, returning the index of its
+    /// result.
+    pub fn add(&mut self, step: Step<'a, S, T>) -> usize {
+        self.sessions.push(Some(step.0));
+        self.sessions.len() - 1
+    }
+
+    /// Number of sessions in the set.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Run all the sessions until they complete, returning their results in
+    /// the order they have been added.
+    ///
+    /// Blocks forever if none of the pending sessions ever becomes ready.
+    pub fn run(mut self, state: &mut S) -> Vec<T> {
+        let mut next = 0;
+        let mut pause = MIN_PAUSE;
+        while let Some(index) = self.pending_from(next) {
+            match self.poll(index, state) {
+                Some(index) => {
+                    next = index + 1;
+                    pause = MIN_PAUSE;
+                },
+                None => {
+                    thread::sleep(pause);
+                    pause = (pause * 2).min(MAX_PAUSE);
+                },
+            }
+        }
+        self.sessions
+            .into_iter()
+            .map(|session| match session {
+                Some(Progress::Done(value)) => value,
+                _ => unreachable!("all the sessions are done"),
+            })
+            .collect()
+    }
+
+    /// Index of the first pending session, starting at `start` round robin.
+    fn pending_from(&self, start: usize) -> Option<usize> {
+        let len = self.sessions.len();
+        (0 .. len)
+            .map(|offset| (start + offset) % len)
+            .find(|&index| matches!(self.sessions[index], Some(Progress::Waiting(..))))
+    }
+
+    /// Resume the first ready session, starting at `start` round robin,
+    /// returning its index.
+    fn poll(&mut self, start: usize, state: &mut S) -> Option<usize> {
+        let len = self.sessions.len();
+        for index in (0 .. len).map(|offset| (start + offset) % len) {
+            let ready = match self.sessions[index] {
+                Some(Progress::Waiting(ref mut session)) => session.ready(state),
+                _ => false,
+            };
+            if ready {
+                if let Some(Progress::Waiting(session)) = self.sessions[index].take() {
+                    self.sessions[index] = Some(session.resume(state).0);
+                }
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+/// Run two sessions on the current thread until both of them complete.
+pub fn interleave<'a, S, T>(state: &mut S, first: Step<'a, S, T>, second: Step<'a, S, T>) -> (T, T) {
+    let mut sessions = Interleave::new();
+    sessions.add(first);
+    sessions.add(second);
+    let mut results = sessions.run(state).into_iter();
+    match (results.next(), results.next()) {
+        (Some(first), Some(second)) => (first, second),
+        _ => unreachable!("both sessions are done"),
+    }
+}
@@ -0,0 +1,98 @@
+//! Composable carrier middleware.
+//!
+//! Carrier wrappers, such as `trace::TracedCarrier`, `observe::ObservedCarrier`
+//! or `fault::FaultyCarrier`, are applied by layers implementing
+//! `CarrierLayer`, in the manner of tower's `Layer`. A layer holds the
+//! configuration of its wrapper and could wrap any number of carriers, e.g.
+//! one per accepted connection, so a stack of middleware is configured once
+//! and composes as `outer.layer(inner.layer(carrier))`, or as a single
+//! `Stack` layer. Any `Fn(C) -> W` closure is a layer as well.
+//!
+//! Every wrapper passes the values implementing `Payload` (all the value
+//! types of the crate and `session_message!` types) through to the carrier
+//! beneath, so such a value is transferable over a stack as long as each
+//! wrapper of it accepts the carrier beneath and the value:
+//!
+//! * deadline, heartbeat, cancel and shutdown wrappers need a
+//!   `timeout::TimeoutCarrier` beneath, which all wrappers are in turn when
+//!   their carriers are;
+//! * `observe::ObservedCarrier` needs an `observe::Metered` carrier beneath;
+//! * `tee::TeeCarrier`, `ack::AckCarrier` and
+//!   `reconnect::ReconnectingCarrier` keep copies of the values, so these
+//!   should be `Clone`;
+//! * `ratelimit::RateLimitedCarrier` needs values implementing
+//!   `ratelimit::Weigh`.
+//!
+//! ```
+//! use std::sync::mpsc::channel;
+//! use std::time::Duration;
+//! use session_types_ng::{Chan, Send, Recv, End, mpsc};
+//! use session_types_ng::layer::{CarrierLayer, Stack};
+//! use session_types_ng::fault::{FaultLayer, FaultPolicy};
+//! use session_types_ng::tee::{TeeLayer, Record};
+//! use session_types_ng::timeout::DeadlineLayer;
+//!
+//! let (audit, records) = channel();
+//! let middleware = Stack::new(DeadlineLayer::new(Duration::from_secs(5)), TeeLayer::new(audit));
+//!
+//! let (a, b) = mpsc::session_channel::<End>();
+//! let a: Chan<_, (), Send<mpsc::Value<u8>, End>> = Chan::new(middleware.layer(a.shutdown()));
+//! let deadline = DeadlineLayer::new(Duration::from_secs(5));
+//! let faults = FaultLayer::new(FaultPolicy::new(7));
+//! let b: Chan<_, (), Recv<mpsc::Value<u8>, End>> = Chan::new(faults.layer(deadline.layer(b.shutdown())));
+//! a.send(mpsc::Value(1)).unwrap().close();
+//! let (b, mpsc::Value(x)) = b.recv().unwrap();
+//! b.close();
+//! assert_eq!(x, 1);
+//! assert!(matches!(records.recv().unwrap(), Record::Value { .. }));
+//! ```
+
+/// Carrier middleware wrapping a carrier `C` into `Self::Carrier`.
+pub trait CarrierLayer<C> {
+    type Carrier;
+
+    fn layer(&self, inner: C) -> Self::Carrier;
+}
+
+/// Any function from a carrier to its wrapper is a layer.
+impl<C, W, F> CarrierLayer<C> for F where F: Fn(C) -> W {
+    type Carrier = W;
+
+    fn layer(&self, inner: C) -> W {
+        self(inner)
+    }
+}
+
+/// The layer leaving carriers as they are.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Identity;
+
+impl<C> CarrierLayer<C> for Identity {
+    type Carrier = C;
+
+    fn layer(&self, inner: C) -> C {
+        inner
+    }
+}
+
+/// Two layers applied one after another: `inner` wraps the carrier first,
+/// and `outer` wraps the result.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    pub fn new(inner: Inner, outer: Outer) -> Stack<Inner, Outer> {
+        Stack { inner, outer }
+    }
+}
+
+impl<C, Inner, Outer> CarrierLayer<C> for Stack<Inner, Outer> where Inner: CarrierLayer<C>, Outer: CarrierLayer<Inner::Carrier> {
+    type Carrier = Outer::Carrier;
+
+    fn layer(&self, inner: C) -> Outer::Carrier {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
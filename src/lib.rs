@@ -2,40 +2,264 @@
 //!
 //! This is an implementation of *session types* in Rust.
 //! ```
+#![allow(clippy::double_must_use, clippy::type_complexity)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "stream")]
+extern crate serde;
+#[cfg(feature = "stream")]
+extern crate bincode;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "protobuf")]
+extern crate prost;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "archive")]
+extern crate rkyv;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
+#[cfg(feature = "derive")]
+extern crate session_types_ng_derive;
+#[cfg(feature = "serial")]
+extern crate embedded_hal_nb;
+#[cfg(feature = "serial")]
+extern crate heapless;
+#[cfg(feature = "web")]
+extern crate wasm_bindgen;
+#[cfg(feature = "web")]
+extern crate js_sys;
+#[cfg(feature = "web")]
+extern crate web_sys;
+#[cfg(feature = "noise")]
+extern crate snow;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_sink;
+
+/// Without `std` feature paths to `std` are resolved to `core` and `alloc`,
+/// so that modules not depending on the standard library are the same in both builds.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+    pub use alloc::fmt;
+
+    /// Items of the standard prelude missing from the `core` one.
+    pub mod prelude {
+        pub use alloc::boxed::Box;
+        pub use alloc::string::{String, ToString};
+        pub use alloc::vec::Vec;
+    }
+}
+
 use std::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{Sender, Receiver, SendError};
+
+#[macro_use]
+mod macros;
 
+#[cfg(feature = "std")]
 pub mod mpsc;
+#[cfg(feature = "std")]
+pub mod bytes;
+#[cfg(feature = "std")]
+pub mod timeout;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "std")]
+pub mod heartbeat;
+#[cfg(feature = "std")]
+pub mod control;
+#[cfg(feature = "std")]
+pub mod shutdown;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+#[cfg(feature = "std")]
+pub mod fault;
+#[cfg(feature = "std")]
+pub mod select;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod supervise;
+#[cfg(feature = "std")]
+pub mod reconnect;
+#[cfg(feature = "std")]
+pub mod ack;
+#[cfg(feature = "std")]
+pub mod rpc;
+#[cfg(feature = "std")]
+pub mod pubsub;
+#[cfg(feature = "std")]
+pub mod par;
+#[cfg(feature = "std")]
+pub mod interleave;
+pub mod abort;
+pub mod poll;
+#[cfg(feature = "futures")]
+pub mod futures;
+pub mod handler;
+pub mod forward;
+pub mod auth;
+pub mod layer;
+pub mod subtype;
+#[cfg(feature = "std")]
+pub mod multiparty;
+pub mod broadcast;
+pub mod visualize;
+pub mod mermaid;
+pub mod scribble;
+pub mod schema;
+pub mod resume;
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "registry")]
+pub mod sessions;
+#[cfg(feature = "deadlock")]
+pub mod deadlock;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod observe;
+#[cfg(feature = "std")]
+pub mod tee;
+#[cfg(feature = "noise")]
+pub mod noise;
+pub mod reflect;
+pub mod fingerprint;
+#[cfg(feature = "stream")]
+pub mod wire;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "stream")]
+pub mod negotiate;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+#[cfg(feature = "archive")]
+pub mod archive;
+
+#[cfg(feature = "derive")]
+pub use session_types_ng_derive::SessionMessage;
 
 /// In order to support sending via session channel a value
-/// should implement `ChannelSend` trait.
-pub trait ChannelSend {
-    type Crr;
+/// should implement `ChannelSend` trait for the carrier `Crr`.
+pub trait ChannelSend<Crr> {
     type Err;
 
-    fn send(self, carrier: &mut Self::Crr) -> Result<(), Self::Err>;
+    fn send(self, carrier: &mut Crr) -> Result<(), Self::Err>;
 }
 
 /// In order to support receiving via session channel a value
-/// should implement `ChannelRecv` trait.
-pub trait ChannelRecv: Sized {
-    type Crr;
+/// should implement `ChannelRecv` trait for the carrier `Crr`.
+pub trait ChannelRecv<Crr>: Sized {
     type Err;
 
-    fn recv(carrier: &mut Self::Crr) -> Result<Self, Self::Err>;
+    fn recv(carrier: &mut Crr) -> Result<Self, Self::Err>;
 }
 
+/// Marker of the types transferred as values of a session. Carrier
+/// wrappers, such as `timeout::DeadlineCarrier`, pass any payload through
+/// to the carrier beneath, so a payload transferable over a carrier is
+/// transferable over any stack of wrappers on top of it. `session_message!`
+/// implements it for user defined messages.
+pub trait Payload { }
+
+impl<SR, E, P> Payload for Chan<SR, E, P> { }
+
 pub trait Carrier: Sized {
     type SendChoiceErr;
     fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr>;
 
     type RecvChoiceErr;
     fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr>;
+
+    /// Notification that a session over this carrier has moved to the
+    /// protocol step `protocol` (its type name). Does nothing by default.
+    fn step(&mut self, _protocol: &'static str) { }
+}
+
+/// A carrier able to exchange a handover mark, see `Chan::migrate`: a
+/// message of its own kind, told apart from any choice or value, so that no
+/// protocol step could produce it.
+pub trait HandoverCarrier: Carrier {
+    fn send_handover(&mut self) -> Result<(), Self::SendChoiceErr>;
+
+    /// Receive the next message of the peer, returning whether it is the
+    /// handover mark. After any other message the carrier should be
+    /// dropped, as the session could not continue.
+    fn recv_handover(&mut self) -> Result<bool, Self::RecvChoiceErr>;
+}
+
+/// A carrier able to buffer outgoing messages, so that a burst of sends
+/// results in a single write or wakeup. Buffered messages are also flushed
+/// before every receive, so the peer is never left waiting for them.
+pub trait BatchCarrier: Carrier {
+    type FlushErr;
+
+    /// Switch batching mode. Messages buffered so far are flushed.
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr>;
+
+    /// Deliver all buffered messages.
+    fn flush(&mut self) -> Result<(), Self::FlushErr>;
 }
 
 /// A session for a session typed channel.
 /// `P` is the protocol
 /// `E` is the environment, containing potential recursion targets
-pub struct Session<E, P>(PhantomData<(E, P)>);
+pub struct Session<E, P> {
+    #[cfg(feature = "registry")]
+    id: u64,
+    marker: PhantomData<(E, P)>,
+}
+
+impl<E, P> Session<E, P> {
+    fn new() -> Session<E, P> {
+        Session {
+            #[cfg(feature = "registry")]
+            id: sessions::register(std::any::type_name::<P>()),
+            marker: PhantomData,
+        }
+    }
+
+    /// Move the session to another protocol state.
+    fn cast<EB, PB>(self) -> Session<EB, PB> {
+        #[cfg(feature = "registry")]
+        sessions::update(self.id, std::any::type_name::<P>(), std::any::type_name::<PB>());
+        let session = Session {
+            #[cfg(feature = "registry")]
+            id: self.id,
+            marker: PhantomData,
+        };
+        std::mem::forget(self);
+        session
+    }
+
+    /// Finish the session bypassing the drop bomb.
+    fn finish(self) {
+        #[cfg(feature = "registry")]
+        sessions::unregister(self.id);
+        std::mem::forget(self);
+    }
+}
 
 /// A session typed channel.
 /// `SR` is the carrier channel for actual sending and receiving
@@ -54,6 +278,23 @@ pub struct Z;
 /// Peano numbers: Increment
 pub struct S<N>(PhantomData<N>);
 
+/// Runtime value of a Peano number
+pub trait Nat {
+    fn value() -> usize;
+}
+
+impl Nat for Z {
+    fn value() -> usize {
+        0
+    }
+}
+
+impl<N: Nat> Nat for S<N> {
+    fn value() -> usize {
+        N::value() + 1
+    }
+}
+
 /// End of communication session
 #[allow(missing_copy_implementations)]
 pub struct End;
@@ -74,6 +315,28 @@ pub struct Choose<P, L>(PhantomData<(P, L)>);
 /// Passive choice (offer) between `P` and protocols in the list `L`
 pub struct Offer<P, L>(PhantomData<(P, L)>);
 
+/// Branch `P` of a `Choose` or `Offer` list tagged by a label type `Lbl`
+pub struct Labeled<Lbl, P>(PhantomData<(Lbl, P)>);
+
+/// Optionally send `A`, then `R`
+pub type SendOpt<A, R> = Choose<Send<A, R>, Choose<R, Nil>>;
+
+/// Optionally receive `A`, then `R` (dual of `SendOpt`)
+pub type RecvOpt<A, R> = Offer<Recv<A, R>, Offer<R, Nil>>;
+
+/// Branch of a choose list carrying payload `A`, then `P`; `L` is the rest
+/// of the list ending with `Nil`
+pub type ChooseWith<A, P, L> = Choose<Send<A, P>, L>;
+
+/// Branch of an offer list carrying payload `A`, then `P` (dual of `ChooseWith`)
+pub type OfferWith<A, P, L> = Offer<Recv<A, P>, L>;
+
+/// Run protocol `P` until its `End`, then continue with `Q`
+pub struct Seq<P, Q>(PhantomData<(P, Q)>);
+
+/// Run protocol `P` exactly `N` (Peano number) times, then continue with `R`
+pub struct Repeat<N, P, R>(PhantomData<(N, P, R)>);
+
 /// Enter a recursive environment
 pub struct Rec<P>(PhantomData<P>);
 
@@ -81,10 +344,38 @@ pub struct Rec<P>(PhantomData<P>);
 /// out of.
 pub struct Var<N>(PhantomData<N>);
 
+/// Enter a recursive environment named by a label type `Lbl`
+pub struct Loop<Lbl, P>(PhantomData<(Lbl, P)>);
+
+/// Recurse to the enclosing `Loop` named `Lbl`, leaving all the inner ones.
+pub struct Goto<Lbl>(PhantomData<Lbl>);
+
+/// Protocol duality relation.
+///
+/// # Safety
+///
+/// `Dual` must be the exact mirror image of the implementing protocol,
+/// otherwise two connected endpoints may disagree on the message types.
 pub unsafe trait HasDual {
     type Dual;
 }
 
+/// Dual protocol of `P`, e.g. `type Client = Dual<Server>;`
+pub type Dual<P> = <P as HasDual>::Dual;
+
+/// Dual protocol of the given protocol type, usable in type position:
+/// `type Client = dual!(Server);`
+#[macro_export]
+macro_rules! dual {
+    ($p:ty) => { <$p as $crate::HasDual>::Dual };
+}
+
+/// Duality witness: implemented for `Q` when `Q` is the dual of `P`.
+#[diagnostic::on_unimplemented(message = "protocol `{Self}` is not dual to `{P}`")]
+pub trait DualOf<P> { }
+
+impl<P, Q> DualOf<P> for Q where P: HasDual<Dual = Q> { }
+
 unsafe impl HasDual for End {
     type Dual = End;
 }
@@ -121,21 +412,189 @@ unsafe impl<P: HasDual> HasDual for Rec<P> {
     type Dual = Rec<P::Dual>;
 }
 
+unsafe impl<Lbl, P: HasDual> HasDual for Loop<Lbl, P> {
+    type Dual = Loop<Lbl, P::Dual>;
+}
+
+unsafe impl<Lbl> HasDual for Goto<Lbl> {
+    type Dual = Goto<Lbl>;
+}
+
+unsafe impl<Lbl, P: HasDual> HasDual for Labeled<Lbl, P> {
+    type Dual = Labeled<Lbl, P::Dual>;
+}
+
+unsafe impl<P: HasDual, Q: HasDual> HasDual for Seq<P, Q> {
+    type Dual = Seq<P::Dual, Q::Dual>;
+}
+
+unsafe impl<N, P: HasDual, R: HasDual> HasDual for Repeat<N, P, R> {
+    type Dual = Repeat<N, P::Dual, R::Dual>;
+}
+
+/// Protocol `Self` with every `End` replaced by `Q`.
+///
+/// Note that `Q` is placed under `Rec` layers of `Self`, so `Var` in `Q`
+/// should not refer to recursion outside of `Seq`.
+pub trait Then<Q> {
+    type Output;
+}
+
+impl<Q> Then<Q> for End {
+    type Output = Q;
+}
+
+impl<A, P: Then<Q>, Q> Then<Q> for Send<A, P> {
+    type Output = Send<A, P::Output>;
+}
+
+impl<A, P: Then<Q>, Q> Then<Q> for Recv<A, P> {
+    type Output = Recv<A, P::Output>;
+}
+
+impl<Q> Then<Q> for Nil {
+    type Output = Nil;
+}
+
+impl<P: Then<Q>, L: Then<Q>, Q> Then<Q> for Choose<P, L> {
+    type Output = Choose<P::Output, L::Output>;
+}
+
+impl<P: Then<Q>, L: Then<Q>, Q> Then<Q> for Offer<P, L> {
+    type Output = Offer<P::Output, L::Output>;
+}
+
+impl<Lbl, P: Then<Q>, Q> Then<Q> for Labeled<Lbl, P> {
+    type Output = Labeled<Lbl, P::Output>;
+}
+
+impl<P: Then<Q>, Q> Then<Q> for Rec<P> {
+    type Output = Rec<P::Output>;
+}
+
+impl<N, Q> Then<Q> for Var<N> {
+    type Output = Var<N>;
+}
+
+impl<Lbl, P: Then<Q>, Q> Then<Q> for Loop<Lbl, P> {
+    type Output = Loop<Lbl, P::Output>;
+}
+
+impl<Lbl, Q> Then<Q> for Goto<Lbl> {
+    type Output = Goto<Lbl>;
+}
+
+impl<P, R: Then<Q>, Q> Then<Q> for Seq<P, R> {
+    type Output = Seq<P, R::Output>;
+}
+
+impl<N, P, R: Then<Q>, Q> Then<Q> for Repeat<N, P, R> {
+    type Output = Repeat<N, P, R::Output>;
+}
+
+#[cfg(feature = "std")]
+static DROP_HANDLER: Mutex<Option<fn(&'static str)>> = Mutex::new(None);
+
+/// Install a handler invoked with the protocol type name whenever a session
+/// channel is dropped before reaching `End`, instead of the default one.
+///
+/// Default handler panics in debug builds and reports to stderr in release
+/// builds. Abandoned sessions are not reported while a thread is panicking.
+#[cfg(feature = "std")]
+pub fn set_drop_handler(handler: fn(protocol: &'static str)) {
+    *DROP_HANDLER.lock().unwrap_or_else(|e| e.into_inner()) = Some(handler);
+}
+
 impl<E, P> Drop for Session<E, P> {
     fn drop(&mut self) {
-        panic!("Session prematurely dropped");
+        #[cfg(feature = "registry")]
+        sessions::unregister(self.id);
+        let protocol = std::any::type_name::<P>();
+        if protocol != std::any::type_name::<End>() {
+            report_dropped(protocol);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    /// Set while a value is dropped on purpose, see `discard`.
+    static DISCARDING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Drop a value on purpose: the sessions it holds are closed without being
+/// reported as abandoned, so their peers fail instead of waiting.
+#[cfg(feature = "std")]
+pub(crate) fn discard<T>(value: T) {
+    let discarding = DISCARDING.with(|flag| flag.replace(true));
+    drop(value);
+    DISCARDING.with(|flag| flag.set(discarding));
+}
+
+#[cfg(feature = "std")]
+fn report_dropped(protocol: &'static str) {
+    if std::thread::panicking() || DISCARDING.with(|flag| flag.get()) {
+        return;
+    }
+    let handler = *DROP_HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+    match handler {
+        Some(handler) =>
+            handler(protocol),
+        None if cfg!(debug_assertions) =>
+            panic!("Session prematurely dropped at {}", protocol),
+        None =>
+            eprintln!("Session prematurely dropped at {}", protocol),
+    }
+}
+
+/// Without `std` abandoned sessions panic in debug builds and are ignored in
+/// release builds, as there is nowhere to report them to.
+#[cfg(not(feature = "std"))]
+fn report_dropped(protocol: &'static str) {
+    if cfg!(debug_assertions) {
+        panic!("Session prematurely dropped at {}", protocol);
     }
 }
 
 impl<SR, E, P> Chan<SR, E, P> {
     pub fn new(carrier: SR) -> Chan<SR, E, P> {
         Chan {
-            carrier: carrier,
-            session: Session(PhantomData),
+            carrier,
+            session: Session::new(),
         }
     }
 }
 
+impl<SR, E, P> Chan<SR, E, P> where SR: BatchCarrier {
+    /// Switch batching mode of the carrier: subsequent sends are buffered
+    /// until `flush` or the next receive.
+    ///
+    /// ```
+    /// use session_types_ng::{Send, Recv, End, mpsc};
+    ///
+    /// type Burst = Send<mpsc::Value<u8>, Send<mpsc::Value<u8>, Recv<mpsc::Value<u8>, End>>>;
+    ///
+    /// let (mut a, b) = mpsc::session_channel::<Burst>();
+    /// a.set_batching(true).unwrap();
+    /// let mut a = a.send(mpsc::Value(1)).unwrap().send(mpsc::Value(2)).unwrap();
+    /// a.flush().unwrap();
+    /// let (b, mpsc::Value(x)) = b.recv().unwrap();
+    /// let (b, mpsc::Value(y)) = b.recv().unwrap();
+    /// b.send(mpsc::Value(x + y)).unwrap().close();
+    /// let (a, mpsc::Value(sum)) = a.recv().unwrap();
+    /// a.close();
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn set_batching(&mut self, enabled: bool) -> Result<(), SR::FlushErr> {
+        self.carrier.set_batching(enabled)
+    }
+
+    /// Deliver all the messages buffered in batching mode.
+    pub fn flush(&mut self) -> Result<(), SR::FlushErr> {
+        self.carrier.flush()
+    }
+}
+
 impl<SR, E> Chan<SR, E, End> {
     /// Close a channel. Should always be used at the end of your program.
     pub fn close(self) {
@@ -146,25 +605,169 @@ impl<SR, E> Chan<SR, E, End> {
 
     /// Same as `close`, but keep underlying carrier alive.
     pub fn shutdown(self) -> SR {
-        std::mem::forget(self.session);
+        self.session.finish();
         self.carrier
     }
+
+    /// alias to `shutdown` method: the recovered carrier could host a
+    /// subsequent session without reconnecting.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Send<mpsc::Value<u8>, End>>();
+    /// let tx = tx.send(mpsc::Value(1)).unwrap().close_and_recover();
+    /// let (rx, _) = rx.recv().unwrap();
+    /// let rx = rx.close_and_recover();
+    ///
+    /// let tx: Chan<_, (), Recv<mpsc::Value<u8>, End>> = Chan::new(tx);
+    /// let rx: Chan<_, (), Send<mpsc::Value<u8>, End>> = Chan::new(rx);
+    /// rx.send(mpsc::Value(2)).unwrap().close();
+    /// let (tx, mpsc::Value(v)) = tx.recv().unwrap();
+    /// assert_eq!(v, 2);
+    /// tx.close();
+    /// ```
+    pub fn close_and_recover(self) -> SR {
+        self.shutdown()
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Give up the session at any step: the carrier is closed, so that the
+    /// next operation of the other side fails instead of waiting forever.
+    /// Used by `try_session!` when an application error interrupts a
+    /// protocol without an abort branch.
+    pub fn poison(self) {
+        close_chan(self);
+    }
+}
+
+/// Failure of `Chan::migrate` or `Chan::migrate_with`. The old carrier
+/// errors are `S` and `R`, and the new carrier ones are `NS` and `NR`.
+#[derive(Debug)]
+pub enum MigrateError<S, R, NS, NR> {
+    SendChoice(S),
+    RecvChoice(R),
+    /// The peer has sent something else instead of the handover mark, e.g.
+    /// it has not migrated at the same protocol step but made a choice.
+    Mismatch,
+    NewSendChoice(NS),
+    NewRecvChoice(NR),
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: HandoverCarrier {
+    /// Transfer the session to another carrier. Both sides should migrate
+    /// at the same protocol step: a handover mark is exchanged over the old
+    /// carrier, so nothing sent before is left in flight, and then over the
+    /// new one, so that the peer is known to have switched as well. Returns
+    /// the channel over the new carrier together with the old carrier.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Choose, Send, End, Nil, MigrateError, mpsc};
+    ///
+    /// let (a, b) = mpsc::session_channel::<Send<mpsc::Value<u8>, End>>();
+    /// let (new_a, new_b) = mpsc::session_channel::<End>();
+    /// let (new_a, new_b) = (new_a.shutdown(), new_b.shutdown());
+    /// let peer = thread::spawn(move || {
+    ///     let (b, _) = b.migrate(new_b).unwrap();
+    ///     let (b, mpsc::Value(x)) = b.recv().unwrap();
+    ///     b.close();
+    ///     x
+    /// });
+    /// let (a, _) = a.migrate(new_a).unwrap();
+    /// a.send(mpsc::Value(7)).unwrap().close();
+    /// assert_eq!(peer.join().unwrap(), 7);
+    ///
+    /// // the peer selects a branch instead of migrating
+    /// let (a, b) = mpsc::session_channel::<Choose<End, Choose<End, Nil>>>();
+    /// let _a = a.first().unwrap().shutdown();
+    /// let (new_b, _) = mpsc::session_channel::<End>();
+    /// match b.migrate(new_b.shutdown()) {
+    ///     Err(MigrateError::Mismatch) => (),
+    ///     _ => panic!("the peer has not migrated"),
+    /// }
+    ///
+    /// let (a, b) = mpsc::session_channel::<Choose<End, Choose<End, Nil>>>();
+    /// let _a = a.second().unwrap().shutdown();
+    /// let (new_b, _) = mpsc::session_channel::<End>();
+    /// match b.migrate(new_b.shutdown()) {
+    ///     Err(MigrateError::Mismatch) => (),
+    ///     _ => panic!("the peer has not migrated"),
+    /// }
+    /// ```
+    #[must_use]
+    pub fn migrate<C2>(self, carrier: C2) -> Result<(Chan<C2, E, P>, SR), MigrateError<SR::SendChoiceErr, SR::RecvChoiceErr, C2::SendChoiceErr, C2::RecvChoiceErr>>
+        where C2: HandoverCarrier
+    {
+        let mut old = None;
+        let chan = self.migrate_with(|prev| {
+            old = Some(prev);
+            carrier
+        })?;
+        Ok((chan, old.unwrap()))
+    }
+
+    /// Same as `migrate`, but the new carrier is made from the old one,
+    /// e.g. by wrapping a plaintext stream into TLS.
+    #[must_use]
+    pub fn migrate_with<C2, F>(mut self, upgrade: F) -> Result<Chan<C2, E, P>, MigrateError<SR::SendChoiceErr, SR::RecvChoiceErr, C2::SendChoiceErr, C2::RecvChoiceErr>>
+        where C2: HandoverCarrier,
+              F: FnOnce(SR) -> C2
+    {
+        if let Err(e) = self.carrier.send_handover() {
+            close_chan(self);
+            return Err(MigrateError::SendChoice(e));
+        }
+        match self.carrier.recv_handover() {
+            Ok(true) =>
+                (),
+            Ok(false) => {
+                close_chan(self);
+                return Err(MigrateError::Mismatch);
+            },
+            Err(e) => {
+                close_chan(self);
+                return Err(MigrateError::RecvChoice(e));
+            },
+        }
+        let mut chan = Chan {
+            carrier: upgrade(self.carrier),
+            session: self.session,
+        };
+        if let Err(e) = chan.carrier.send_handover() {
+            close_chan(chan);
+            return Err(MigrateError::NewSendChoice(e));
+        }
+        match chan.carrier.recv_handover() {
+            Ok(true) =>
+                Ok(chan),
+            Ok(false) => {
+                close_chan(chan);
+                Err(MigrateError::Mismatch)
+            },
+            Err(e) => {
+                close_chan(chan);
+                Err(MigrateError::NewRecvChoice(e))
+            },
+        }
+    }
 }
 
 fn close_chan<SR, E, P>(chan: Chan<SR, E, P>) {
     drop(chan.carrier);
-    std::mem::forget(chan.session);
+    chan.session.finish();
 }
 
-fn cast_chan<SR, EA, EB, PA, PB>(chan: Chan<SR, EA, PA>) -> Chan<SR, EB, PB> {
-    std::mem::forget(chan.session);
+fn cast_chan<SR, EA, EB, PA, PB>(mut chan: Chan<SR, EA, PA>) -> Chan<SR, EB, PB> where SR: Carrier {
+    chan.carrier.step(std::any::type_name::<PB>());
     Chan {
         carrier: chan.carrier,
-        session: Session(PhantomData),
+        session: chan.session.cast(),
     }
 }
 
-impl<SR, E, P, T> Chan<SR, E, Send<T, P>> where SR: Carrier, T: ChannelSend<Crr = SR> {
+impl<SR, E, P, T> Chan<SR, E, Send<T, P>> where SR: Carrier, T: ChannelSend<SR> {
     /// Send a value of type `T` over the channel. Returns a channel with
     /// protocol `P`
     #[must_use]
@@ -180,12 +783,30 @@ impl<SR, E, P, T> Chan<SR, E, Send<T, P>> where SR: Carrier, T: ChannelSend<Crr
     }
 }
 
-impl<SR, E, P, T> Chan<SR, E, Recv<T, P>> where SR: Carrier, T: ChannelRecv<Crr = SR> {
+impl<SR, E, P, T> Chan<SR, E, Recv<T, P>> where SR: Carrier, T: ChannelRecv<SR> {
     /// Receives a value of type `T` from the channel. Returns a tuple
     /// containing the resulting channel and the received value.
+    ///
+    /// If the peer hangs up or the carrier fails, the error of the carrier
+    /// is returned and the channel is closed, see `resume` to keep it.
+    ///
+    /// ```
+    /// use std::net::{TcpListener, TcpStream};
+    /// use session_types_ng::{Chan, Recv, End};
+    /// use session_types_ng::stream::{self, Value};
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// drop(TcpStream::connect(listener.local_addr().unwrap()).unwrap());
+    ///
+    /// let chan: Chan<_, (), Recv<Value<u8>, End>> = stream::session_channel(listener.accept().unwrap().0);
+    /// match chan.recv() {
+    ///     Err(stream::Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+    ///     _ => panic!("the peer has hung up"),
+    /// }
+    /// ```
     #[must_use]
     pub fn recv(mut self) -> Result<(Chan<SR, E, P>, T), T::Err> {
-        match <T as ChannelRecv>::recv(&mut self.carrier) {
+        match <T as ChannelRecv<SR>>::recv(&mut self.carrier) {
             Ok(v) =>
                 Ok((cast_chan(self), v)),
             Err(e) => {
@@ -238,47 +859,380 @@ impl<SR, E, P, Q, L> Chan<SR, E, Choose<P, Choose<Q, L>>> where SR: Carrier {
     }
 }
 
-impl<SR, Z, PA, PB, Q, L> Chan<SR, Z, Choose<PA, Choose<PB, Choose<Q, L>>>> where SR: Carrier {
+/// Lookup of the branch at Peano index `N` in a choose list.
+pub trait Nth<N> {
+    type Cont;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier;
+}
+
+impl<P, L> Nth<Z> for Choose<P, L> {
+    type Cont = P;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        carrier.send_choice(true)
+    }
+}
+
+impl<P, Q, L, N> Nth<S<N>> for Choose<P, Choose<Q, L>> where Choose<Q, L>: Nth<N> {
+    type Cont = <Choose<Q, L> as Nth<N>>::Cont;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        carrier.send_choice(false)?;
+        <Choose<Q, L> as Nth<N>>::select(carrier)
+    }
+}
+
+/// Tail of a choose list after skipping `N` (Peano number) branches.
+pub trait Skip<N> {
+    type Tail;
+
+    fn skip<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier;
+}
+
+impl<P, L> Skip<Z> for Choose<P, L> {
+    type Tail = Choose<P, L>;
+
+    fn skip<SR>(_carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        Ok(())
+    }
+}
+
+impl<P, Q, L, N> Skip<S<N>> for Choose<P, Choose<Q, L>> where Choose<Q, L>: Skip<N> {
+    type Tail = <Choose<Q, L> as Skip<N>>::Tail;
+
+    fn skip<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        carrier.send_choice(false)?;
+        <Choose<Q, L> as Skip<N>>::skip(carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Perform an active choice, selecting the branch at Peano index `N`
+    /// (counting from `Z`) of a choose list of any length in one call.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Choose, Offer, End, Nil, S, Z, mpsc};
+    ///
+    /// type Menu = Choose<End, Choose<End, Choose<End, Choose<End, Choose<End, Choose<End, Nil>>>>>>;
+    ///
+    /// let (client, server): (Chan<_, (), Menu>, _) = mpsc::session_channel();
+    /// client.nth::<S<S<S<S<S<Z>>>>>>().unwrap().close();
+    /// let picked = server.offer()
+    ///     .option(|c| { c.close(); 0 })
+    ///     .option(|c| { c.close(); 1 })
+    ///     .option(|c| { c.close(); 2 })
+    ///     .option(|c| { c.close(); 3 })
+    ///     .option(|c| { c.close(); 4 })
+    ///     .option(|c| { c.close(); 5 })
+    ///     .unwrap();
+    /// assert_eq!(picked, 5);
+    /// ```
+    #[must_use]
+    pub fn nth<N>(mut self) -> Result<Chan<SR, E, P::Cont>, SR::SendChoiceErr> where P: Nth<N> {
+        match P::select(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(e)
+            },
+        }
+    }
+
+    /// Perform an active choice, skipping `N` (Peano number) branches and
+    /// selecting the tail of the choose list.
+    #[must_use]
+    pub fn skip<N>(mut self) -> Result<Chan<SR, E, P::Tail>, SR::SendChoiceErr> where P: Skip<N> {
+        match P::skip(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(e)
+            },
+        }
+    }
+
+    /// Perform an active choice of the branch at Peano index `N` of a
+    /// `ChooseWith` list and send its payload in one call.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, ChooseWith, OfferWith, End, Nil, S, Z, mpsc};
+    ///
+    /// type Login = ChooseWith<mpsc::Value<String>, End, ChooseWith<mpsc::Value<u64>, End, Nil>>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Login>();
+    /// client.nth_send::<S<Z>, _, _>(mpsc::Value(0xdead)).unwrap().close();
+    /// let token = server.offer()
+    ///     .option_recv(|received| received.map(|(c, mpsc::Value(name))| { c.close(); name.len() as u64 }))
+    ///     .option_recv(|received| received.map(|(c, mpsc::Value(token))| { c.close(); token }))
+    ///     .unwrap();
+    /// assert_eq!(token.unwrap(), 0xdead);
+    /// ```
+    #[must_use]
+    pub fn nth_send<N, A, Q>(self, v: A) -> Result<Chan<SR, E, Q>, OptError<SR::SendChoiceErr, A::Err>>
+        where P: Nth<N, Cont = Send<A, Q>>, A: ChannelSend<SR>
+    {
+        self.nth()
+            .map_err(OptError::Choice)?
+            .send(v)
+            .map_err(OptError::Value)
+    }
+
     /// Convenience function. This is identical to `.cdr().cdr()`
     #[must_use]
-    pub fn cddr(self) -> Result<Chan<SR, Z, Choose<Q, L>>, SR::SendChoiceErr> {
-        self.cdr().and_then(|c| c.cdr())
+    pub fn cddr(self) -> Result<Chan<SR, E, P::Tail>, SR::SendChoiceErr> where P: Skip<S<S<Z>>> {
+        self.skip()
+    }
+
+    /// Convenience function. This is identical to `.cdr().cdr().cdr()`
+    #[must_use]
+    pub fn cdddr(self) -> Result<Chan<SR, E, P::Tail>, SR::SendChoiceErr> where P: Skip<S<S<S<Z>>>> {
+        self.skip()
+    }
+
+    /// Convenience function. This is identical to `.cdr().cdr().cdr().cdr()`
+    #[must_use]
+    pub fn cddddr(self) -> Result<Chan<SR, E, P::Tail>, SR::SendChoiceErr> where P: Skip<S<S<S<S<Z>>>>> {
+        self.skip()
     }
 
     /// Perform an active choice, selecting the third element of the choose list.
     #[must_use]
-    pub fn third(self) -> Result<Chan<SR, Z, Q>, SR::SendChoiceErr> {
-        self.cddr().and_then(|c| c.car())
+    pub fn third(self) -> Result<Chan<SR, E, P::Cont>, SR::SendChoiceErr> where P: Nth<S<S<Z>>> {
+        self.nth()
+    }
+
+    /// Perform an active choice, selecting the fourth element of the choose list.
+    #[must_use]
+    pub fn fourth(self) -> Result<Chan<SR, E, P::Cont>, SR::SendChoiceErr> where P: Nth<S<S<S<Z>>>> {
+        self.nth()
+    }
+
+    /// Perform an active choice, selecting the fifth element of the choose list.
+    #[must_use]
+    pub fn fifth(self) -> Result<Chan<SR, E, P::Cont>, SR::SendChoiceErr> where P: Nth<S<S<S<S<Z>>>>> {
+        self.nth()
     }
 }
 
-impl<SR, Z, PA, PB, PC, Q, L> Chan<SR, Z, Choose<PA, Choose<PB, Choose<PC, Choose<Q, L>>>>> where SR: Carrier {
-    /// Convenience function. This is identical to `.cdr().cdr().cdr()`
+/// An enum of the continuations of a choose list, one variant per branch,
+/// declared with `choice_enum!`.
+pub trait RuntimeChoice<SR, E>: Sized where SR: Carrier {
+    /// The choose list.
+    type Choice;
+
+    /// Number of branches.
+    const LEN: usize;
+
+    /// Select the branch `index`, which is less than `LEN`.
+    fn choose(chan: Chan<SR, E, Self::Choice>, index: usize) -> Result<Self, SR::SendChoiceErr>;
+}
+
+/// Failure of a choice made at runtime.
+#[derive(Debug)]
+pub enum SelRuntimeError<C, E> {
+    /// There is no branch with the index, the channel is returned intact.
+    OutOfRange(C),
+    /// Selection of the branch has failed.
+    Choice(E),
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Perform an active choice of the branch `index` determined at runtime,
+    /// e.g. by a dispatch table, returning the channel of the branch in the
+    /// corresponding variant of `C` (see `choice_enum!`).
     #[must_use]
-    pub fn cdddr(self) -> Result<Chan<SR, Z, Choose<Q, L>>, SR::SendChoiceErr> {
-        self.cddr().and_then(|c| c.cdr())
+    pub fn sel_runtime<C>(self, index: usize) -> Result<C, SelRuntimeError<Chan<SR, E, P>, SR::SendChoiceErr>>
+        where C: RuntimeChoice<SR, E, Choice = P>
+    {
+        if index >= C::LEN {
+            return Err(SelRuntimeError::OutOfRange(self));
+        }
+        C::choose(self, index).map_err(SelRuntimeError::Choice)
     }
+}
 
-    /// Perform an active choice, selecting the fourth element of the choose list.
+/// An enum of the branches of an offer list, one variant per branch with
+/// its channel and the payload received first, declared with `offer_enum!`.
+/// `V` is the error of receiving the payloads.
+pub trait OfferEnum<SR, E, V>: Sized where SR: Carrier {
+    /// The offer list.
+    type Offer;
+
+    /// Wait for the choice of the other side and receive its payload.
+    fn offer(chan: Chan<SR, E, Self::Offer>) -> Result<Self, OptError<SR::RecvChoiceErr, V>>;
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Passive choice handled with a single `match` over the variants of
+    /// `T` (see `offer_enum!`).
     #[must_use]
-    pub fn fourth(self) -> Result<Chan<SR, Z, Q>, SR::SendChoiceErr> {
-        self.cdddr().and_then(|c| c.car())
+    pub fn offer_enum<T, V>(self) -> Result<T, OptError<SR::RecvChoiceErr, V>> where T: OfferEnum<SR, E, V, Offer = P> {
+        T::offer(self)
     }
 }
 
-impl<SR, Z, PA, PB, PC, PD, Q, L>
-    Chan<SR, Z, Choose<PA, Choose<PB, Choose<PC, Choose<PD, Choose<Q, L>>>>>> where SR: Carrier
-{
-    /// Convenience function. This is identical to `.cdr().cdr().cdr().cdr()`
+/// Lookup of a branch labeled `Lbl` in a choose list. `I` is the Peano
+/// index of the branch, it is always inferred.
+pub trait Select<Lbl, I> {
+    type Cont;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier;
+}
+
+impl<Lbl, P, L> Select<Lbl, Z> for Choose<Labeled<Lbl, P>, L> {
+    type Cont = P;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        carrier.send_choice(true)
+    }
+}
+
+impl<Lbl, P, Q, L, I> Select<Lbl, S<I>> for Choose<P, Choose<Q, L>> where Choose<Q, L>: Select<Lbl, I> {
+    type Cont = <Choose<Q, L> as Select<Lbl, I>>::Cont;
+
+    fn select<SR>(carrier: &mut SR) -> Result<(), SR::SendChoiceErr> where SR: Carrier {
+        carrier.send_choice(false)?;
+        <Choose<Q, L> as Select<Lbl, I>>::select(carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Perform an active choice, selecting the branch labeled `Lbl` in one call,
+    /// e.g. `chan.sel::<Quit, _>()`. The second parameter `I` is the position
+    /// of the branch in the choose list, found by the compiler from the label,
+    /// so it is always written as `_`.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Choose, Offer, Labeled, Send, Recv, End, Nil, HasDual, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    ///
+    /// struct Ping;
+    /// struct Echo;
+    /// struct Quit;
+    ///
+    /// type Client = Choose<Labeled<Ping, End>,
+    ///               Choose<Labeled<Echo, Send<Value<u8>, Recv<Value<u8>, End>>>,
+    ///               Choose<Labeled<Quit, End>, Nil>>>;
+    /// type Server = <Client as HasDual>::Dual;
+    ///
+    /// fn serve(chan: Chan<mpsc::Channel, (), Server>) -> &'static str {
+    ///     chan.offer()
+    ///         .on::<Ping, _>(|c| { c.close(); "ping" })
+    ///         .on::<Echo, _>(|c| {
+    ///             let (c, Value(x)) = c.recv().unwrap();
+    ///             c.send(Value(x)).unwrap().close();
+    ///             "echo"
+    ///         })
+    ///         .on::<Quit, _>(|c| { c.close(); "quit" })
+    ///         .unwrap()
+    /// }
+    ///
+    /// let (client, server): (Chan<_, (), Client>, _) = mpsc::session_channel();
+    /// let server = std::thread::spawn(move || serve(server));
+    /// let (client, Value(x)) = client.sel::<Echo, _>().unwrap().send(Value(7)).unwrap().recv().unwrap();
+    /// client.close();
+    /// assert_eq!(x, 7);
+    /// assert_eq!(server.join().unwrap(), "echo");
+    ///
+    /// let (client, server): (Chan<_, (), Client>, _) = mpsc::session_channel();
+    /// client.sel::<Quit, _>().unwrap().close();
+    /// assert_eq!(serve(server), "quit");
+    /// ```
     #[must_use]
-    pub fn cddddr(self) -> Result<Chan<SR, Z, Choose<Q, L>>, SR::SendChoiceErr> {
-        self.cdddr().and_then(|c| c.cdr())
+    pub fn sel<Lbl, I>(mut self) -> Result<Chan<SR, E, P::Cont>, SR::SendChoiceErr> where P: Select<Lbl, I> {
+        match P::select(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(e)
+            },
+        }
     }
+}
 
-    /// Perform an active choice, selecting the fifth element of the choose list.
+impl<SR, E, Lbl, P> Chan<SR, E, Labeled<Lbl, P>> where SR: Carrier {
+    /// Strip the label of a branch selected positionally (e.g. via `first` or `option`).
     #[must_use]
-    pub fn fifth(self) -> Result<Chan<SR, Z, Q>, SR::SendChoiceErr> {
-        self.cddddr().and_then(|c| c.car())
+    pub fn unlabel(self) -> Chan<SR, E, P> {
+        cast_chan(self)
+    }
+}
+
+/// Either a choice or a value transmission failure.
+#[derive(Debug)]
+pub enum OptError<C, V> {
+    Choice(C),
+    Value(V),
+}
+
+impl<SR, E, A, R> Chan<SR, E, SendOpt<A, R>> where SR: Carrier, A: ChannelSend<SR> {
+    /// Send a value of the optional message.
+    #[must_use]
+    pub fn send_some(self, v: A) -> Result<Chan<SR, E, R>, OptError<SR::SendChoiceErr, A::Err>> {
+        self.first()
+            .map_err(OptError::Choice)?
+            .send(v)
+            .map_err(OptError::Value)
+    }
+
+    /// Skip the optional message.
+    #[must_use]
+    pub fn send_none(self) -> Result<Chan<SR, E, R>, SR::SendChoiceErr> {
+        self.second()
+    }
+
+    /// Send `Some` value or skip the message on `None`.
+    #[must_use]
+    pub fn send_opt(self, v: Option<A>) -> Result<Chan<SR, E, R>, OptError<SR::SendChoiceErr, A::Err>> {
+        match v {
+            Some(v) =>
+                self.send_some(v),
+            None =>
+                self.send_none().map_err(OptError::Choice),
+        }
+    }
+}
+
+impl<SR, E, A, R> Chan<SR, E, RecvOpt<A, R>> where SR: Carrier, A: ChannelRecv<SR> {
+    /// Receive the optional message, if the other side sent it.
+    ///
+    /// ```
+    /// use session_types_ng::{SendOpt, End, OptError, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    ///
+    /// type Hint = SendOpt<Value<u8>, End>;
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Hint>();
+    /// tx.send_opt(Some(Value(7))).unwrap().close();
+    /// let (rx, hint) = rx.recv_opt().unwrap();
+    /// assert_eq!(hint, Some(Value(7)));
+    /// rx.close();
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Hint>();
+    /// tx.send_none().unwrap().close();
+    /// let (rx, hint) = rx.recv_opt().unwrap();
+    /// assert_eq!(hint, None);
+    /// rx.close();
+    ///
+    /// // the peer has gone before the choice, or between the choice and the value
+    /// let (tx, rx) = mpsc::session_channel::<Hint>();
+    /// tx.poison();
+    /// assert!(matches!(rx.recv_opt(), Err(OptError::Choice(_))));
+    /// let (tx, rx) = mpsc::session_channel::<Hint>();
+    /// tx.first().unwrap().poison();
+    /// assert!(matches!(rx.recv_opt(), Err(OptError::Value(_))));
+    /// ```
+    #[must_use]
+    pub fn recv_opt(self) -> Result<(Chan<SR, E, R>, Option<A>), OptError<SR::RecvChoiceErr, A::Err>> {
+        self.offer()
+            .option(|chan| chan.recv().map(|(chan, v)| (chan, Some(v))))
+            .option(|chan| Ok((chan, None)))
+            .map_err(OptError::Choice)?
+            .map_err(OptError::Value)
     }
 }
 
@@ -325,6 +1279,12 @@ impl<SR, E, P, Q, L, T> Offers<SR, E, Offer<P, Offer<Q, L>>, T> where SR: Carrie
 }
 
 impl<SR, E, P, T> Offers<SR, E, Offer<P, Nil>, T> where SR: Carrier {
+    /// Handle the last branch, completing the offer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the peer has selected a branch beyond the offer list,
+    /// which is possible only if it does not follow the dual protocol.
     #[must_use]
     pub fn option<F>(self, mut handler: F) -> Result<T, SR::RecvChoiceErr>
         where F: FnMut(Chan<SR, E, P>) -> T
@@ -349,7 +1309,133 @@ impl<SR, E, P, T> Offers<SR, E, Offer<P, Nil>, T> where SR: Carrier {
     }
 }
 
-impl<SR, E, P> Chan<SR, E, Rec<P>> {
+impl<SR, E, A, P, Q, L, T> Offers<SR, E, OfferWith<A, P, Offer<Q, L>>, T> where SR: Carrier, A: ChannelRecv<SR> {
+    /// Handle a branch carrying a payload: it is received before the
+    /// handler runs, which gets either the channel with the payload or the
+    /// failure to receive it.
+    #[must_use]
+    pub fn option_recv<F>(self, mut handler: F) -> Offers<SR, E, Offer<Q, L>, T>
+        where F: FnMut(Result<(Chan<SR, E, P>, A), A::Err>) -> T
+    {
+        self.option(|chan| handler(chan.recv()))
+    }
+}
+
+impl<SR, E, A, P, T> Offers<SR, E, OfferWith<A, P, Nil>, T> where SR: Carrier, A: ChannelRecv<SR> {
+    /// Handle the last branch carrying a payload.
+    #[must_use]
+    pub fn option_recv<F>(self, mut handler: F) -> Result<T, SR::RecvChoiceErr>
+        where F: FnMut(Result<(Chan<SR, E, P>, A), A::Err>) -> T
+    {
+        self.option(|chan| handler(chan.recv()))
+    }
+}
+
+impl<SR, E, Lbl, P, Q, L, T> Offers<SR, E, Offer<Labeled<Lbl, P>, Offer<Q, L>>, T> where SR: Carrier {
+    /// Handle the branch labeled `Lbl`. Branches must be handled in the
+    /// order of the offer list, and the chain is complete only when every
+    /// branch is handled.
+    #[must_use]
+    pub fn on<K, F>(self, mut handler: F) -> Offers<SR, E, Offer<Q, L>, T>
+        where K: Same<Lbl>, F: FnMut(Chan<SR, E, P>) -> T
+    {
+        self.option(|chan| handler(chan.unlabel()))
+    }
+}
+
+impl<SR, E, Lbl, P, T> Offers<SR, E, Offer<Labeled<Lbl, P>, Nil>, T> where SR: Carrier {
+    /// Handle the last branch labeled `Lbl`.
+    #[must_use]
+    pub fn on<K, F>(self, mut handler: F) -> Result<T, SR::RecvChoiceErr>
+        where K: Same<Lbl>, F: FnMut(Chan<SR, E, P>) -> T
+    {
+        self.option(|chan| handler(chan.unlabel()))
+    }
+}
+
+/// Type equality witness used to check branch labels.
+#[diagnostic::on_unimplemented(message = "`{Self}` is not the same type as `{T}`")]
+pub trait Same<T> { }
+
+impl<T> Same<T> for T { }
+
+impl<SR, E, P, Q> Chan<SR, E, Seq<P, Q>> where SR: Carrier, P: Then<Q> {
+    /// Unfold the sequence: continue with protocol `P` followed by `Q`.
+    ///
+    /// ```
+    /// # #[macro_use] extern crate session_types_ng;
+    /// use std::thread;
+    /// use session_types_ng::{Chan, Seq, Send, Recv, End, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    ///
+    /// type Login = Send<Value<String>, Recv<Value<bool>, End>>;
+    /// type Query = Send<Value<u32>, Recv<Value<u32>, End>>;
+    /// type Client = Seq<Login, Query>;
+    /// type Server = Seq<Recv<Value<String>, Send<Value<bool>, End>>, Recv<Value<u32>, Send<Value<u32>, End>>>;
+    ///
+    /// assert_dual!(Client, Server);
+    ///
+    /// # fn main() {
+    /// let (client, server): (Chan<_, (), Client>, Chan<_, (), Server>) = mpsc::session_channel();
+    /// let server = thread::spawn(move || {
+    ///     let (chan, Value(user)) = server.seq().recv().unwrap();
+    ///     let chan = chan.send(Value(user == "guest")).unwrap();
+    ///     let (chan, Value(x)) = chan.recv().unwrap();
+    ///     chan.send(Value(x + 1)).unwrap().close();
+    /// });
+    /// let (chan, Value(admitted)) = client.seq().send(Value("guest".to_string())).unwrap().recv().unwrap();
+    /// assert!(admitted);
+    /// let (chan, Value(y)) = chan.send(Value(41)).unwrap().recv().unwrap();
+    /// chan.close();
+    /// server.join().unwrap();
+    /// assert_eq!(y, 42);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn seq(self) -> Chan<SR, E, P::Output> {
+        cast_chan(self)
+    }
+}
+
+impl<SR, E, N, P, R> Chan<SR, E, Repeat<S<N>, P, R>> where SR: Carrier, P: Then<Repeat<N, P, R>> {
+    /// Unroll one repetition: continue with protocol `P` followed by the rest `N` repetitions.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Repeat, Send, Recv, End, S, Z, mpsc};
+    /// use session_types_ng::mpsc::Value;
+    ///
+    /// // two values, then their sum back
+    /// type Sum = Repeat<S<S<Z>>, Send<Value<u32>, End>, Recv<Value<u32>, End>>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Sum>();
+    /// let server = thread::spawn(move || {
+    ///     let (chan, Value(a)) = server.unroll().recv().unwrap();
+    ///     let (chan, Value(b)) = chan.unroll().recv().unwrap();
+    ///     chan.unroll().send(Value(a + b)).unwrap().close();
+    /// });
+    /// let chan = client.unroll().send(Value(2)).unwrap();
+    /// let chan = chan.unroll().send(Value(3)).unwrap();
+    /// let (chan, Value(sum)) = chan.unroll().recv().unwrap();
+    /// chan.close();
+    /// server.join().unwrap();
+    /// assert_eq!(sum, 5);
+    /// ```
+    #[must_use]
+    pub fn unroll(self) -> Chan<SR, E, P::Output> {
+        cast_chan(self)
+    }
+}
+
+impl<SR, E, P, R> Chan<SR, E, Repeat<Z, P, R>> where SR: Carrier {
+    /// All repetitions are done: continue with protocol `R`.
+    #[must_use]
+    pub fn unroll(self) -> Chan<SR, E, R> {
+        cast_chan(self)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, Rec<P>> where SR: Carrier {
     /// Enter a recursive environment, putting the current environment on the
     /// top of the environment stack.
     #[must_use]
@@ -358,7 +1444,31 @@ impl<SR, E, P> Chan<SR, E, Rec<P>> {
     }
 }
 
-impl<SR, E, P> Chan<SR, (P, E), Var<Z>> {
+/// Outcome of one iteration of a loop driven by `Chan::run_loop`: either
+/// the channel at the point of recursion, or the result of the loop.
+pub enum LoopStep<C, B> {
+    Continue(C),
+    Break(B),
+}
+
+impl<SR, E, P> Chan<SR, E, Rec<P>> where SR: Carrier {
+    /// Enter a recursive environment and run `body` for every iteration,
+    /// recursing with `zero` whenever it returns `LoopStep::Continue`.
+    /// Usually called through the `loop_session!` macro.
+    pub fn run_loop<B, F>(self, mut body: F) -> B
+        where F: FnMut(Chan<SR, (P, E), P>) -> LoopStep<Chan<SR, (P, E), Var<Z>>, B>
+    {
+        let mut chan = self.enter();
+        loop {
+            match body(chan) {
+                LoopStep::Continue(next) => chan = next.zero(),
+                LoopStep::Break(result) => return result,
+            }
+        }
+    }
+}
+
+impl<SR, E, P> Chan<SR, (P, E), Var<Z>> where SR: Carrier {
     /// Recurse to the environment on the top of the environment stack.
     #[must_use]
     pub fn zero(self) -> Chan<SR, (P, E), P> {
@@ -366,10 +1476,284 @@ impl<SR, E, P> Chan<SR, (P, E), Var<Z>> {
     }
 }
 
-impl<SR, E, P, N> Chan<SR, (P, E), Var<S<N>>> {
+impl<SR, E, Lbl, P> Chan<SR, E, Loop<Lbl, P>> where SR: Carrier {
+    /// Enter a named recursive environment, putting it on the top of the
+    /// environment stack.
+    #[must_use]
+    pub fn enter(self) -> Chan<SR, (Loop<Lbl, P>, E), P> {
+        cast_chan(self)
+    }
+}
+
+/// Lookup of the `Loop` named `Lbl` in an environment stack. `I` is the
+/// Peano depth of the loop, it is always inferred.
+pub trait FindLoop<Lbl, I> {
+    type Env;
+    type Body;
+}
+
+impl<Lbl, P, E> FindLoop<Lbl, Z> for (Loop<Lbl, P>, E) {
+    type Env = (Loop<Lbl, P>, E);
+    type Body = P;
+}
+
+impl<Lbl, X, E, I> FindLoop<Lbl, S<I>> for (X, E) where E: FindLoop<Lbl, I> {
+    type Env = E::Env;
+    type Body = E::Body;
+}
+
+impl<SR, E, Lbl> Chan<SR, E, Goto<Lbl>> where SR: Carrier {
+    /// Recurse to the loop named `Lbl`, popping all the environments entered after it.
+    #[must_use]
+    pub fn goto<I>(self) -> Chan<SR, E::Env, E::Body> where E: FindLoop<Lbl, I> {
+        cast_chan(self)
+    }
+}
+
+impl<SR, E, P, N> Chan<SR, (P, E), Var<S<N>>> where SR: Carrier {
     /// Pop the top environment from the environment stack.
     #[must_use]
     pub fn succ(self) -> Chan<SR, E, Var<N>> {
         cast_chan(self)
     }
 }
+
+impl<SR, E, A, R> Chan<SR, E, Rec<Choose<Send<A, Var<Z>>, Choose<R, Nil>>>> where SR: Carrier, A: ChannelSend<SR> {
+    /// Drive a producer loop: send every item choosing the first branch,
+    /// then choose the second branch to leave the loop.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Chan, Rec, Choose, Offer, Send, Recv, Var, Z, End, Nil, HasDual, mpsc};
+    ///
+    /// type Producer = Rec<Choose<Send<mpsc::Value<u32>, Var<Z>>, Choose<End, Nil>>>;
+    ///
+    /// fn consume(chan: Chan<mpsc::Channel, (), <Producer as HasDual>::Dual>) -> u32 {
+    ///     let mut chan = chan.enter();
+    ///     let mut sum = 0;
+    ///     loop {
+    ///         chan = match chan.offer()
+    ///             .option(|c| {
+    ///                 let (c, mpsc::Value(v)) = c.recv().unwrap();
+    ///                 Some((c.zero(), v))
+    ///             })
+    ///             .option(|c| { c.close(); None })
+    ///             .unwrap()
+    ///         {
+    ///             Some((c, v)) => { sum += v; c },
+    ///             None => return sum,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Producer>();
+    /// let consumer = thread::spawn(move || consume(rx));
+    /// tx.send_all((1 .. 4).map(mpsc::Value)).unwrap().close();
+    /// assert_eq!(consumer.join().unwrap(), 6);
+    /// ```
+    #[must_use]
+    pub fn send_all<I>(self, items: I) -> Result<Chan<SR, (Choose<Send<A, Var<Z>>, Choose<R, Nil>>, E), R>, OptError<SR::SendChoiceErr, A::Err>>
+        where I: IntoIterator<Item = A>
+    {
+        let mut chan = self.enter();
+        for item in items {
+            chan = chan.first()
+                .map_err(OptError::Choice)?
+                .send(item)
+                .map_err(OptError::Value)?
+                .zero();
+        }
+        chan.second().map_err(OptError::Choice)
+    }
+}
+
+type RecvLoop<A, R> = Offer<Recv<A, Var<Z>>, Offer<R, Nil>>;
+
+enum RecvIterState<SR, E, A, R> where SR: Carrier, A: ChannelRecv<SR> {
+    Loop(Chan<SR, (RecvLoop<A, R>, E), RecvLoop<A, R>>),
+    Done(Chan<SR, (RecvLoop<A, R>, E), R>),
+    Failed(OptError<SR::RecvChoiceErr, A::Err>),
+    Empty,
+}
+
+/// Iterator over the values of a consumer loop, see `Chan::recv_iter`.
+pub struct RecvIter<SR, E, A, R>(RecvIterState<SR, E, A, R>) where SR: Carrier, A: ChannelRecv<SR>;
+
+/// Failure of a request/response exchange, see `Chan::call`.
+#[derive(Debug)]
+pub enum CallError<S, R> {
+    /// Sending the request has failed.
+    Send(S),
+    /// Receiving the response has failed.
+    Recv(R),
+}
+
+impl<SR, E, A, B, R> Chan<SR, E, Send<A, Recv<B, R>>> where SR: Carrier, A: ChannelSend<SR>, B: ChannelRecv<SR> {
+    /// Send a request and receive the response in one call.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Send, Recv, End, mpsc};
+    ///
+    /// type Square = Send<mpsc::Value<u32>, Recv<mpsc::Value<u32>, End>>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Square>();
+    /// let server = thread::spawn(move || {
+    ///     let (chan, mpsc::Value(x)) = server.recv().unwrap();
+    ///     chan.send(mpsc::Value(x * x)).unwrap().close();
+    /// });
+    /// let (client, mpsc::Value(squared)) = client.call(mpsc::Value(7)).unwrap();
+    /// client.close();
+    /// server.join().unwrap();
+    /// assert_eq!(squared, 49);
+    /// ```
+    #[must_use]
+    pub fn call(self, request: A) -> Result<(Chan<SR, E, R>, B), CallError<A::Err, B::Err>> {
+        self.send(request)
+            .map_err(CallError::Send)?
+            .recv()
+            .map_err(CallError::Recv)
+    }
+}
+
+impl<SR, E, A, R> Chan<SR, E, Rec<RecvLoop<A, R>>> where SR: Carrier, A: ChannelRecv<SR> {
+    /// Drive a consumer loop, dual to `send_all`: iterate over the received
+    /// values until the other side leaves the loop.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{Rec, Choose, Send, Var, Z, End, Nil, mpsc};
+    ///
+    /// type Producer = Rec<Choose<Send<mpsc::Value<u32>, Var<Z>>, Choose<End, Nil>>>;
+    ///
+    /// let (tx, rx) = mpsc::session_channel::<Producer>();
+    /// let producer = thread::spawn(move || tx.send_all((1 .. 4).map(mpsc::Value)).unwrap().close());
+    /// let mut values = rx.recv_iter();
+    /// let sum: u32 = values.by_ref().map(|mpsc::Value(v)| v).sum();
+    /// values.finish().unwrap().close();
+    /// producer.join().unwrap();
+    /// assert_eq!(sum, 6);
+    /// ```
+    #[must_use]
+    pub fn recv_iter(self) -> RecvIter<SR, E, A, R> {
+        RecvIter(RecvIterState::Loop(self.enter()))
+    }
+}
+
+impl<SR, E, A, R> Iterator for RecvIter<SR, E, A, R> where SR: Carrier, A: ChannelRecv<SR> {
+    type Item = A;
+
+    fn next(&mut self) -> Option<A> {
+        let chan = match std::mem::replace(&mut self.0, RecvIterState::Empty) {
+            RecvIterState::Loop(chan) =>
+                chan,
+            state => {
+                self.0 = state;
+                return None;
+            },
+        };
+        let branch = chan.offer()
+            .option(|chan| match chan.recv() {
+                Ok((chan, v)) =>
+                    Ok(Ok((chan.zero(), v))),
+                Err(e) =>
+                    Err(OptError::Value(e)),
+            })
+            .option(|chan| Ok(Err(chan)));
+        match branch {
+            Ok(Ok(Ok((chan, v)))) => {
+                self.0 = RecvIterState::Loop(chan);
+                Some(v)
+            },
+            Ok(Ok(Err(chan))) => {
+                self.0 = RecvIterState::Done(chan);
+                None
+            },
+            Ok(Err(e)) => {
+                self.0 = RecvIterState::Failed(e);
+                None
+            },
+            Err(e) => {
+                self.0 = RecvIterState::Failed(OptError::Choice(e));
+                None
+            },
+        }
+    }
+}
+
+impl<SR, E, A, R> RecvIter<SR, E, A, R> where SR: Carrier, A: ChannelRecv<SR> {
+    /// Get the channel after the loop. Values not yet iterated are received
+    /// and discarded, a transmission failure is reported here.
+    #[must_use]
+    pub fn finish(mut self) -> Result<Chan<SR, (RecvLoop<A, R>, E), R>, OptError<SR::RecvChoiceErr, A::Err>> {
+        while self.next().is_some() { }
+        match self.0 {
+            RecvIterState::Done(chan) =>
+                Ok(chan),
+            RecvIterState::Failed(e) =>
+                Err(e),
+            RecvIterState::Loop(..) | RecvIterState::Empty =>
+                unreachable!(),
+        }
+    }
+}
+
+/// Wait for a session requested with `request` over `rx`. Returns `None`
+/// once all the requesting sides are gone.
+#[cfg(feature = "std")]
+pub fn borrow_accept<SR, P>(rx: &Receiver<Chan<SR, (), P>>) -> Option<Chan<SR, (), P>> {
+    rx.recv().ok()
+}
+
+/// Like `borrow_accept`, taking the receiver.
+#[cfg(feature = "std")]
+pub fn accept<SR, P>(rx: Receiver<Chan<SR, (), P>>) -> Option<Chan<SR, (), P>> {
+    borrow_accept(&rx)
+}
+
+/// Request a session from the accepting side of `tx`: a pair of channels is
+/// made with `connect` (for example `mpsc::session_channel`), one of them
+/// is handed over and the dual one is returned. Returns `None` if the
+/// accepting side is gone.
+///
+/// ```
+/// use std::thread;
+/// use std::sync::mpsc::channel;
+/// use session_types_ng::{Send, End, mpsc, accept, borrow_request};
+///
+/// type Greeting = Send<mpsc::Value<&'static str>, End>;
+///
+/// let (tx, rx) = channel();
+/// let server = thread::spawn(move || {
+///     if let Some(chan) = accept::<_, Greeting>(rx) {
+///         chan.send(mpsc::Value("hello")).unwrap().close();
+///     }
+/// });
+/// let (chan, mpsc::Value(greeting)) = borrow_request(&tx, mpsc::session_channel).unwrap().recv().unwrap();
+/// chan.close();
+/// assert_eq!(greeting, "hello");
+/// server.join().unwrap();
+/// ```
+#[cfg(feature = "std")]
+pub fn borrow_request<SR, P, F>(tx: &Sender<Chan<SR, (), P>>, connect: F) -> Option<Chan<SR, (), P::Dual>>
+    where P: HasDual, F: FnOnce() -> (Chan<SR, (), P>, Chan<SR, (), P::Dual>)
+{
+    let (theirs, ours) = connect();
+    match tx.send(theirs) {
+        Ok(()) =>
+            Some(ours),
+        Err(SendError(theirs)) => {
+            close_chan(theirs);
+            close_chan(ours);
+            None
+        },
+    }
+}
+
+/// Like `borrow_request`, taking the sender.
+#[cfg(feature = "std")]
+pub fn request<SR, P, F>(tx: Sender<Chan<SR, (), P>>, connect: F) -> Option<Chan<SR, (), P::Dual>>
+    where P: HasDual, F: FnOnce() -> (Chan<SR, (), P>, Chan<SR, (), P::Dual>)
+{
+    borrow_request(&tx, connect)
+}
@@ -0,0 +1,1164 @@
+/// Build a protocol type from a readable description.
+///
+/// Steps are separated by `;`:
+///
+/// * `send T; ...` is `Send<T, ...>`, `recv T; ...` is `Recv<T, ...>`
+/// * `end` is `End`
+/// * `loop { ... }` is `Rec<...>`, `continue` is `Var<Z>`
+/// * `loop L { ... }` is `Loop<L, ...>`, `goto L` is `Goto<L>`
+/// * `choose { A => p, B => q }` and `offer { A => p, B => q }` are
+///   `Choose`/`Offer` lists of branches `Labeled<A, p>`, `Labeled<B, q>`,
+///   where a branch is either a single step or a block `{ ... }`
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// struct Quit;
+/// struct More;
+///
+/// type Srv = protocol_type!(recv Value<i64>; loop { offer { Quit => end, More => { send Value<i64>; continue } } });
+/// # fn main() { }
+/// ```
+#[macro_export]
+macro_rules! protocol_type {
+    (end) => { $crate::End };
+    (continue) => { $crate::Var<$crate::Z> };
+    ({ $($body:tt)* }) => { $crate::protocol_type!($($body)*) };
+    (send $t:ty; $($rest:tt)+) => { $crate::Send<$t, $crate::protocol_type!($($rest)+)> };
+    (recv $t:ty; $($rest:tt)+) => { $crate::Recv<$t, $crate::protocol_type!($($rest)+)> };
+    (loop { $($body:tt)* }) => { $crate::Rec<$crate::protocol_type!($($body)*)> };
+    (loop $lbl:ident { $($body:tt)* }) => { $crate::Loop<$lbl, $crate::protocol_type!($($body)*)> };
+    (goto $lbl:ident) => { $crate::Goto<$lbl> };
+    (choose { $($arms:tt)* }) => { $crate::protocol_type!(@choose $($arms)*) };
+    (offer { $($arms:tt)* }) => { $crate::protocol_type!(@offer $($arms)*) };
+    (@choose) => { $crate::Nil };
+    (@choose $lbl:ident => $body:tt) => { $crate::protocol_type!(@choose $lbl => $body,) };
+    (@choose $lbl:ident => $body:tt, $($rest:tt)*) => {
+        $crate::Choose<$crate::Labeled<$lbl, $crate::protocol_type!($body)>, $crate::protocol_type!(@choose $($rest)*)>
+    };
+    (@offer) => { $crate::Nil };
+    (@offer $lbl:ident => $body:tt) => { $crate::protocol_type!(@offer $lbl => $body,) };
+    (@offer $lbl:ident => $body:tt, $($rest:tt)*) => {
+        $crate::Offer<$crate::Labeled<$lbl, $crate::protocol_type!($body)>, $crate::protocol_type!(@offer $($rest)*)>
+    };
+}
+
+/// Declare a protocol type alias together with an alias for its dual,
+/// using `protocol_type!` grammar:
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// protocol! {
+///     pub type Server, Client = { send Value<i64>; recv Value<bool>; end };
+/// }
+/// # fn main() { }
+/// ```
+///
+/// A service, a loop offering a list of methods each running its own
+/// multi-step exchange, is declared as `service` with the protocol of every
+/// method on the side of the server. Every method either returns to the
+/// loop with `continue` or ends the session. It expands to a module with
+/// the `Server` and `Client` protocols, the environments `Env` and
+/// `ClientEnv` of the methods, a server `Skeleton` trait with a method
+/// taking the channel of every method, `serve` dispatching the methods
+/// chosen by the client to a skeleton, and a client `Stub` which selects a
+/// method by name and returns its channel:
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// use std::thread;
+///
+/// protocol! {
+///     pub service kv {
+///         fn put => { recv Value<String>; recv Value<u32>; send Value<bool>; continue };
+///         fn quit => end;
+///     }
+/// }
+///
+/// struct Store(Vec<(String, u32)>);
+///
+/// impl kv::Skeleton<mpsc::Channel> for Store {
+///     type Error = std::sync::mpsc::RecvError;
+///
+///     fn put(&mut self, chan: Chan<mpsc::Channel, kv::Env, protocol_type!(recv Value<String>; recv Value<u32>; send Value<bool>; continue)>) -> Result<Option<Chan<mpsc::Channel, kv::Env, Var<Z>>>, Self::Error> {
+///         let (chan, Value(key)) = chan.recv()?;
+///         let (chan, Value(value)) = chan.recv()?;
+///         let fresh = self.0.iter().all(|&(ref k, _)| *k != key);
+///         self.0.push((key, value));
+///         Ok(chan.send(Value(fresh)).ok())
+///     }
+///
+///     fn quit(&mut self, chan: Chan<mpsc::Channel, kv::Env, End>) -> Result<Option<Chan<mpsc::Channel, kv::Env, Var<Z>>>, Self::Error> {
+///         chan.close();
+///         Ok(None)
+///     }
+/// }
+///
+/// # fn main() {
+/// let (server, client) = mpsc::session_channel::<kv::Server>();
+/// let server = thread::spawn(move || {
+///     let mut store = Store(Vec::new());
+///     kv::serve(server, &mut store).unwrap();
+///     store.0.len()
+/// });
+///
+/// let stub = kv::Stub::new(client);
+/// let chan = stub.put().unwrap().send(Value("a".to_string())).unwrap().send(Value(1)).unwrap();
+/// let (chan, Value(fresh)) = chan.recv().unwrap();
+/// assert!(fresh);
+/// kv::Stub::resume(chan).quit().unwrap().close();
+/// assert_eq!(server.join().unwrap(), 1);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! protocol {
+    () => { };
+    ($vis:vis service $name:ident { $(fn $method:ident => $body:tt;)+ } $($rest:tt)*) => {
+        $vis mod $name {
+            #![allow(unused_imports)]
+            use super::*;
+
+            /// Offer of the methods, the body of the `Server` loop.
+            pub type Body = $crate::protocol!(@offer $($body)+);
+            /// Choice of the methods, the body of the `Client` loop.
+            pub type ClientBody = <Body as $crate::HasDual>::Dual;
+
+            pub type Server = $crate::Rec<Body>;
+            pub type Client = <Server as $crate::HasDual>::Dual;
+
+            /// Environment of the methods within the `Server` loop.
+            pub type Env = (Body, ());
+            /// Environment of the methods within the `Client` loop.
+            pub type ClientEnv = (ClientBody, ());
+
+            /// Server side of the methods, called by `serve`. Every method
+            /// returns the channel back at the loop, or `None` to end it.
+            pub trait Skeleton<SR> where SR: $crate::Carrier {
+                type Error;
+
+                $(fn $method(&mut self, chan: $crate::Chan<SR, Env, $crate::protocol_type!($body)>)
+                    -> ::std::result::Result<::std::option::Option<$crate::Chan<SR, Env, $crate::Var<$crate::Z>>>, Self::Error>;)+
+            }
+
+            /// Dispatch the methods chosen by the client to `skeleton` until
+            /// one of them ends the loop.
+            pub fn serve<SR, K>(chan: $crate::Chan<SR, (), Server>, skeleton: &mut K)
+                -> ::std::result::Result<(), $crate::handler::Error<SR::RecvChoiceErr, ::std::convert::Infallible, K::Error>>
+                where SR: $crate::Carrier, K: Skeleton<SR>
+            {
+                let mut chan = chan.enter();
+                loop {
+                    let next = chan.offer()
+                        $(.option(|chan| skeleton.$method(chan)))+
+                        .map_err($crate::handler::Error::Choice)?
+                        .map_err($crate::handler::Error::Handler)?;
+                    match next {
+                        Some(next) => chan = next.zero(),
+                        None => return Ok(()),
+                    }
+                }
+            }
+
+            /// Client at the start of the loop, selecting methods by name.
+            pub struct Stub<SR> {
+                chan: $crate::Chan<SR, ClientEnv, ClientBody>,
+            }
+
+            impl<SR> Stub<SR> where SR: $crate::Carrier {
+                pub fn new(chan: $crate::Chan<SR, (), Client>) -> Stub<SR> {
+                    Stub { chan: chan.enter() }
+                }
+
+                /// Continue after a method which has returned to the loop.
+                pub fn resume(chan: $crate::Chan<SR, ClientEnv, $crate::Var<$crate::Z>>) -> Stub<SR> {
+                    Stub { chan: chan.zero() }
+                }
+
+                $crate::protocol!(@stub [] $(($method, $body))+);
+            }
+        }
+        $crate::protocol!($($rest)*);
+    };
+    ($vis:vis type $name:ident, $dual:ident = { $($body:tt)* }; $($rest:tt)*) => {
+        $vis type $name = $crate::protocol_type!($($body)*);
+        $vis type $dual = <$name as $crate::HasDual>::Dual;
+        $crate::protocol!($($rest)*);
+    };
+    ($vis:vis type $name:ident = { $($body:tt)* }; $($rest:tt)*) => {
+        $vis type $name = $crate::protocol_type!($($body)*);
+        $crate::protocol!($($rest)*);
+    };
+    (@offer $body:tt) => {
+        $crate::Offer<$crate::protocol_type!($body), $crate::Nil>
+    };
+    (@offer $body:tt $($rest:tt)+) => {
+        $crate::Offer<$crate::protocol_type!($body), $crate::protocol!(@offer $($rest)+)>
+    };
+    (@stub [$($skip:ident)*]) => { };
+    (@stub [$($skip:ident)*] ($method:ident, $body:tt) $($rest:tt)*) => {
+        /// Select the method, returning its channel.
+        pub fn $method(self) -> ::std::result::Result<$crate::Chan<SR, ClientEnv, <$crate::protocol_type!($body) as $crate::HasDual>::Dual>, SR::SendChoiceErr> {
+            self.chan $(.$skip()?)* .car()
+        }
+
+        $crate::protocol!(@stub [$($skip)* cdr] $($rest)*);
+    };
+}
+
+/// Fail compilation unless protocols `P` and `Q` are dual to each other:
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// type Server = Recv<Value<u8>, Send<Value<bool>, End>>;
+/// type Client = Send<Value<u8>, Recv<Value<bool>, End>>;
+///
+/// assert_dual!(Server, Client);
+/// # fn main() { }
+/// ```
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// type Server = Recv<Value<u8>, End>;
+/// type Client = Send<Value<u16>, End>;
+///
+/// assert_dual!(Server, Client);
+/// # fn main() { }
+/// ```
+#[macro_export]
+macro_rules! assert_dual {
+    ($p:ty, $q:ty $(,)*) => {
+        const _: fn() = || {
+            fn assert_dual<P, Q>() where Q: $crate::DualOf<P>, P: $crate::DualOf<Q> { }
+            assert_dual::<$p, $q>();
+        };
+    };
+}
+
+/// Fail compilation unless protocols `P` and `Q` are the same type:
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// type Server = Recv<Value<u8>, End>;
+///
+/// assert_protocol_eq!(Server, protocol_type!(recv Value<u8>; end));
+/// # fn main() { }
+/// ```
+#[macro_export]
+macro_rules! assert_protocol_eq {
+    ($p:ty, $q:ty $(,)*) => {
+        const _: fn() = || {
+            fn assert_protocol_eq<P, Q>() where Q: $crate::Same<P> { }
+            assert_protocol_eq::<$p, $q>();
+        };
+    };
+}
+
+/// Wait for whichever of the channels is ready and run its arm, using
+/// `select::ChanSelect`. Channel arms are `pat = chan.recv() => expr`,
+/// `pat = chan.offer() => expr` and, for carriers with bounded capacity,
+/// `pat = chan.send(value) => expr`, where the pattern binds the result of
+/// the operation on the ready channel (the value is evaluated only if its
+/// arm is chosen). At most one extra arm is allowed:
+///
+/// * `default => expr` runs if no channel is ready right now
+/// * `timeout(duration) => expr` runs if no channel is ready in time
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use std::time::Duration;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// # fn main() {
+/// let (mut rx, tx) = mpsc::session_channel::<Recv<Value<u32>, End>>();
+/// tx.send(Value(7)).unwrap().close();
+///
+/// let n = chan_select! {
+///     result = rx.recv() => {
+///         let (chan, Value(n)) = result.unwrap();
+///         chan.close();
+///         n
+///     },
+///     timeout(Duration::from_secs(1)) => 0,
+/// };
+/// assert_eq!(n, 7);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! chan_select {
+    (@parse [$($arms:tt)*] [] default => $code:expr $(, $($rest:tt)*)?) => {
+        $crate::chan_select!(@parse [$($arms)*] [default $code] $($($rest)*)?)
+    };
+    (@parse [$($arms:tt)*] [] timeout($dur:expr) => $code:expr $(, $($rest:tt)*)?) => {
+        $crate::chan_select!(@parse [$($arms)*] [timeout $dur, $code] $($($rest)*)?)
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)+] default => $($rest:tt)*) => {
+        compile_error!("chan_select! allows at most one default or timeout arm")
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)+] timeout $($rest:tt)*) => {
+        compile_error!("chan_select! allows at most one default or timeout arm")
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)*] $res:pat = $rx:ident.recv() => $code:expr $(, $($rest:tt)*)?) => {
+        $crate::chan_select!(@parse [$($arms)* { $res, $rx, add_recv, recv(), $code }] [$($wait)*] $($($rest)*)?)
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)*] $res:pat = $rx:ident.offer() => $code:expr $(, $($rest:tt)*)?) => {
+        $crate::chan_select!(@parse [$($arms)* { $res, $rx, add_offer, offer(), $code }] [$($wait)*] $($($rest)*)?)
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)*] $res:pat = $tx:ident.send($v:expr) => $code:expr $(, $($rest:tt)*)?) => {
+        $crate::chan_select!(@parse [$($arms)* { $res, $tx, add_send, send($v), $code }] [$($wait)*] $($($rest)*)?)
+    };
+    (@parse [$($arms:tt)*] [$($wait:tt)*]) => {
+        {
+            let ready = {
+                let mut select = $crate::select::ChanSelect::new();
+                let mut arm = 0usize;
+                $($crate::chan_select!(@add select arm $arms);)*
+                $crate::chan_select!(@wait select $($wait)*)
+            };
+            $crate::chan_select!(@dispatch ready (0usize) [$($wait)*] $($arms)*)
+        }
+    };
+    (@add $select:ident $arm:ident { $res:pat, $rx:ident, $add:ident, $op:ident $args:tt, $code:expr }) => {
+        $select.$add(&mut $rx, $arm);
+        $arm += 1;
+    };
+    (@wait $select:ident) => { Some($select.wait()) };
+    (@wait $select:ident default $code:expr) => { $select.try_wait() };
+    (@wait $select:ident timeout $dur:expr, $code:expr) => { $select.wait_timeout($dur) };
+    (@dispatch $ready:ident $idx:tt [$($wait:tt)*] { $res:pat, $rx:ident, $add:ident, $op:ident $args:tt, $code:expr } $($rest:tt)*) => {
+        if $ready == Some($idx) {
+            let $res = $rx.$op $args;
+            $code
+        } else {
+            $crate::chan_select!(@dispatch $ready ($idx + 1) [$($wait)*] $($rest)*)
+        }
+    };
+    (@dispatch $ready:ident $idx:tt []) => { unreachable!() };
+    (@dispatch $ready:ident $idx:tt [default $code:expr]) => { $code };
+    (@dispatch $ready:ident $idx:tt [timeout $dur:expr, $code:expr]) => { $code };
+    ($($arms:tt)*) => {
+        $crate::chan_select!(@parse [] [] $($arms)*)
+    };
+}
+
+/// Declare an enum with a variant for each of differently typed channels,
+/// generic over their types, together with `select` which takes whichever
+/// channel is ready out of its slot and returns it wrapped in the variant.
+/// Empty slots are skipped, the other channels stay in their slots.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use std::time::Duration;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// select_enum! {
+///     enum Event {
+///         Number,
+///         Flag,
+///     }
+/// }
+///
+/// # fn main() {
+/// let (numbers, numbers_tx) = mpsc::session_channel::<Recv<Value<u32>, End>>();
+/// let (flags, flags_tx) = mpsc::session_channel::<Recv<Value<bool>, End>>();
+/// numbers_tx.send(Value(7)).unwrap().close();
+/// flags_tx.send(Value(true)).unwrap().close();
+///
+/// let (mut numbers, mut flags) = (Some(numbers), Some(flags));
+/// while numbers.is_some() || flags.is_some() {
+///     match Event::select(&mut numbers, &mut flags) {
+///         Event::Number(chan) => {
+///             let (chan, Value(n)) = chan.recv().unwrap();
+///             assert_eq!(n, 7);
+///             chan.close();
+///         },
+///         Event::Flag(chan) => {
+///             let (chan, Value(flag)) = chan.recv().unwrap();
+///             assert!(flag);
+///             chan.close();
+///         },
+///     }
+/// }
+/// assert!(Event::select_timeout(Duration::from_millis(1), &mut numbers, &mut flags).is_none());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! select_enum {
+    ($(#[$attr:meta])* $vis:vis enum $name:ident { $($variant:ident),+ $(,)? }) => {
+        $(#[$attr])*
+        $vis enum $name<$($variant),+> {
+            $($variant($variant)),+
+        }
+
+        #[allow(non_snake_case, unused_assignments)]
+        impl<$($variant),+> $name<$($variant),+> where $($variant: $crate::select::Selectable),+ {
+            /// Block until one of the channels is ready and take it out of its slot.
+            ///
+            /// # Panics
+            ///
+            /// Panics if all the slots are empty.
+            $vis fn select($($variant: &mut Option<$variant>),+) -> Self {
+                let ready = {
+                    let mut select = $crate::select::ChanSelect::new();
+                    let mut arm = 0usize;
+                    $(
+                        if let Some(chan) = $variant.as_mut() {
+                            select.add(chan, arm);
+                        }
+                        arm += 1;
+                    )+
+                    select.wait()
+                };
+                $crate::select_enum!(@take $name ready $($variant)+)
+            }
+
+            /// Same as `select`, but give up after `timeout`.
+            $vis fn select_timeout(timeout: ::std::time::Duration, $($variant: &mut Option<$variant>),+) -> Option<Self> {
+                let ready = {
+                    let mut select = $crate::select::ChanSelect::new();
+                    let mut arm = 0usize;
+                    $(
+                        if let Some(chan) = $variant.as_mut() {
+                            select.add(chan, arm);
+                        }
+                        arm += 1;
+                    )+
+                    select.wait_timeout(timeout)?
+                };
+                Some($crate::select_enum!(@take $name ready $($variant)+))
+            }
+        }
+    };
+    (@take $name:ident $ready:ident $($variant:ident)+) => {
+        {
+            let mut taken = None;
+            let mut arm = 0usize;
+            $(
+                if $ready == arm {
+                    taken = $variant.take().map($name::$variant);
+                }
+                arm += 1;
+            )+
+            taken.expect("ready slot is not empty")
+        }
+    };
+}
+
+/// Declare an enum of the continuations of a choose list built from its
+/// variants, for choices determined by runtime data. The enum is generic
+/// over the carrier and the environment of the channel, and implements
+/// `RuntimeChoice`, so that `chan.sel_runtime(index)` selects the branch
+/// `index` and returns its channel in the corresponding variant.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// choice_enum! {
+///     pub enum Op {
+///         Add(Send<Value<i64>, End>),
+///         Neg(End),
+///     }
+/// }
+///
+/// # fn main() {
+/// type Calc = <Op<mpsc::Channel, ()> as RuntimeChoice<mpsc::Channel, ()>>::Choice;
+///
+/// let table = ["add", "neg"];
+/// let (client, server) = mpsc::session_channel::<Calc>();
+/// let index = table.iter().position(|&op| op == "add").unwrap();
+/// match client.sel_runtime(index).ok().unwrap() {
+///     Op::Add(chan) => chan.send(Value(3)).unwrap().close(),
+///     Op::Neg(chan) => chan.close(),
+/// }
+/// server.offer()
+///     .option(|c| { let (c, Value(x)) = c.recv().unwrap(); c.close(); assert_eq!(x, 3) })
+///     .option(|c| c.close())
+///     .unwrap();
+///
+/// let (client, server) = mpsc::session_channel::<Calc>();
+/// match client.sel_runtime::<Op<_, _>>(table.len()) {
+///     Err(SelRuntimeError::OutOfRange(chan)) => chan.second().unwrap().close(),
+///     _ => unreachable!(),
+/// }
+/// server.offer().option(|_| unreachable!()).option(|c| c.close()).unwrap();
+/// # }
+/// ```
+#[macro_export]
+macro_rules! choice_enum {
+    ($(#[$attr:meta])* $vis:vis enum $name:ident { $($(#[$vattr:meta])* $variant:ident($proto:ty)),+ $(,)? }) => {
+        $(#[$attr])*
+        $vis enum $name<SR, E> {
+            $($(#[$vattr])* $variant($crate::Chan<SR, E, $proto>)),+
+        }
+
+        impl<SR, E> $crate::RuntimeChoice<SR, E> for $name<SR, E> where SR: $crate::Carrier {
+            type Choice = $crate::choice_enum!(@choose $($proto),+);
+
+            const LEN: usize = [$(stringify!($variant)),+].len();
+
+            fn choose(chan: $crate::Chan<SR, E, Self::Choice>, index: usize) -> ::std::result::Result<Self, SR::SendChoiceErr> {
+                $crate::choice_enum!(@select $name chan index $($variant)+)
+            }
+        }
+    };
+    (@choose $proto:ty) => {
+        $crate::Choose<$proto, $crate::Nil>
+    };
+    (@choose $proto:ty, $($rest:ty),+) => {
+        $crate::Choose<$proto, $crate::choice_enum!(@choose $($rest),+)>
+    };
+    (@select $name:ident $chan:ident $index:ident $variant:ident) => {
+        $chan.car().map($name::$variant)
+    };
+    (@select $name:ident $chan:ident $index:ident $variant:ident $($rest:ident)+) => {
+        if $index == 0 {
+            $chan.car().map($name::$variant)
+        } else {
+            let $index = $index - 1;
+            match $chan.cdr() {
+                ::std::result::Result::Ok($chan) => $crate::choice_enum!(@select $name $chan $index $($rest)+),
+                ::std::result::Result::Err(e) => ::std::result::Result::Err(e),
+            }
+        }
+    };
+}
+
+/// Declare an enum of the branches of an offer list built from its
+/// variants. A branch `Variant(A) => P` receives payload `A` and continues
+/// with `P`, a branch `Variant => P` just continues with `P`. The enum is
+/// generic over the carrier and the environment of the channel, with the
+/// channel of the branch and its payload in each variant, and implements
+/// `OfferEnum`, so that the offering side handles the branches with a
+/// single `match chan.offer_enum()`. All the payloads should fail to
+/// receive with the same error.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// offer_enum! {
+///     pub enum Request {
+///         Login(Value<String>) => Send<Value<bool>, End>,
+///         Quit => End,
+///     }
+/// }
+///
+/// # fn main() {
+/// type Server = <Request<mpsc::Channel, ()> as OfferEnum<mpsc::Channel, (), std::sync::mpsc::RecvError>>::Offer;
+///
+/// let (server, client) = mpsc::session_channel::<Server>();
+/// let client = client.nth_send::<Z, _, _>(Value("root".to_string())).unwrap();
+/// match server.offer_enum().unwrap() {
+///     Request::Login(chan, Value(user)) => chan.send(Value(user == "root")).unwrap().close(),
+///     Request::Quit(chan) => chan.close(),
+/// }
+/// let (client, Value(ok)) = client.recv().unwrap();
+/// client.close();
+/// assert!(ok);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! offer_enum {
+    ($(#[$attr:meta])* $vis:vis enum $name:ident { $($(#[$vattr:meta])* $variant:ident $(($payload:ty))? => $proto:ty),+ $(,)? }) => {
+        $(#[$attr])*
+        $vis enum $name<SR, E> {
+            $($(#[$vattr])* $variant($crate::Chan<SR, E, $proto> $(, $payload)?)),+
+        }
+
+        impl<SR, E, V> $crate::OfferEnum<SR, E, V> for $name<SR, E>
+            where SR: $crate::Carrier $($(, $payload: $crate::ChannelRecv<SR, Err = V>)?)+
+        {
+            type Offer = $crate::offer_enum!(@offer $(($($payload)?) $proto),+);
+
+            fn offer(chan: $crate::Chan<SR, E, Self::Offer>) -> ::std::result::Result<Self, $crate::OptError<SR::RecvChoiceErr, V>> {
+                let offered: ::std::result::Result<_, _> = chan
+                    .offer()
+                    $(.option(|chan| $crate::offer_enum!(@branch $name $variant chan $($payload)?)))+;
+                match offered {
+                    ::std::result::Result::Ok(::std::result::Result::Ok(branch)) => ::std::result::Result::Ok(branch),
+                    ::std::result::Result::Ok(::std::result::Result::Err(e)) => ::std::result::Result::Err($crate::OptError::Value(e)),
+                    ::std::result::Result::Err(e) => ::std::result::Result::Err($crate::OptError::Choice(e)),
+                }
+            }
+        }
+    };
+    (@offer ($($payload:ty)?) $proto:ty) => {
+        $crate::Offer<$crate::offer_enum!(@step ($($payload)?) $proto), $crate::Nil>
+    };
+    (@offer ($($payload:ty)?) $proto:ty, $($rest:tt)+) => {
+        $crate::Offer<$crate::offer_enum!(@step ($($payload)?) $proto), $crate::offer_enum!(@offer $($rest)+)>
+    };
+    (@step () $proto:ty) => {
+        $proto
+    };
+    (@step ($payload:ty) $proto:ty) => {
+        $crate::Recv<$payload, $proto>
+    };
+    (@branch $name:ident $variant:ident $chan:ident) => {
+        ::std::result::Result::Ok($name::$variant($chan))
+    };
+    (@branch $name:ident $variant:ident $chan:ident $payload:ty) => {
+        $chan.recv().map(|($chan, payload)| $name::$variant($chan, payload))
+    };
+}
+
+/// Declare a server loop as a list of branches handled by the methods of a
+/// `SessionHandler` trait, see `handler` module. Expands to a module with
+/// the `Server` and `Client` protocols, the environment `Env` of the
+/// branches, the `SessionHandler` trait and `run` dispatching branches to
+/// a handler.
+#[macro_export]
+macro_rules! session_handler {
+    ($vis:vis mod $name:ident { $(fn $method:ident($($payload:ty)?) => $proto:ty;)+ }) => {
+        $vis mod $name {
+            #![allow(unused_imports)]
+            use super::*;
+
+            /// Offer of the branches, the body of the `Server` loop.
+            pub type Body = $crate::offer_enum!(@offer $(($($payload)?) $proto),+);
+
+            pub type Server = $crate::Rec<Body>;
+            pub type Client = <Server as $crate::HasDual>::Dual;
+
+            /// Environment of the branches within the `Server` loop.
+            pub type Env = (Body, ());
+
+            $crate::offer_enum! {
+                #[allow(non_camel_case_types)]
+                enum Branch {
+                    $($method $(($payload))? => $proto),+
+                }
+            }
+
+            /// Handler of the branches, called by `run`. Every method returns
+            /// the channel back at the loop, or `None` to end it.
+            pub trait SessionHandler<SR> where SR: $crate::Carrier {
+                type Error;
+
+                $(fn $method(&mut self, chan: $crate::Chan<SR, Env, $proto> $(, payload: $payload)?)
+                    -> ::std::result::Result<::std::option::Option<$crate::Chan<SR, Env, $crate::Var<$crate::Z>>>, Self::Error>;)+
+            }
+
+            /// Dispatch the branches chosen by the client to `handler` until
+            /// one of its methods ends the loop.
+            pub fn run<SR, H, V>(chan: $crate::Chan<SR, (), Server>, handler: &mut H)
+                -> ::std::result::Result<(), $crate::handler::Error<SR::RecvChoiceErr, V, H::Error>>
+                where SR: $crate::Carrier, H: SessionHandler<SR>, $($($payload: $crate::ChannelRecv<SR, Err = V>,)?)+
+            {
+                $crate::handler::run(chan, |branch| match branch {
+                    $(Branch::$method(chan $(, $crate::session_handler!(@bind payload $payload))?) =>
+                        handler.$method(chan $(, $crate::session_handler!(@bind payload $payload))?),)+
+                })
+            }
+        }
+    };
+    (@bind $bind:ident $payload:ty) => {
+        $bind
+    };
+}
+
+/// Handle an offer of labeled branches, one arm per label in the order of
+/// the offer list, with the channel of each branch bound to the given name.
+/// A missing or an extra arm fails compilation. Evaluates to the `Result`
+/// of the offer.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// struct Ping;
+/// struct Quit;
+///
+/// type Server = Offer<Labeled<Ping, Send<Value<u8>, End>>, Offer<Labeled<Quit, End>, Nil>>;
+///
+/// # fn main() {
+/// let (server, client) = mpsc::session_channel::<Server>();
+/// let client = client.sel::<Ping, _>().unwrap();
+///
+/// offer! { server,
+///     Ping => server.send(Value(42)).unwrap().close(),
+///     Quit => server.close(),
+/// }.unwrap();
+///
+/// let (client, Value(answer)) = client.recv().unwrap();
+/// assert_eq!(answer, 42);
+/// client.close();
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// struct Ping;
+/// struct Quit;
+///
+/// type Server = Offer<Labeled<Ping, Send<Value<u8>, End>>, Offer<Labeled<Quit, End>, Nil>>;
+///
+/// fn serve(server: Chan<mpsc::Channel, (), Server>) {
+///     offer! { server,
+///         Ping => server.send(Value(42)).unwrap().close(),
+///     }.unwrap();
+/// }
+/// # fn main() { }
+/// ```
+#[macro_export]
+macro_rules! offer {
+    ($chan:ident, $($lbl:ty => $code:expr),+ $(,)?) => {
+        {
+            let offered: ::std::result::Result<_, _> = $chan
+                .offer()
+                $(.on::<$lbl, _>(|$chan| $code))+;
+            offered
+        }
+    };
+}
+
+/// Drive a `Rec` loop without the `enter`, `zero` and `succ` bookkeeping.
+///
+/// The body runs once per iteration with the channel at the start of the
+/// loop bound to the given name, and every path through it ends with
+/// either `continue_loop!(chan)` on a channel at `Var<Z>` to recurse, or
+/// `break_loop!(value)` to leave the loop with `value`. The body is the body
+/// of a closure, so `return` leaves the iteration, not the enclosing function.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// use std::thread;
+///
+/// type Summer = Rec<Offer<Recv<Value<u32>, Var<Z>>, Offer<Send<Value<u32>, End>, Nil>>>;
+///
+/// # fn main() {
+/// let (server, client) = mpsc::session_channel::<Summer>();
+/// let server = thread::spawn(move || {
+///     let mut sum = 0;
+///     loop_session!(server, |c| {
+///         match c.offer().option(Ok).option(Err).unwrap() {
+///             Ok(c) => {
+///                 let (c, Value(n)) = c.recv().unwrap();
+///                 sum += n;
+///                 continue_loop!(c)
+///             },
+///             Err(c) => break_loop!(c.send(Value(sum)).unwrap().close()),
+///         }
+///     })
+/// });
+///
+/// let mut client = client.enter();
+/// for n in 1 .. 4 {
+///     client = client.first().unwrap().send(Value(n)).unwrap().zero();
+/// }
+/// let (client, Value(sum)) = client.second().unwrap().recv().unwrap();
+/// client.close();
+/// server.join().unwrap();
+/// assert_eq!(sum, 6);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! loop_session {
+    ($chan:expr, |$c:ident| $body:expr) => {
+        $chan.run_loop(|$c| $body)
+    };
+}
+
+/// Recurse to the start of the innermost `loop_session!`, see there.
+#[macro_export]
+macro_rules! continue_loop {
+    ($chan:expr) => {
+        return $crate::LoopStep::Continue($chan)
+    };
+}
+
+/// Leave the innermost `loop_session!` with a value, see there.
+#[macro_export]
+macro_rules! break_loop {
+    () => {
+        return $crate::LoopStep::Break(())
+    };
+    ($value:expr) => {
+        return $crate::LoopStep::Break($value)
+    };
+}
+
+/// Unwrap the result of an application computation inside a session, like
+/// `?` does, without leaving the other side hanging on an error.
+///
+/// `try_session!(chan, result)` poisons the channel before returning the
+/// error, so the next operation of the other side fails. For a channel at a
+/// `Guarded` or `Checked` step, `try_session!(chan, result, handler)`
+/// aborts into the failure continuation of the protocol and drives it with
+/// `handler` instead. The error is converted with `From`, as `?` does.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// # use session_types_ng::abort::{Abortable, Abort};
+/// use std::num::ParseIntError;
+///
+/// type Reply = Abortable<Send<Value<u32>, End>, Send<Value<String>, End>>;
+///
+/// fn reply(chan: Chan<mpsc::Channel, (), Reply>, input: &str) -> Result<(), ParseIntError> {
+///     let chan = chan.guard();
+///     let n = try_session!(chan, input.parse(), |chan| {
+///         chan.send(Value("not a number".to_string())).unwrap().close()
+///     });
+///     match chan.proceed() {
+///         Ok(chan) => chan.send(Value(n)).unwrap().close(),
+///         Err(Abort::Peer(chan)) => chan.poison(),
+///         Err(Abort::Carrier(_)) => (),
+///     }
+///     Ok(())
+/// }
+///
+/// # fn main() {
+/// let (server, client) = mpsc::session_channel::<Reply>();
+/// let server = std::thread::spawn(move || reply(server, "forty two"));
+/// match client.guard().check() {
+///     Err(Abort::Peer(chan)) => {
+///         let (chan, Value(reason)) = chan.recv().unwrap();
+///         assert_eq!(reason, "not a number");
+///         chan.close();
+///     },
+///     _ => panic!("the server should abort"),
+/// }
+/// assert!(server.join().unwrap().is_err());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! try_session {
+    ($chan:ident, $result:expr) => {
+        match $result {
+            ::std::result::Result::Ok(value) => value,
+            ::std::result::Result::Err(err) => {
+                $chan.poison();
+                return ::std::result::Result::Err(::std::convert::From::from(err));
+            },
+        }
+    };
+    ($chan:ident, $result:expr, $handler:expr) => {
+        match $result {
+            ::std::result::Result::Ok(value) => value,
+            ::std::result::Result::Err(err) => {
+                $chan.abort_with($handler);
+                return ::std::result::Result::Err(::std::convert::From::from(err));
+            },
+        }
+    };
+}
+
+/// Make a user type transferable as a message over the in-process carrier
+/// and, with the `stream` feature, over the serde-based byte stream carrier,
+/// in the same way as it would be wrapped into `mpsc::Value` and
+/// `stream::Value`. Generic parameters are given in brackets, followed by
+/// the type and an optional `where` clause. With the `derive` feature
+/// `#[derive(SessionMessage)]` expands to this macro.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// struct Point<T> {
+///     x: T,
+///     y: T,
+/// }
+///
+/// session_message!([T] Point<T> where T: Copy);
+///
+/// # fn main() {
+/// let (tx, rx) = mpsc::session_channel::<Send<Point<i32>, End>>();
+/// tx.send(Point { x: 1, y: 2 }).unwrap().close();
+/// let (rx, point) = rx.recv().unwrap();
+/// rx.close();
+/// assert_eq!((point.x, point.y), (1, 2));
+///
+/// // messages pass through carrier wrappers as well
+/// let (tx, rx) = mpsc::session_channel::<Send<Point<i32>, End>>();
+/// let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+/// let (tx, rx) = (tx.with_deadline(deadline), rx.with_deadline(deadline));
+/// tx.send(Point { x: 3, y: 4 }).unwrap().close();
+/// let (rx, point) = rx.recv().unwrap();
+/// rx.close();
+/// assert_eq!((point.x, point.y), (3, 4));
+/// # }
+/// ```
+#[cfg(feature = "stream")]
+#[macro_export]
+macro_rules! session_message {
+    ([$($generics:tt)*] $value:ty $(where $($bound:tt)*)?) => {
+        $crate::__session_message_mpsc!([$($generics)*] $value $(where $($bound)*)?);
+
+        impl<SessionStream, SessionFormat, $($generics)*> $crate::ChannelSend<$crate::stream::Channel<SessionStream, SessionFormat>> for $value
+            where $crate::stream::Value<$value>: $crate::ChannelSend<$crate::stream::Channel<SessionStream, SessionFormat>> $(, $($bound)*)?
+        {
+            type Err = <$crate::stream::Value<$value> as $crate::ChannelSend<$crate::stream::Channel<SessionStream, SessionFormat>>>::Err;
+
+            fn send(self, carrier: &mut $crate::stream::Channel<SessionStream, SessionFormat>) -> ::std::result::Result<(), Self::Err> {
+                $crate::ChannelSend::send($crate::stream::Value(self), carrier)
+            }
+        }
+
+        impl<SessionStream, SessionFormat, $($generics)*> $crate::ChannelRecv<$crate::stream::Channel<SessionStream, SessionFormat>> for $value
+            where $crate::stream::Value<$value>: $crate::ChannelRecv<$crate::stream::Channel<SessionStream, SessionFormat>> $(, $($bound)*)?
+        {
+            type Err = <$crate::stream::Value<$value> as $crate::ChannelRecv<$crate::stream::Channel<SessionStream, SessionFormat>>>::Err;
+
+            fn recv(carrier: &mut $crate::stream::Channel<SessionStream, SessionFormat>) -> ::std::result::Result<Self, Self::Err> {
+                <$crate::stream::Value<$value> as $crate::ChannelRecv<_>>::recv(carrier).map(|value| value.0)
+            }
+        }
+    };
+}
+
+/// See the `stream` feature variant.
+#[cfg(not(feature = "stream"))]
+#[macro_export]
+macro_rules! session_message {
+    ([$($generics:tt)*] $value:ty $(where $($bound:tt)*)?) => {
+        $crate::__session_message_mpsc!([$($generics)*] $value $(where $($bound)*)?);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __session_message_mpsc {
+    ([$($generics:tt)*] $value:ty $(where $($bound:tt)*)?) => {
+        impl<$($generics)*> $crate::Payload for $value $(where $($bound)*)? { }
+
+        impl<$($generics)*> $crate::ChannelSend<$crate::mpsc::Channel> for $value
+            where $value: ::std::marker::Send + 'static $(, $($bound)*)?
+        {
+            type Err = ::std::sync::mpsc::SendError<::std::boxed::Box<$value>>;
+
+            fn send(self, carrier: &mut $crate::mpsc::Channel) -> ::std::result::Result<(), Self::Err> {
+                $crate::ChannelSend::send($crate::mpsc::Value(self), carrier)
+            }
+        }
+
+        impl<$($generics)*> $crate::ChannelRecv<$crate::mpsc::Channel> for $value
+            where $value: ::std::marker::Send + 'static $(, $($bound)*)?
+        {
+            type Err = ::std::sync::mpsc::RecvError;
+
+            fn recv(carrier: &mut $crate::mpsc::Channel) -> ::std::result::Result<Self, Self::Err> {
+                <$crate::mpsc::Value<$value> as $crate::ChannelRecv<_>>::recv(carrier).map(|value| value.0)
+            }
+        }
+    };
+}
+
+/// Generate C functions `$send` and `$recv` transferring values of the type
+/// `$value` (wrapped into `mpsc::Value`) over an `ffi::Session`, for payload
+/// types the `ffi` module has no functions for. The type should be FFI-safe,
+/// e.g. a `#[repr(C)]` struct.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Default)]
+/// pub struct Point {
+///     x: f64,
+///     y: f64,
+/// }
+///
+/// ffi_value!(session_send_point, session_recv_point, Point);
+///
+/// # fn main() {
+/// let (chan, session) = ffi::session_channel::<Send<mpsc::Value<Point>, End>>();
+/// chan.send(mpsc::Value(Point { x: 1.0, y: 2.0 })).unwrap().close();
+///
+/// let session = Box::into_raw(session);
+/// let mut point = Point::default();
+/// unsafe {
+///     assert_eq!(session_recv_point(session, &mut point), ffi::SESSION_OK);
+///     assert_eq!(ffi::session_close(session), ffi::SESSION_OK);
+/// }
+/// assert_eq!(point.y, 2.0);
+/// # }
+/// ```
+#[cfg(feature = "ffi")]
+#[macro_export]
+macro_rules! ffi_value {
+    ($send:ident, $recv:ident, $value:ty) => {
+        /// Send a value to the Rust endpoint.
+        ///
+        /// # Safety
+        ///
+        /// `session` should be null or a session handle not released yet.
+        #[no_mangle]
+        pub unsafe extern "C" fn $send(session: *mut $crate::ffi::Session, value: $value) -> ::std::os::raw::c_int {
+            $crate::ffi::send(session, value)
+        }
+
+        /// Receive a value from the Rust endpoint.
+        ///
+        /// # Safety
+        ///
+        /// `session` should be null or a session handle not released yet,
+        /// `value` should be null or valid for writes.
+        #[no_mangle]
+        pub unsafe extern "C" fn $recv(session: *mut $crate::ffi::Session, value: *mut $value) -> ::std::os::raw::c_int {
+            $crate::ffi::recv(session, value)
+        }
+    };
+}
+
+/// Declare a request/response service, see the `rpc` module. The service is
+/// a module with the given name and visibility containing:
+///
+/// * `Server` and `Client` protocols of the service
+/// * `Service` trait with a method for every method of the service
+/// * `serve(chan, &mut service)` loop dispatching calls until the client
+///   closes the session
+/// * `Stub<SR>` client with a method for every method of the service and
+///   `close` to end the session
+///
+/// Types of requests and responses are resolved in the enclosing module.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// use std::thread;
+/// use session_types_ng::mpsc::{self, Value};
+///
+/// rpc_service! {
+///     pub mod calc {
+///         fn add(Value<(i64, i64)>) -> Value<i64>;
+///         fn neg(Value<i64>) -> Value<i64>;
+///     }
+/// }
+///
+/// struct Calc;
+///
+/// impl calc::Service for Calc {
+///     fn add(&mut self, Value((x, y)): Value<(i64, i64)>) -> Value<i64> {
+///         Value(x + y)
+///     }
+///
+///     fn neg(&mut self, Value(x): Value<i64>) -> Value<i64> {
+///         Value(-x)
+///     }
+/// }
+///
+/// # fn main() {
+/// let (server, client) = mpsc::session_channel::<calc::Server>();
+/// let server = thread::spawn(move || calc::serve(server, &mut Calc).unwrap());
+///
+/// let mut stub = calc::Stub::new(client);
+/// let Value(sum) = stub.add(Value((2, 3))).unwrap();
+/// let Value(neg) = stub.neg(Value(sum)).unwrap();
+/// stub.close().unwrap();
+/// server.join().unwrap();
+/// assert_eq!(neg, -5);
+/// # }
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! rpc_service {
+    ($vis:vis mod $name:ident { $(fn $method:ident($req:ty) -> $resp:ty;)* }) => {
+        $vis mod $name {
+            #![allow(unused_imports)]
+            use super::*;
+
+            type ServerLoop = $crate::rpc_service!(@offer $(($req, $resp))*);
+            type ClientLoop = <ServerLoop as $crate::HasDual>::Dual;
+
+            pub type Server = $crate::Rec<ServerLoop>;
+            pub type Client = <Server as $crate::HasDual>::Dual;
+
+            /// Implementation of the service, called by `serve`.
+            pub trait Service {
+                $(fn $method(&mut self, request: $req) -> $resp;)*
+            }
+
+            /// Dispatch calls to `service` until the client closes the session.
+            pub fn serve<SR, S>(chan: $crate::Chan<SR, (), Server>, service: &mut S) -> ::std::result::Result<(), $crate::rpc::Error<SR::RecvChoiceErr>>
+                where SR: $crate::Carrier,
+                      S: Service,
+                      $($req: $crate::ChannelRecv<SR>,
+                        <$req as $crate::ChannelRecv<SR>>::Err: ::std::fmt::Debug,
+                        $resp: $crate::ChannelSend<SR>,
+                        <$resp as $crate::ChannelSend<SR>>::Err: ::std::fmt::Debug,)*
+            {
+                let mut chan = chan.enter();
+                loop {
+                    let next = chan.offer()
+                        $(.option(|chan| {
+                            let (chan, request) = chan.recv()
+                                .map_err(|e| $crate::rpc::Error::Transfer { method: stringify!($method), error: format!("{:?}", e) })?;
+                            let response = service.$method(request);
+                            let chan = chan.send(response)
+                                .map_err(|e| $crate::rpc::Error::Transfer { method: stringify!($method), error: format!("{:?}", e) })?;
+                            Ok(Some(chan.zero()))
+                        }))*
+                        .option(|chan| {
+                            chan.close();
+                            Ok(None)
+                        })
+                        .map_err($crate::rpc::Error::Choice)??;
+                    match next {
+                        Some(next) => chan = next,
+                        None => return Ok(()),
+                    }
+                }
+            }
+
+            /// Client of the service.
+            pub struct Stub<SR> {
+                chan: ::std::option::Option<$crate::Chan<SR, (ClientLoop, ()), ClientLoop>>,
+            }
+
+            impl<SR> Stub<SR> where SR: $crate::Carrier {
+                pub fn new(chan: $crate::Chan<SR, (), Client>) -> Stub<SR> {
+                    Stub { chan: Some(chan.enter()) }
+                }
+
+                $crate::rpc_service!(@stub [] $(($method, $req, $resp))*);
+            }
+        }
+    };
+    (@offer) => { $crate::Offer<$crate::End, $crate::Nil> };
+    (@offer ($req:ty, $resp:ty) $($rest:tt)*) => {
+        $crate::Offer<$crate::Recv<$req, $crate::Send<$resp, $crate::Var<$crate::Z>>>, $crate::rpc_service!(@offer $($rest)*)>
+    };
+    (@stub [$($skip:ident)*]) => {
+        /// End the session.
+        pub fn close(mut self) -> ::std::result::Result<(), $crate::rpc::Error<SR::SendChoiceErr>> {
+            if let Some(chan) = self.chan.take() {
+                chan $(.$skip().map_err($crate::rpc::Error::Choice)?)* .car().map_err($crate::rpc::Error::Choice)?.close();
+            }
+            Ok(())
+        }
+    };
+    (@stub [$($skip:ident)*] ($method:ident, $req:ty, $resp:ty) $($rest:tt)*) => {
+        pub fn $method(&mut self, request: $req) -> ::std::result::Result<$resp, $crate::rpc::Error<SR::SendChoiceErr>>
+            where $req: $crate::ChannelSend<SR>,
+                  <$req as $crate::ChannelSend<SR>>::Err: ::std::fmt::Debug,
+                  $resp: $crate::ChannelRecv<SR>,
+                  <$resp as $crate::ChannelRecv<SR>>::Err: ::std::fmt::Debug
+        {
+            let chan = self.chan.take().ok_or($crate::rpc::Error::Closed)?;
+            let chan = chan $(.$skip().map_err($crate::rpc::Error::Choice)?)* .car().map_err($crate::rpc::Error::Choice)?
+                .send(request)
+                .map_err(|e| $crate::rpc::Error::Transfer { method: stringify!($method), error: format!("{:?}", e) })?;
+            let (chan, response) = chan.recv()
+                .map_err(|e| $crate::rpc::Error::Transfer { method: stringify!($method), error: format!("{:?}", e) })?;
+            self.chan = Some(chan.zero());
+            Ok(response)
+        }
+
+        $crate::rpc_service!(@stub [$($skip)* cdr] $($rest)*);
+    };
+}
@@ -0,0 +1,150 @@
+//! Rendering of protocols as Mermaid sequence diagrams.
+//!
+//! The endpoint following the protocol is drawn as participant `Me` and its
+//! peer as participant `Peer`.
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::reflect::{ProtocolDesc, Desc, short_name};
+
+#[derive(Default)]
+struct Diagram {
+    out: String,
+    depth: usize,
+}
+
+impl Diagram {
+    fn line<L>(&mut self, line: L) where L: AsRef<str> {
+        for _ in 0 ..= self.depth {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    fn open<L>(&mut self, line: L) where L: AsRef<str> {
+        self.line(line);
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.line("end");
+    }
+
+    fn note<L>(&mut self, text: L) where L: AsRef<str> {
+        self.line(format!("Note over Me,Peer: {}", text.as_ref()));
+    }
+}
+
+/// Render protocol `P` as a Mermaid sequence diagram.
+///
+/// ```
+/// use session_types_ng::{Send, Recv, End, mpsc::Value};
+/// use session_types_ng::mermaid::describe;
+///
+/// let diagram = describe::<Send<Value<u32>, Recv<Value<bool>, End>>>();
+/// assert!(diagram.contains("Me->>Peer: Value<u32>"));
+/// assert!(diagram.contains("Peer->>Me: Value<bool>"));
+/// ```
+pub fn describe<P>() -> String where P: ProtocolDesc {
+    let mut diagram = Diagram::default();
+    diagram.out.push_str("sequenceDiagram\n");
+    diagram.line("participant Me");
+    diagram.line("participant Peer");
+    messages(&mut diagram, &P::desc());
+    diagram.out
+}
+
+/// Add messages of the protocol to the diagram.
+fn messages(diagram: &mut Diagram, desc: &Desc) {
+    let mut desc = desc;
+    loop {
+        desc = match *desc {
+            Desc::End =>
+                return diagram.note("close"),
+            Desc::Send(payload, ref next) => {
+                diagram.line(format!("Me->>Peer: {}", short_name(payload)));
+                next
+            },
+            Desc::Recv(payload, ref next) => {
+                diagram.line(format!("Peer->>Me: {}", short_name(payload)));
+                next
+            },
+            Desc::Bcast(payload, ref next) => {
+                diagram.line(format!("Me->>Peer: {} (broadcast)", short_name(payload)));
+                next
+            },
+            Desc::Scatter(payload, ref next) => {
+                diagram.line(format!("Me->>Peer: {} (scatter)", short_name(payload)));
+                next
+            },
+            Desc::Gather(payload, ref next) => {
+                diagram.line(format!("Peer->>Me: {} (gather)", short_name(payload)));
+                next
+            },
+            Desc::Choose(ref branches) =>
+                return choice(diagram, "Me", branches),
+            Desc::Offer(ref branches) =>
+                return choice(diagram, "Peer", branches),
+            Desc::Labeled(_, ref next) =>
+                next,
+            Desc::Rec(ref body) => {
+                diagram.open("loop");
+                messages(diagram, body);
+                return diagram.close();
+            },
+            Desc::Var(0) =>
+                return diagram.note("continue"),
+            Desc::Var(n) =>
+                return diagram.note(format!("continue outer loop {}", n)),
+            Desc::Loop(label, ref body) => {
+                diagram.open(format!("loop {}", short_name(label)));
+                messages(diagram, body);
+                return diagram.close();
+            },
+            Desc::Goto(label) =>
+                return diagram.note(format!("continue {}", short_name(label))),
+            Desc::RecvTimeout { payload, deadline, ref in_time, ref timed_out } => {
+                diagram.line(format!("Peer->>Me: {}", short_name(payload)));
+                return outcome(diagram, "in time", in_time, &format!("timed out {}", short_name(deadline)), timed_out);
+            },
+            Desc::SendTimeout { payload, deadline, ref in_time, ref timed_out } => {
+                diagram.line(format!("Me->>Peer: {}", short_name(payload)));
+                return outcome(diagram, "in time", in_time, &format!("timed out {}", short_name(deadline)), timed_out);
+            },
+            Desc::Guarded(ref next, ref abort) =>
+                return outcome(diagram, "Me proceeds", next, "Me or Peer aborts", abort),
+            Desc::Checked(ref next, ref abort) =>
+                return outcome(diagram, "Peer proceeds", next, "Peer or Me aborts", abort),
+            Desc::Par(ref left, ref right, ref next) => {
+                diagram.open("par");
+                messages(diagram, left);
+                diagram.depth -= 1;
+                diagram.open("and");
+                messages(diagram, right);
+                diagram.close();
+                next
+            },
+        };
+    }
+}
+
+fn choice(diagram: &mut Diagram, chooser: &str, branches: &[Desc]) {
+    for (index, branch) in branches.iter().enumerate() {
+        let label = branch.label().map(short_name).unwrap_or_else(|| index.to_string());
+        let keyword = if index == 0 { "alt" } else { "else" };
+        diagram.open(format!("{} {} chooses {}", keyword, chooser, label));
+        messages(diagram, branch);
+        diagram.depth -= 1;
+    }
+    diagram.line("end");
+}
+
+fn outcome(diagram: &mut Diagram, proceed: &str, next: &Desc, abort: &str, failure: &Desc) {
+    diagram.open(format!("alt {}", proceed));
+    messages(diagram, next);
+    diagram.depth -= 1;
+    diagram.open(format!("else {}", abort));
+    messages(diagram, failure);
+    diagram.close();
+}
@@ -1,80 +1,709 @@
-use std::thread::spawn;
-use std::mem::transmute;
-use std::sync::mpsc::{Sender, SendError, Receiver, RecvError, channel};
-use super::{ChannelSend, ChannelRecv, Carrier, HasDual, Chan};
+use std::ptr;
+use std::collections::VecDeque;
+use std::thread::{self, spawn};
+use std::panic::{self, AssertUnwindSafe};
+use std::any::{Any, TypeId};
+use std::mem::{size_of, align_of, needs_drop, MaybeUninit};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, SendError, Receiver, RecvError, RecvTimeoutError, channel, sync_channel};
+use super::{ChannelSend, ChannelRecv, Payload, Carrier, HandoverCarrier, BatchCarrier, HasDual, Chan, discard};
+use super::timeout::{TimeoutCarrier, DiscardCarrier};
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+#[cfg(feature = "deadlock")]
+use super::deadlock;
 
 pub struct Channel {
-    tx: Sender<Box<u8>>,
-    rx: Receiver<Box<u8>>,
+    tx: Tx,
+    rx: Receiver<Packet>,
+    /// Number of packets sent by the peer and not taken yet, for a bounded channel.
+    rx_in_flight: Option<Arc<InFlight>>,
+    pending: VecDeque<Packet>,
+    /// Number of packets of the peer to drop on arrival, see `DiscardCarrier`.
+    discard: usize,
+    batch: Option<Vec<Packet>>,
+    #[cfg(feature = "deadlock")]
+    endpoint: deadlock::Endpoint,
+    /// Dropped last, so that the peer is woken once it is disconnected.
+    wakeups: Wakeups,
 }
 
-#[derive(Clone, Debug)]
+/// Sending half of a channel. A bounded one keeps track of packets not
+/// taken by the peer yet, so that its capacity could be checked.
+enum Tx {
+    Unbounded(Sender<Packet>),
+    Bounded {
+        tx: SyncSender<Packet>,
+        capacity: usize,
+        in_flight: Arc<InFlight>,
+    },
+}
+
+/// Count of packets sent over a bounded channel and not taken by the peer
+/// yet. The sender could wait for the peer to take some.
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    lock: Mutex<()>,
+    taken: Condvar,
+}
+
+impl InFlight {
+    fn take(&self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        // a sender checks the count under the lock, so it is either waiting
+        // or will see the new one
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.taken.notify_all();
+    }
+
+    fn wait_below(&self, capacity: usize, timeout: Duration) -> bool {
+        let started = Instant::now();
+        let mut lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        while self.count.load(Ordering::SeqCst) >= capacity {
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining == Duration::from_secs(0) {
+                return false;
+            }
+            lock = self.taken.wait_timeout(lock, remaining).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        true
+    }
+}
+
+/// Task parked on an end of a channel, see `NonBlockingCarrier::register_waker`.
+#[derive(Default)]
+struct Parked {
+    parked: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Tasks parked on both ends of a channel. The peer is woken once it could
+/// make progress: a packet has been sent, one has been taken from a bounded
+/// channel, or the channel has been dropped.
+struct Wakeups {
+    own: Arc<Parked>,
+    peer: Arc<Parked>,
+}
+
+impl Wakeups {
+    fn pair() -> (Wakeups, Wakeups) {
+        let (master, slave) = (Arc::new(Parked::default()), Arc::new(Parked::default()));
+        (Wakeups { own: master.clone(), peer: slave.clone() }, Wakeups { own: slave, peer: master })
+    }
+
+    fn register(&self, waker: &Waker) {
+        *self.own.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(waker.clone());
+        self.own.parked.store(true, Ordering::SeqCst);
+    }
+
+    fn wake_peer(&self) {
+        // nobody is parked most of the time, so the lock is not taken
+        if self.peer.parked.swap(false, Ordering::SeqCst) {
+            if let Some(waker) = self.peer.waker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for Wakeups {
+    fn drop(&mut self) {
+        self.wake_peer();
+    }
+}
+
+impl Tx {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        match *self {
+            Tx::Unbounded(ref tx) =>
+                tx.send(packet),
+            Tx::Bounded { ref tx, ref in_flight, .. } => {
+                in_flight.count.fetch_add(1, Ordering::SeqCst);
+                tx.send(packet).inspect_err(|_| { in_flight.count.fetch_sub(1, Ordering::SeqCst); })
+            },
+        }
+    }
+
+    fn has_capacity(&self) -> bool {
+        match *self {
+            Tx::Unbounded(..) =>
+                true,
+            Tx::Bounded { capacity, ref in_flight, .. } =>
+                in_flight.count.load(Ordering::SeqCst) < capacity,
+        }
+    }
+
+    fn wait_capacity(&self, timeout: Duration) -> bool {
+        match *self {
+            Tx::Unbounded(..) =>
+                true,
+            Tx::Bounded { capacity, ref in_flight, .. } =>
+                in_flight.wait_below(capacity, timeout),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Value<T>(pub T) where T: Send + 'static;
 
-impl<T> ChannelSend for Value<T> where T: Send + 'static {
-    type Crr = Channel;
-    type Err = SendError<Box<T>>;
+impl<T> Payload for Value<T> where T: Send + 'static { }
+
+type Slot = [MaybeUninit<u64>; 2];
+
+/// Type erased value in transit. Small values without destructors (numbers,
+/// choices, ...) are stored inline, so that sending them does not allocate.
+/// Both kinds carry the type of the value, which is checked on arrival.
+enum Packet {
+    Inline(Tag, Slot),
+    Boxed(Tag, Box<dyn Any + Send>),
+    /// Packets sent in batching mode, delivered with a single wakeup.
+    Batch(Vec<Packet>),
+}
+
+/// Type of a value in transit. Debug builds also keep the type name to
+/// report a mismatch.
+#[derive(Clone, Copy)]
+struct Tag {
+    id: TypeId,
+    #[cfg(debug_assertions)]
+    name: &'static str,
+}
 
-    fn send(self, carrier: &mut Self::Crr) -> Result<(), Self::Err> {
-        unsafe {
-            let tx: &Sender<Box<T>> = transmute(&carrier.tx);
-            tx.send(Box::new(self.0))
+impl Tag {
+    fn of<T>() -> Tag where T: 'static {
+        Tag {
+            id: TypeId::of::<T>(),
+            #[cfg(debug_assertions)]
+            name: std::any::type_name::<T>(),
         }
     }
+
+    /// Panics if the value is not of type `T`, which is only possible when
+    /// the sides do not follow dual protocols, e.g. after an `unsafe impl
+    /// HasDual` which is not a mirror image.
+    fn check<T>(self) where T: 'static {
+        if self.id != TypeId::of::<T>() {
+            self.mismatch::<T>();
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn mismatch<T>(self) -> ! {
+        panic!("mpsc: session types of the peers disagree: expected to receive `{}`, but `{}` has been sent",
+               std::any::type_name::<T>(), self.name)
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn mismatch<T>(self) -> ! {
+        panic!("mpsc: session types of the peers disagree: received a value of unexpected type")
+    }
 }
 
-impl<T> ChannelRecv for Value<T> where T: Sized + Send + 'static {
-    type Crr = Channel;
-    type Err = RecvError;
+fn is_inline<T>() -> bool {
+    size_of::<T>() <= size_of::<Slot>() && align_of::<T>() <= align_of::<Slot>() && !needs_drop::<T>()
+}
+
+fn pack<T>(value: T) -> Packet where T: Send + 'static {
+    if is_inline::<T>() {
+        let mut slot = [MaybeUninit::uninit(); 2];
+        // Safety: `T` fits into the slot by size and alignment.
+        unsafe { ptr::write(slot.as_mut_ptr() as *mut T, value) };
+        Packet::Inline(Tag::of::<T>(), slot)
+    } else {
+        Packet::Boxed(Tag::of::<T>(), Box::new(value))
+    }
+}
+
+fn unpack<T>(packet: Packet) -> T where T: Send + 'static {
+    match packet {
+        Packet::Inline(tag, slot) => {
+            tag.check::<T>();
+            // Safety: the slot has been written by `pack::<T>`, and `T` has no destructor.
+            unsafe { ptr::read(slot.as_ptr() as *const T) }
+        },
+        Packet::Boxed(tag, boxed) => {
+            tag.check::<T>();
+            *boxed.downcast::<T>().unwrap_or_else(|_| tag.mismatch::<T>())
+        },
+        Packet::Batch(..) =>
+            unreachable!("batches are unpacked on arrival"),
+    }
+}
+
+impl Channel {
+    #[cfg(not(feature = "deadlock"))]
+    fn recv_packet(&self) -> Result<Packet, RecvError> {
+        self.rx.recv()
+    }
+
+    #[cfg(feature = "deadlock")]
+    fn recv_packet(&self) -> Result<Packet, RecvError> {
+        self.endpoint.recv(&self.rx)
+    }
+
+    /// Take a packet which has arrived from the peer.
+    fn enqueue(&mut self, packet: Packet) {
+        if let Some(ref in_flight) = self.rx_in_flight {
+            in_flight.take();
+            self.wakeups.wake_peer();
+        }
+        match packet {
+            Packet::Batch(packets) =>
+                self.pending.extend(packets),
+            packet =>
+                self.pending.push_back(packet),
+        }
+        self.drop_discarded();
+    }
+
+    /// Drop the packets which have arrived too late.
+    fn drop_discarded(&mut self) {
+        while self.discard > 0 && self.pending.pop_front().is_some() {
+            self.discard -= 1;
+        }
+    }
+
+    fn send_packet(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        self.tx.send(packet)?;
+        self.wakeups.wake_peer();
+        Ok(())
+    }
 
-    fn recv(carrier: &mut Self::Crr) -> Result<Self, Self::Err> {
-        unsafe {
-            let rx: &Receiver<Box<T>> = transmute(&carrier.rx);
-            rx.recv().map(|v| Value(*v))
+    fn flush_batch(&mut self) -> Result<(), SendError<()>> {
+        if let Some(ref mut batch) = self.batch {
+            if !batch.is_empty() {
+                let packets = std::mem::take(batch);
+                self.send_packet(Packet::Batch(packets)).map_err(|_| SendError(()))?;
+            }
         }
+        Ok(())
+    }
+}
+
+pub(crate) fn send_value<T>(carrier: &mut Channel, value: T) -> Result<(), SendError<Box<T>>> where T: Send + 'static {
+    if let Some(ref mut batch) = carrier.batch {
+        batch.push(pack(value));
+        return Ok(());
+    }
+    carrier.send_packet(pack(value))
+        .map_err(|SendError(packet)| SendError(Box::new(unpack(packet))))
+}
+
+/// Take the next packet of the peer, waiting for it to arrive.
+fn next_packet(carrier: &mut Channel) -> Result<Packet, RecvError> {
+    carrier.flush_batch().map_err(|_| RecvError)?;
+    while carrier.pending.is_empty() {
+        let packet = carrier.recv_packet()?;
+        carrier.enqueue(packet);
+    }
+    Ok(carrier.pending.pop_front().expect("a packet has just been received"))
+}
+
+pub(crate) fn recv_value<T>(carrier: &mut Channel) -> Result<T, RecvError> where T: Send + 'static {
+    next_packet(carrier).map(unpack)
+}
+
+impl<T> ChannelSend<Channel> for Value<T> where T: Send + 'static {
+    type Err = SendError<Box<T>>;
+
+    fn send(self, carrier: &mut Channel) -> Result<(), Self::Err> {
+        send_value(carrier, self.0)
+    }
+}
+
+impl<T> ChannelRecv<Channel> for Value<T> where T: Sized + Send + 'static {
+    type Err = RecvError;
+
+    fn recv(carrier: &mut Channel) -> Result<Self, Self::Err> {
+        recv_value(carrier).map(Value)
+    }
+}
+
+/// Session delegation: an in-progress session channel could be sent over
+/// another channel, e.g. `Send<Chan<SR, E, P>, Q>`. On failure the error
+/// still owns the delegated channel.
+impl<SR, E, P> ChannelSend<Channel> for Chan<SR, E, P> where Chan<SR, E, P>: Send + 'static {
+    type Err = SendError<Box<Chan<SR, E, P>>>;
+
+    fn send(self, carrier: &mut Channel) -> Result<(), Self::Err> {
+        send_value(carrier, self)
+    }
+}
+
+impl<SR, E, P> ChannelRecv<Channel> for Chan<SR, E, P> where Chan<SR, E, P>: Send + 'static {
+    type Err = RecvError;
+
+    fn recv(carrier: &mut Channel) -> Result<Self, Self::Err> {
+        recv_value(carrier)
+    }
+}
+
+/// Handover mark, a value of a type private to this module.
+struct Handover;
+
+/// A mark which could not be sent is reported as a choice.
+impl HandoverCarrier for Channel {
+    fn send_handover(&mut self) -> Result<(), Self::SendChoiceErr> {
+        send_value(self, Handover).map_err(|_| SendError(Box::new(true)))
+    }
+
+    fn recv_handover(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        let packet = next_packet(self)?;
+        let handover = match packet {
+            Packet::Inline(tag, _) | Packet::Boxed(tag, _) => tag.id == TypeId::of::<Handover>(),
+            Packet::Batch(..) => unreachable!("batches are unpacked on arrival"),
+        };
+        // a delegated session is closed, so that its peer fails instead of waiting
+        discard(packet);
+        Ok(handover)
     }
 }
 
 impl Carrier for Channel {
     type SendChoiceErr = SendError<Box<bool>>;
     fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
-        Value(choice).send(self)
+        send_value(self, choice)
     }
 
     type RecvChoiceErr = RecvError;
     fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
-        Value::recv(self).map(|Value(value)| value)
+        recv_value(self)
+    }
+
+    #[cfg(feature = "deadlock")]
+    fn step(&mut self, protocol: &'static str) {
+        self.endpoint.step(protocol);
+    }
+}
+
+impl TimeoutCarrier for Channel {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.flush_batch().map_err(|_| RecvError)?;
+        let started = Instant::now();
+        while self.pending.is_empty() {
+            match self.rx.recv_timeout(timeout.saturating_sub(started.elapsed())) {
+                Ok(v) =>
+                    self.enqueue(v),
+                Err(RecvTimeoutError::Timeout) =>
+                    return Ok(false),
+                Err(RecvTimeoutError::Disconnected) =>
+                    return Err(RecvError),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl DiscardCarrier for Channel {
+    fn discard_next(&mut self) {
+        self.discard += 1;
+        self.drop_discarded();
+    }
+}
+
+/// All values sent in batching mode are delivered to the peer at once on `flush`.
+impl BatchCarrier for Channel {
+    type FlushErr = SendError<()>;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.flush_batch()?;
+        self.batch = if enabled { Some(Vec::new()) } else { None };
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.flush_batch()
     }
 }
 
 /// Returns two session channels
+///
+/// Every value is tagged with its type, so that a receive panics on a value
+/// of another type instead of reading garbage. It could only happen if the
+/// sides do not follow dual protocols, e.g. when channels are built with
+/// `Chan::new` over recovered carriers. Debug builds report both type names:
+///
+/// ```should_panic
+/// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+///
+/// let (tx, rx) = mpsc::session_channel::<End>();
+/// let tx: Chan<_, (), Send<mpsc::Value<u32>, End>> = Chan::new(tx.close_and_recover());
+/// let rx: Chan<_, (), Recv<mpsc::Value<String>, End>> = Chan::new(rx.close_and_recover());
+/// tx.send(mpsc::Value(42)).unwrap().close();
+/// // expected to receive `alloc::string::String`, but `u32` has been sent
+/// let _ = rx.recv();
+/// ```
 #[must_use]
 pub fn session_channel<P: HasDual>() -> (Chan<Channel, (), P>, Chan<Channel, (), P::Dual>) {
     let (master_tx, slave_rx) = channel();
     let (slave_tx, master_rx) = channel();
 
+    #[cfg(feature = "deadlock")]
+    let (master_endpoint, slave_endpoint) = deadlock::pair();
+    let (master_wakeups, slave_wakeups) = Wakeups::pair();
+
     let master_carrier = Channel {
-        tx: master_tx,
+        tx: Tx::Unbounded(master_tx),
         rx: master_rx,
+        rx_in_flight: None,
+        pending: VecDeque::new(),
+        discard: 0,
+        batch: None,
+        #[cfg(feature = "deadlock")]
+        endpoint: master_endpoint,
+        wakeups: master_wakeups,
     };
     let slave_carrier = Channel {
-        tx: slave_tx,
+        tx: Tx::Unbounded(slave_tx),
         rx: slave_rx,
+        rx_in_flight: None,
+        pending: VecDeque::new(),
+        discard: 0,
+        batch: None,
+        #[cfg(feature = "deadlock")]
+        endpoint: slave_endpoint,
+        wakeups: slave_wakeups,
     };
 
     (Chan::new(master_carrier),
      Chan::new(slave_carrier))
 }
 
-/// Connect two functions using a session typed channel.
-pub fn connect<FM, FS, P>(master_fn: FM, slave_fn: FS) where
-    FM: Fn(Chan<Channel, (), P>) + Send,
-    FS: Fn(Chan<Channel, (), P::Dual>) + Send + 'static,
+/// Returns two session channels where at most `capacity` messages could be
+/// in flight in each direction: a send blocks while the peer has not taken
+/// enough of them. Use `CapacityCarrier::has_capacity` or `chan_select!`
+/// send arms to avoid blocking.
+///
+/// ```
+/// # #[macro_use] extern crate session_types_ng;
+/// # use session_types_ng::*;
+/// # use session_types_ng::mpsc::Value;
+/// # fn main() {
+/// type Producer = Send<Value<u8>, Send<Value<u8>, End>>;
+///
+/// let (tx, rx) = mpsc::bounded_session_channel::<Producer>(1);
+/// let mut tx = tx.send(Value(1)).unwrap();
+/// let rx = chan_select! {
+///     _ = tx.send(Value(2)) => panic!("the buffer is full"),
+///     default => {
+///         let (rx, Value(first)) = rx.recv().unwrap();
+///         assert_eq!(first, 1);
+///         tx.send(Value(2)).unwrap().close();
+///         rx
+///     },
+/// };
+/// let (rx, Value(second)) = rx.recv().unwrap();
+/// assert_eq!(second, 2);
+/// rx.close();
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+#[must_use]
+pub fn bounded_session_channel<P: HasDual>(capacity: usize) -> (Chan<Channel, (), P>, Chan<Channel, (), P::Dual>) {
+    assert!(capacity > 0, "bounded session channel capacity should be positive");
+    let (master_tx, slave_rx) = sync_channel(capacity);
+    let (slave_tx, master_rx) = sync_channel(capacity);
+    let master_in_flight = Arc::new(InFlight::default());
+    let slave_in_flight = Arc::new(InFlight::default());
+
+    #[cfg(feature = "deadlock")]
+    let (master_endpoint, slave_endpoint) = deadlock::pair();
+    let (master_wakeups, slave_wakeups) = Wakeups::pair();
+
+    let master_carrier = Channel {
+        tx: Tx::Bounded { tx: master_tx, capacity, in_flight: master_in_flight.clone() },
+        rx: master_rx,
+        rx_in_flight: Some(slave_in_flight.clone()),
+        pending: VecDeque::new(),
+        discard: 0,
+        batch: None,
+        #[cfg(feature = "deadlock")]
+        endpoint: master_endpoint,
+        wakeups: master_wakeups,
+    };
+    let slave_carrier = Channel {
+        tx: Tx::Bounded { tx: slave_tx, capacity, in_flight: slave_in_flight },
+        rx: slave_rx,
+        rx_in_flight: Some(master_in_flight),
+        pending: VecDeque::new(),
+        discard: 0,
+        batch: None,
+        #[cfg(feature = "deadlock")]
+        endpoint: slave_endpoint,
+        wakeups: slave_wakeups,
+    };
+
+    (Chan::new(master_carrier),
+     Chan::new(slave_carrier))
+}
+
+/// An unbounded channel always has capacity. A bounded one has it while
+/// fewer than `capacity` of the packets sent are waiting for the peer to
+/// take them, so that the next send won't block, and waiting for capacity
+/// wakes up as soon as the peer takes one.
+impl CapacityCarrier for Channel {
+    fn has_capacity(&mut self) -> bool {
+        self.tx.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.tx.wait_capacity(timeout)
+    }
+}
+
+/// Hung up peer is reported as `RecvError` by both operations. A parked
+/// task is woken by the peer once it has sent or taken a packet, or has
+/// hung up.
+impl NonBlockingCarrier for Channel {
+    type PollErr = RecvError;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        if !self.tx.has_capacity() {
+            return Err(PollError::WouldBlock);
+        }
+        self.flush_batch().map_err(|_| PollError::Carrier(RecvError))
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        match self.wait_timeout(Duration::from_secs(0)) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(PollError::WouldBlock),
+            Err(e) => Err(PollError::Carrier(e)),
+        }
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.wakeups.register(waker);
+    }
+}
+
+/// Connect two functions using a session typed channel, returning results
+/// of both of them. `slave_fn` runs in a new thread, a panic in it is
+/// propagated to the caller.
+///
+/// ```
+/// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+///
+/// type Answer = Send<mpsc::Value<u32>, End>;
+///
+/// let (sent, received) = mpsc::connect(
+///     |c: Chan<mpsc::Channel, (), Answer>| c.send(mpsc::Value(42)).unwrap().close(),
+///     |c: Chan<mpsc::Channel, (), Recv<mpsc::Value<u32>, End>>| {
+///         let (c, mpsc::Value(answer)) = c.recv().unwrap();
+///         c.close();
+///         answer
+///     });
+/// assert_eq!((sent, received), ((), 42));
+/// ```
+pub fn connect<FM, FS, P, R1, R2>(master_fn: FM, slave_fn: FS) -> (R1, R2) where
+    FM: FnOnce(Chan<Channel, (), P>) -> R1,
+    FS: FnOnce(Chan<Channel, (), P::Dual>) -> R2 + Send + 'static,
+    P: HasDual + Send + 'static,
+    <P as HasDual>::Dual: HasDual + Send + 'static,
+    R2: Send + 'static
+{
+    let (master, slave) = session_channel();
+    let thread = spawn(move || slave_fn(slave));
+    let master_result = master_fn(master);
+    match thread.join() {
+        Ok(slave_result) => (master_result, slave_result),
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Same as `connect`, but the functions run in a thread scope, so that they
+/// could borrow from the stack of the caller.
+///
+/// ```
+/// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+///
+/// type Answer = Send<mpsc::Value<u32>, End>;
+///
+/// let answer = 42;
+/// let mut received = Vec::new();
+/// mpsc::connect_scoped(
+///     |c: Chan<mpsc::Channel, (), Answer>| c.send(mpsc::Value(answer)).unwrap().close(),
+///     |c: Chan<mpsc::Channel, (), Recv<mpsc::Value<u32>, End>>| {
+///         let (c, mpsc::Value(answer)) = c.recv().unwrap();
+///         c.close();
+///         received.push(answer);
+///     });
+/// assert_eq!(received, [42]);
+/// ```
+pub fn connect_scoped<FM, FS, P, R1, R2>(master_fn: FM, slave_fn: FS) -> (R1, R2) where
+    FM: FnOnce(Chan<Channel, (), P>) -> R1,
+    FS: FnOnce(Chan<Channel, (), P::Dual>) -> R2 + Send,
+    P: HasDual + Send,
+    <P as HasDual>::Dual: HasDual + Send,
+    R2: Send
+{
+    let (master, slave) = session_channel();
+    thread::scope(|scope| {
+        let thread = scope.spawn(move || slave_fn(slave));
+        let master_result = master_fn(master);
+        match thread.join() {
+            Ok(slave_result) => (master_result, slave_result),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+/// Failure of one of the sides connected by `connect_result`.
+#[derive(Debug)]
+pub enum ConnectError<E1, E2> {
+    /// The master function has returned an error.
+    Master(E1),
+    /// The slave function has returned an error.
+    Slave(E2),
+    /// The master function has panicked with the payload.
+    MasterPanicked(Box<dyn Any + Send>),
+    /// The slave function has panicked with the payload.
+    SlavePanicked(Box<dyn Any + Send>),
+}
+
+/// Same as `connect` for fallible functions: an error or a panic of either
+/// side is returned instead of unwinding, failures of the master side are
+/// reported first. Both sides are run to completion in any case.
+///
+/// ```
+/// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+/// use session_types_ng::mpsc::ConnectError;
+///
+/// type Answer = Send<mpsc::Value<u32>, End>;
+///
+/// let result = mpsc::connect_result(
+///     |c: Chan<mpsc::Channel, (), Answer>| c.send(mpsc::Value(13)).map(|c| c.close()).map_err(|_| "hung up"),
+///     |c: Chan<mpsc::Channel, (), Recv<mpsc::Value<u32>, End>>| {
+///         let (c, mpsc::Value(answer)) = c.recv().map_err(|_| "hung up")?;
+///         c.close();
+///         if answer == 42 { Ok(answer) } else { Err("wrong answer") }
+///     });
+/// assert!(matches!(result, Err(ConnectError::Slave("wrong answer"))));
+/// ```
+pub fn connect_result<FM, FS, P, R1, R2, E1, E2>(master_fn: FM, slave_fn: FS) -> Result<(R1, R2), ConnectError<E1, E2>> where
+    FM: FnOnce(Chan<Channel, (), P>) -> Result<R1, E1>,
+    FS: FnOnce(Chan<Channel, (), P::Dual>) -> Result<R2, E2> + Send + 'static,
     P: HasDual + Send + 'static,
-    <P as HasDual>::Dual: HasDual + Send + 'static
+    <P as HasDual>::Dual: HasDual + Send + 'static,
+    R2: Send + 'static,
+    E2: Send + 'static
 {
     let (master, slave) = session_channel();
     let thread = spawn(move || slave_fn(slave));
-    master_fn(master);
-    thread.join().unwrap();
+    let master_result = panic::catch_unwind(AssertUnwindSafe(|| master_fn(master)));
+    let slave_result = thread.join();
+    match (master_result, slave_result) {
+        (Err(payload), _) => Err(ConnectError::MasterPanicked(payload)),
+        (Ok(Err(e)), _) => Err(ConnectError::Master(e)),
+        (Ok(Ok(..)), Err(payload)) => Err(ConnectError::SlavePanicked(payload)),
+        (Ok(Ok(..)), Ok(Err(e))) => Err(ConnectError::Slave(e)),
+        (Ok(Ok(master)), Ok(Ok(slave))) => Ok((master, slave)),
+    }
 }
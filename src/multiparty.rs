@@ -0,0 +1,301 @@
+//! Multiparty session types.
+//!
+//! A global protocol describes interactions among several roles. It is
+//! projected to a local protocol for every role, which is then followed by
+//! a `RoleChan` of that role.
+use std::any::Any;
+use std::marker::PhantomData;
+use std::sync::mpsc::{Sender, Receiver, channel};
+use super::{Z, S, End, Same};
+
+/// Role number `N` (Peano) of a multiparty protocol
+pub struct Role<N>(PhantomData<N>);
+
+pub type R0 = Role<Z>;
+pub type R1 = Role<S<Z>>;
+pub type R2 = Role<S<S<Z>>>;
+pub type R3 = Role<S<S<S<Z>>>>;
+
+/// Global protocol: role `F` sends `A` to role `T`, then `G`
+pub struct Msg<F, T, A, G>(PhantomData<(F, T, A, G)>);
+
+/// Global protocol: role `F` chooses between `G1` and `G2` and informs role `T`.
+/// All other roles must behave the same way in both branches.
+pub struct Branch<F, T, G1, G2>(PhantomData<(F, T, G1, G2)>);
+
+/// Global protocol end, also local protocol end.
+pub type GEnd = End;
+
+/// Local protocol: send `A` to role `T`, then `L`
+pub struct SendTo<T, A, L>(PhantomData<(T, A, L)>);
+
+/// Local protocol: receive `A` from role `F`, then `L`
+pub struct RecvFrom<F, A, L>(PhantomData<(F, A, L)>);
+
+/// Local protocol: choose between `L1` and `L2`, informing role `T`
+pub struct ChooseTo<T, L1, L2>(PhantomData<(T, L1, L2)>);
+
+/// Local protocol: role `F` chooses between `L1` and `L2`
+pub struct OfferFrom<F, L1, L2>(PhantomData<(F, L1, L2)>);
+
+pub struct True;
+pub struct False;
+
+/// Type level role equality.
+pub trait RoleEq<R> {
+    type Out;
+}
+
+impl RoleEq<Role<Z>> for Role<Z> {
+    type Out = True;
+}
+
+impl<N> RoleEq<Role<S<N>>> for Role<Z> {
+    type Out = False;
+}
+
+impl<N> RoleEq<Role<Z>> for Role<S<N>> {
+    type Out = False;
+}
+
+impl<N, M> RoleEq<Role<S<M>>> for Role<S<N>> where Role<N>: RoleEq<Role<M>> {
+    type Out = <Role<N> as RoleEq<Role<M>>>::Out;
+}
+
+pub trait RoleIndex {
+    fn index() -> usize;
+}
+
+impl RoleIndex for Role<Z> {
+    fn index() -> usize {
+        0
+    }
+}
+
+impl<N> RoleIndex for Role<S<N>> where Role<N>: RoleIndex {
+    fn index() -> usize {
+        Role::<N>::index() + 1
+    }
+}
+
+/// Projection of a global protocol to the local protocol of role `R`.
+pub trait Project<R> {
+    type Local;
+}
+
+impl<R> Project<R> for End {
+    type Local = End;
+}
+
+impl<F, T, A, G, R> Project<R> for Msg<F, T, A, G>
+    where G: Project<R>,
+          R: RoleEq<F> + RoleEq<T>,
+          (<R as RoleEq<F>>::Out, <R as RoleEq<T>>::Out): ProjectMsg<F, T, A, G::Local>
+{
+    type Local = <(<R as RoleEq<F>>::Out, <R as RoleEq<T>>::Out) as ProjectMsg<F, T, A, G::Local>>::Local;
+}
+
+impl<F, T, G1, G2, R> Project<R> for Branch<F, T, G1, G2>
+    where G1: Project<R>,
+          G2: Project<R>,
+          R: RoleEq<F> + RoleEq<T>,
+          (<R as RoleEq<F>>::Out, <R as RoleEq<T>>::Out): ProjectBranch<F, T, G1::Local, G2::Local>
+{
+    type Local = <(<R as RoleEq<F>>::Out, <R as RoleEq<T>>::Out) as ProjectBranch<F, T, G1::Local, G2::Local>>::Local;
+}
+
+/// Projection helper for `Msg`, selected by whether the role is the sender or the receiver.
+pub trait ProjectMsg<F, T, A, L> {
+    type Local;
+}
+
+impl<F, T, A, L> ProjectMsg<F, T, A, L> for (True, False) {
+    type Local = SendTo<T, A, L>;
+}
+
+impl<F, T, A, L> ProjectMsg<F, T, A, L> for (False, True) {
+    type Local = RecvFrom<F, A, L>;
+}
+
+impl<F, T, A, L> ProjectMsg<F, T, A, L> for (False, False) {
+    type Local = L;
+}
+
+/// Projection helper for `Branch`, selected by whether the role is the chooser or the informed one.
+pub trait ProjectBranch<F, T, L1, L2> {
+    type Local;
+}
+
+impl<F, T, L1, L2> ProjectBranch<F, T, L1, L2> for (True, False) {
+    type Local = ChooseTo<T, L1, L2>;
+}
+
+impl<F, T, L1, L2> ProjectBranch<F, T, L1, L2> for (False, True) {
+    type Local = OfferFrom<F, L1, L2>;
+}
+
+impl<F, T, L1, L2> ProjectBranch<F, T, L1, L2> for (False, False) where L2: Same<L1> {
+    type Local = L1;
+}
+
+type Packet = Box<dyn Any + Send>;
+
+/// In-process carrier connecting one role with all the others.
+pub struct Mesh {
+    txs: Vec<Option<Sender<Packet>>>,
+    rxs: Vec<Option<Receiver<Packet>>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The peer role has hung up.
+    Disconnected,
+    /// The peer has sent a value of unexpected type.
+    Mismatch,
+}
+
+impl Mesh {
+    /// Build carriers for `roles` fully connected roles, indexed by role number.
+    pub fn new(roles: usize) -> Vec<Mesh> {
+        let mut meshes: Vec<_> = (0 .. roles)
+            .map(|_| Mesh {
+                txs: (0 .. roles).map(|_| None).collect(),
+                rxs: (0 .. roles).map(|_| None).collect(),
+            })
+            .collect();
+        for from in 0 .. roles {
+            for to in 0 .. roles {
+                if from != to {
+                    let (tx, rx) = channel();
+                    meshes[from].txs[to] = Some(tx);
+                    meshes[to].rxs[from] = Some(rx);
+                }
+            }
+        }
+        meshes
+    }
+
+    fn send<T>(&mut self, to: usize, value: T) -> Result<(), Error> where T: Send + 'static {
+        self.txs[to].as_ref()
+            .ok_or(Error::Disconnected)?
+            .send(Box::new(value))
+            .map_err(|_| Error::Disconnected)
+    }
+
+    fn recv<T>(&mut self, from: usize) -> Result<T, Error> where T: Send + 'static {
+        let packet = self.rxs[from].as_ref()
+            .ok_or(Error::Disconnected)?
+            .recv()
+            .map_err(|_| Error::Disconnected)?;
+        packet.downcast().map(|v| *v).map_err(|_| Error::Mismatch)
+    }
+}
+
+/// Session channel of role `Me` following local protocol `L`.
+#[must_use]
+pub struct RoleChan<Me, L> {
+    mesh: Mesh,
+    _marker: PhantomData<(Me, L)>,
+}
+
+fn cast<Me, L, M>(chan: RoleChan<Me, L>) -> RoleChan<Me, M> {
+    RoleChan {
+        mesh: chan.mesh,
+        _marker: PhantomData,
+    }
+}
+
+/// Start a session of the global protocol `G` for the role `R` over its carrier.
+pub fn session<G, R>(mesh: Mesh) -> RoleChan<R, G::Local> where G: Project<R> {
+    RoleChan {
+        mesh,
+        _marker: PhantomData,
+    }
+}
+
+/// Start a session of the global protocol `G` with three roles.
+#[must_use]
+pub fn session3<G>() -> (RoleChan<R0, <G as Project<R0>>::Local>,
+                         RoleChan<R1, <G as Project<R1>>::Local>,
+                         RoleChan<R2, <G as Project<R2>>::Local>)
+    where G: Project<R0> + Project<R1> + Project<R2>
+{
+    let mut meshes = Mesh::new(3).into_iter();
+    let r0 = session::<G, R0>(meshes.next().unwrap());
+    let r1 = session::<G, R1>(meshes.next().unwrap());
+    let r2 = session::<G, R2>(meshes.next().unwrap());
+    (r0, r1, r2)
+}
+
+/// Start a session of the global protocol `G` with four roles.
+#[must_use]
+pub fn session4<G>() -> (RoleChan<R0, <G as Project<R0>>::Local>,
+                         RoleChan<R1, <G as Project<R1>>::Local>,
+                         RoleChan<R2, <G as Project<R2>>::Local>,
+                         RoleChan<R3, <G as Project<R3>>::Local>)
+    where G: Project<R0> + Project<R1> + Project<R2> + Project<R3>
+{
+    let mut meshes = Mesh::new(4).into_iter();
+    let r0 = session::<G, R0>(meshes.next().unwrap());
+    let r1 = session::<G, R1>(meshes.next().unwrap());
+    let r2 = session::<G, R2>(meshes.next().unwrap());
+    let r3 = session::<G, R3>(meshes.next().unwrap());
+    (r0, r1, r2, r3)
+}
+
+/// Outcome of a choice made by another role.
+pub enum Chosen<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<Me> RoleChan<Me, End> {
+    /// Close the session for this role.
+    pub fn close(self) { }
+}
+
+impl<Me, T, A, L> RoleChan<Me, SendTo<T, A, L>> where T: RoleIndex, A: Send + 'static {
+    /// Send a value to the role `T`.
+    #[must_use]
+    pub fn send(mut self, v: A) -> Result<RoleChan<Me, L>, Error> {
+        self.mesh.send(T::index(), v)?;
+        Ok(cast(self))
+    }
+}
+
+impl<Me, F, A, L> RoleChan<Me, RecvFrom<F, A, L>> where F: RoleIndex, A: Send + 'static {
+    /// Receive a value from the role `F`.
+    #[must_use]
+    pub fn recv(mut self) -> Result<(RoleChan<Me, L>, A), Error> {
+        let v = self.mesh.recv(F::index())?;
+        Ok((cast(self), v))
+    }
+}
+
+impl<Me, T, L1, L2> RoleChan<Me, ChooseTo<T, L1, L2>> where T: RoleIndex {
+    /// Choose the first branch.
+    #[must_use]
+    pub fn first(mut self) -> Result<RoleChan<Me, L1>, Error> {
+        self.mesh.send(T::index(), true)?;
+        Ok(cast(self))
+    }
+
+    /// Choose the second branch.
+    #[must_use]
+    pub fn second(mut self) -> Result<RoleChan<Me, L2>, Error> {
+        self.mesh.send(T::index(), false)?;
+        Ok(cast(self))
+    }
+}
+
+impl<Me, F, L1, L2> RoleChan<Me, OfferFrom<F, L1, L2>> where F: RoleIndex {
+    /// Learn the choice made by the role `F`.
+    #[must_use]
+    pub fn offer(mut self) -> Result<Chosen<RoleChan<Me, L1>, RoleChan<Me, L2>>, Error> {
+        if self.mesh.recv(F::index())? {
+            Ok(Chosen::First(cast(self)))
+        } else {
+            Ok(Chosen::Second(cast(self)))
+        }
+    }
+}
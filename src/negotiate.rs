@@ -0,0 +1,164 @@
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use super::{HasDual, Chan, Nil};
+use super::wire::WireFormat;
+use super::stream::{Channel, HandshakeError};
+use super::fingerprint::Fingerprint;
+
+/// A list of supported protocol versions: `P` followed by older versions `L`.
+/// The list is terminated with `Nil`, e.g. `Version<V3, Version<V2, Version<V1, Nil>>>`.
+pub struct Version<P, L>(PhantomData<(P, L)>);
+
+/// A session channel for the negotiated version: either `P` itself
+/// (`Version`) or one of the older versions (`Older`).
+pub enum Negotiated<C, L> {
+    Version(C),
+    Older(L),
+}
+
+/// No version is left in the list. This type has no values.
+pub enum NoVersion {}
+
+pub trait Versions<S, F> {
+    type Chans;
+
+    fn fingerprints(out: &mut Vec<u64>);
+    fn dual_fingerprints(out: &mut Vec<u64>);
+    fn select(carrier: Channel<S, F>, index: usize) -> Self::Chans;
+}
+
+impl<S, F> Versions<S, F> for Nil {
+    type Chans = NoVersion;
+
+    fn fingerprints(_out: &mut Vec<u64>) { }
+    fn dual_fingerprints(_out: &mut Vec<u64>) { }
+
+    fn select(_carrier: Channel<S, F>, _index: usize) -> Self::Chans {
+        panic!("session protocol version list out of range")
+    }
+}
+
+impl<P, L, S, F> Versions<S, F> for Version<P, L>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          L: Versions<S, F>
+{
+    type Chans = Negotiated<Chan<Channel<S, F>, (), P>, L::Chans>;
+
+    fn fingerprints(out: &mut Vec<u64>) {
+        out.push(P::fingerprint());
+        L::fingerprints(out);
+    }
+
+    fn dual_fingerprints(out: &mut Vec<u64>) {
+        out.push(P::Dual::fingerprint());
+        L::dual_fingerprints(out);
+    }
+
+    fn select(carrier: Channel<S, F>, index: usize) -> Self::Chans {
+        if index == 0 {
+            Negotiated::Version(Chan::new(carrier))
+        } else {
+            Negotiated::Older(L::select(carrier, index - 1))
+        }
+    }
+}
+
+/// Exchange supported protocol versions with the peer and start a session
+/// for the newest version known to both sides. Versions in `V` should be
+/// listed newest first on both sides.
+///
+/// ```
+/// use std::thread;
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Recv, End, Nil, Dual};
+/// use session_types_ng::stream::{self, Value};
+/// use session_types_ng::wire::Bincode;
+/// use session_types_ng::negotiate::{negotiate, Version, Negotiated};
+///
+/// type V1 = Recv<Value<u32>, End>;
+/// type V2 = Recv<Value<u64>, End>;
+/// type V3 = Recv<Value<String>, End>;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (server, _) = listener.accept().unwrap();
+///
+/// // the client is one version behind the server
+/// let client = thread::spawn(move || {
+///     match negotiate::<Version<Dual<V2>, Version<Dual<V1>, Nil>>, _, Bincode>(stream::Channel::new(client)).unwrap() {
+///         Negotiated::Version(chan) => chan.send(Value(42)).unwrap().close(),
+///         Negotiated::Older(_) => panic!("both sides know V2"),
+///     }
+/// });
+/// match negotiate::<Version<V3, Version<V2, Version<V1, Nil>>>, _, Bincode>(stream::Channel::new(server)).unwrap() {
+///     Negotiated::Older(Negotiated::Version(chan)) => {
+///         let (chan, Value(answer)) = chan.recv().unwrap();
+///         assert_eq!(answer, 42u64);
+///         chan.close();
+///     },
+///     _ => panic!("V2 is the newest common version"),
+/// }
+/// client.join().unwrap();
+/// ```
+///
+/// Both sides fail if they have no version in common, or if they list
+/// their common versions in different order:
+///
+/// ```
+/// use std::thread;
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Recv, End, Nil, Dual};
+/// use session_types_ng::stream::{self, Value, HandshakeError};
+/// use session_types_ng::wire::Bincode;
+/// use session_types_ng::negotiate::{negotiate, Version};
+///
+/// type V1 = Recv<Value<u32>, End>;
+/// type V2 = Recv<Value<u64>, End>;
+///
+/// fn sockets() -> (TcpStream, TcpStream) {
+///     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+///     let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+///     (client, listener.accept().unwrap().0)
+/// }
+///
+/// let (client, server) = sockets();
+/// let client = thread::spawn(move || {
+///     negotiate::<Version<Dual<V1>, Nil>, _, Bincode>(stream::Channel::new(client)).err()
+/// });
+/// let server = negotiate::<Version<V2, Nil>, _, Bincode>(stream::Channel::new(server)).err();
+/// assert!(matches!(server, Some(HandshakeError::NoCommonVersion)));
+/// assert!(matches!(client.join().unwrap(), Some(HandshakeError::NoCommonVersion)));
+///
+/// let (client, server) = sockets();
+/// let client = thread::spawn(move || {
+///     negotiate::<Version<Dual<V1>, Version<Dual<V2>, Nil>>, _, Bincode>(stream::Channel::new(client)).err()
+/// });
+/// let server = negotiate::<Version<V2, Version<V1, Nil>>, _, Bincode>(stream::Channel::new(server)).err();
+/// assert!(matches!(server, Some(HandshakeError::VersionOrder)));
+/// assert!(matches!(client.join().unwrap(), Some(HandshakeError::VersionOrder)));
+/// ```
+pub fn negotiate<V, S, F>(mut carrier: Channel<S, F>) -> Result<V::Chans, HandshakeError<F::Err>>
+    where V: Versions<S, F>,
+          S: Read + Write,
+          F: WireFormat
+{
+    let mut local = Vec::new();
+    V::fingerprints(&mut local);
+    let mut local_dual = Vec::new();
+    V::dual_fingerprints(&mut local_dual);
+
+    carrier.send_value(&local).map_err(HandshakeError::Carrier)?;
+    let remote: Vec<u64> = carrier.recv_value().map_err(HandshakeError::Carrier)?;
+
+    let ours = local_dual.iter().position(|fp| remote.contains(fp));
+    let theirs = remote.iter().position(|fp| local_dual.contains(fp));
+    match (ours, theirs) {
+        (Some(index), Some(remote_index)) if local_dual[index] == remote[remote_index] =>
+            Ok(V::select(carrier, index)),
+        (Some(_), Some(_)) =>
+            Err(HandshakeError::VersionOrder),
+        _ =>
+            Err(HandshakeError::NoCommonVersion),
+    }
+}
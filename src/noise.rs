@@ -0,0 +1,227 @@
+//! Encrypted and mutually authenticated byte streams (enabled with `noise`
+//! feature).
+//!
+//! `NoiseStream` performs a [Noise](https://noiseprotocol.org) handshake over
+//! a byte stream before the session begins and then encrypts everything
+//! written to it, so a `stream::Channel` over it is a carrier for peer to
+//! peer deployments where TLS certificates are impractical. Every peer has a
+//! static `Keypair`:
+//!
+//! * with the `XX` pattern neither peer knows the other one in advance,
+//!   their static public keys are exchanged during the handshake;
+//! * with the `IK` pattern the initiator knows the static public key of the
+//!   responder, which saves a round trip.
+//!
+//! Either way the handshake proves that the peer owns the private key of
+//! `remote_static`, which the application should check against the keys it
+//! trusts before starting the session. The protocol is
+//! `Noise_XX_25519_ChaChaPoly_BLAKE2s` (`IK` respectively), and every
+//! handshake and transport message is prefixed by its length (`u16`, big
+//! endian). Only blocking streams are supported.
+//!
+//! ```
+//! use std::net::{TcpListener, TcpStream};
+//! use std::thread;
+//! use session_types_ng::{Send, End, HasDual};
+//! use session_types_ng::stream::{self, Value};
+//! use session_types_ng::noise::{NoiseStream, generate_keypair};
+//!
+//! type Greeting = Send<Value<String>, End>;
+//!
+//! let server_keys = generate_keypair().unwrap();
+//! let client_keys = generate_keypair().unwrap();
+//! let client_public = client_keys.public.clone();
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+//! let addr = listener.local_addr().unwrap();
+//! let server = thread::spawn(move || {
+//!     let (socket, _) = listener.accept().unwrap();
+//!     let noise = NoiseStream::respond_xx(socket, &server_keys.private).unwrap();
+//!     assert_eq!(noise.remote_static(), &client_public[..]);
+//!     let chan = stream::session_channel::<Greeting, _>(noise);
+//!     chan.send(Value("hello".to_string())).unwrap().close();
+//! });
+//!
+//! let socket = TcpStream::connect(addr).unwrap();
+//! let noise = NoiseStream::initiate_xx(socket, &client_keys.private).unwrap();
+//! let chan = stream::session_channel::<<Greeting as HasDual>::Dual, _>(noise);
+//! let (chan, Value(greeting)) = chan.recv().unwrap();
+//! chan.close();
+//! server.join().unwrap();
+//! assert_eq!(greeting, "hello");
+//! ```
+use std::io::{self, Read, Write};
+use snow::{Builder, HandshakeState, TransportState};
+
+pub use snow::Keypair;
+
+const XX: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const IK: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+/// Maximum length of a Noise message.
+const MAX_MESSAGE: usize = 65535;
+/// Length of the authentication tag of every transport message.
+const TAG: usize = 16;
+/// Maximum amount of plaintext in a transport message.
+const MAX_PLAINTEXT: usize = MAX_MESSAGE - TAG;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Noise(snow::Error),
+}
+
+/// Generate a static keypair for the Noise handshakes.
+pub fn generate_keypair() -> Result<Keypair, snow::Error> {
+    Builder::new(XX.parse()?).generate_keypair()
+}
+
+/// Byte stream encrypted after a Noise handshake, see the module
+/// documentation.
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: TransportState,
+    remote_static: Vec<u8>,
+    /// Decrypted bytes not read yet.
+    inbound: Vec<u8>,
+    /// Plaintext written but not encrypted yet.
+    outbound: Vec<u8>,
+}
+
+impl<S> NoiseStream<S> where S: Read + Write {
+    /// Perform the `XX` handshake as the initiator, with the local static
+    /// private key `private`.
+    pub fn initiate_xx(stream: S, private: &[u8]) -> Result<NoiseStream<S>, Error> {
+        let state = Builder::new(XX.parse().map_err(Error::Noise)?)
+            .local_private_key(private)
+            .build_initiator()
+            .map_err(Error::Noise)?;
+        NoiseStream::handshake(stream, state)
+    }
+
+    /// Perform the `XX` handshake as the responder.
+    pub fn respond_xx(stream: S, private: &[u8]) -> Result<NoiseStream<S>, Error> {
+        let state = Builder::new(XX.parse().map_err(Error::Noise)?)
+            .local_private_key(private)
+            .build_responder()
+            .map_err(Error::Noise)?;
+        NoiseStream::handshake(stream, state)
+    }
+
+    /// Perform the `IK` handshake as the initiator, knowing the static
+    /// public key `remote` of the responder in advance.
+    pub fn initiate_ik(stream: S, private: &[u8], remote: &[u8]) -> Result<NoiseStream<S>, Error> {
+        let state = Builder::new(IK.parse().map_err(Error::Noise)?)
+            .local_private_key(private)
+            .remote_public_key(remote)
+            .build_initiator()
+            .map_err(Error::Noise)?;
+        NoiseStream::handshake(stream, state)
+    }
+
+    /// Perform the `IK` handshake as the responder.
+    pub fn respond_ik(stream: S, private: &[u8]) -> Result<NoiseStream<S>, Error> {
+        let state = Builder::new(IK.parse().map_err(Error::Noise)?)
+            .local_private_key(private)
+            .build_responder()
+            .map_err(Error::Noise)?;
+        NoiseStream::handshake(stream, state)
+    }
+
+    fn handshake(mut stream: S, mut state: HandshakeState) -> Result<NoiseStream<S>, Error> {
+        let mut buf = vec![0; MAX_MESSAGE];
+        while !state.is_handshake_finished() {
+            if state.is_my_turn() {
+                let len = state.write_message(&[], &mut buf).map_err(Error::Noise)?;
+                write_message(&mut stream, &buf[.. len]).map_err(Error::Io)?;
+            } else {
+                let message = read_message(&mut stream).map_err(Error::Io)?;
+                state.read_message(&message, &mut buf).map_err(Error::Noise)?;
+            }
+        }
+        let remote_static = state.get_remote_static().map(<[u8]>::to_vec).unwrap_or_default();
+        Ok(NoiseStream {
+            inner: stream,
+            transport: state.into_transport_mode().map_err(Error::Noise)?,
+            remote_static,
+            inbound: Vec::new(),
+            outbound: Vec::new(),
+        })
+    }
+
+    /// Static public key of the peer, authenticated by the handshake.
+    pub fn remote_static(&self) -> &[u8] {
+        &self.remote_static
+    }
+
+    /// Get a reference to underlying byte stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Encrypt and write out the pending plaintext, at most `MAX_PLAINTEXT`
+    /// bytes of it.
+    fn seal(&mut self) -> io::Result<()> {
+        let len = self.outbound.len().min(MAX_PLAINTEXT);
+        let mut message = vec![0; len + TAG];
+        let sealed = self.transport
+            .write_message(&self.outbound[.. len], &mut message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_message(&mut self.inner, &message[.. sealed])?;
+        self.outbound.drain(.. len);
+        Ok(())
+    }
+}
+
+impl<S> Read for NoiseStream<S> where S: Read + Write {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inbound.is_empty() {
+            let message = read_message(&mut self.inner)?;
+            let mut payload = vec![0; message.len()];
+            let len = self.transport
+                .read_message(&message, &mut payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            payload.truncate(len);
+            self.inbound = payload;
+        }
+        let len = buf.len().min(self.inbound.len());
+        buf[.. len].copy_from_slice(&self.inbound[.. len]);
+        self.inbound.drain(.. len);
+        Ok(len)
+    }
+}
+
+/// Written bytes are encrypted in messages as large as possible: a message
+/// is sent once it is full or on `flush`.
+impl<S> Write for NoiseStream<S> where S: Read + Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outbound.extend_from_slice(buf);
+        while self.outbound.len() >= MAX_PLAINTEXT {
+            self.seal()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.outbound.is_empty() {
+            self.seal()?;
+        }
+        self.inner.flush()
+    }
+}
+
+fn write_message<S>(stream: &mut S, bytes: &[u8]) -> io::Result<()> where S: Write {
+    let mut message = Vec::with_capacity(2 + bytes.len());
+    message.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    message.extend_from_slice(bytes);
+    stream.write_all(&message)?;
+    stream.flush()
+}
+
+fn read_message<S>(stream: &mut S) -> io::Result<Vec<u8>> where S: Read {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header)?;
+    let mut message = vec![0; u16::from_be_bytes(header) as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
@@ -0,0 +1,469 @@
+//! Per-session metrics instrumentation.
+//!
+//! A carrier wrapped into `ObservedCarrier` reports every protocol
+//! transition, value and choice to a `SessionObserver`. `Metrics` is the
+//! default observer collecting per-step counters, byte totals and latency
+//! histograms, which could be exported to a metrics crate of choice by
+//! walking `Metrics::steps`.
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, mpsc};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::layer::CarrierLayer;
+#[cfg(feature = "stream")]
+use super::{stream, wire};
+
+/// Instrumentation hook invoked by `ObservedCarrier`. Steps are protocol
+/// type names (`start` until the first transition).
+pub trait SessionObserver {
+    /// The session has moved from step `from` to step `to` after spending
+    /// `elapsed` time at `from`.
+    fn transition(&mut self, from: &'static str, to: &'static str, elapsed: Duration);
+
+    /// A value or a choice has been sent at `step` taking `bytes` on the wire.
+    fn sent(&mut self, _step: &'static str, _bytes: u64) { }
+
+    /// A value or a choice has been received at `step` taking `bytes` on the wire.
+    fn received(&mut self, _step: &'static str, _bytes: u64) { }
+}
+
+/// Shared observer, e.g. to aggregate metrics of many sessions.
+impl<O> SessionObserver for Arc<Mutex<O>> where O: SessionObserver {
+    fn transition(&mut self, from: &'static str, to: &'static str, elapsed: Duration) {
+        if let Ok(mut observer) = self.lock() {
+            observer.transition(from, to, elapsed);
+        }
+    }
+
+    fn sent(&mut self, step: &'static str, bytes: u64) {
+        if let Ok(mut observer) = self.lock() {
+            observer.sent(step, bytes);
+        }
+    }
+
+    fn received(&mut self, step: &'static str, bytes: u64) {
+        if let Ok(mut observer) = self.lock() {
+            observer.received(step, bytes);
+        }
+    }
+}
+
+/// Carrier able to tell how many bytes it has transferred so far.
+pub trait Metered {
+    fn bytes_sent(&self) -> u64;
+    fn bytes_received(&self) -> u64;
+}
+
+/// Values are moved rather than encoded, so nothing is counted.
+impl Metered for mpsc::Channel {
+    fn bytes_sent(&self) -> u64 {
+        0
+    }
+
+    fn bytes_received(&self) -> u64 {
+        0
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<S, F> Metered for stream::Channel<S, F> where S: Read + Write + Metered, F: wire::WireFormat {
+    fn bytes_sent(&self) -> u64 {
+        self.get_ref().bytes_sent()
+    }
+
+    fn bytes_received(&self) -> u64 {
+        self.get_ref().bytes_received()
+    }
+}
+
+/// Byte stream wrapper counting bytes read and written, so that a
+/// `stream::Channel` over it is `Metered`.
+pub struct Counted<S> {
+    inner: S,
+    sent: u64,
+    received: u64,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S) -> Counted<S> {
+        Counted {
+            inner,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    /// Get back underlying byte stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying byte stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S> Read for Counted<S> where S: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.received += count as u64;
+        Ok(count)
+    }
+}
+
+impl<S> Write for Counted<S> where S: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.sent += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<S> stream::ReadTimeout for Counted<S> where S: stream::ReadTimeout {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.inner.read_timeout()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+impl<S> Metered for Counted<S> {
+    fn bytes_sent(&self) -> u64 {
+        self.sent
+    }
+
+    fn bytes_received(&self) -> u64 {
+        self.received
+    }
+}
+
+/// Carrier wrapper which reports the session activity to an observer.
+pub struct ObservedCarrier<C, O> {
+    inner: C,
+    observer: O,
+    step: &'static str,
+    since: Instant,
+}
+
+impl<C, O> ObservedCarrier<C, O> where O: SessionObserver {
+    /// Wrap a carrier reporting to the observer.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+    /// use session_types_ng::observe::{ObservedCarrier, Metrics};
+    ///
+    /// let metrics = Arc::new(Mutex::new(Metrics::new()));
+    /// let (a, b) = mpsc::session_channel::<End>();
+    /// let a: Chan<_, (), Send<mpsc::Value<u8>, End>> =
+    ///     Chan::new(ObservedCarrier::new(a.shutdown(), metrics.clone()));
+    /// let b: Chan<_, (), Recv<mpsc::Value<u8>, End>> =
+    ///     Chan::new(ObservedCarrier::new(b.shutdown(), metrics.clone()));
+    /// a.send(mpsc::Value(1)).unwrap().close();
+    /// b.recv().unwrap().0.close();
+    ///
+    /// let metrics = metrics.lock().unwrap();
+    /// let start = metrics.step("start").unwrap();
+    /// assert_eq!((start.sent, start.received), (1, 1));
+    /// assert_eq!(start.latency.count(), 2);
+    /// ```
+    pub fn new(inner: C, observer: O) -> ObservedCarrier<C, O> {
+        ObservedCarrier {
+            inner,
+            observer,
+            step: "start",
+            since: Instant::now(),
+        }
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get back underlying carrier and the observer.
+    pub fn into_parts(self) -> (C, O) {
+        (self.inner, self.observer)
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    /// Get a reference to the observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+}
+
+/// Layer wrapping carriers into `ObservedCarrier`s, each one reporting to
+/// a clone of the observer, e.g. a shared `Arc<Mutex<Metrics>>`.
+#[derive(Clone, Debug)]
+pub struct ObserveLayer<O> {
+    observer: O,
+}
+
+impl<O> ObserveLayer<O> where O: SessionObserver + Clone {
+    pub fn new(observer: O) -> ObserveLayer<O> {
+        ObserveLayer { observer }
+    }
+}
+
+impl<C, O> CarrierLayer<C> for ObserveLayer<O> where O: SessionObserver + Clone {
+    type Carrier = ObservedCarrier<C, O>;
+
+    fn layer(&self, inner: C) -> ObservedCarrier<C, O> {
+        ObservedCarrier::new(inner, self.observer.clone())
+    }
+}
+
+impl<C, O> Carrier for ObservedCarrier<C, O> where C: Carrier + Metered, O: SessionObserver {
+    type SendChoiceErr = C::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        let step = self.step;
+        let sent = self.inner.bytes_sent();
+        let result = self.inner.send_choice(choice);
+        if result.is_ok() {
+            let bytes = self.inner.bytes_sent() - sent;
+            self.observer.sent(step, bytes);
+        }
+        result
+    }
+
+    type RecvChoiceErr = C::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        let step = self.step;
+        let received = self.inner.bytes_received();
+        let result = self.inner.recv_choice();
+        if result.is_ok() {
+            let bytes = self.inner.bytes_received() - received;
+            self.observer.received(step, bytes);
+        }
+        result
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        let now = Instant::now();
+        self.observer.transition(self.step, protocol, now - self.since);
+        self.step = protocol;
+        self.since = now;
+        self.inner.step(protocol);
+    }
+}
+
+impl<C, O> TimeoutCarrier for ObservedCarrier<C, O> where C: TimeoutCarrier + Metered, O: SessionObserver {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout)
+    }
+}
+
+impl<C, O> Metered for ObservedCarrier<C, O> where C: Metered {
+    fn bytes_sent(&self) -> u64 {
+        self.inner.bytes_sent()
+    }
+
+    fn bytes_received(&self) -> u64 {
+        self.inner.bytes_received()
+    }
+}
+
+fn observed_send<T, C, O>(value: T, carrier: &mut ObservedCarrier<C, O>) -> Result<(), T::Err>
+    where T: ChannelSend<C>, C: Metered, O: SessionObserver
+{
+    let step = carrier.step;
+    let sent = carrier.inner.bytes_sent();
+    let result = value.send(&mut carrier.inner);
+    if result.is_ok() {
+        let bytes = carrier.inner.bytes_sent() - sent;
+        carrier.observer.sent(step, bytes);
+    }
+    result
+}
+
+fn observed_recv<T, C, O>(carrier: &mut ObservedCarrier<C, O>) -> Result<T, T::Err>
+    where T: ChannelRecv<C>, C: Metered, O: SessionObserver
+{
+    let step = carrier.step;
+    let received = carrier.inner.bytes_received();
+    let result = T::recv(&mut carrier.inner);
+    if result.is_ok() {
+        let bytes = carrier.inner.bytes_received() - received;
+        carrier.observer.received(step, bytes);
+    }
+    result
+}
+
+impl<C, O> BatchCarrier for ObservedCarrier<C, O> where C: BatchCarrier + Metered, O: SessionObserver {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+impl<C, O> CapacityCarrier for ObservedCarrier<C, O> where C: CapacityCarrier + Metered, O: SessionObserver {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+impl<C, O> NonBlockingCarrier for ObservedCarrier<C, O> where C: NonBlockingCarrier + Metered, O: SessionObserver {
+    type PollErr = C::PollErr;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_send()
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_recv()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.inner.register_waker(waker);
+    }
+}
+
+/// Values transferable over a carrier are transferable over its observed wrapper.
+impl<C, O, T> ChannelSend<ObservedCarrier<C, O>> for T
+    where T: Payload + ChannelSend<C>, C: Metered, O: SessionObserver
+{
+    type Err = <T as ChannelSend<C>>::Err;
+
+    fn send(self, carrier: &mut ObservedCarrier<C, O>) -> Result<(), Self::Err> {
+        observed_send(self, carrier)
+    }
+}
+
+impl<C, O, T> ChannelRecv<ObservedCarrier<C, O>> for T
+    where T: Payload + ChannelRecv<C>, C: Metered, O: SessionObserver
+{
+    type Err = <T as ChannelRecv<C>>::Err;
+
+    fn recv(carrier: &mut ObservedCarrier<C, O>) -> Result<Self, Self::Err> {
+        observed_recv(carrier)
+    }
+}
+
+/// Latency histogram with power of two buckets in microseconds.
+#[derive(Clone, Default, Debug)]
+pub struct Histogram {
+    buckets: [u64; 32],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (64 - micros.leading_zeros() as usize).min(self.buckets.len() - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Non-empty buckets as pairs of exclusive upper bound and number of
+    /// samples. The last bucket also holds all the longer samples.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets.iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(bucket, &count)| (Duration::from_micros(1 << bucket), count))
+    }
+}
+
+/// Metrics of a single protocol step.
+#[derive(Clone, Default, Debug)]
+pub struct StepMetrics {
+    /// Number of times the step has been entered.
+    pub entered: u64,
+    /// Values and choices sent.
+    pub sent: u64,
+    /// Values and choices received.
+    pub received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Time spent at the step before moving to the next one.
+    pub latency: Histogram,
+}
+
+/// Default observer collecting `StepMetrics` for every protocol step.
+#[derive(Clone, Default, Debug)]
+pub struct Metrics {
+    steps: BTreeMap<&'static str, StepMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Metrics of a step by its protocol type name.
+    pub fn step(&self, step: &str) -> Option<&StepMetrics> {
+        self.steps.get(step)
+    }
+
+    /// All the steps seen so far.
+    pub fn steps(&self) -> impl Iterator<Item = (&'static str, &StepMetrics)> {
+        self.steps.iter().map(|(&step, metrics)| (step, metrics))
+    }
+
+    /// Forget everything collected so far.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+}
+
+impl SessionObserver for Metrics {
+    fn transition(&mut self, from: &'static str, to: &'static str, elapsed: Duration) {
+        self.steps.entry(from).or_default().latency.record(elapsed);
+        self.steps.entry(to).or_default().entered += 1;
+    }
+
+    fn sent(&mut self, step: &'static str, bytes: u64) {
+        let metrics = self.steps.entry(step).or_default();
+        metrics.sent += 1;
+        metrics.bytes_sent += bytes;
+    }
+
+    fn received(&mut self, step: &'static str, bytes: u64) {
+        let metrics = self.steps.entry(step).or_default();
+        metrics.received += 1;
+        metrics.bytes_received += bytes;
+    }
+}
@@ -0,0 +1,214 @@
+//! Parallel composition of protocols.
+//!
+//! `Par<P, Q, R>` runs independent sub-conversations `P` and `Q` at the
+//! same time, then continues with `R` once both of them have ended.
+//! `Chan::split` turns a channel at a `Par` step into two strands which
+//! could be driven from different threads, and `join` of the strands at
+//! their `End` recombines them into the channel at `R`.
+//!
+//! Both strands share the carrier of the session: every message and choice
+//! is preceded by a choice bit telling the strand it belongs to. A strand
+//! waiting for a message whose tag has arrived for the other strand leaves
+//! it to the other one, so the carrier should support timeouts, and waiting
+//! polls it backing off up to a millisecond. `P` and `Q` start with an
+//! empty environment: they could not continue loops of the session.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::{Send, Recv, End, mpsc};
+//! use session_types_ng::par::Par;
+//!
+//! // prices and the order are independent conversations
+//! type Shop = Par<Send<mpsc::Value<u32>, End>, Recv<mpsc::Value<String>, End>, Send<mpsc::Value<bool>, End>>;
+//!
+//! let (shop, customer) = mpsc::session_channel::<Shop>();
+//! let customer = thread::spawn(move || {
+//!     let (prices, orders) = customer.split();
+//!     let orders = orders.send(mpsc::Value("apple".to_string())).unwrap();
+//!     let (prices, mpsc::Value(price)) = prices.recv().unwrap();
+//!     let (chan, mpsc::Value(shipped)) = prices.join(orders).recv().unwrap();
+//!     chan.close();
+//!     (price, shipped)
+//! });
+//!
+//! let (prices, orders) = shop.split();
+//! let pricing = thread::spawn(move || prices.send(mpsc::Value(42)).unwrap());
+//! let (orders, mpsc::Value(item)) = orders.recv().unwrap();
+//! let prices = pricing.join().unwrap();
+//! prices.join(orders).send(mpsc::Value(item == "apple")).unwrap().close();
+//! assert_eq!(customer.join().unwrap(), (42, true));
+//! ```
+use std::any::type_name;
+use std::cmp;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, ChannelSend, ChannelRecv, HasDual, Then, Chan, End};
+use super::reflect::{ProtocolDesc, Desc};
+use super::subtype::SubtypeOf;
+use super::timeout::TimeoutCarrier;
+
+const MIN_PAUSE: Duration = Duration::from_micros(10);
+const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+/// Run `P` and `Q` concurrently, then `R`.
+pub struct Par<P, Q, R>(PhantomData<(P, Q, R)>);
+
+unsafe impl<P: HasDual, Q: HasDual, R: HasDual> HasDual for Par<P, Q, R> {
+    type Dual = Par<P::Dual, Q::Dual, R::Dual>;
+}
+
+impl<P, Q, R: Then<T>, T> Then<T> for Par<P, Q, R> {
+    type Output = Par<P, Q, R::Output>;
+}
+
+impl<P, Q, R, P2, Q2, R2> SubtypeOf<Par<P2, Q2, R2>> for Par<P, Q, R>
+    where P: SubtypeOf<P2>, Q: SubtypeOf<Q2>, R: SubtypeOf<R2> { }
+
+impl<P: ProtocolDesc, Q: ProtocolDesc, R: ProtocolDesc> ProtocolDesc for Par<P, Q, R> {
+    fn desc() -> Desc {
+        Desc::Par(Box::new(P::desc()), Box::new(Q::desc()), Box::new(R::desc()))
+    }
+}
+
+struct Shared<SR> {
+    carrier: SR,
+    /// Tag read from the carrier, the message of its strand is next.
+    arrived: Option<bool>,
+}
+
+/// Carrier of one strand of a `Par` step, which continues with `R` in the
+/// environment `E` after the join.
+pub struct Strand<SR, E, R> {
+    shared: Arc<Mutex<Shared<SR>>>,
+    /// `false` for the strand of `P`, `true` for the strand of `Q`.
+    side: bool,
+    continuation: PhantomData<(E, R)>,
+}
+
+#[derive(Debug)]
+pub enum ParError<E, T> {
+    /// Transfer of the strand tag has failed.
+    Tag(T),
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+impl<SR, E, R> Strand<SR, E, R> where SR: TimeoutCarrier {
+    fn lock(&self) -> MutexGuard<'_, Shared<SR>> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_tag(&self) -> Result<MutexGuard<'_, Shared<SR>>, SR::SendChoiceErr> {
+        let mut shared = self.lock();
+        shared.carrier.send_choice(self.side)?;
+        Ok(shared)
+    }
+
+    /// Wait at most `timeout` (forever if `None`) until the next message of
+    /// the strand is about to arrive, reading tags as they come.
+    fn wait(&self, timeout: Option<Duration>) -> Result<Option<MutexGuard<'_, Shared<SR>>>, SR::RecvChoiceErr> {
+        let started = Instant::now();
+        let mut pause = MIN_PAUSE;
+        loop {
+            {
+                let mut shared = self.lock();
+                if shared.arrived.is_none() && shared.carrier.wait_timeout(Duration::from_secs(0))? {
+                    shared.arrived = Some(shared.carrier.recv_choice()?);
+                }
+                if shared.arrived == Some(self.side) {
+                    return Ok(Some(shared));
+                }
+            }
+            let elapsed = started.elapsed();
+            if timeout.is_some_and(|timeout| elapsed >= timeout) {
+                return Ok(None);
+            }
+            let slice = timeout.map_or(pause, |timeout| cmp::min(pause, timeout - elapsed));
+            thread::sleep(slice);
+            pause = cmp::min(pause * 2, MAX_PAUSE);
+        }
+    }
+
+    /// Wait for the next message of the strand and take its tag.
+    fn recv_tag(&self) -> Result<MutexGuard<'_, Shared<SR>>, SR::RecvChoiceErr> {
+        let mut shared = self.wait(None)?.expect("waiting without timeout");
+        shared.arrived = None;
+        Ok(shared)
+    }
+}
+
+impl<SR, E, R> Carrier for Strand<SR, E, R> where SR: TimeoutCarrier {
+    type SendChoiceErr = SR::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.send_tag()?.carrier.send_choice(choice)
+    }
+
+    type RecvChoiceErr = SR::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.recv_tag()?.carrier.recv_choice()
+    }
+}
+
+impl<SR, E, R> TimeoutCarrier for Strand<SR, E, R> where SR: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.wait(Some(timeout)).map(|shared| shared.is_some())
+    }
+}
+
+/// Values transferable over a carrier are transferable over its strands.
+impl<SR, E, R, T> ChannelSend<Strand<SR, E, R>> for T where T: Payload + ChannelSend<SR>, SR: TimeoutCarrier {
+    type Err = ParError<<T as ChannelSend<SR>>::Err, SR::SendChoiceErr>;
+
+    fn send(self, carrier: &mut Strand<SR, E, R>) -> Result<(), Self::Err> {
+        let mut shared = carrier.send_tag().map_err(ParError::Tag)?;
+        self.send(&mut shared.carrier).map_err(ParError::Carrier)
+    }
+}
+
+impl<SR, E, R, T> ChannelRecv<Strand<SR, E, R>> for T where T: Payload + ChannelRecv<SR>, SR: TimeoutCarrier {
+    type Err = ParError<<T as ChannelRecv<SR>>::Err, SR::RecvChoiceErr>;
+
+    fn recv(carrier: &mut Strand<SR, E, R>) -> Result<Self, Self::Err> {
+        let mut shared = carrier.recv_tag().map_err(ParError::Tag)?;
+        <T>::recv(&mut shared.carrier).map_err(ParError::Carrier)
+    }
+}
+
+impl<SR, E, P, Q, R> Chan<SR, E, Par<P, Q, R>> where SR: TimeoutCarrier {
+    /// Split the channel into the strands of `P` and `Q`.
+    #[must_use]
+    pub fn split(self) -> (Chan<Strand<SR, E, R>, (), P>, Chan<Strand<SR, E, R>, (), Q>) {
+        let Chan { carrier, session } = self;
+        session.finish();
+        let shared = Arc::new(Mutex::new(Shared { carrier, arrived: None }));
+        let left = Strand { shared: shared.clone(), side: false, continuation: PhantomData };
+        let right = Strand { shared, side: true, continuation: PhantomData };
+        (Chan::new(left), Chan::new(right))
+    }
+}
+
+impl<SR, E, R> Chan<Strand<SR, E, R>, (), End> where SR: TimeoutCarrier {
+    /// Recombine both ended strands of a split into the channel at the
+    /// continuation of the `Par` step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `other` is not the other strand of the same split.
+    #[must_use]
+    pub fn join(self, other: Chan<Strand<SR, E, R>, (), End>) -> Chan<SR, E, R> {
+        let Chan { carrier: strand, session } = self;
+        session.finish();
+        let Chan { carrier: other, session } = other;
+        session.finish();
+        assert!(Arc::ptr_eq(&strand.shared, &other.shared) && strand.side != other.side,
+                "joined strands are not of the same split at {}", type_name::<R>());
+        drop(other);
+        let shared = match Arc::try_unwrap(strand.shared) {
+            Ok(shared) => shared.into_inner().unwrap_or_else(|e| e.into_inner()),
+            Err(..) => unreachable!("both strands are joined"),
+        };
+        Chan::new(shared.carrier)
+    }
+}
@@ -0,0 +1,289 @@
+//! Driving sessions from event loops (e.g. `mio` or `epoll` based).
+//!
+//! A `NonBlockingCarrier` makes progress without blocking: `poll_recv`
+//! reads whatever has arrived and tells whether the next message is
+//! complete, and `poll_send` writes out whatever has been buffered. An event
+//! loop calls them when the underlying device becomes readable or writable,
+//! and many sessions are served by a single thread.
+//!
+//! `Chan::poll_recv` and `Chan::poll_offer` return the channel in the same
+//! protocol state on `WouldBlock`, so it could be parked until the next
+//! event. Async tasks park it with `Chan::register_waker` instead, and are
+//! woken once it could make progress.
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{self, Context, Waker};
+use super::{ChannelSend, ChannelRecv, Carrier, Chan, Send, Recv, Offer, Offers, cast_chan, close_chan};
+
+/// Failure of a non-blocking operation.
+#[derive(Debug)]
+pub enum PollError<E> {
+    /// The operation could not be completed now, retry on the next event.
+    WouldBlock,
+    Carrier(E),
+}
+
+impl<E> PollError<E> {
+    /// Convert the carrier failure with `f`, e.g. into the error of a wrapping carrier.
+    pub fn map<F, G>(self, f: F) -> PollError<G> where F: FnOnce(E) -> G {
+        match self {
+            PollError::WouldBlock => PollError::WouldBlock,
+            PollError::Carrier(e) => PollError::Carrier(f(e)),
+        }
+    }
+}
+
+/// A carrier which could be driven without blocking.
+pub trait NonBlockingCarrier: Carrier {
+    type PollErr;
+
+    /// Deliver buffered outgoing messages as far as possible. Returns
+    /// `WouldBlock` while some of them are still pending.
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>>;
+
+    /// Read what has arrived so far. Returns `Ok` once the next message
+    /// (or choice) is complete, so that receiving it won't block.
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>>;
+
+    /// Have `waker` woken once `poll_send` or `poll_recv` could make
+    /// progress after returning `WouldBlock`. Whatever has happened before
+    /// the registration is not reported, so the caller should poll once
+    /// more after it. By default the waker is woken right away, so a
+    /// carrier which could not notify (e.g. `stream::Channel` over an
+    /// arbitrary byte stream) is polled again and again.
+    fn register_waker(&mut self, waker: &Waker) {
+        waker.wake_by_ref();
+    }
+}
+
+/// Failure of `Chan::poll_recv` or `Chan::poll_offer`.
+#[derive(Debug)]
+pub enum PollRecvError<C, W, V> {
+    /// The message is not complete yet, the channel is returned in the same protocol state.
+    WouldBlock(C),
+    /// Carrier has failed while polling.
+    Poll(W),
+    /// The message has arrived but could not be received.
+    Value(V),
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: NonBlockingCarrier {
+    /// Deliver the messages buffered by the carrier, see `NonBlockingCarrier::poll_send`.
+    pub fn poll_send(&mut self) -> Result<(), PollError<SR::PollErr>> {
+        self.carrier.poll_send()
+    }
+
+    /// Have `waker` woken once the channel could make progress, see
+    /// `NonBlockingCarrier::register_waker`.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::task::{Wake, Waker};
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::poll::PollRecvError;
+    ///
+    /// struct Wakes(AtomicUsize);
+    ///
+    /// impl Wake for Wakes {
+    ///     fn wake(self: Arc<Self>) {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+    /// let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let mut rx = match rx.poll_recv() {
+    ///     Err(PollRecvError::WouldBlock(rx)) => rx,
+    ///     _ => panic!("nothing has been sent yet"),
+    /// };
+    /// rx.register_waker(&Waker::from(wakes.clone()));
+    /// assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+    /// tx.send(mpsc::Value(42)).unwrap().close();
+    /// assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    /// match rx.poll_recv() {
+    ///     Ok((rx, mpsc::Value(v))) => { assert_eq!(v, 42); rx.close() },
+    ///     _ => panic!("value should be available"),
+    /// }
+    /// ```
+    pub fn register_waker(&mut self, waker: &Waker) {
+        self.carrier.register_waker(waker);
+    }
+}
+
+impl<SR, E, A, R> Chan<SR, E, Recv<A, R>> where SR: NonBlockingCarrier, A: ChannelRecv<SR> {
+    /// Receive a value if it has arrived entirely, without blocking.
+    ///
+    /// ```
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::poll::PollRecvError;
+    ///
+    /// let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let rx = match rx.poll_recv() {
+    ///     Err(PollRecvError::WouldBlock(rx)) => rx,
+    ///     _ => panic!("nothing has been sent yet"),
+    /// };
+    /// tx.send(mpsc::Value(42)).unwrap().close();
+    /// match rx.poll_recv() {
+    ///     Ok((rx, mpsc::Value(v))) => { assert_eq!(v, 42); rx.close() },
+    ///     _ => panic!("value should be available"),
+    /// }
+    /// ```
+    #[must_use]
+    pub fn poll_recv(mut self) -> Result<(Chan<SR, E, R>, A), PollRecvError<Chan<SR, E, Recv<A, R>>, SR::PollErr, A::Err>> {
+        match self.carrier.poll_recv() {
+            Ok(()) =>
+                (),
+            Err(PollError::WouldBlock) =>
+                return Err(PollRecvError::WouldBlock(self)),
+            Err(PollError::Carrier(e)) => {
+                close_chan(self);
+                return Err(PollRecvError::Poll(e));
+            },
+        }
+        match A::recv(&mut self.carrier) {
+            Ok(v) =>
+                Ok((cast_chan(self), v)),
+            Err(e) => {
+                close_chan(self);
+                Err(PollRecvError::Value(e))
+            },
+        }
+    }
+}
+
+impl<SR, E, P, L> Chan<SR, E, Offer<P, L>> where SR: NonBlockingCarrier {
+    /// Passive choice, once the choice of the other side has arrived.
+    ///
+    /// Only the first choice bit of a selection is polled for: with more
+    /// than two branches the rest is received as the offer proceeds, which
+    /// could block if the carrier splits the selection.
+    #[must_use]
+    pub fn poll_offer<T>(mut self) -> Result<Offers<SR, E, Offer<P, L>, T>, PollRecvError<Chan<SR, E, Offer<P, L>>, SR::PollErr, Infallible>> {
+        match self.carrier.poll_recv() {
+            Ok(()) =>
+                Ok(self.offer()),
+            Err(PollError::WouldBlock) =>
+                Err(PollRecvError::WouldBlock(self)),
+            Err(PollError::Carrier(e)) => {
+                close_chan(self);
+                Err(PollRecvError::Poll(e))
+            },
+        }
+    }
+}
+
+/// Failure of `Chan::call_async`.
+#[derive(Debug)]
+pub enum PollCallError<S, W, V> {
+    /// Sending the request has failed.
+    Send(S),
+    /// Carrier has failed while polling.
+    Poll(W),
+    /// The response has arrived but could not be received.
+    Value(V),
+}
+
+enum CallState<SR, E, A, B, R> {
+    Request(Chan<SR, E, Send<A, Recv<B, R>>>, A),
+    Flush(Chan<SR, E, Recv<B, R>>),
+    Response(Chan<SR, E, Recv<B, R>>),
+}
+
+/// Future of a request/response exchange, see `Chan::call_async`.
+pub struct Call<SR, E, A, B, R>(Option<CallState<SR, E, A, B, R>>);
+
+// nothing is pinned structurally
+impl<SR, E, A, B, R> Unpin for Call<SR, E, A, B, R> { }
+
+impl<SR, E, A, B, R> Chan<SR, E, Send<A, Recv<B, R>>> where SR: NonBlockingCarrier, A: ChannelSend<SR>, B: ChannelRecv<SR> {
+    /// Send a request and receive the response as a future, the async
+    /// counterpart of `call`. The request is flushed with `poll_send` and
+    /// the response awaited with `poll_recv`, so the future never blocks.
+    /// While the exchange is pending the task is parked with
+    /// `register_waker`.
+    ///
+    /// ```
+    /// extern crate futures;
+    /// # extern crate session_types_ng;
+    /// use std::thread;
+    /// use futures::executor::block_on;
+    /// use session_types_ng::{Send, Recv, End, mpsc};
+    ///
+    /// type Square = Send<mpsc::Value<u32>, Recv<mpsc::Value<u32>, End>>;
+    ///
+    /// # fn main() {
+    /// let (client, server) = mpsc::session_channel::<Square>();
+    /// let server = thread::spawn(move || {
+    ///     let (chan, mpsc::Value(x)) = server.recv().unwrap();
+    ///     chan.send(mpsc::Value(x * x)).unwrap().close();
+    /// });
+    /// let (client, mpsc::Value(squared)) = block_on(client.call_async(mpsc::Value(7))).unwrap();
+    /// client.close();
+    /// server.join().unwrap();
+    /// assert_eq!(squared, 49);
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn call_async(self, request: A) -> Call<SR, E, A, B, R> {
+        Call(Some(CallState::Request(self, request)))
+    }
+}
+
+impl<SR, E, A, B, R> Future for Call<SR, E, A, B, R> where SR: NonBlockingCarrier, A: ChannelSend<SR>, B: ChannelRecv<SR> {
+    type Output = Result<(Chan<SR, E, R>, B), PollCallError<A::Err, SR::PollErr, B::Err>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> task::Poll<Self::Output> {
+        let call = self.get_mut();
+        let mut registered = false;
+        loop {
+            match call.0.take().expect("call polled after completion") {
+                CallState::Request(chan, request) =>
+                    match chan.send(request) {
+                        Ok(chan) =>
+                            call.0 = Some(CallState::Flush(chan)),
+                        Err(e) =>
+                            return task::Poll::Ready(Err(PollCallError::Send(e))),
+                    },
+                CallState::Flush(mut chan) =>
+                    match chan.poll_send() {
+                        Ok(()) =>
+                            call.0 = Some(CallState::Response(chan)),
+                        Err(PollError::WouldBlock) if registered => {
+                            call.0 = Some(CallState::Flush(chan));
+                            return task::Poll::Pending;
+                        },
+                        Err(PollError::WouldBlock) => {
+                            // poll once more, it could have become ready meanwhile
+                            chan.register_waker(cx.waker());
+                            registered = true;
+                            call.0 = Some(CallState::Flush(chan));
+                        },
+                        Err(PollError::Carrier(e)) => {
+                            close_chan(chan);
+                            return task::Poll::Ready(Err(PollCallError::Poll(e)));
+                        },
+                    },
+                CallState::Response(chan) =>
+                    match chan.poll_recv() {
+                        Ok(response) =>
+                            return task::Poll::Ready(Ok(response)),
+                        Err(PollRecvError::WouldBlock(chan)) if registered => {
+                            call.0 = Some(CallState::Response(chan));
+                            return task::Poll::Pending;
+                        },
+                        Err(PollRecvError::WouldBlock(mut chan)) => {
+                            chan.register_waker(cx.waker());
+                            registered = true;
+                            call.0 = Some(CallState::Response(chan));
+                        },
+                        Err(PollRecvError::Poll(e)) =>
+                            return task::Poll::Ready(Err(PollCallError::Poll(e))),
+                        Err(PollRecvError::Value(e)) =>
+                            return task::Poll::Ready(Err(PollCallError::Value(e))),
+                    },
+            }
+        }
+    }
+}
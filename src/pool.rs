@@ -0,0 +1,127 @@
+//! Bounded pool of worker threads serving sessions.
+//!
+//! A `SessionPool` runs a handler for every session submitted to it on one
+//! of a fixed number of workers. Sessions could come from any source: a
+//! rendezvous receiver (see `accept`), `stream::SessionListener::accept`
+//! or anything else producing channels. While all the workers are busy and
+//! the queue is full, `submit` blocks, so a flood of clients is not turned
+//! into a flood of threads.
+//!
+//! ```
+//! use std::thread;
+//! use std::sync::mpsc::channel;
+//! use session_types_ng::{Send, Recv, End, mpsc, request};
+//! use session_types_ng::pool::SessionPool;
+//!
+//! type Doubler = Recv<mpsc::Value<u32>, Send<mpsc::Value<u32>, End>>;
+//!
+//! let (tx, rx) = channel();
+//! let server = thread::spawn(move || {
+//!     let pool = SessionPool::new(4, |chan: session_types_ng::Chan<mpsc::Channel, (), Doubler>| {
+//!         let (chan, mpsc::Value(x)) = chan.recv().unwrap();
+//!         chan.send(mpsc::Value(x * 2)).unwrap().close();
+//!     });
+//!     // until all the requesting sides are gone
+//!     pool.run(rx);
+//!     pool.shutdown();
+//! });
+//!
+//! let clients: Vec<_> = (0 .. 16).map(|x| {
+//!     let tx = tx.clone();
+//!     thread::spawn(move || {
+//!         let chan = request(tx, mpsc::session_channel).unwrap();
+//!         let (chan, mpsc::Value(y)) = chan.send(mpsc::Value(x)).unwrap().recv().unwrap();
+//!         chan.close();
+//!         assert_eq!(y, x * 2);
+//!     })
+//! }).collect();
+//! drop(tx);
+//! for client in clients {
+//!     client.join().unwrap();
+//! }
+//! server.join().unwrap();
+//! ```
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+use super::Chan;
+
+/// Pool of worker threads running a handler for every submitted session
+/// following `P` over `SR`.
+pub struct SessionPool<SR, P> {
+    queue: Option<SyncSender<Chan<SR, (), P>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<SR, P> SessionPool<SR, P> where SR: std::marker::Send + 'static, P: std::marker::Send + 'static {
+    /// Start `workers` threads running `handler`. Up to `workers` more
+    /// sessions are queued while all of them are busy.
+    ///
+    /// A panic in the handler ends only the session being handled, the
+    /// worker goes on with the next one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn new<H>(workers: usize, handler: H) -> SessionPool<SR, P>
+        where H: Fn(Chan<SR, (), P>) + std::marker::Send + Sync + 'static
+    {
+        assert!(workers > 0, "session pool needs at least one worker");
+        let (queue, sessions) = sync_channel(workers);
+        let sessions = Arc::new(Mutex::new(sessions));
+        let handler = Arc::new(handler);
+        let workers = (0 .. workers)
+            .map(|_| {
+                let sessions = sessions.clone();
+                let handler = handler.clone();
+                thread::spawn(move || work(&sessions, &*handler))
+            })
+            .collect();
+        SessionPool {
+            queue: Some(queue),
+            workers,
+        }
+    }
+
+    /// Hand `chan` over to a worker, waiting while the queue is full.
+    pub fn submit(&self, chan: Chan<SR, (), P>) {
+        if let Some(ref queue) = self.queue {
+            // workers outlive the queue, so it is never disconnected
+            let _ = queue.send(chan);
+        }
+    }
+
+    /// Submit every session of `sessions`, until it is exhausted.
+    pub fn run<I>(&self, sessions: I) where I: IntoIterator<Item = Chan<SR, (), P>> {
+        for chan in sessions {
+            self.submit(chan);
+        }
+    }
+
+    /// Stop accepting sessions and wait until the workers have handled all
+    /// the submitted ones. Dropping the pool does the same.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+}
+
+impl<SR, P> Drop for SessionPool<SR, P> {
+    fn drop(&mut self) {
+        self.queue = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work<SR, P, H>(sessions: &Mutex<Receiver<Chan<SR, (), P>>>, handler: &H) where H: Fn(Chan<SR, (), P>) {
+    loop {
+        // the lock is released before the session is handled
+        let chan = match sessions.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+            Ok(chan) => chan,
+            Err(_) => return,
+        };
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(chan)));
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{self, Read, Write};
+use super::{ChannelSend, ChannelRecv, Payload, Carrier, Chan};
+use super::stream::{Limits, write_frame, read_frame};
+
+/// Carrier over any `Read` + `Write` byte stream exchanging `prost` messages,
+/// so existing protobuf schemas can be used as session payloads. Framing is
+/// the same as for `stream::Channel`, and so are `Limits` on frame sizes.
+///
+/// ```
+/// extern crate prost;
+/// # extern crate session_types_ng;
+/// use std::thread;
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, HasDual, Send, Recv, Choose, End, Nil};
+/// use session_types_ng::protobuf::{self, Message};
+///
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// struct Point {
+///     #[prost(int32, tag = "1")]
+///     x: i32,
+///     #[prost(int32, tag = "2")]
+///     y: i32,
+/// }
+///
+/// type Mirror = Send<Message<Point>, Choose<Recv<Message<Point>, End>, Choose<End, Nil>>>;
+///
+/// # fn main() {
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (server, _) = listener.accept().unwrap();
+/// let client: Chan<_, (), Mirror> = protobuf::session_channel(client);
+/// let server: Chan<_, (), <Mirror as HasDual>::Dual> = protobuf::session_channel(server);
+///
+/// let server = thread::spawn(move || {
+///     let (chan, Message(point)) = server.recv().unwrap();
+///     chan.offer()
+///         .option(|chan| chan.send(Message(Point { x: point.y, y: point.x })).unwrap().close())
+///         .option(|chan| chan.close())
+///         .unwrap();
+/// });
+/// let chan = client.send(Message(Point { x: 1, y: 2 })).unwrap().first().unwrap();
+/// let (chan, Message(mirrored)) = chan.recv().unwrap();
+/// chan.close();
+/// server.join().unwrap();
+/// assert_eq!(mirrored, Point { x: 2, y: 1 });
+/// # }
+/// ```
+///
+/// A frame which is not a valid message fails to decode:
+///
+/// ```
+/// use std::io::Write;
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, Recv, End};
+/// use session_types_ng::protobuf::{self, Message};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (socket, _) = listener.accept().unwrap();
+/// let chan: Chan<_, (), Recv<Message<String>, End>> = protobuf::session_channel(socket);
+///
+/// // a truncated varint
+/// peer.write_all(&[0, 0, 0, 2, 0x0a, 0xff]).unwrap();
+/// match chan.recv() {
+///     Err(protobuf::Error::Decode(_)) => (),
+///     _ => panic!("the frame is malformed"),
+/// }
+/// ```
+pub struct Channel<S> {
+    stream: S,
+    limits: Limits,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Decode(prost::DecodeError),
+}
+
+/// A wrapper for any `prost::Message` to be transferred over the channel.
+#[derive(Clone, Debug)]
+pub struct Message<M>(pub M);
+
+impl<M> Payload for Message<M> { }
+
+/// Control message used to transfer choices.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Choice {
+    #[prost(bool, tag = "1")]
+    first: bool,
+}
+
+impl<S> Channel<S> where S: Read + Write {
+    pub fn new(stream: S) -> Channel<S> {
+        Channel::with_limits(stream, Limits::new())
+    }
+
+    /// Refuse frames beyond `limits`, e.g. a frame header announcing
+    /// gigabytes, before allocating for them. `Limits::max_buffered` does
+    /// not apply, as nothing is buffered.
+    ///
+    /// ```
+    /// use std::net::{TcpListener, TcpStream};
+    /// use std::io::{self, Write};
+    /// use session_types_ng::{Chan, Recv, End};
+    /// use session_types_ng::protobuf::{self, Message};
+    /// use session_types_ng::stream::{Limits, LimitError};
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    /// let (socket, _) = listener.accept().unwrap();
+    /// let carrier = protobuf::Channel::with_limits(socket, Limits::new().max_message(1024));
+    /// let chan: Chan<_, (), Recv<Message<String>, End>> = Chan::new(carrier);
+    ///
+    /// // a frame header announcing a gigabyte
+    /// peer.write_all(&(1u32 << 30).to_be_bytes()).unwrap();
+    /// match chan.recv() {
+    ///     Err(protobuf::Error::Io(e)) => {
+    ///         assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    ///         let limit = e.get_ref().and_then(|e| e.downcast_ref::<LimitError>());
+    ///         assert_eq!(limit, Some(&LimitError::TooLarge { size: 1 << 30, limit: 1024 }));
+    ///     },
+    ///     _ => panic!("the frame is too large"),
+    /// }
+    /// ```
+    pub fn with_limits(stream: S, limits: Limits) -> Channel<S> {
+        Channel { stream, limits }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Get back underlying byte stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn send_message<M>(&mut self, message: &M) -> Result<(), Error> where M: prost::Message {
+        let bytes = message.encode_to_vec();
+        self.limits.check_message(bytes.len()).map_err(Error::Io)?;
+        write_frame(&mut self.stream, &bytes).map_err(Error::Io)
+    }
+
+    fn recv_message<M>(&mut self) -> Result<M, Error> where M: prost::Message + Default {
+        let bytes = read_frame(&mut self.stream, self.limits).map_err(Error::Io)?;
+        M::decode(&bytes[..]).map_err(Error::Decode)
+    }
+}
+
+impl<M, S> ChannelSend<Channel<S>> for Message<M> where M: prost::Message, S: Read + Write {
+    type Err = Error;
+
+    fn send(self, carrier: &mut Channel<S>) -> Result<(), Self::Err> {
+        carrier.send_message(&self.0)
+    }
+}
+
+impl<M, S> ChannelRecv<Channel<S>> for Message<M> where M: prost::Message + Default, S: Read + Write {
+    type Err = Error;
+
+    fn recv(carrier: &mut Channel<S>) -> Result<Self, Self::Err> {
+        carrier.recv_message().map(Message)
+    }
+}
+
+impl<S> Carrier for Channel<S> where S: Read + Write {
+    type SendChoiceErr = Error;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.send_message(&Choice { first: choice })
+    }
+
+    type RecvChoiceErr = Error;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.recv_message().map(|Choice { first }| first)
+    }
+}
+
+/// Returns a session channel exchanging protobuf messages over the byte stream.
+#[must_use]
+pub fn session_channel<P, S>(stream: S) -> Chan<Channel<S>, (), P> where S: Read + Write {
+    Chan::new(Channel::new(stream))
+}
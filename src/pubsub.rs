@@ -0,0 +1,167 @@
+//! Publish/subscribe over sessions.
+//!
+//! Every subscriber is a session following `Subscription<T>` on the side of
+//! the publisher: each message is broadcast to the subscribers, which
+//! acknowledge it either keeping the subscription or unsubscribing, until
+//! the publisher closes the feed. A `Publisher` sends a message to all of
+//! them with a `broadcast::Group` and collects acknowledgements with
+//! `select::ChanSelect` in order of arrival, so a slow subscriber does not
+//! hold up reading the others. Subscribers follow the dual protocol
+//! `Subscriber<T>`, driven by `receive`.
+//!
+//! ```
+//! use std::thread;
+//! use session_types_ng::mpsc::{self, Value};
+//! use session_types_ng::pubsub::{self, Publisher, Subscription};
+//!
+//! let mut publisher = Publisher::new();
+//! let readers: Vec<_> = (1 .. 4).map(|wanted| {
+//!     let (subscription, subscriber) = mpsc::session_channel::<Subscription<Value<u32>>>();
+//!     publisher.subscribe(subscription);
+//!     // every reader unsubscribes after `wanted` messages
+//!     thread::spawn(move || {
+//!         let mut seen = 0;
+//!         pubsub::receive(subscriber, |Value(_)| { seen += 1; seen < wanted }).unwrap();
+//!         seen
+//!     })
+//! }).collect();
+//!
+//! assert_eq!(publisher.publish(Value(1)), 3);
+//! assert_eq!(publisher.publish(Value(2)), 2);
+//! assert_eq!(publisher.len(), 1);
+//! publisher.close();
+//! let seen: Vec<_> = readers.into_iter().map(|reader| reader.join().unwrap()).collect();
+//! assert_eq!(seen, [1, 2, 2]);
+//! ```
+use super::{Carrier, ChannelSend, ChannelRecv, HasDual, Chan, Rec, Var, Z, Choose, Offer, End, Nil};
+use super::broadcast::{Bcast, Group};
+use super::select::ChanSelect;
+use super::timeout::TimeoutCarrier;
+
+/// Acknowledgement of a message: keep the subscription or unsubscribe.
+pub type Ack = Offer<Var<Z>, Offer<End, Nil>>;
+
+/// Body of the `Subscription` loop: either a message or the end of the feed.
+pub type Feed<T> = Choose<Bcast<T, Ack>, Choose<End, Nil>>;
+
+/// Protocol of a subscription on the side of the publisher.
+pub type Subscription<T> = Rec<Feed<T>>;
+
+/// Protocol of a subscription on the side of the subscriber.
+pub type Subscriber<T> = <Subscription<T> as HasDual>::Dual;
+
+/// Failure of a subscriber.
+#[derive(Debug)]
+pub enum Error<R, S, V> {
+    /// Waiting for the next message has failed.
+    Offer(R),
+    /// Acknowledgement of a message has failed.
+    Ack(S),
+    /// Receiving a message has failed.
+    Message(V),
+}
+
+/// Publisher of messages `T` to a set of subscriptions.
+pub struct Publisher<SR, T> {
+    subscribers: Vec<Chan<SR, (Feed<T>, ()), Feed<T>>>,
+}
+
+impl<SR, T> Default for Publisher<SR, T> where SR: TimeoutCarrier, T: ChannelSend<SR> + Clone {
+    fn default() -> Publisher<SR, T> {
+        Publisher::new()
+    }
+}
+
+impl<SR, T> Publisher<SR, T> where SR: TimeoutCarrier, T: ChannelSend<SR> + Clone {
+    pub fn new() -> Publisher<SR, T> {
+        Publisher { subscribers: Vec::new() }
+    }
+
+    /// Add a subscription, it receives the messages published from now on.
+    pub fn subscribe(&mut self, chan: Chan<SR, (), Subscription<T>>) {
+        self.subscribers.push(chan.enter());
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Send a copy of `message` to every subscriber and wait until all of
+    /// them have acknowledged it. Returns the number of subscribers the
+    /// message has been delivered to. Subscribers which have unsubscribed
+    /// or failed are closed and removed.
+    pub fn publish(&mut self, message: T) -> usize {
+        let mut group = Group::new();
+        for chan in self.subscribers.drain(..) {
+            if let Ok(chan) = chan.first() {
+                group.subscribe(chan);
+            }
+        }
+        let (group, _) = group.send(message);
+        let delivered = group.len();
+        let mut pending = group.into_chans();
+        while !pending.is_empty() {
+            let ready = {
+                let mut select = ChanSelect::new();
+                for (index, chan) in pending.iter_mut().enumerate() {
+                    select.add_offer(chan, index);
+                }
+                select.wait()
+            };
+            let kept = pending.swap_remove(ready)
+                .offer()
+                .option(|chan| Some(chan.zero()))
+                .option(|chan| {
+                    chan.close();
+                    None
+                })
+                .unwrap_or(None);
+            if let Some(chan) = kept {
+                self.subscribers.push(chan);
+            }
+        }
+        delivered
+    }
+
+    /// End the feed of every subscriber.
+    pub fn close(self) {
+        for chan in self.subscribers {
+            if let Ok(chan) = chan.second() {
+                chan.close();
+            }
+        }
+    }
+}
+
+/// Receive messages of a subscription with `handler` until it returns
+/// `false`, which unsubscribes, or until the publisher closes the feed.
+pub fn receive<SR, T, F>(chan: Chan<SR, (), Subscriber<T>>, mut handler: F) -> Result<(), Error<SR::RecvChoiceErr, SR::SendChoiceErr, T::Err>>
+    where SR: Carrier, T: ChannelRecv<SR>, F: FnMut(T) -> bool
+{
+    let mut chan = chan.enter();
+    loop {
+        let next = chan.offer()
+            .option(|chan| {
+                let (chan, message) = chan.recv().map_err(Error::Message)?;
+                if handler(message) {
+                    Ok(Some(chan.first().map_err(Error::Ack)?.zero()))
+                } else {
+                    chan.second().map_err(Error::Ack)?.close();
+                    Ok(None)
+                }
+            })
+            .option(|chan| {
+                chan.close();
+                Ok(None)
+            })
+            .map_err(Error::Offer)??;
+        match next {
+            Some(next) => chan = next,
+            None => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Rate limiting of sessions.
+//!
+//! A `RateLimitedCarrier` enforces a `RatePolicy` on a session: budgets of
+//! messages and of bytes per second, separately for sends and receives.
+//! Every budget is a token bucket holding at most one second worth of
+//! tokens (see `RatePolicy::burst`), so short bursts are allowed. An
+//! operation over budget waits until it fits, or with `RatePolicy::reject`
+//! fails with `RateLimitError::Exceeded` instead, so that a multi-tenant
+//! server could refuse a greedy session rather than slow down.
+//!
+//! Values are weighed with `Weigh`: `bytes::Bytes` and archived values by
+//! their length, `stream::Value` by its `bincode` encoding, and in-process
+//! values by their size in memory (not counting the data they own on the
+//! heap). A choice weighs one byte. A received value is weighed once it has
+//! arrived, so it could overdraw the byte budget of receives, and then the
+//! next receive waits until the debt is repaid.
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//! use session_types_ng::{Send, Recv, End, mpsc};
+//! use session_types_ng::ratelimit::{RatePolicy, RateLimitError};
+//!
+//! type Burst = Send<mpsc::Value<u8>, Send<mpsc::Value<u8>, Send<mpsc::Value<u8>, End>>>;
+//!
+//! // 20 messages per second, at most 2 at once: the third one waits for 50 ms
+//! let (tx, rx) = mpsc::session_channel::<Burst>();
+//! let tx = tx.with_rate_limit(RatePolicy::new().send_messages(20).burst(Duration::from_millis(100)));
+//! let started = Instant::now();
+//! tx.send(mpsc::Value(1)).unwrap().send(mpsc::Value(2)).unwrap().send(mpsc::Value(3)).unwrap().close();
+//! assert!(started.elapsed() >= Duration::from_millis(40));
+//! let (rx, _) = rx.recv().unwrap();
+//! let (rx, _) = rx.recv().unwrap();
+//! let (rx, _) = rx.recv().unwrap();
+//! rx.close();
+//!
+//! // a rejected message is not sent
+//! let (tx, rx) = mpsc::session_channel::<Burst>();
+//! let tx = tx.with_rate_limit(RatePolicy::new().send_messages(20).burst(Duration::from_millis(100)).reject());
+//! let tx = tx.send(mpsc::Value(1)).unwrap().send(mpsc::Value(2)).unwrap();
+//! assert!(matches!(tx.send(mpsc::Value(3)), Err(RateLimitError::Exceeded)));
+//! let (rx, _) = rx.recv().unwrap();
+//! let (rx, _) = rx.recv().unwrap();
+//! assert!(rx.recv().is_err());
+//! ```
+use std::mem::size_of;
+use std::thread;
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, Chan, mpsc, bytes};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::layer::CarrierLayer;
+#[cfg(feature = "stream")]
+use super::stream;
+#[cfg(feature = "protobuf")]
+use super::protobuf;
+#[cfg(feature = "archive")]
+use super::archive;
+
+/// Budgets of a session, all of them unlimited by default.
+#[derive(Clone, Debug, Default)]
+pub struct RatePolicy {
+    send_messages: Option<u64>,
+    send_bytes: Option<u64>,
+    recv_messages: Option<u64>,
+    recv_bytes: Option<u64>,
+    burst: Option<Duration>,
+    reject: bool,
+}
+
+impl RatePolicy {
+    pub fn new() -> RatePolicy {
+        RatePolicy::default()
+    }
+
+    /// Send at most `rate` values and choices per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn send_messages(mut self, rate: u64) -> RatePolicy {
+        assert!(rate > 0, "rate limit must be positive");
+        self.send_messages = Some(rate);
+        self
+    }
+
+    /// Send at most `rate` bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn send_bytes(mut self, rate: u64) -> RatePolicy {
+        assert!(rate > 0, "rate limit must be positive");
+        self.send_bytes = Some(rate);
+        self
+    }
+
+    /// Receive at most `rate` values and choices per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn recv_messages(mut self, rate: u64) -> RatePolicy {
+        assert!(rate > 0, "rate limit must be positive");
+        self.recv_messages = Some(rate);
+        self
+    }
+
+    /// Receive at most `rate` bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is zero.
+    pub fn recv_bytes(mut self, rate: u64) -> RatePolicy {
+        assert!(rate > 0, "rate limit must be positive");
+        self.recv_bytes = Some(rate);
+        self
+    }
+
+    /// Let budgets hold `burst` worth of tokens at most, instead of one
+    /// second worth of them.
+    pub fn burst(mut self, burst: Duration) -> RatePolicy {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// Fail operations over budget instead of waiting.
+    pub fn reject(mut self) -> RatePolicy {
+        self.reject = true;
+        self
+    }
+}
+
+/// Error of an operation over `RateLimitedCarrier`.
+#[derive(Debug)]
+pub enum RateLimitError<E> {
+    /// The operation is over budget, and the policy rejects it.
+    Exceeded,
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+/// Size of a value for byte budgets.
+pub trait Weigh {
+    fn weight(&self) -> usize;
+}
+
+impl<T> Weigh for mpsc::Value<T> where T: Send + 'static {
+    fn weight(&self) -> usize {
+        size_of::<T>()
+    }
+}
+
+impl<SR, E, P> Weigh for Chan<SR, E, P> {
+    fn weight(&self) -> usize {
+        size_of::<Self>()
+    }
+}
+
+impl Weigh for bytes::Bytes {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T> Weigh for stream::Value<T> where T: serde::Serialize {
+    fn weight(&self) -> usize {
+        bincode::serialized_size(&self.0).map_or(0, |size| size as usize)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<M> Weigh for protobuf::Message<M> where M: prost::Message {
+    fn weight(&self) -> usize {
+        self.0.encoded_len()
+    }
+}
+
+#[cfg(feature = "archive")]
+impl<T> Weigh for archive::Archived<T> {
+    fn weight(&self) -> usize {
+        self.as_bytes().len()
+    }
+}
+
+/// Token bucket of a single budget.
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, burst: Duration) -> Bucket {
+        let rate = rate as f64;
+        let capacity = (rate * burst.as_secs_f64()).max(1.0);
+        Bucket { rate, capacity, tokens: capacity, refilled: Instant::now() }
+    }
+
+    /// How long to wait until `amount` tokens (at most a full bucket) are
+    /// available.
+    fn delay(&mut self, amount: f64) -> Duration {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * self.rate).min(self.capacity);
+        self.refilled = now;
+        let missing = amount.min(self.capacity) - self.tokens;
+        if missing > 0.0 {
+            Duration::from_secs_f64(missing / self.rate)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// Budgets of one direction.
+struct Budget {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl Budget {
+    fn new(messages: Option<u64>, bytes: Option<u64>, burst: Option<Duration>) -> Budget {
+        let burst = burst.unwrap_or(Duration::from_secs(1));
+        Budget {
+            messages: messages.map(|rate| Bucket::new(rate, burst)),
+            bytes: bytes.map(|rate| Bucket::new(rate, burst)),
+        }
+    }
+
+    /// Wait until a message of `weight` bytes fits into the budget (or a
+    /// message of unknown weight, once the budget is not in debt), and
+    /// charge for it.
+    fn admit(&mut self, weight: Option<usize>, reject: bool) -> Result<(), ()> {
+        let mut delay = Duration::from_secs(0);
+        if let Some(ref mut bucket) = self.messages {
+            delay = delay.max(bucket.delay(1.0));
+        }
+        if let Some(ref mut bucket) = self.bytes {
+            delay = delay.max(bucket.delay(weight.unwrap_or(0) as f64));
+        }
+        if delay > Duration::from_secs(0) {
+            if reject {
+                return Err(());
+            }
+            thread::sleep(delay);
+        }
+        if let Some(ref mut bucket) = self.messages {
+            bucket.delay(1.0);
+            bucket.tokens -= 1.0;
+        }
+        if let Some(weight) = weight {
+            self.charge(weight);
+        }
+        Ok(())
+    }
+
+    fn charge(&mut self, weight: usize) {
+        if let Some(ref mut bucket) = self.bytes {
+            bucket.delay(0.0);
+            bucket.tokens -= weight as f64;
+        }
+    }
+}
+
+/// Carrier wrapper enforcing a `RatePolicy`.
+pub struct RateLimitedCarrier<C> {
+    inner: C,
+    send: Budget,
+    recv: Budget,
+    reject: bool,
+}
+
+impl<C> RateLimitedCarrier<C> {
+    pub fn new(inner: C, policy: RatePolicy) -> RateLimitedCarrier<C> {
+        RateLimitedCarrier {
+            inner,
+            send: Budget::new(policy.send_messages, policy.send_bytes, policy.burst),
+            recv: Budget::new(policy.recv_messages, policy.recv_bytes, policy.burst),
+            reject: policy.reject,
+        }
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn admit_send<E>(&mut self, weight: usize) -> Result<(), RateLimitError<E>> {
+        self.send.admit(Some(weight), self.reject).map_err(|()| RateLimitError::Exceeded)
+    }
+
+    fn admit_recv<E>(&mut self) -> Result<(), RateLimitError<E>> {
+        self.recv.admit(None, self.reject).map_err(|()| RateLimitError::Exceeded)
+    }
+}
+
+/// Layer wrapping carriers into `RateLimitedCarrier`s, each one with
+/// budgets of its own.
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    policy: RatePolicy,
+}
+
+impl RateLimitLayer {
+    pub fn new(policy: RatePolicy) -> RateLimitLayer {
+        RateLimitLayer { policy }
+    }
+}
+
+impl<C> CarrierLayer<C> for RateLimitLayer {
+    type Carrier = RateLimitedCarrier<C>;
+
+    fn layer(&self, inner: C) -> RateLimitedCarrier<C> {
+        RateLimitedCarrier::new(inner, self.policy.clone())
+    }
+}
+
+impl<C> Carrier for RateLimitedCarrier<C> where C: Carrier {
+    type SendChoiceErr = RateLimitError<C::SendChoiceErr>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.admit_send(1)?;
+        self.inner.send_choice(choice).map_err(RateLimitError::Carrier)
+    }
+
+    type RecvChoiceErr = RateLimitError<C::RecvChoiceErr>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.admit_recv()?;
+        let choice = self.inner.recv_choice().map_err(RateLimitError::Carrier)?;
+        self.recv.charge(1);
+        Ok(choice)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+/// Waiting for the peer is free.
+impl<C> TimeoutCarrier for RateLimitedCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout).map_err(RateLimitError::Carrier)
+    }
+}
+
+/// Messages are charged when sent, not when flushed.
+impl<C> BatchCarrier for RateLimitedCarrier<C> where C: BatchCarrier {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+impl<C> CapacityCarrier for RateLimitedCarrier<C> where C: CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+/// Values transferable over a carrier are transferable over its rate
+/// limited wrapper.
+impl<C, T> ChannelSend<RateLimitedCarrier<C>> for T where T: Payload + ChannelSend<C> + Weigh {
+    type Err = RateLimitError<<T as ChannelSend<C>>::Err>;
+
+    fn send(self, carrier: &mut RateLimitedCarrier<C>) -> Result<(), Self::Err> {
+        carrier.admit_send(self.weight())?;
+        self.send(&mut carrier.inner).map_err(RateLimitError::Carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<RateLimitedCarrier<C>> for T where T: Payload + ChannelRecv<C> + Weigh {
+    type Err = RateLimitError<<T as ChannelRecv<C>>::Err>;
+
+    fn recv(carrier: &mut RateLimitedCarrier<C>) -> Result<Self, Self::Err> {
+        carrier.admit_recv()?;
+        let value = <T>::recv(&mut carrier.inner).map_err(RateLimitError::Carrier)?;
+        carrier.recv.charge(value.weight());
+        Ok(value)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Enforce `policy` on the rest of the session, see the `ratelimit`
+    /// module documentation.
+    #[must_use]
+    pub fn with_rate_limit(self, policy: RatePolicy) -> Chan<RateLimitedCarrier<SR>, E, P> {
+        Chan {
+            carrier: RateLimitedCarrier::new(self.carrier, policy),
+            session: self.session,
+        }
+    }
+}
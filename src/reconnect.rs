@@ -0,0 +1,247 @@
+//! Transparent reconnection of carriers.
+//!
+//! A `ReconnectingCarrier` owns a function establishing a session over its
+//! underlying carrier, for example with `stream::connect`, which connects a
+//! TCP stream and passes the handshake (respectively with
+//! `SessionListener::accept` on the server). When an
+//! operation fails while the session is at a safe re-entry point, the
+//! carrier is established anew following a `supervise::RestartPolicy` and
+//! the operation is retried, so the session goes on as if nothing happened.
+//! Elsewhere failures are returned as usual.
+//!
+//! A position is safe when it has been declared with `safe_at::<P>()`, `P`
+//! being the protocol of the session at it (typically the body of a `Rec`
+//! loop, which is safe at its head then), and nothing has been transferred since. The
+//! start of the session is always safe. Both sides should declare the same
+//! (dual) positions, and it is up to the application to ensure that a step
+//! failing there has not been delivered to the peer, for example because
+//! the peer only replies after a step following it. Sent values are cloned
+//! for retries, so only payloads implementing `Clone` could be sent over a
+//! `ReconnectingCarrier`.
+//!
+//! ```
+//! use std::net::{Shutdown, TcpListener};
+//! use std::thread;
+//! use std::time::Duration;
+//! use session_types_ng::{Chan, Rec, Var, Z, Offer, Nil, Send, Recv, End, Dual};
+//! use session_types_ng::stream::{self, SessionListener, Value};
+//! use session_types_ng::supervise::RestartPolicy;
+//! use session_types_ng::reconnect::ReconnectingCarrier;
+//!
+//! type Srv = Offer<End, Offer<Recv<Value<u64>, Send<Value<u64>, Var<Z>>>, Nil>>;
+//! type Cli = Dual<Srv>;
+//!
+//! let policy = RestartPolicy::new().max_restarts(5).backoff(Duration::from_millis(1), Duration::from_millis(10));
+//! let listener = SessionListener::<Rec<Srv>>::new(TcpListener::bind("127.0.0.1:0").unwrap());
+//! let addr = listener.local_addr().unwrap();
+//!
+//! let server_policy = policy.clone();
+//! let server = thread::spawn(move || {
+//!     let carrier = ReconnectingCarrier::new(move || listener.accept().map(|(chan, _)| chan), server_policy)
+//!         .unwrap()
+//!         .safe_at::<Srv>();
+//!     let chan: Chan<_, (), Rec<Srv>> = Chan::new(carrier);
+//!     let mut chan = chan.enter();
+//!     loop {
+//!         let next = chan
+//!             .offer()
+//!             .option(|chan| {
+//!                 chan.close();
+//!                 None
+//!             })
+//!             .option(|chan| {
+//!                 let (chan, Value(n)) = chan.recv().unwrap();
+//!                 Some(chan.send(Value(n * 2)).unwrap().zero())
+//!             })
+//!             .unwrap();
+//!         match next {
+//!             Some(next) => chan = next,
+//!             None => break,
+//!         }
+//!     }
+//! });
+//!
+//! let carrier = ReconnectingCarrier::new(move || stream::connect::<Rec<Cli>, _>(addr), policy)
+//!     .unwrap()
+//!     .safe_at::<Cli>();
+//! let chan: Chan<_, (), Rec<Cli>> = Chan::new(carrier);
+//! let (chan, Value(a)) = chan.enter().second().unwrap().send(Value(1)).unwrap().recv().unwrap();
+//! let mut chan = chan.zero();
+//!
+//! // the connection breaks at the head of the loop
+//! chan.carrier_mut().get_mut().get_mut().shutdown(Shutdown::Both).unwrap();
+//! let (mut chan, Value(b)) = chan.second().unwrap().send(Value(2)).unwrap().recv().unwrap();
+//! assert_eq!(chan.carrier_mut().reconnects(), 1);
+//! chan.zero().first().unwrap().close();
+//! server.join().unwrap();
+//! assert_eq!((a, b), (2, 4));
+//! ```
+use std::any::type_name;
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+use super::{Carrier, Payload, ChannelSend, ChannelRecv, Chan};
+use super::timeout::TimeoutCarrier;
+use super::supervise::RestartPolicy;
+
+/// Error of an operation over `ReconnectingCarrier`.
+#[derive(Debug)]
+pub enum ReconnectError<E, X> {
+    /// The operation has failed, and either the session has not been at a
+    /// safe position, or the carrier has been given up on after retries.
+    Carrier(E),
+    /// The carrier could not be established anew.
+    Connect(X),
+}
+
+/// Carrier wrapper re-establishing its carrier with `connect`, see the
+/// module documentation.
+pub struct ReconnectingCarrier<C, F> {
+    inner: C,
+    connect: F,
+    policy: RestartPolicy,
+    safe: Vec<&'static str>,
+    step: &'static str,
+    at_safe: bool,
+    reconnects: u32,
+}
+
+impl<C, F, P, X> ReconnectingCarrier<C, F> where F: FnMut() -> Result<Chan<C, (), P>, X> {
+    /// Establish the carrier with `connect`, which is called again to
+    /// re-establish it after failures according to `policy`. A session over
+    /// the wrapper should follow the protocol `P` of the established ones.
+    pub fn new(mut connect: F, policy: RestartPolicy) -> Result<ReconnectingCarrier<C, F>, X> {
+        Ok(ReconnectingCarrier {
+            inner: carrier_of(connect()?),
+            connect,
+            policy,
+            safe: Vec::new(),
+            step: "start",
+            at_safe: true,
+            reconnects: 0,
+        })
+    }
+}
+
+impl<C, F> ReconnectingCarrier<C, F> {
+    /// Declare the position of a session at the protocol `P` a safe
+    /// re-entry point.
+    pub fn safe_at<P>(mut self) -> ReconnectingCarrier<C, F> {
+        self.safe.push(type_name::<P>());
+        self
+    }
+
+    /// Number of times the carrier has been re-established so far.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C, F, P, X> ReconnectingCarrier<C, F> where C: Carrier, F: FnMut() -> Result<Chan<C, (), P>, X> {
+    /// Run `op` over the carrier, re-establishing it and retrying the
+    /// operation while it fails at a safe position.
+    fn retry<T, E, O>(&mut self, mut op: O) -> Result<T, ReconnectError<E, X>> where O: FnMut(&mut C) -> Result<T, E> {
+        let mut failures = 0;
+        let mut backoff = self.policy.initial_backoff;
+        let mut failure = match op(&mut self.inner) {
+            Ok(value) => {
+                self.at_safe = false;
+                return Ok(value);
+            },
+            Err(e) =>
+                ReconnectError::Carrier(e),
+        };
+        while self.at_safe && self.policy.max_restarts.is_none_or(|max| failures < max) {
+            failures += 1;
+            thread::sleep(backoff);
+            backoff = cmp::min(backoff * 2, self.policy.max_backoff);
+            failure = match (self.connect)() {
+                Ok(chan) => {
+                    self.inner = carrier_of(chan);
+                    self.inner.step(self.step);
+                    self.reconnects += 1;
+                    match op(&mut self.inner) {
+                        Ok(value) => {
+                            self.at_safe = false;
+                            return Ok(value);
+                        },
+                        Err(e) =>
+                            ReconnectError::Carrier(e),
+                    }
+                },
+                Err(e) =>
+                    ReconnectError::Connect(e),
+            };
+        }
+        Err(failure)
+    }
+}
+
+impl<C, F, P, X> Carrier for ReconnectingCarrier<C, F> where C: Carrier, F: FnMut() -> Result<Chan<C, (), P>, X> {
+    type SendChoiceErr = ReconnectError<C::SendChoiceErr, X>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.retry(|carrier| carrier.send_choice(choice))
+    }
+
+    type RecvChoiceErr = ReconnectError<C::RecvChoiceErr, X>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.retry(C::recv_choice)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.step = protocol;
+        if self.safe.contains(&protocol) {
+            self.at_safe = true;
+        }
+        self.inner.step(protocol);
+    }
+}
+
+/// Waiting for the peer transfers nothing, so the position stays safe.
+impl<C, F, P, X> TimeoutCarrier for ReconnectingCarrier<C, F> where C: TimeoutCarrier, F: FnMut() -> Result<Chan<C, (), P>, X> {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        let at_safe = self.at_safe;
+        let ready = self.retry(|carrier| carrier.wait_timeout(timeout))?;
+        self.at_safe = at_safe;
+        Ok(ready)
+    }
+}
+
+/// Take over the carrier of a freshly established session.
+fn carrier_of<C, P>(chan: Chan<C, (), P>) -> C {
+    chan.session.finish();
+    chan.carrier
+}
+
+/// Cloneable values transferable over a carrier are transferable over its
+/// reconnecting wrapper.
+impl<C, F, P, X, T> ChannelSend<ReconnectingCarrier<C, F>> for T
+    where T: Payload + ChannelSend<C> + Clone, C: Carrier, F: FnMut() -> Result<Chan<C, (), P>, X>
+{
+    type Err = ReconnectError<<T as ChannelSend<C>>::Err, X>;
+
+    fn send(self, carrier: &mut ReconnectingCarrier<C, F>) -> Result<(), Self::Err> {
+        carrier.retry(|carrier| self.clone().send(carrier))
+    }
+}
+
+impl<C, F, P, X, T> ChannelRecv<ReconnectingCarrier<C, F>> for T
+    where T: Payload + ChannelRecv<C>, C: Carrier, F: FnMut() -> Result<Chan<C, (), P>, X>
+{
+    type Err = ReconnectError<<T as ChannelRecv<C>>::Err, X>;
+
+    fn recv(carrier: &mut ReconnectingCarrier<C, F>) -> Result<Self, Self::Err> {
+        carrier.retry(<T>::recv)
+    }
+}
+
@@ -0,0 +1,304 @@
+//! Runtime description of protocol types.
+//!
+//! `ProtocolDesc` turns a protocol type into a `Desc` tree which could be
+//! inspected at runtime. Sequencing combinators (`Seq`, `Repeat`,
+//! `Abortable`) are described by the protocol they expand to, so equal
+//! protocols on the wire have equal descriptions.
+use std::any::type_name;
+use std::fmt;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::{Nat, Then, End, Send, Recv, Nil, Choose, Offer, Labeled, Seq, Repeat, Rec, Var, Loop, Goto, Z, S};
+
+/// Description of a protocol step. Payload types and labels are identified by
+/// `std::any::type_name`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Desc {
+    End,
+    Send(&'static str, Box<Desc>),
+    Recv(&'static str, Box<Desc>),
+    Choose(Vec<Desc>),
+    Offer(Vec<Desc>),
+    Labeled(&'static str, Box<Desc>),
+    Rec(Box<Desc>),
+    /// Recursion variable, `0` refers to the innermost `Rec`.
+    Var(usize),
+    Loop(&'static str, Box<Desc>),
+    Goto(&'static str),
+    RecvTimeout { payload: &'static str, deadline: &'static str, in_time: Box<Desc>, timed_out: Box<Desc> },
+    SendTimeout { payload: &'static str, deadline: &'static str, in_time: Box<Desc>, timed_out: Box<Desc> },
+    Guarded(Box<Desc>, Box<Desc>),
+    Checked(Box<Desc>, Box<Desc>),
+    Bcast(&'static str, Box<Desc>),
+    Scatter(&'static str, Box<Desc>),
+    Gather(&'static str, Box<Desc>),
+    /// Strands running concurrently and the continuation after them.
+    Par(Box<Desc>, Box<Desc>, Box<Desc>),
+}
+
+/// Protocol type with a runtime description.
+pub trait ProtocolDesc {
+    fn desc() -> Desc;
+}
+
+/// Elements of a choose or offer list.
+pub trait ProtocolDescList {
+    fn branches(out: &mut Vec<Desc>);
+}
+
+/// Get a runtime description of protocol `P`.
+///
+/// ```
+/// use session_types_ng::{Send, Recv, End, mpsc::Value};
+/// use session_types_ng::reflect::describe;
+///
+/// let desc = describe::<Send<Value<u32>, Recv<Value<bool>, End>>>();
+/// assert_eq!(desc.to_string(), "Send<Value<u32>, Recv<Value<bool>, End>>");
+/// ```
+pub fn describe<P>() -> Desc where P: ProtocolDesc {
+    P::desc()
+}
+
+impl Desc {
+    /// Label of a labeled branch.
+    pub fn label(&self) -> Option<&'static str> {
+        match *self {
+            Desc::Labeled(label, _) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Append a compact description with full type names to `out`.
+    pub fn write_canonical(&self, out: &mut String) {
+        let step = |out: &mut String, kind: &str, names: &[&str], next: &[&Desc]| {
+            out.push_str(kind);
+            out.push('<');
+            let mut first = true;
+            for name in names {
+                if !first { out.push(','); }
+                out.push_str(name);
+                first = false;
+            }
+            for desc in next {
+                if !first { out.push(','); }
+                desc.write_canonical(out);
+                first = false;
+            }
+            out.push('>');
+        };
+        match *self {
+            Desc::End => out.push_str("End"),
+            Desc::Send(payload, ref next) => step(out, "Send", &[payload], &[next]),
+            Desc::Recv(payload, ref next) => step(out, "Recv", &[payload], &[next]),
+            Desc::Choose(ref branches) => step(out, "Choose", &[], &branches.iter().collect::<Vec<_>>()),
+            Desc::Offer(ref branches) => step(out, "Offer", &[], &branches.iter().collect::<Vec<_>>()),
+            Desc::Labeled(label, ref next) => step(out, "Labeled", &[label], &[next]),
+            Desc::Rec(ref body) => step(out, "Rec", &[], &[body]),
+            Desc::Var(n) => step(out, "Var", &[&n.to_string()], &[]),
+            Desc::Loop(label, ref body) => step(out, "Loop", &[label], &[body]),
+            Desc::Goto(label) => step(out, "Goto", &[label], &[]),
+            Desc::RecvTimeout { payload, deadline, ref in_time, ref timed_out } =>
+                step(out, "RecvTimeout", &[payload, deadline], &[in_time, timed_out]),
+            Desc::SendTimeout { payload, deadline, ref in_time, ref timed_out } =>
+                step(out, "SendTimeout", &[payload, deadline], &[in_time, timed_out]),
+            Desc::Guarded(ref next, ref abort) => step(out, "Guarded", &[], &[next, abort]),
+            Desc::Checked(ref next, ref abort) => step(out, "Checked", &[], &[next, abort]),
+            Desc::Bcast(payload, ref next) => step(out, "Bcast", &[payload], &[next]),
+            Desc::Scatter(payload, ref next) => step(out, "Scatter", &[payload], &[next]),
+            Desc::Gather(payload, ref next) => step(out, "Gather", &[payload], &[next]),
+            Desc::Par(ref left, ref right, ref next) => step(out, "Par", &[], &[left, right, next]),
+        }
+    }
+}
+
+/// Pretty printed in the protocol type syntax with short type names.
+impl fmt::Display for Desc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter, kind: &str, branches: &[Desc]) -> fmt::Result {
+            for branch in branches {
+                write!(f, "{}<{}, ", kind, branch)?;
+            }
+            f.write_str("Nil")?;
+            for _ in branches {
+                f.write_str(">")?;
+            }
+            Ok(())
+        }
+        match *self {
+            Desc::End => f.write_str("End"),
+            Desc::Send(payload, ref next) => write!(f, "Send<{}, {}>", short_name(payload), next),
+            Desc::Recv(payload, ref next) => write!(f, "Recv<{}, {}>", short_name(payload), next),
+            Desc::Choose(ref branches) => list(f, "Choose", branches),
+            Desc::Offer(ref branches) => list(f, "Offer", branches),
+            Desc::Labeled(label, ref next) => write!(f, "Labeled<{}, {}>", short_name(label), next),
+            Desc::Rec(ref body) => write!(f, "Rec<{}>", body),
+            Desc::Var(n) => write!(f, "Var<{}>", n),
+            Desc::Loop(label, ref body) => write!(f, "Loop<{}, {}>", short_name(label), body),
+            Desc::Goto(label) => write!(f, "Goto<{}>", short_name(label)),
+            Desc::RecvTimeout { payload, deadline, ref in_time, ref timed_out } =>
+                write!(f, "RecvTimeout<{}, {}, {}, {}>", short_name(payload), short_name(deadline), in_time, timed_out),
+            Desc::SendTimeout { payload, deadline, ref in_time, ref timed_out } =>
+                write!(f, "SendTimeout<{}, {}, {}, {}>", short_name(payload), short_name(deadline), in_time, timed_out),
+            Desc::Guarded(ref next, ref abort) => write!(f, "Guarded<{}, {}>", next, abort),
+            Desc::Checked(ref next, ref abort) => write!(f, "Checked<{}, {}>", next, abort),
+            Desc::Bcast(payload, ref next) => write!(f, "Bcast<{}, {}>", short_name(payload), next),
+            Desc::Scatter(payload, ref next) => write!(f, "Scatter<{}, {}>", short_name(payload), next),
+            Desc::Gather(payload, ref next) => write!(f, "Gather<{}, {}>", short_name(payload), next),
+            Desc::Par(ref left, ref right, ref next) => write!(f, "Par<{}, {}, {}>", left, right, next),
+        }
+    }
+}
+
+/// Strip module paths from a type name.
+pub fn short_name(name: &str) -> String {
+    let mut pieces = name.split("::").peekable();
+    let mut short = String::new();
+    while let Some(piece) = pieces.next() {
+        if pieces.peek().is_some() {
+            short.push_str(piece.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_'));
+        } else {
+            short.push_str(piece);
+        }
+    }
+    short
+}
+
+impl ProtocolDesc for End {
+    fn desc() -> Desc {
+        Desc::End
+    }
+}
+
+impl<A, P: ProtocolDesc> ProtocolDesc for Send<A, P> {
+    fn desc() -> Desc {
+        Desc::Send(type_name::<A>(), Box::new(P::desc()))
+    }
+}
+
+impl<A, P: ProtocolDesc> ProtocolDesc for Recv<A, P> {
+    fn desc() -> Desc {
+        Desc::Recv(type_name::<A>(), Box::new(P::desc()))
+    }
+}
+
+impl ProtocolDescList for Nil {
+    fn branches(_out: &mut Vec<Desc>) { }
+}
+
+impl<P: ProtocolDesc, L: ProtocolDescList> ProtocolDescList for Choose<P, L> {
+    fn branches(out: &mut Vec<Desc>) {
+        out.push(P::desc());
+        L::branches(out);
+    }
+}
+
+impl<P: ProtocolDesc, L: ProtocolDescList> ProtocolDescList for Offer<P, L> {
+    fn branches(out: &mut Vec<Desc>) {
+        out.push(P::desc());
+        L::branches(out);
+    }
+}
+
+impl<P: ProtocolDesc, L: ProtocolDescList> ProtocolDesc for Choose<P, L> {
+    fn desc() -> Desc {
+        let mut branches = Vec::new();
+        <Self as ProtocolDescList>::branches(&mut branches);
+        Desc::Choose(branches)
+    }
+}
+
+impl<P: ProtocolDesc, L: ProtocolDescList> ProtocolDesc for Offer<P, L> {
+    fn desc() -> Desc {
+        let mut branches = Vec::new();
+        <Self as ProtocolDescList>::branches(&mut branches);
+        Desc::Offer(branches)
+    }
+}
+
+impl<Lbl, P: ProtocolDesc> ProtocolDesc for Labeled<Lbl, P> {
+    fn desc() -> Desc {
+        Desc::Labeled(type_name::<Lbl>(), Box::new(P::desc()))
+    }
+}
+
+impl<P: Then<Q>, Q> ProtocolDesc for Seq<P, Q> where P::Output: ProtocolDesc {
+    fn desc() -> Desc {
+        P::Output::desc()
+    }
+}
+
+impl<P, R: ProtocolDesc> ProtocolDesc for Repeat<Z, P, R> {
+    fn desc() -> Desc {
+        R::desc()
+    }
+}
+
+impl<N, P: Then<Repeat<N, P, R>>, R> ProtocolDesc for Repeat<S<N>, P, R> where P::Output: ProtocolDesc {
+    fn desc() -> Desc {
+        P::Output::desc()
+    }
+}
+
+impl<P: ProtocolDesc> ProtocolDesc for Rec<P> {
+    fn desc() -> Desc {
+        Desc::Rec(Box::new(P::desc()))
+    }
+}
+
+impl<N: Nat> ProtocolDesc for Var<N> {
+    fn desc() -> Desc {
+        Desc::Var(N::value())
+    }
+}
+
+impl<Lbl, P: ProtocolDesc> ProtocolDesc for Loop<Lbl, P> {
+    fn desc() -> Desc {
+        Desc::Loop(type_name::<Lbl>(), Box::new(P::desc()))
+    }
+}
+
+impl<Lbl> ProtocolDesc for Goto<Lbl> {
+    fn desc() -> Desc {
+        Desc::Goto(type_name::<Lbl>())
+    }
+}
+
+/// Position in a protocol description: the loops entered so far.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Cursor<'d> {
+    env: Vec<(Option<&'static str>, &'d Desc)>,
+}
+
+#[cfg(feature = "std")]
+impl<'d> Cursor<'d> {
+    /// Pass labels and recursion up to the next communication step or `End`.
+    pub(crate) fn settle(&mut self, mut step: &'d Desc) -> &'d Desc {
+        loop {
+            step = match *step {
+                Desc::Labeled(_, ref next) =>
+                    next,
+                Desc::Rec(ref body) => {
+                    self.env.push((None, body));
+                    body
+                },
+                Desc::Loop(label, ref body) => {
+                    self.env.push((Some(label), body));
+                    body
+                },
+                Desc::Var(n) => {
+                    let depth = self.env.len() - 1 - n;
+                    self.env.truncate(depth + 1);
+                    self.env[depth].1
+                },
+                Desc::Goto(label) => {
+                    let depth = self.env.iter().rposition(|&(name, _)| name == Some(label)).expect("goto refers to an enclosing loop");
+                    self.env.truncate(depth + 1);
+                    self.env[depth].1
+                },
+                _ =>
+                    return step,
+            };
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Recovery from carrier failures.
+//!
+//! Plain channel operations close the channel on failure. Their resumable
+//! counterparts keep the channel in the protocol state of the failed step
+//! instead, so that the carrier could be re-established (for example by
+//! reconnecting a byte stream through `carrier_mut`) and the step retried.
+//!
+//! It is up to the application (and the carrier) to ensure that the failed
+//! step has not been partially delivered to the peer.
+use std::fmt;
+use super::{Carrier, ChannelSend, ChannelRecv, Chan, Send, Recv, Offer, Nil, Nth, cast_chan, close_chan};
+
+/// The carrier has failed at a step, the channel still owns the carrier and
+/// stays at that step.
+pub struct Disconnected<C, Err> {
+    pub chan: C,
+    pub error: Err,
+}
+
+impl<C, Err> fmt::Debug for Disconnected<C, Err> where Err: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Disconnected").field("error", &self.error).finish()
+    }
+}
+
+/// The choice of the peer received by `offer_resumable`: either the head
+/// branch of the offer list, or the rest of the list.
+pub enum Branch<P, Q> {
+    Car(P),
+    Cdr(Q),
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Get a mutable reference to underlying carrier, e.g. to re-establish it.
+    pub fn carrier_mut(&mut self) -> &mut SR {
+        &mut self.carrier
+    }
+}
+
+impl<SR, E, P, T> Chan<SR, E, Send<T, P>> where SR: Carrier, T: ChannelSend<SR> {
+    /// Same as `send`, but on failure the channel stays at this step.
+    #[must_use]
+    pub fn send_resumable(mut self, v: T) -> Result<Chan<SR, E, P>, Disconnected<Chan<SR, E, Send<T, P>>, T::Err>> {
+        match v.send(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(error) =>
+                Err(Disconnected { chan: self, error }),
+        }
+    }
+}
+
+impl<SR, E, P, T> Chan<SR, E, Recv<T, P>> where SR: Carrier, T: ChannelRecv<SR> {
+    /// Same as `recv`, but on failure the channel stays at this step.
+    #[must_use]
+    pub fn recv_resumable(mut self) -> Result<(Chan<SR, E, P>, T), Disconnected<Chan<SR, E, Recv<T, P>>, T::Err>> {
+        match T::recv(&mut self.carrier) {
+            Ok(v) =>
+                Ok((cast_chan(self), v)),
+            Err(error) =>
+                Err(Disconnected { chan: self, error }),
+        }
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Same as `nth`, but on failure the channel stays at this step and the
+    /// whole selection could be retried.
+    #[must_use]
+    pub fn sel_resumable<N>(mut self) -> Result<Chan<SR, E, P::Cont>, Disconnected<Chan<SR, E, P>, SR::SendChoiceErr>> where P: Nth<N> {
+        match P::select(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(error) =>
+                Err(Disconnected { chan: self, error }),
+        }
+    }
+}
+
+impl<SR, E, P, Q, L> Chan<SR, E, Offer<P, Offer<Q, L>>> where SR: Carrier {
+    /// Resumable passive choice: receive whether the peer has selected the
+    /// head branch, otherwise the channel moves to the rest of the offer
+    /// list, where `offer_resumable` is called again. On failure the channel
+    /// stays at this step.
+    ///
+    /// ```
+    /// use std::net::{Shutdown, TcpListener, TcpStream};
+    /// use std::thread;
+    /// use session_types_ng::{Chan, Offer, Send, End, Nil, HasDual, S, Z};
+    /// use session_types_ng::stream::{self, Value};
+    /// use session_types_ng::resume::Branch;
+    ///
+    /// type Srv = Offer<End, Offer<Send<Value<u8>, End>, Nil>>;
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let addr = listener.local_addr().unwrap();
+    /// let client = thread::spawn(move || {
+    ///     // the connection breaks right before the choice
+    ///     let socket = TcpStream::connect(addr).unwrap();
+    ///     socket.shutdown(Shutdown::Both).unwrap();
+    ///     let chan: Chan<_, (), <Srv as HasDual>::Dual> = stream::session_channel(socket);
+    ///     let mut disconnected = match chan.sel_resumable::<S<Z>>() {
+    ///         Err(disconnected) => disconnected,
+    ///         Ok(..) => panic!("the connection is broken"),
+    ///     };
+    ///     *disconnected.chan.carrier_mut().get_mut() = TcpStream::connect(addr).unwrap();
+    ///     let (chan, Value(x)) = disconnected.chan.sel_resumable::<S<Z>>().unwrap().recv().unwrap();
+    ///     chan.close();
+    ///     x
+    /// });
+    ///
+    /// let chan: Chan<_, (), Srv> = stream::session_channel(listener.accept().unwrap().0);
+    /// let mut disconnected = match chan.offer_resumable() {
+    ///     Err(disconnected) => disconnected,
+    ///     Ok(..) => panic!("the peer has hung up"),
+    /// };
+    /// *disconnected.chan.carrier_mut().get_mut() = listener.accept().unwrap().0;
+    /// match disconnected.chan.offer_resumable().unwrap() {
+    ///     Branch::Car(chan) => chan.close(),
+    ///     Branch::Cdr(chan) => chan.offer_resumable().unwrap().send(Value(7)).unwrap().close(),
+    /// }
+    /// assert_eq!(client.join().unwrap(), 7);
+    /// ```
+    #[must_use]
+    pub fn offer_resumable(mut self) -> Result<Branch<Chan<SR, E, P>, Chan<SR, E, Offer<Q, L>>>, Disconnected<Chan<SR, E, Offer<P, Offer<Q, L>>>, SR::RecvChoiceErr>> {
+        match self.carrier.recv_choice() {
+            Ok(true) =>
+                Ok(Branch::Car(cast_chan(self))),
+            Ok(false) =>
+                Ok(Branch::Cdr(cast_chan(self))),
+            Err(error) =>
+                Err(Disconnected { chan: self, error }),
+        }
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, Offer<P, Nil>> where SR: Carrier {
+    /// Resumable passive choice of the last branch of the offer list. On
+    /// failure the channel stays at this step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the peer has selected a branch beyond the offer list.
+    #[must_use]
+    pub fn offer_resumable(mut self) -> Result<Chan<SR, E, P>, Disconnected<Chan<SR, E, Offer<P, Nil>>, SR::RecvChoiceErr>> {
+        match self.carrier.recv_choice() {
+            Ok(true) =>
+                Ok(cast_chan(self)),
+            Ok(false) => {
+                close_chan(self);
+                panic!("session protocol offer list out of range")
+            },
+            Err(error) =>
+                Err(Disconnected { chan: self, error }),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Request/response services over sessions.
+//!
+//! A service is declared once with `rpc_service!` as a list of methods,
+//! each taking a request and returning a response. The macro derives the
+//! protocol of the service, a loop offering every method and the end of the
+//! session:
+//!
+//! ```text
+//! Rec<Offer<Recv<Req1, Send<Resp1, Var<Z>>>, Offer<Recv<Req2, Send<Resp2, Var<Z>>>, Offer<End, Nil>>>>
+//! ```
+//!
+//! together with a `Service` trait implemented by the server, a `serve`
+//! loop dispatching calls to it and a client `Stub` with a typed method for
+//! every method of the service.
+
+/// Failure of a call or of a server loop.
+#[derive(Debug)]
+pub enum Error<C> {
+    /// Selection or offer of a method has failed.
+    Choice(C),
+    /// Transfer of a request or a response of `method` has failed, with the
+    /// debug representation of the carrier error (which type differs from
+    /// one method to another).
+    Transfer { method: &'static str, error: String },
+    /// The session has been closed by a previous failure.
+    Closed,
+}
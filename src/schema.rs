@@ -0,0 +1,209 @@
+//! Language neutral protocol schemas.
+//!
+//! `json` exports a protocol type as a JSON document, so that
+//! implementations in other languages and code generators could target the
+//! same wire protocol. Every step is an object with a `step` field:
+//!
+//! * `{"step": "end"}`
+//! * `{"step": "send", "payload": .., "rust_type": .., "next": ..}` and the
+//!   same for `recv`, `bcast`, `scatter` and `gather`, where `payload` is
+//!   the short name of the payload type and `rust_type` the full one
+//! * `{"step": "choose", "branches": [{"label": .., "protocol": ..}, ..]}`
+//!   and the same for `offer`, `label` is `null` for unlabeled branches
+//! * `{"step": "rec", "label": .., "body": ..}` for `Rec` (with `null`
+//!   label) and `Loop`, `{"step": "continue", "depth": ..}` for `Var`
+//!   counting from the innermost `rec`, and `{"step": "continue", "label":
+//!   ..}` for `Goto`
+//! * `recv_timeout` and `send_timeout` with `payload`, `rust_type`,
+//!   `deadline`, `in_time` and `timed_out`
+//! * `guarded` and `checked` with `next` and `abort`
+//! * `par` with `left`, `right` and `next`
+//!
+//! On the wire a choice of the branch `i` (counting from zero) is
+//! transmitted as `i` choice bits `false` followed by one `true`. Labels are
+//! not transmitted.
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::reflect::{ProtocolDesc, Desc, short_name};
+
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn string(&mut self, value: &str) {
+        self.out.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                c if (c as u32) < 0x20 => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn key(&mut self, key: &str) {
+        self.out.push_str(", ");
+        self.string(key);
+        self.out.push_str(": ");
+    }
+
+    fn open(&mut self, step: &str) {
+        self.out.push_str("{\"step\": ");
+        self.string(step);
+    }
+
+    fn payload(&mut self, payload: &str) {
+        self.key("payload");
+        self.string(&short_name(payload));
+        self.key("rust_type");
+        self.string(payload);
+    }
+
+    fn label(&mut self, label: Option<&str>) {
+        self.key("label");
+        match label {
+            Some(label) => self.string(&short_name(label)),
+            None => self.out.push_str("null"),
+        }
+    }
+
+    fn field(&mut self, key: &str, desc: &Desc) {
+        self.key(key);
+        self.desc(desc);
+    }
+
+    fn message(&mut self, step: &str, payload: &str, next: &Desc) {
+        self.open(step);
+        self.payload(payload);
+        self.field("next", next);
+        self.out.push('}');
+    }
+
+    fn branches(&mut self, step: &str, branches: &[Desc]) {
+        self.open(step);
+        self.key("branches");
+        self.out.push('[');
+        for (i, branch) in branches.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            let (label, protocol) = match *branch {
+                Desc::Labeled(label, ref protocol) => (Some(label), &**protocol),
+                ref protocol => (None, protocol),
+            };
+            self.out.push_str("{\"label\": ");
+            match label {
+                Some(label) => self.string(&short_name(label)),
+                None => self.out.push_str("null"),
+            }
+            self.field("protocol", protocol);
+            self.out.push('}');
+        }
+        self.out.push_str("]}");
+    }
+
+    fn desc(&mut self, desc: &Desc) {
+        match *desc {
+            Desc::End =>
+                self.out.push_str("{\"step\": \"end\"}"),
+            Desc::Send(payload, ref next) =>
+                self.message("send", payload, next),
+            Desc::Recv(payload, ref next) =>
+                self.message("recv", payload, next),
+            Desc::Bcast(payload, ref next) =>
+                self.message("bcast", payload, next),
+            Desc::Scatter(payload, ref next) =>
+                self.message("scatter", payload, next),
+            Desc::Gather(payload, ref next) =>
+                self.message("gather", payload, next),
+            Desc::Choose(ref branches) =>
+                self.branches("choose", branches),
+            Desc::Offer(ref branches) =>
+                self.branches("offer", branches),
+            Desc::Labeled(label, ref next) => {
+                // a labeled step outside of a choice list
+                self.open("labeled");
+                self.label(Some(label));
+                self.field("next", next);
+                self.out.push('}');
+            },
+            Desc::Rec(ref body) => {
+                self.open("rec");
+                self.label(None);
+                self.field("body", body);
+                self.out.push('}');
+            },
+            Desc::Loop(label, ref body) => {
+                self.open("rec");
+                self.label(Some(label));
+                self.field("body", body);
+                self.out.push('}');
+            },
+            Desc::Var(depth) => {
+                self.open("continue");
+                self.key("depth");
+                self.out.push_str(&depth.to_string());
+                self.out.push('}');
+            },
+            Desc::Goto(label) => {
+                self.open("continue");
+                self.label(Some(label));
+                self.out.push('}');
+            },
+            Desc::RecvTimeout { payload, deadline, ref in_time, ref timed_out } |
+            Desc::SendTimeout { payload, deadline, ref in_time, ref timed_out } => {
+                self.open(if matches!(*desc, Desc::RecvTimeout { .. }) { "recv_timeout" } else { "send_timeout" });
+                self.payload(payload);
+                self.key("deadline");
+                self.string(&short_name(deadline));
+                self.field("in_time", in_time);
+                self.field("timed_out", timed_out);
+                self.out.push('}');
+            },
+            Desc::Guarded(ref next, ref abort) | Desc::Checked(ref next, ref abort) => {
+                self.open(if matches!(*desc, Desc::Guarded(..)) { "guarded" } else { "checked" });
+                self.field("next", next);
+                self.field("abort", abort);
+                self.out.push('}');
+            },
+            Desc::Par(ref left, ref right, ref next) => {
+                self.open("par");
+                self.field("left", left);
+                self.field("right", right);
+                self.field("next", next);
+                self.out.push('}');
+            },
+        }
+    }
+}
+
+/// Render a protocol description as a JSON schema, see the module
+/// documentation for its format.
+pub fn to_json(name: &str, desc: &Desc) -> String {
+    let mut writer = Writer { out: String::new() };
+    writer.out.push_str("{\"name\": ");
+    writer.string(name);
+    writer.out.push_str(", \"protocol\": ");
+    writer.desc(desc);
+    writer.out.push('}');
+    writer.out
+}
+
+/// Export protocol `P` named `name` as a JSON schema.
+///
+/// ```
+/// use session_types_ng::{Rec, Choose, Send, Recv, Var, Z, End, Nil, mpsc::Value};
+/// use session_types_ng::schema::json;
+///
+/// let schema = json::<Rec<Choose<Send<Value<u32>, Var<Z>>, Choose<Recv<Value<bool>, End>, Nil>>>>("Counter");
+/// assert_eq!(schema, concat!(
+///     r#"{"name": "Counter", "protocol": {"step": "rec", "label": null, "body": {"step": "choose", "branches": ["#,
+///     r#"{"label": null, "protocol": {"step": "send", "payload": "Value<u32>", "rust_type": "session_types_ng::mpsc::Value<u32>", "next": {"step": "continue", "depth": 0}}}, "#,
+///     r#"{"label": null, "protocol": {"step": "recv", "payload": "Value<bool>", "rust_type": "session_types_ng::mpsc::Value<bool>", "next": {"step": "end"}}}]}}}"#));
+/// ```
+pub fn json<P>(name: &str) -> String where P: ProtocolDesc {
+    to_json(name, &P::desc())
+}
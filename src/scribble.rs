@@ -0,0 +1,451 @@
+//! Interoperability with the Scribble protocol language.
+//!
+//! `import` translates Scribble local protocols into protocol type aliases.
+//! It is meant to be invoked from a build script:
+//!
+//! ```ignore
+//! // build.rs
+//! let source = std::fs::read_to_string("protocols/Shop.scr").unwrap();
+//! let types = session_types_ng::scribble::import(&source, Some("mpsc::Value")).unwrap();
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("shop.rs");
+//! std::fs::write(out, types).unwrap();
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/shop.rs"));
+//! ```
+//!
+//! Supported statements are `Label(T) to R;`, `Label(T) from R;`,
+//! `choice at R { .. } or { .. }`, `rec X { .. }` and `continue X;`.
+//! Message labels are not transferred, only their payloads. A message
+//! without payload at the beginning of a choice branch is taken as the
+//! choice itself. Module, import and payload type declarations are skipped.
+//!
+//! `export` does the opposite: it emits a Scribble local protocol for a
+//! protocol type, so participants in other languages could be generated
+//! from the Rust definition. Every choice is exported as a label message
+//! sent by the choosing side, which matches the choice transmitted by a
+//! carrier.
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::reflect::{ProtocolDesc, Desc, short_name};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Source ends in the middle of a declaration.
+    UnexpectedEnd,
+    /// Unexpected token at the given line.
+    Unexpected { line: usize, found: String, expected: &'static str },
+    /// `continue` refers to a recursion which is not in scope.
+    UnknownRecursion { line: usize, name: String },
+}
+
+enum Step {
+    Send(String),
+    Recv(String),
+    Choice { mine: bool, branches: Vec<Vec<Step>> },
+    Rec(Vec<Step>),
+    Continue(usize),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        self.source[.. self.pos].matches('\n').count() + 1
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos ..]
+    }
+
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map(|i| i + 2).unwrap_or(trimmed.len());
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn unexpected<T>(&mut self, expected: &'static str) -> Result<T, Error> {
+        self.skip_blank();
+        let found: String = self.rest().chars().take_while(|c| !c.is_whitespace()).collect();
+        if found.is_empty() {
+            Err(Error::UnexpectedEnd)
+        } else {
+            Err(Error::Unexpected { line: self.line(), found, expected })
+        }
+    }
+
+    fn peek_ident(&mut self) -> Option<&'a str> {
+        self.skip_blank();
+        let rest = self.rest();
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+        if len == 0 { None } else { Some(&rest[.. len]) }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, Error> {
+        match self.peek_ident() {
+            Some(ident) => {
+                self.pos += ident.len();
+                Ok(ident)
+            },
+            None => self.unexpected("identifier"),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &'static str) -> Result<(), Error> {
+        if self.peek_ident() == Some(keyword) {
+            self.pos += keyword.len();
+            Ok(())
+        } else {
+            self.unexpected(keyword)
+        }
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        self.skip_blank();
+        if self.rest().starts_with(punct) {
+            self.pos += punct.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char, expected: &'static str) -> Result<(), Error> {
+        if self.eat(punct) { Ok(()) } else { self.unexpected(expected) }
+    }
+
+    /// Skip everything up to and including the matching `close` character.
+    fn skip_past(&mut self, close: char) -> Result<&'a str, Error> {
+        let start = self.pos;
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '(' | '{' | '<' => depth += 1,
+                ')' | '}' | '>' | ';' if depth == 0 && c == close => {
+                    self.pos += i + c.len_utf8();
+                    return Ok(self.source[start .. start + i].trim());
+                },
+                ')' | '}' | '>' => depth -= 1,
+                _ => (),
+            }
+        }
+        Err(Error::UnexpectedEnd)
+    }
+
+    fn protocols(&mut self, wrapper: Option<&str>) -> Result<String, Error> {
+        let mut out = String::new();
+        loop {
+            match self.peek_ident() {
+                None => {
+                    self.skip_blank();
+                    return if self.rest().is_empty() { Ok(out) } else { self.unexpected("declaration") };
+                },
+                Some("module") | Some("import") | Some("type") | Some("sig") => {
+                    self.skip_past(';')?;
+                },
+                Some("local") => {
+                    self.keyword("local")?;
+                    self.keyword("protocol")?;
+                    let name = self.ident()?;
+                    self.keyword("at")?;
+                    let me = self.ident()?;
+                    self.expect('(', "role list")?;
+                    self.skip_past(')')?;
+                    let steps = self.block(me, &mut Vec::new())?;
+                    out.push_str(&format!("pub type {} = {};\n", name, render(&[&steps], wrapper)));
+                },
+                Some(_) => return self.unexpected("local protocol"),
+            }
+        }
+    }
+
+    fn block(&mut self, me: &str, recs: &mut Vec<String>) -> Result<Vec<Step>, Error> {
+        self.expect('{', "{")?;
+        let mut steps = Vec::new();
+        while !self.eat('}') {
+            let step = match self.ident()? {
+                "choice" => {
+                    self.keyword("at")?;
+                    let mine = self.ident()? == me;
+                    let mut branches = vec![self.block(me, recs)?];
+                    while self.peek_ident() == Some("or") {
+                        self.keyword("or")?;
+                        branches.push(self.block(me, recs)?);
+                    }
+                    for branch in &mut branches {
+                        let label = match branch.first() {
+                            Some(&Step::Send(ref payload)) | Some(&Step::Recv(ref payload)) => payload.is_empty(),
+                            _ => false,
+                        };
+                        if label {
+                            branch.remove(0);
+                        }
+                    }
+                    Step::Choice { mine, branches }
+                },
+                "rec" => {
+                    let name = self.ident()?.to_string();
+                    recs.push(name);
+                    let body = self.block(me, recs)?;
+                    recs.pop();
+                    Step::Rec(body)
+                },
+                "continue" => {
+                    let line = self.line();
+                    let name = self.ident()?;
+                    self.expect(';', ";")?;
+                    match recs.iter().rev().position(|rec| rec == name) {
+                        Some(depth) => Step::Continue(depth),
+                        None => return Err(Error::UnknownRecursion { line, name: name.to_string() }),
+                    }
+                },
+                _label => {
+                    self.expect('(', "message payload")?;
+                    let payload = self.skip_past(')')?.to_string();
+                    let step = match self.ident()? {
+                        "to" => Step::Send(payload),
+                        "from" => Step::Recv(payload),
+                        _ => return self.unexpected("to or from"),
+                    };
+                    self.ident()?;
+                    self.expect(';', ";")?;
+                    step
+                },
+            };
+            steps.push(step);
+        }
+        Ok(steps)
+    }
+}
+
+/// Render the first step of pending sequences `todo`, continuing with the
+/// rest. Statements following a block are rendered inside each of its
+/// branches.
+fn render(todo: &[&[Step]], wrapper: Option<&str>) -> String {
+    let (step, rest) = match todo.iter().position(|steps| !steps.is_empty()) {
+        Some(i) => {
+            let (step, tail) = todo[i].split_first().unwrap();
+            let mut rest = vec![tail];
+            rest.extend_from_slice(&todo[i + 1 ..]);
+            (step, rest)
+        },
+        None => return "End".to_string(),
+    };
+    let value = |payload: &str| {
+        let payload = if payload.is_empty() { "()" } else { payload };
+        match wrapper {
+            Some(wrapper) => format!("{}<{}>", wrapper, payload),
+            None => payload.to_string(),
+        }
+    };
+    match *step {
+        Step::Send(ref payload) =>
+            format!("Send<{}, {}>", value(payload), render(&rest, wrapper)),
+        Step::Recv(ref payload) =>
+            format!("Recv<{}, {}>", value(payload), render(&rest, wrapper)),
+        Step::Choice { mine, ref branches } => {
+            let list = if mine { "Choose" } else { "Offer" };
+            let mut out = "Nil".to_string();
+            for branch in branches.iter().rev() {
+                let mut todo = vec![&branch[..]];
+                todo.extend_from_slice(&rest);
+                out = format!("{}<{}, {}>", list, render(&todo, wrapper), out);
+            }
+            out
+        },
+        Step::Rec(ref body) => {
+            let mut todo = vec![&body[..]];
+            todo.extend_from_slice(&rest);
+            format!("Rec<{}>", render(&todo, wrapper))
+        },
+        Step::Continue(depth) => {
+            let mut nat = "Z".to_string();
+            for _ in 0 .. depth {
+                nat = format!("S<{}>", nat);
+            }
+            format!("Var<{}>", nat)
+        },
+    }
+}
+
+/// Translate Scribble local protocols from `source` into `pub type`
+/// aliases, one per protocol. Every payload type is wrapped into `wrapper`
+/// (for example `mpsc::Value`) if given. Generated code expects the crate
+/// prelude (`use session_types_ng::*`) to be in scope.
+///
+/// ```
+/// use session_types_ng::scribble::import;
+///
+/// let source = "
+///     local protocol Counter at C(role C, role S) {
+///         rec Loop {
+///             choice at C {
+///                 Add(u64) to S;
+///                 continue Loop;
+///             } or {
+///                 Total() to S;
+///                 Sum(u64) from S;
+///                 Bye() to S;
+///             }
+///         }
+///     }";
+/// assert_eq!(
+///     import(source, Some("Value")).unwrap(),
+///     "pub type Counter = Rec<Choose<Send<Value<u64>, Var<Z>>, \
+///      Choose<Recv<Value<u64>, Send<Value<()>, End>>, Nil>>>;\n");
+/// ```
+pub fn import(source: &str, wrapper: Option<&str>) -> Result<String, Error> {
+    Parser { source, pos: 0 }.protocols(wrapper)
+}
+
+struct Local {
+    out: String,
+    depth: usize,
+    recs: Vec<String>,
+}
+
+impl Local {
+    fn line<L>(&mut self, line: L) where L: AsRef<str> {
+        for _ in 0 ..= self.depth {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(line.as_ref());
+        self.out.push('\n');
+    }
+
+    fn message(&mut self, label: &str, payload: &str, mine: bool) {
+        let payload = if payload.is_empty() { String::new() } else { short_name(payload) };
+        if mine {
+            self.line(format!("{}({}) to Peer;", label, payload));
+        } else {
+            self.line(format!("{}({}) from Peer;", label, payload));
+        }
+    }
+
+    fn rec(&mut self, name: String, body: &Desc) {
+        self.line(format!("rec {} {{", name));
+        self.recs.push(name);
+        self.depth += 1;
+        self.statements(body);
+        self.depth -= 1;
+        self.recs.pop();
+        self.line("}");
+    }
+
+    /// Every branch starts with a label message from the choosing side,
+    /// followed by `prefix` messages and the branch protocol.
+    fn choice(&mut self, mine: bool, branches: &[(String, &Desc)], prefix: &[(&str, bool)]) {
+        self.line(format!("choice at {} {{", if mine { "Me" } else { "Peer" }));
+        for (i, &(ref label, branch)) in branches.iter().enumerate() {
+            if i > 0 {
+                self.line("} or {");
+            }
+            self.depth += 1;
+            self.message(label, "", mine);
+            for &(payload, sent) in prefix {
+                self.message("Msg", payload, sent);
+            }
+            self.statements(branch);
+            self.depth -= 1;
+        }
+        self.line("}");
+    }
+
+    fn statements(&mut self, desc: &Desc) {
+        match *desc {
+            Desc::End => (),
+            Desc::Send(payload, ref next) | Desc::Bcast(payload, ref next) | Desc::Scatter(payload, ref next) => {
+                self.message("Msg", payload, true);
+                self.statements(next);
+            },
+            Desc::Recv(payload, ref next) | Desc::Gather(payload, ref next) => {
+                self.message("Msg", payload, false);
+                self.statements(next);
+            },
+            Desc::Choose(ref branches) | Desc::Offer(ref branches) => {
+                let branches: Vec<_> = branches.iter().enumerate()
+                    .map(|(i, branch)| {
+                        let label = branch.label().map(short_name).unwrap_or_else(|| format!("Branch{}", i));
+                        (label, branch)
+                    })
+                    .collect();
+                let mine = matches!(*desc, Desc::Choose(..));
+                self.choice(mine, &branches, &[]);
+            },
+            Desc::Labeled(_, ref next) =>
+                self.statements(next),
+            Desc::Rec(ref body) => {
+                let name = format!("Rec{}", self.recs.len());
+                self.rec(name, body);
+            },
+            Desc::Var(n) => {
+                let name = self.recs[self.recs.len() - 1 - n].clone();
+                self.line(format!("continue {};", name));
+            },
+            Desc::Loop(label, ref body) =>
+                self.rec(short_name(label), body),
+            Desc::Goto(label) =>
+                self.line(format!("continue {};", short_name(label))),
+            Desc::RecvTimeout { payload, ref in_time, ref timed_out, .. } => {
+                let branches = [("InTime".to_string(), &**in_time), ("TimedOut".to_string(), &**timed_out)];
+                self.choice(true, &branches, &[(payload, false)]);
+            },
+            Desc::SendTimeout { payload, ref in_time, ref timed_out, .. } => {
+                self.message("Msg", payload, true);
+                let branches = [("InTime".to_string(), &**in_time), ("TimedOut".to_string(), &**timed_out)];
+                self.choice(false, &branches, &[]);
+            },
+            Desc::Guarded(ref next, ref abort) | Desc::Checked(ref next, ref abort) => {
+                let branches = [("Proceed".to_string(), &**next), ("Abort".to_string(), &**abort)];
+                self.choice(matches!(*desc, Desc::Guarded(..)), &branches, &[]);
+            },
+            Desc::Par(ref left, ref right, ref next) => {
+                self.line("par {");
+                self.depth += 1;
+                self.statements(left);
+                self.depth -= 1;
+                self.line("} and {");
+                self.depth += 1;
+                self.statements(right);
+                self.depth -= 1;
+                self.line("}");
+                self.statements(next);
+            },
+        }
+    }
+}
+
+/// Emit Scribble local protocol `name` for protocol `P`. The endpoint
+/// following `P` is role `Me` and its peer is role `Peer`.
+///
+/// ```
+/// use session_types_ng::{Send, Recv, End, mpsc::Value};
+/// use session_types_ng::scribble::export;
+///
+/// let local = export::<Send<Value<u32>, Recv<Value<bool>, End>>>("Ping");
+/// assert_eq!(local, "local protocol Ping at Me(role Me, role Peer) {\n    \
+///                    Msg(Value<u32>) to Peer;\n    \
+///                    Msg(Value<bool>) from Peer;\n}\n");
+/// ```
+pub fn export<P>(name: &str) -> String where P: ProtocolDesc {
+    let mut local = Local {
+        out: format!("local protocol {} at Me(role Me, role Peer) {{\n", name),
+        depth: 0,
+        recs: Vec::new(),
+    };
+    local.statements(&P::desc());
+    local.out.push_str("}\n");
+    local.out
+}
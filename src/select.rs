@@ -0,0 +1,206 @@
+//! Waiting on many channels at once.
+//!
+//! `ChanSelect` multiplexes channels which are about to receive: either at
+//! a `Recv` step or at an `Offer` step waiting for the choice of the other
+//! side. It returns the token of a channel which is ready, so the
+//! corresponding `recv` or `offer` won't block. Channels at a `Send` step
+//! over a bounded carrier could be added as well, they are ready when the
+//! carrier has capacity.
+//!
+//! Readiness is polled through `TimeoutCarrier::wait_timeout`, backing off
+//! up to a millisecond between polling rounds.
+use std::thread;
+use std::time::{Duration, Instant};
+use super::{Carrier, Chan, Send, Recv, Offer};
+use super::timeout::TimeoutCarrier;
+
+const MIN_PAUSE: Duration = Duration::from_micros(10);
+const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+/// A carrier which could tell whether a send would block, e.g. because of
+/// a bounded buffer.
+pub trait CapacityCarrier: Carrier {
+    fn has_capacity(&mut self) -> bool;
+
+    /// Wait at most `timeout` for the carrier to have capacity. Returns
+    /// whether it has. By default `has_capacity` is polled, backing off up
+    /// to a millisecond between the attempts.
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        let mut pause = MIN_PAUSE;
+        while !self.has_capacity() {
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining == Duration::from_secs(0) {
+                return false;
+            }
+            thread::sleep(pause.min(remaining));
+            pause = (pause * 2).min(MAX_PAUSE);
+        }
+        true
+    }
+}
+
+/// A channel which could be checked for readiness without blocking.
+pub trait Selectable {
+    fn ready(&mut self) -> bool;
+}
+
+/// A failed carrier is reported as ready, so that the following receive
+/// returns its error.
+pub(crate) fn carrier_ready<SR>(carrier: &mut SR) -> bool where SR: TimeoutCarrier {
+    carrier.wait_timeout(Duration::from_secs(0)).unwrap_or(true)
+}
+
+impl<SR, E, A, R> Selectable for Chan<SR, E, Recv<A, R>> where SR: TimeoutCarrier {
+    fn ready(&mut self) -> bool {
+        carrier_ready(&mut self.carrier)
+    }
+}
+
+/// A channel at a `Send` step is ready when the carrier has capacity.
+impl<SR, E, A, P> Selectable for Chan<SR, E, Send<A, P>> where SR: CapacityCarrier {
+    fn ready(&mut self) -> bool {
+        self.carrier.has_capacity()
+    }
+}
+
+impl<SR, E, P, L> Selectable for Chan<SR, E, Offer<P, L>> where SR: TimeoutCarrier {
+    fn ready(&mut self) -> bool {
+        carrier_ready(&mut self.carrier)
+    }
+}
+
+/// A set of channels waiting to receive, each one registered with a token.
+pub struct ChanSelect<'c, T> {
+    arms: Vec<(&'c mut dyn Selectable, T)>,
+}
+
+impl<'c, T> Default for ChanSelect<'c, T> {
+    fn default() -> ChanSelect<'c, T> {
+        ChanSelect { arms: Vec::new() }
+    }
+}
+
+impl<'c, T> ChanSelect<'c, T> {
+    pub fn new() -> ChanSelect<'c, T> {
+        ChanSelect::default()
+    }
+
+    /// Add a channel at a `Recv` step, returning `ret` when a value arrives.
+    pub fn add_recv<SR, E, A, R>(&mut self, chan: &'c mut Chan<SR, E, Recv<A, R>>, ret: T)
+        where SR: TimeoutCarrier + 'c, E: 'c, A: 'c, R: 'c
+    {
+        self.add(chan, ret);
+    }
+
+    /// Add a channel at an `Offer` step, returning `ret` when the other side
+    /// has made its choice.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Offer, Choose, End, Nil, mpsc};
+    /// use session_types_ng::select::ChanSelect;
+    ///
+    /// type Server = Offer<End, Offer<End, Nil>>;
+    /// type Client = Choose<End, Choose<End, Nil>>;
+    ///
+    /// let (mut idle, idle_client): (Chan<_, (), Server>, Chan<_, (), Client>) = mpsc::session_channel();
+    /// let (mut busy, busy_client): (Chan<_, (), Server>, Chan<_, (), Client>) = mpsc::session_channel();
+    /// busy_client.second().unwrap().close();
+    ///
+    /// let mut select = ChanSelect::new();
+    /// select.add_offer(&mut idle, "idle");
+    /// select.add_offer(&mut busy, "busy");
+    /// assert_eq!(select.wait(), "busy");
+    ///
+    /// busy.offer().option(|c| c.close()).option(|c| c.close()).unwrap();
+    /// idle_client.first().unwrap().close();
+    /// idle.offer().option(|c| c.close()).option(|c| c.close()).unwrap();
+    /// ```
+    pub fn add_offer<SR, E, P, L>(&mut self, chan: &'c mut Chan<SR, E, Offer<P, L>>, ret: T)
+        where SR: TimeoutCarrier + 'c, E: 'c, P: 'c, L: 'c
+    {
+        self.add(chan, ret);
+    }
+
+    /// Add a channel at a `Send` step, returning `ret` when the value could
+    /// be sent without blocking.
+    pub fn add_send<SR, E, A, P>(&mut self, chan: &'c mut Chan<SR, E, Send<A, P>>, ret: T)
+        where SR: CapacityCarrier + 'c, E: 'c, A: 'c, P: 'c
+    {
+        self.add(chan, ret);
+    }
+
+    /// Add any selectable channel, returning `ret` when it is ready.
+    pub fn add<C>(&mut self, chan: &'c mut C, ret: T) where C: Selectable {
+        self.arms.push((chan, ret));
+    }
+
+    /// Number of channels in the set.
+    pub fn len(&self) -> usize {
+        self.arms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arms.is_empty()
+    }
+
+    /// Block until one of the channels is ready and return its token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the set is empty.
+    pub fn wait(self) -> T {
+        assert!(!self.arms.is_empty(), "ChanSelect::wait on an empty set");
+        self.wait_until(None).expect("there is no deadline")
+    }
+
+    /// Same as `wait`, but give up after `timeout`, so that an event loop
+    /// could service timers and other work.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::select::ChanSelect;
+    ///
+    /// let (mut rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let mut select = ChanSelect::new();
+    /// select.add_recv(&mut rx, ());
+    /// assert_eq!(select.wait_timeout(Duration::from_millis(10)), None);
+    ///
+    /// tx.send(mpsc::Value(1)).unwrap().close();
+    /// let mut select = ChanSelect::new();
+    /// select.add_recv(&mut rx, ());
+    /// assert_eq!(select.try_wait(), Some(()));
+    /// rx.recv().unwrap().0.close();
+    /// ```
+    pub fn wait_timeout(self, timeout: Duration) -> Option<T> {
+        self.wait_until(Some(Instant::now() + timeout))
+    }
+
+    /// Return the token of a channel which is ready right now, if any.
+    pub fn try_wait(mut self) -> Option<T> {
+        self.poll().map(|index| self.arms.swap_remove(index).1)
+    }
+
+    fn wait_until(mut self, deadline: Option<Instant>) -> Option<T> {
+        let mut pause = MIN_PAUSE;
+        loop {
+            if let Some(index) = self.poll() {
+                return Some(self.arms.swap_remove(index).1);
+            }
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::from_secs(0) {
+                    return None;
+                }
+                pause = pause.min(remaining);
+            }
+            thread::sleep(pause);
+            pause = (pause * 2).min(MAX_PAUSE);
+        }
+    }
+
+    fn poll(&mut self) -> Option<usize> {
+        self.arms.iter_mut().position(|arm| arm.0.ready())
+    }
+}
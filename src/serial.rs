@@ -0,0 +1,176 @@
+//! Carrier over `embedded-hal` serial ports (enabled with `serial` feature),
+//! for session typed device protocols on microcontrollers over UART or CAN.
+//!
+//! Does not depend on `std` or `alloc`: values are `Frame`s of at most `N`
+//! bytes stored inline in a `heapless::Vec`. A frame is sent prefixed by its
+//! length (`u16`, big endian) and a choice as a single byte. Reads and writes
+//! block by spinning on the port.
+//!
+//! ```
+//! # extern crate embedded_hal_nb;
+//! # extern crate session_types_ng;
+//! # use std::cell::RefCell;
+//! # use std::collections::VecDeque;
+//! # use std::convert::Infallible;
+//! # use std::rc::Rc;
+//! # use embedded_hal_nb::{nb, serial};
+//! use session_types_ng::{Send, Recv, End, HasDual};
+//! use session_types_ng::serial::{self as uart, Frame};
+//!
+//! # /// Loopback serial port.
+//! # #[derive(Default)]
+//! # struct Wire { rx: Rc<RefCell<VecDeque<u8>>>, tx: Rc<RefCell<VecDeque<u8>>> }
+//! # impl serial::ErrorType for Wire { type Error = Infallible; }
+//! # impl serial::Read for Wire {
+//! #     fn read(&mut self) -> nb::Result<u8, Infallible> { self.rx.borrow_mut().pop_front().ok_or(nb::Error::WouldBlock) }
+//! # }
+//! # impl serial::Write for Wire {
+//! #     fn write(&mut self, word: u8) -> nb::Result<(), Infallible> { self.tx.borrow_mut().push_back(word); Ok(()) }
+//! #     fn flush(&mut self) -> nb::Result<(), Infallible> { Ok(()) }
+//! # }
+//! # fn main() {
+//! type Probe = Send<Frame<8>, Recv<Frame<8>, End>>;
+//!
+//! // ports of a loopback wire
+//! let host_port = Wire::default();
+//! let device_port = Wire { rx: host_port.tx.clone(), tx: host_port.rx.clone() };
+//! let host = uart::session_channel::<Probe, _, 8>(host_port);
+//! let device = uart::session_channel::<<Probe as HasDual>::Dual, _, 8>(device_port);
+//!
+//! let host = host.send(Frame::from_slice(b"ping").unwrap()).unwrap();
+//! let (device, request) = device.recv().unwrap();
+//! assert_eq!(&request[..], b"ping");
+//! device.send(Frame::from_slice(b"pong").unwrap()).unwrap().close();
+//! let (host, reply) = host.recv().unwrap();
+//! host.close();
+//! assert_eq!(&reply[..], b"pong");
+//! # }
+//! ```
+use std::ops::Deref;
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial::{Read, Write};
+use heapless::Vec;
+use super::{ChannelSend, ChannelRecv, Payload, Carrier, Chan};
+
+/// Carrier over a serial port `S` transferring frames of at most `N` bytes.
+pub struct Channel<S, const N: usize> {
+    port: S,
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Serial(E),
+    /// Frame length exceeds the capacity of the frame buffer or the `u16` prefix.
+    Overflow(usize),
+    /// Choice byte other than `0` or `1`, the peers are out of sync.
+    BadChoice(u8),
+}
+
+/// Payload of at most `N` bytes.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Frame<const N: usize>(pub Vec<u8, N>);
+
+impl<const N: usize> Payload for Frame<N> { }
+
+impl<const N: usize> Frame<N> {
+    /// Copy `bytes` into a frame, or `None` if there are more than `N` of them.
+    pub fn from_slice(bytes: &[u8]) -> Option<Frame<N>> {
+        Vec::from_slice(bytes).ok().map(Frame)
+    }
+}
+
+impl<const N: usize> Deref for Frame<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<S, const N: usize> Channel<S, N> where S: Read + Write {
+    pub fn new(port: S) -> Channel<S, N> {
+        Channel { port }
+    }
+
+    /// Get back underlying serial port.
+    pub fn into_inner(self) -> S {
+        self.port
+    }
+
+    /// Get a mutable reference to underlying serial port.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.port
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error<S::Error>> {
+        for &byte in bytes {
+            nb::block!(self.port.write(byte)).map_err(Error::Serial)?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<u8, Error<S::Error>> {
+        nb::block!(self.port.read()).map_err(Error::Serial)
+    }
+
+    fn send_frame(&mut self, bytes: &[u8]) -> Result<(), Error<S::Error>> {
+        if bytes.len() > u16::MAX as usize {
+            return Err(Error::Overflow(bytes.len()));
+        }
+        self.write(&(bytes.len() as u16).to_be_bytes())?;
+        self.write(bytes)?;
+        nb::block!(self.port.flush()).map_err(Error::Serial)
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8, N>, Error<S::Error>> {
+        let len = u16::from_be_bytes([self.read()?, self.read()?]) as usize;
+        if len > N {
+            return Err(Error::Overflow(len));
+        }
+        let mut frame = Vec::new();
+        for _ in 0 .. len {
+            // never fails as the length is checked
+            let _ = frame.push(self.read()?);
+        }
+        Ok(frame)
+    }
+}
+
+impl<S, const N: usize> ChannelSend<Channel<S, N>> for Frame<N> where S: Read + Write {
+    type Err = Error<S::Error>;
+
+    fn send(self, carrier: &mut Channel<S, N>) -> Result<(), Self::Err> {
+        carrier.send_frame(&self.0)
+    }
+}
+
+impl<S, const N: usize> ChannelRecv<Channel<S, N>> for Frame<N> where S: Read + Write {
+    type Err = Error<S::Error>;
+
+    fn recv(carrier: &mut Channel<S, N>) -> Result<Self, Self::Err> {
+        carrier.recv_frame().map(Frame)
+    }
+}
+
+impl<S, const N: usize> Carrier for Channel<S, N> where S: Read + Write {
+    type SendChoiceErr = Error<S::Error>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.write(&[choice as u8])?;
+        nb::block!(self.port.flush()).map_err(Error::Serial)
+    }
+
+    type RecvChoiceErr = Error<S::Error>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        match self.read()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(Error::BadChoice(other)),
+        }
+    }
+}
+
+/// Returns a session channel over the serial port.
+#[must_use]
+pub fn session_channel<P, S, const N: usize>(port: S) -> Chan<Channel<S, N>, (), P> where S: Read + Write {
+    Chan::new(Channel::new(port))
+}
@@ -0,0 +1,191 @@
+//! Registry of live sessions (enabled with `registry` feature).
+//!
+//! Every session channel is registered on creation together with its
+//! current protocol step, updated on each transition and removed when the
+//! session is closed or dropped. Long-running servers could use `snapshot`
+//! to find stuck or leaked sessions, or to feed an ops dashboard.
+//!
+//! Values and choices are counted by the transitions from `Send`, `Choose`,
+//! `Recv` and `Offer` steps. Sessions accepted by `stream::SessionListener`
+//! or opened by `stream::connect` are registered with their peer address,
+//! other carriers could set it with `Chan::set_peer_addr`.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use super::{Chan, Send, Recv, Choose, Offer, End, Nil};
+
+/// A live session.
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    /// Unique session identifier.
+    pub id: u64,
+    /// Type name of the protocol the session has been created with.
+    pub session: &'static str,
+    /// Type name of the protocol at the current step.
+    pub protocol: &'static str,
+    /// Address of the peer for network carriers.
+    pub peer: Option<SocketAddr>,
+    /// Count of values and choices sent so far.
+    pub sent: u64,
+    /// Count of values and choices received so far.
+    pub received: u64,
+    /// When the session has been created.
+    pub started: Instant,
+    /// When the session has moved to the current step.
+    pub stepped: Instant,
+}
+
+impl SessionInfo {
+    /// How long the session is at the current step.
+    pub fn idle(&self) -> Duration {
+        self.stepped.elapsed()
+    }
+
+    /// How long the session is running.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// One line per session: identifier, peer, uptime, idle time, message
+/// counts and the current step.
+impl fmt::Display for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} ", self.id)?;
+        match self.peer {
+            Some(peer) => write!(f, "{}", peer)?,
+            None => write!(f, "-")?,
+        }
+        write!(f, " up {:?} idle {:?} sent {} received {} at {}",
+               self.uptime(), self.idle(), self.sent, self.received, self.protocol)
+    }
+}
+
+/// All live sessions at some moment, see `snapshot`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// When the snapshot has been taken.
+    pub taken: Instant,
+    /// Live sessions, ordered by creation.
+    pub sessions: Vec<SessionInfo>,
+}
+
+impl Snapshot {
+    /// Sessions staying at their current step for `idle` or longer.
+    pub fn stuck(&self, idle: Duration) -> impl Iterator<Item = &SessionInfo> {
+        let taken = self.taken;
+        self.sessions.iter().filter(move |info| taken.duration_since(info.stepped) >= idle)
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} live sessions", self.sessions.len())?;
+        for info in &self.sessions {
+            writeln!(f, "{}", info)?;
+        }
+        Ok(())
+    }
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static REGISTRY: Mutex<BTreeMap<u64, SessionInfo>> = Mutex::new(BTreeMap::new());
+
+fn with_registry<F, T>(action: F) -> T where F: FnOnce(&mut BTreeMap<u64, SessionInfo>) -> T {
+    action(&mut REGISTRY.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Type name of a protocol step without its parameters.
+fn head(protocol: &str) -> &str {
+    protocol.split('<').next().unwrap_or(protocol)
+}
+
+pub(crate) fn register(protocol: &'static str) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let now = Instant::now();
+    let info = SessionInfo {
+        id,
+        session: protocol,
+        protocol,
+        peer: None,
+        sent: 0,
+        received: 0,
+        started: now,
+        stepped: now,
+    };
+    with_registry(|sessions| sessions.insert(id, info));
+    id
+}
+
+pub(crate) fn update(id: u64, from: &'static str, protocol: &'static str) {
+    let from = head(from);
+    let sent = from == head(std::any::type_name::<Send<(), End>>()) || from == head(std::any::type_name::<Choose<End, Nil>>());
+    let received = from == head(std::any::type_name::<Recv<(), End>>()) || from == head(std::any::type_name::<Offer<End, Nil>>());
+    with_registry(|sessions| if let Some(info) = sessions.get_mut(&id) {
+        info.protocol = protocol;
+        info.stepped = Instant::now();
+        info.sent += sent as u64;
+        info.received += received as u64;
+    });
+}
+
+pub(crate) fn unregister(id: u64) {
+    with_registry(|sessions| sessions.remove(&id));
+}
+
+/// All live sessions, ordered by creation.
+///
+/// ```
+/// use session_types_ng::{Send, End, mpsc, sessions};
+///
+/// let (tx, rx) = mpsc::session_channel::<Send<mpsc::Value<u8>, End>>();
+/// let stuck = sessions::active();
+/// assert!(stuck.iter().any(|info| info.protocol.contains("Recv")));
+/// tx.send(mpsc::Value(1)).unwrap().close();
+/// rx.recv().unwrap().0.close();
+/// ```
+pub fn active() -> Vec<SessionInfo> {
+    with_registry(|sessions| sessions.values().cloned().collect())
+}
+
+/// Take a snapshot of all live sessions.
+///
+/// ```
+/// use std::time::Duration;
+/// use session_types_ng::{Send, Recv, End, mpsc, sessions};
+///
+/// type Ping = Send<mpsc::Value<u8>, Recv<mpsc::Value<u8>, End>>;
+///
+/// let (tx, rx) = mpsc::session_channel::<Ping>();
+/// let tx = tx.send(mpsc::Value(1)).unwrap();
+/// let snapshot = sessions::snapshot();
+/// let info = snapshot.sessions.iter()
+///     .find(|info| info.session.contains("Send") && info.protocol.contains("Recv"))
+///     .unwrap();
+/// assert_eq!((info.sent, info.received), (1, 0));
+/// assert!(snapshot.stuck(Duration::from_secs(0)).count() >= 2);
+/// println!("{}", snapshot);
+///
+/// let (rx, mpsc::Value(x)) = rx.recv().unwrap();
+/// rx.send(mpsc::Value(x)).unwrap().close();
+/// tx.recv().unwrap().0.close();
+/// ```
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        taken: Instant::now(),
+        sessions: active(),
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> {
+    /// Register the address of the peer of the session, reported by
+    /// `snapshot`.
+    pub fn set_peer_addr(&self, peer: SocketAddr) {
+        with_registry(|sessions| if let Some(info) = sessions.get_mut(&self.session.id) {
+            info.peer = Some(peer);
+        });
+    }
+}
@@ -0,0 +1,276 @@
+//! Graceful shutdown of session servers.
+//!
+//! Sessions of a server are tracked by a `ShutdownCoordinator` with
+//! `track`, which also registers its `cancel::CancelToken` on them. On
+//! `shutdown` the coordinator first only asks the sessions to finish:
+//! `is_draining` turns true, so that handlers could wind down at the next
+//! convenient step, e.g. by taking the quit branch of their loop or sending
+//! `control::Control::Cancel` to the peer. Once all of them have reached
+//! `End`, or the grace period is over, the token is cancelled, failing the
+//! rest of the sessions with `cancel::CancelError::Cancelled`, and the steps
+//! they were interrupted at are reported.
+//!
+//! ```
+//! use std::thread;
+//! use std::time::Duration;
+//! use session_types_ng::{Send, Recv, End, mpsc};
+//! use session_types_ng::shutdown::ShutdownCoordinator;
+//!
+//! let coordinator = ShutdownCoordinator::new();
+//!
+//! // a session waiting for a request which never comes
+//! let (stuck, idle) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+//! let stuck = coordinator.track(stuck);
+//! let stuck = thread::spawn(move || stuck.recv().is_err());
+//!
+//! // a session finishing as soon as asked to
+//! let (polite, client) = mpsc::session_channel::<Send<mpsc::Value<u8>, End>>();
+//! let polite = coordinator.track(polite);
+//! let polite = thread::spawn(move || {
+//!     while !polite.is_draining() {
+//!         thread::sleep(Duration::from_millis(1));
+//!     }
+//!     polite.send(mpsc::Value(0)).unwrap().close();
+//! });
+//!
+//! let report = coordinator.shutdown(Duration::from_millis(200));
+//! assert_eq!(report.interrupted.len(), 1);
+//! assert!(report.interrupted[0].step.contains("Recv"));
+//! assert!(stuck.join().unwrap());
+//! polite.join().unwrap();
+//! client.recv().unwrap().0.close();
+//! idle.poison();
+//! ```
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, Chan, End};
+use super::timeout::TimeoutCarrier;
+use super::cancel::{CancelCarrier, CancelToken};
+
+/// A tracked session which has not reached `End` yet.
+struct Active {
+    session: &'static str,
+    step: &'static str,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    active: BTreeMap<u64, Active>,
+}
+
+#[derive(Default)]
+struct Shared {
+    draining: AtomicBool,
+    token: CancelToken,
+    sessions: Mutex<Sessions>,
+    /// Notified whenever a session leaves `active`.
+    finished: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn remove(&self, id: u64) {
+        if self.lock().active.remove(&id).is_some() {
+            self.finished.notify_all();
+        }
+    }
+}
+
+/// A session interrupted by `ShutdownCoordinator::shutdown`.
+#[derive(Clone, Debug)]
+pub struct Interrupted {
+    /// Type name of the protocol the session has been tracked with.
+    pub session: &'static str,
+    /// Type name of the protocol at the step it has been interrupted at.
+    pub step: &'static str,
+    /// How long the session has been running.
+    pub elapsed: Duration,
+}
+
+/// Outcome of `ShutdownCoordinator::shutdown`.
+#[derive(Clone, Default, Debug)]
+pub struct ShutdownReport {
+    /// Count of sessions which have reached `End` within the grace period.
+    pub finished: usize,
+    /// Sessions which have been cancelled, ordered by tracking.
+    pub interrupted: Vec<Interrupted>,
+}
+
+impl ShutdownReport {
+    /// Whether all the sessions have finished in time.
+    pub fn is_clean(&self) -> bool {
+        self.interrupted.is_empty()
+    }
+}
+
+/// Coordinator of a graceful shutdown, shared by all its clones, see the
+/// module documentation.
+#[derive(Clone, Default)]
+pub struct ShutdownCoordinator {
+    shared: Arc<Shared>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> ShutdownCoordinator {
+        ShutdownCoordinator::default()
+    }
+
+    /// Track the rest of the session until it reaches `End` or its carrier
+    /// is dropped.
+    #[must_use]
+    pub fn track<SR, E, P>(&self, chan: Chan<SR, E, P>) -> Chan<ShutdownCarrier<SR>, E, P> where SR: TimeoutCarrier {
+        let protocol = std::any::type_name::<P>();
+        let id = {
+            let mut sessions = self.shared.lock();
+            let id = sessions.next_id;
+            sessions.next_id += 1;
+            sessions.active.insert(id, Active { session: protocol, step: protocol, started: Instant::now() });
+            id
+        };
+        Chan {
+            carrier: ShutdownCarrier {
+                inner: CancelCarrier::new(chan.carrier, self.shared.token.clone()),
+                id,
+                shared: self.shared.clone(),
+            },
+            session: chan.session,
+        }
+    }
+
+    /// Whether the shutdown has begun.
+    pub fn is_draining(&self) -> bool {
+        self.shared.draining.load(Ordering::SeqCst)
+    }
+
+    /// Count of tracked sessions which have not reached `End` yet.
+    pub fn active(&self) -> usize {
+        self.shared.lock().active.len()
+    }
+
+    /// Ask all the sessions to finish, wait for them for `grace` at most,
+    /// and cancel the rest. Sessions tracked afterwards are cancelled right
+    /// away.
+    pub fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        let started = Instant::now();
+        self.shared.draining.store(true, Ordering::SeqCst);
+        let mut sessions = self.shared.lock();
+        let initial = sessions.active.len();
+        while !sessions.active.is_empty() {
+            let left = match grace.checked_sub(started.elapsed()) {
+                Some(left) if left > Duration::from_secs(0) => left,
+                _ => break,
+            };
+            sessions = self.shared.finished.wait_timeout(sessions, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        self.shared.token.cancel();
+        let interrupted: Vec<_> = sessions.active.values()
+            .map(|active| Interrupted {
+                session: active.session,
+                step: active.step,
+                elapsed: active.started.elapsed(),
+            })
+            .collect();
+        ShutdownReport {
+            finished: initial - interrupted.len(),
+            interrupted,
+        }
+    }
+}
+
+/// Carrier wrapper of a session tracked by a `ShutdownCoordinator`.
+pub struct ShutdownCarrier<C> where C: TimeoutCarrier {
+    inner: CancelCarrier<C>,
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl<C> ShutdownCarrier<C> where C: TimeoutCarrier {
+    /// Whether the shutdown has begun, so the session should finish.
+    pub fn is_draining(&self) -> bool {
+        self.shared.draining.load(Ordering::SeqCst)
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        self.inner.get_mut()
+    }
+}
+
+impl<C> Drop for ShutdownCarrier<C> where C: TimeoutCarrier {
+    fn drop(&mut self) {
+        self.shared.remove(self.id);
+    }
+}
+
+impl<C> Carrier for ShutdownCarrier<C> where C: TimeoutCarrier {
+    type SendChoiceErr = <CancelCarrier<C> as Carrier>::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.inner.send_choice(choice)
+    }
+
+    type RecvChoiceErr = <CancelCarrier<C> as Carrier>::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.recv_choice()
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+        if protocol == std::any::type_name::<End>() {
+            self.shared.remove(self.id);
+        } else if let Some(active) = self.shared.lock().active.get_mut(&self.id) {
+            active.step = protocol;
+        }
+    }
+}
+
+impl<C> TimeoutCarrier for ShutdownCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout)
+    }
+}
+
+impl<C> BatchCarrier for ShutdownCarrier<C> where C: TimeoutCarrier + BatchCarrier {
+    type FlushErr = <CancelCarrier<C> as BatchCarrier>::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+/// Values transferable until cancellation are transferable over a tracked
+/// carrier.
+impl<C, T> ChannelSend<ShutdownCarrier<C>> for T where T: Payload + ChannelSend<CancelCarrier<C>>, C: TimeoutCarrier {
+    type Err = <T as ChannelSend<CancelCarrier<C>>>::Err;
+
+    fn send(self, carrier: &mut ShutdownCarrier<C>) -> Result<(), Self::Err> {
+        self.send(&mut carrier.inner)
+    }
+}
+
+impl<C, T> ChannelRecv<ShutdownCarrier<C>> for T where T: Payload + ChannelRecv<CancelCarrier<C>>, C: TimeoutCarrier {
+    type Err = <T as ChannelRecv<CancelCarrier<C>>>::Err;
+
+    fn recv(carrier: &mut ShutdownCarrier<C>) -> Result<Self, Self::Err> {
+        <T>::recv(&mut carrier.inner)
+    }
+}
+
+impl<SR, E, P> Chan<ShutdownCarrier<SR>, E, P> where SR: TimeoutCarrier {
+    /// Whether the server is shutting down, so the session should finish.
+    pub fn is_draining(&self) -> bool {
+        self.carrier.is_draining()
+    }
+}
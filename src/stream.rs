@@ -0,0 +1,886 @@
+use std::error;
+use std::fmt;
+use std::io::{self, IoSlice, Read, Write};
+use std::marker::PhantomData;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use super::{ChannelSend, ChannelRecv, Payload, Carrier, HandoverCarrier, BatchCarrier, HasDual, Chan};
+use super::wire::{WireFormat, Bincode};
+use super::fingerprint::Fingerprint;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::timeout::{TimeoutCarrier, DiscardCarrier};
+
+/// Carrier over any `Read` + `Write` byte stream (for example `TcpStream`).
+/// Every value is encoded with the wire format `F` and sent as a frame
+/// prefixed by its length (`u32`, big endian).
+///
+/// Over a nonblocking stream the channel is driven with `NonBlockingCarrier`:
+/// sends should be buffered in batching mode and written out by `poll_send`,
+/// and frames are read ahead by `poll_recv` until they are complete. Over a
+/// blocking stream with read timeouts, such as `TcpStream`, the channel is a
+/// `TimeoutCarrier` (see `ReadTimeout`), reading frames ahead the same way.
+pub struct Channel<S, F = Bincode> {
+    stream: S,
+    batch: Option<Vec<u8>>,
+    /// Bytes read ahead by `poll_recv` or `wait_timeout`.
+    inbound: Vec<u8>,
+    /// Number of frames of the peer to drop on arrival, see `DiscardCarrier`.
+    discard: usize,
+    limits: Limits,
+    format: PhantomData<F>,
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Io(io::Error),
+    Format(E),
+    /// A frame or the buffered bytes have exceeded the `Limits` of the
+    /// channel.
+    Limit(LimitError),
+}
+
+/// Limits on memory a channel takes for the frames of the peer, so that a
+/// malicious or buggy peer could not exhaust it, e.g. by announcing a frame
+/// of 4 GiB. Nothing is limited by default.
+///
+/// ```
+/// use std::net::{TcpListener, TcpStream};
+/// use std::io::Write;
+/// use session_types_ng::{Chan, Recv, End};
+/// use session_types_ng::stream::{self, Value, Limits, LimitError};
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (socket, _) = listener.accept().unwrap();
+/// let carrier: stream::TcpChannel = stream::Channel::with_limits(socket, Limits::new().max_message(1024));
+/// let chan: Chan<_, (), Recv<Value<Vec<u8>>, End>> = Chan::new(carrier);
+///
+/// // a frame header announcing a gigabyte
+/// peer.write_all(&(1u32 << 30).to_be_bytes()).unwrap();
+/// match chan.recv() {
+///     Err(stream::Error::Limit(LimitError::TooLarge { size, limit })) =>
+///         assert_eq!((size, limit), (1 << 30, 1024)),
+///     _ => panic!("the frame is too large"),
+/// }
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    max_message: Option<usize>,
+    max_buffered: Option<usize>,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Refuse frames (encoded values, choices or `Bytes`) larger than
+    /// `bytes`, either received or sent.
+    pub fn max_message(mut self, bytes: usize) -> Limits {
+        self.max_message = Some(bytes);
+        self
+    }
+
+    /// Refuse to buffer more than `bytes`, either frames sent in batching
+    /// mode or bytes read ahead by `poll_recv` or `wait_timeout`.
+    pub fn max_buffered(mut self, bytes: usize) -> Limits {
+        self.max_buffered = Some(bytes);
+        self
+    }
+
+    pub(crate) fn check_message(&self, size: usize) -> io::Result<()> {
+        match self.max_message {
+            Some(limit) if size > limit =>
+                Err(LimitError::TooLarge { size, limit }.into()),
+            _ =>
+                Ok(()),
+        }
+    }
+
+    fn check_buffered(&self, buffered: usize) -> io::Result<()> {
+        match self.max_buffered {
+            Some(limit) if buffered > limit =>
+                Err(LimitError::QuotaExceeded { buffered, limit }.into()),
+            _ =>
+                Ok(()),
+        }
+    }
+}
+
+/// Violation of `Limits`. Operations failing with `io::Error`, such as
+/// transferring `bytes::Bytes`, report it as an error of `InvalidData`
+/// kind wrapping this one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LimitError {
+    /// A frame of `size` bytes is larger than `limit`.
+    TooLarge { size: usize, limit: usize },
+    /// Buffering would take `buffered` bytes, more than `limit`.
+    QuotaExceeded { buffered: usize, limit: usize },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitError::TooLarge { size, limit } =>
+                write!(f, "frame of {} bytes exceeds the limit of {}", size, limit),
+            LimitError::QuotaExceeded { buffered, limit } =>
+                write!(f, "{} buffered bytes exceed the limit of {}", buffered, limit),
+        }
+    }
+}
+
+impl error::Error for LimitError { }
+
+impl From<LimitError> for io::Error {
+    fn from(e: LimitError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Channel error of an I/O failure, telling limit violations apart.
+pub(crate) fn io_error<E>(e: io::Error) -> Error<E> {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<LimitError>()) {
+        Some(&limit) => Error::Limit(limit),
+        None => Error::Io(e),
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError<E> {
+    Carrier(Error<E>),
+    /// Peer protocol fingerprint is not dual to the local one.
+    Mismatch { expected: u64, received: u64 },
+    /// None of the peer protocol versions is dual to a local one.
+    NoCommonVersion,
+    /// Peers have listed their common versions in different order.
+    VersionOrder,
+}
+
+#[derive(Clone, Debug)]
+pub struct Value<T>(pub T);
+
+impl<T> Payload for Value<T> { }
+
+impl<S, F> Channel<S, F> where S: Read + Write, F: WireFormat {
+    pub fn new(stream: S) -> Channel<S, F> {
+        Channel::with_limits(stream, Limits::new())
+    }
+
+    pub fn with_limits(stream: S, limits: Limits) -> Channel<S, F> {
+        Channel {
+            stream,
+            batch: None,
+            inbound: Vec::new(),
+            discard: 0,
+            limits,
+            format: PhantomData,
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Get back underlying byte stream. Frames buffered in batching mode
+    /// are discarded, so `flush` the channel first, as well as the bytes
+    /// read ahead by `poll_recv`.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Get a reference to underlying byte stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Get a mutable reference to underlying byte stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub(crate) fn send_value<T>(&mut self, value: &T) -> Result<(), Error<F::Err>> where T: Serialize {
+        let bytes = F::encode(value).map_err(Error::Format)?;
+        self.send_frame(&bytes).map_err(io_error)
+    }
+
+    pub(crate) fn recv_value<T>(&mut self) -> Result<T, Error<F::Err>> where T: DeserializeOwned {
+        let bytes = self.recv_frame().map_err(io_error)?;
+        F::decode(&bytes).map_err(Error::Format)
+    }
+
+    /// Read a frame within the limits.
+    pub(crate) fn recv_frame(&mut self) -> io::Result<Vec<u8>> {
+        let limits = self.limits;
+        let mut reader = self.reader()?;
+        let len = read_frame_len(&mut reader)?;
+        limits.check_message(len)?;
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Write a frame, or buffer it in batching mode.
+    pub(crate) fn send_frame(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.limits.check_message(bytes.len())?;
+        match self.batch {
+            Some(ref mut batch) => {
+                self.limits.check_buffered(batch.len() + 4 + bytes.len())?;
+                batch.extend_from_slice(&frame_header(bytes.len())?);
+                batch.extend_from_slice(bytes);
+                Ok(())
+            },
+            None =>
+                write_frame(&mut self.stream, bytes),
+        }
+    }
+
+    /// Underlying byte stream ready for reading: buffered frames are written
+    /// out first, unless the stream is nonblocking and can't take them now,
+    /// and frames to be discarded are skipped.
+    pub(crate) fn reader(&mut self) -> io::Result<Reader<'_, S>> {
+        match self.flush_batch() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            result => result?,
+        }
+        while self.discard > 0 {
+            let mut reader = Reader { inbound: &mut self.inbound, stream: &mut self.stream };
+            let len = read_frame_len(&mut reader)?;
+            self.limits.check_message(len)?;
+            reader.read_exact(&mut vec![0; len])?;
+            self.discard -= 1;
+        }
+        Ok(Reader { inbound: &mut self.inbound, stream: &mut self.stream })
+    }
+
+    /// Write out the frames buffered in batching mode. Whatever has been
+    /// written is removed from the buffer, so a nonblocking stream could
+    /// continue later.
+    fn flush_batch(&mut self) -> io::Result<()> {
+        if let Some(ref mut batch) = self.batch {
+            if batch.is_empty() {
+                return Ok(());
+            }
+            while !batch.is_empty() {
+                match self.stream.write(batch) {
+                    Ok(0) =>
+                        return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write frames")),
+                    Ok(written) => {
+                        batch.drain(.. written);
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted =>
+                        (),
+                    Err(e) =>
+                        return Err(e),
+                }
+            }
+            self.stream.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Length of the frame read ahead, once its header is complete. The
+    /// handover mark is a frame without payload.
+    fn frame_len(&self) -> Option<usize> {
+        if self.inbound.len() >= 4 {
+            match u32::from_be_bytes([self.inbound[0], self.inbound[1], self.inbound[2], self.inbound[3]]) {
+                HANDOVER => Some(0),
+                len => Some(len as usize),
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Read whatever the stream has got into the read-ahead buffer.
+    fn read_ahead(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+        let read = self.stream.read(&mut chunk)?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream has been closed"));
+        }
+        self.inbound.extend_from_slice(&chunk[.. read]);
+        if let Some(len) = self.frame_len() {
+            self.limits.check_message(len)?;
+        }
+        self.limits.check_buffered(self.inbound.len())
+    }
+
+    /// Whether a complete frame has been read ahead. Frames to be discarded
+    /// are dropped first.
+    fn frame_ready(&mut self) -> bool {
+        while let Some(len) = self.frame_len().filter(|&len| self.inbound.len() - 4 >= len) {
+            if self.discard == 0 {
+                return true;
+            }
+            self.discard -= 1;
+            self.inbound.drain(.. 4 + len);
+        }
+        false
+    }
+}
+
+/// Reads the bytes read ahead by `poll_recv` first, then the stream itself.
+pub(crate) struct Reader<'c, S> {
+    inbound: &'c mut Vec<u8>,
+    stream: &'c mut S,
+}
+
+impl<'c, S> Read for Reader<'c, S> where S: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inbound.is_empty() {
+            return self.stream.read(buf);
+        }
+        let len = buf.len().min(self.inbound.len());
+        buf[.. len].copy_from_slice(&self.inbound[.. len]);
+        self.inbound.drain(.. len);
+        Ok(len)
+    }
+}
+
+/// All frames sent in batching mode are written out with a single write on `flush`.
+impl<S, F> BatchCarrier for Channel<S, F> where S: Read + Write, F: WireFormat {
+    type FlushErr = Error<F::Err>;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.flush_batch().map_err(Error::Io)?;
+        self.batch = if enabled { Some(Vec::new()) } else { None };
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.flush_batch().map_err(Error::Io)
+    }
+}
+
+/// Frame header of the handover mark, which is not a length of any frame.
+const HANDOVER: u32 = u32::MAX;
+
+fn frame_header(len: usize) -> io::Result<[u8; 4]> {
+    if len >= HANDOVER as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame is too large"));
+    }
+    Ok((len as u32).to_be_bytes())
+}
+
+pub(crate) fn write_frame<S>(stream: &mut S, bytes: &[u8]) -> io::Result<()> where S: Write {
+    let header = frame_header(bytes.len())?;
+    let mut slices = [IoSlice::new(&header), IoSlice::new(bytes)];
+    let mut slices = &mut slices[..];
+    while !slices.is_empty() {
+        match stream.write_vectored(slices) {
+            Ok(0) =>
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write frame")),
+            Ok(written) =>
+                IoSlice::advance_slices(&mut slices, written),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted =>
+                (),
+            Err(e) =>
+                return Err(e),
+        }
+    }
+    stream.flush()
+}
+
+pub(crate) fn read_frame_len<S>(stream: &mut S) -> io::Result<usize> where S: Read {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    match u32::from_be_bytes(header) {
+        HANDOVER => Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected handover mark")),
+        len => Ok(len as usize),
+    }
+}
+
+/// Read a frame within the limits.
+#[cfg(feature = "protobuf")]
+pub(crate) fn read_frame<S>(stream: &mut S, limits: Limits) -> io::Result<Vec<u8>> where S: Read {
+    let len = read_frame_len(stream)?;
+    limits.check_message(len)?;
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl<T, S, F> ChannelSend<Channel<S, F>> for Value<T> where T: Serialize, S: Read + Write, F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn send(self, carrier: &mut Channel<S, F>) -> Result<(), Self::Err> {
+        carrier.send_value(&self.0)
+    }
+}
+
+impl<T, S, F> ChannelRecv<Channel<S, F>> for Value<T> where T: DeserializeOwned, S: Read + Write, F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn recv(carrier: &mut Channel<S, F>) -> Result<Self, Self::Err> {
+        carrier.recv_value().map(Value)
+    }
+}
+
+impl<S, F> Carrier for Channel<S, F> where S: Read + Write, F: WireFormat {
+    type SendChoiceErr = Error<F::Err>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.send_value(&choice)
+    }
+
+    type RecvChoiceErr = Error<F::Err>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.recv_value()
+    }
+}
+
+/// A frame is dropped as soon as it has been read ahead or received.
+impl<S, F> DiscardCarrier for Channel<S, F> where S: ReadTimeout, F: WireFormat {
+    fn discard_next(&mut self) {
+        self.discard += 1;
+        self.frame_ready();
+    }
+}
+
+/// The handover mark is a frame header which is not a length of any frame,
+/// so it is never mistaken for a value or a choice.
+impl<S, F> HandoverCarrier for Channel<S, F> where S: Read + Write, F: WireFormat {
+    fn send_handover(&mut self) -> Result<(), Self::SendChoiceErr> {
+        let header = HANDOVER.to_be_bytes();
+        match self.batch {
+            Some(ref mut batch) => {
+                batch.extend_from_slice(&header);
+                Ok(())
+            },
+            None =>
+                self.stream.write_all(&header).and_then(|()| self.stream.flush()).map_err(Error::Io),
+        }
+    }
+
+    fn recv_handover(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        let mut header = [0; 4];
+        self.reader().and_then(|mut reader| reader.read_exact(&mut header)).map_err(io_error)?;
+        Ok(u32::from_be_bytes(header) == HANDOVER)
+    }
+}
+
+impl<S, F> NonBlockingCarrier for Channel<S, F> where S: Read + Write, F: WireFormat {
+    type PollErr = io::Error;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.flush_batch().map_err(poll_error)
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        while !self.frame_ready() {
+            match self.read_ahead() {
+                Ok(()) =>
+                    (),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted =>
+                    (),
+                Err(e) =>
+                    return Err(poll_error(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Byte stream whose reads could time out, so that a `Channel` over it is
+/// a `TimeoutCarrier`. The stream should be in blocking mode.
+///
+/// ```
+/// use std::net::{TcpListener, TcpStream};
+/// use std::time::{Duration, Instant};
+/// use session_types_ng::{Chan, Recv, End, HasDual};
+/// use session_types_ng::stream::{self, Value};
+/// use session_types_ng::timeout::{TimeoutCarrier, DeadlineError};
+///
+/// type Srv = Recv<Value<u8>, Recv<Value<u8>, End>>;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (socket, _) = listener.accept().unwrap();
+/// let mut server: Chan<stream::TcpChannel, (), Srv> = stream::session_channel(socket);
+/// let client: Chan<stream::TcpChannel, (), <Srv as HasDual>::Dual> = stream::session_channel(peer);
+///
+/// assert!(!server.carrier_mut().wait_timeout(Duration::from_millis(10)).unwrap());
+/// let client = client.send(Value(1)).unwrap();
+/// assert!(server.carrier_mut().wait_timeout(Duration::from_secs(5)).unwrap());
+/// let (server, Value(x)) = server.recv().unwrap();
+/// assert_eq!(x, 1);
+///
+/// // nothing else arrives in time
+/// let server = server.with_deadline(Instant::now() + Duration::from_millis(20));
+/// match server.recv() {
+///     Err(DeadlineError::Expired) => (),
+///     _ => panic!("the client has not sent the second value"),
+/// }
+/// if let Ok(client) = client.send(Value(2)) {
+///     client.close();
+/// }
+/// ```
+pub trait ReadTimeout: Read + Write {
+    fn read_timeout(&self) -> io::Result<Option<Duration>>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for UnixStream {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        UnixStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<S, F> Channel<S, F> where S: ReadTimeout, F: WireFormat {
+    /// Read ahead until a complete frame is there, for `timeout` at most.
+    /// A closed stream counts as ready, so the next receive reports it.
+    fn wait_frame(&mut self, timeout: Duration) -> io::Result<bool> {
+        let started = Instant::now();
+        let mut attempted = false;
+        while !self.frame_ready() {
+            let left = timeout.saturating_sub(started.elapsed());
+            if attempted && left == Duration::from_secs(0) {
+                return Ok(false);
+            }
+            attempted = true;
+            // a zero read timeout means blocking forever
+            self.stream.set_read_timeout(Some(left.max(Duration::from_micros(1))))?;
+            match self.read_ahead() {
+                Ok(()) =>
+                    (),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof =>
+                    return Ok(true),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::Interrupted =>
+                    (),
+                Err(e) =>
+                    return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Waits by reading frames ahead with the read timeout of the stream set,
+/// restoring the previous one afterwards. Frames buffered in batching mode
+/// are written out first.
+impl<S, F> TimeoutCarrier for Channel<S, F> where S: ReadTimeout, F: WireFormat {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.flush_batch().map_err(io_error)?;
+        let previous = self.stream.read_timeout().map_err(Error::Io)?;
+        let ready = self.wait_frame(timeout);
+        self.stream.set_read_timeout(previous).map_err(Error::Io)?;
+        ready.map_err(io_error)
+    }
+}
+
+fn poll_error(e: io::Error) -> PollError<io::Error> {
+    if e.kind() == io::ErrorKind::WouldBlock {
+        PollError::WouldBlock
+    } else {
+        PollError::Carrier(e)
+    }
+}
+
+/// Returns a session channel over the byte stream using default wire format.
+#[must_use]
+pub fn session_channel<P, S>(stream: S) -> Chan<Channel<S>, (), P> where S: Read + Write {
+    Chan::new(Channel::new(stream))
+}
+
+/// Exchange protocol fingerprints with the peer and start a session only if
+/// peer protocol is dual to `P`. Fails fast on version skew between binaries
+/// which the type system can't see.
+///
+/// Peers following unrelated protocols both get a `Mismatch`:
+///
+/// ```
+/// use session_types_ng::{Send, End};
+/// use session_types_ng::stream::{self, HandshakeError, SessionListener, Value};
+///
+/// type Server = Send<Value<u64>, End>;
+/// type Client = Send<Value<String>, End>;
+///
+/// let listener = SessionListener::<Server>::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// let server = std::thread::spawn(move || listener.accept().err());
+///
+/// match stream::connect::<Client, _>(addr) {
+///     Err(HandshakeError::Mismatch { expected, received }) => assert_ne!(expected, received),
+///     _ => panic!("client expected a mismatch"),
+/// }
+/// match server.join().unwrap() {
+///     Some(HandshakeError::Mismatch { expected, received }) => assert_ne!(expected, received),
+///     _ => panic!("server expected a mismatch"),
+/// }
+/// ```
+pub fn handshake<P, S, F>(mut carrier: Channel<S, F>) -> Result<Chan<Channel<S, F>, (), P>, HandshakeError<F::Err>>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          S: Read + Write,
+          F: WireFormat
+{
+    carrier.send_value(&P::fingerprint()).map_err(HandshakeError::Carrier)?;
+    let received: u64 = carrier.recv_value().map_err(HandshakeError::Carrier)?;
+    let expected = P::Dual::fingerprint();
+    if received == expected {
+        Ok(Chan::new(carrier))
+    } else {
+        Err(HandshakeError::Mismatch { expected, received })
+    }
+}
+
+/// Carrier over a TCP connection.
+pub type TcpChannel<F = Bincode> = Channel<TcpStream, F>;
+
+/// TCP listener accepting sessions following the protocol `P`: every
+/// connection goes through the fingerprint `handshake` first.
+///
+/// ```
+/// use session_types_ng::{Send, End, HasDual};
+/// use session_types_ng::stream::{self, SessionListener, Value};
+///
+/// type Greeting = Send<Value<String>, End>;
+///
+/// let listener = SessionListener::<Greeting>::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// std::thread::spawn(move || listener.serve(|chan| {
+///     chan.send(Value("hello".to_string())).unwrap().close();
+/// }));
+///
+/// let chan = stream::connect::<<Greeting as HasDual>::Dual, _>(addr).unwrap();
+/// let (chan, Value(greeting)) = chan.recv().unwrap();
+/// chan.close();
+/// assert_eq!(greeting, "hello");
+/// ```
+pub struct SessionListener<P, F = Bincode> {
+    listener: TcpListener,
+    limits: Limits,
+    handshake_timeout: Option<Duration>,
+    protocol: PhantomData<(P, F)>,
+}
+
+impl<P, F> SessionListener<P, F>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          F: WireFormat
+{
+    pub fn new(listener: TcpListener) -> SessionListener<P, F> {
+        SessionListener {
+            listener,
+            limits: Limits::new(),
+            handshake_timeout: Some(Duration::from_secs(10)),
+            protocol: PhantomData,
+        }
+    }
+
+    /// Apply `limits` to the channels of all accepted connections, from the
+    /// handshake on.
+    pub fn with_limits(mut self, limits: Limits) -> SessionListener<P, F> {
+        self.limits = limits;
+        self
+    }
+
+    /// Drop connections which have not completed the handshake within
+    /// `timeout`, ten seconds by default, or never with `None`. The session
+    /// itself is not limited by it.
+    ///
+    /// ```
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    /// use session_types_ng::{Send, End};
+    /// use session_types_ng::stream::{SessionListener, Value};
+    ///
+    /// let listener = SessionListener::<Send<Value<u8>, End>>::bind("127.0.0.1:0").unwrap()
+    ///     .with_handshake_timeout(Some(Duration::from_millis(50)));
+    /// // a peer which never sends its fingerprint
+    /// let _silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    /// assert!(listener.accept().is_err());
+    /// ```
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> SessionListener<P, F> {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn bind<A>(addr: A) -> io::Result<SessionListener<P, F>> where A: ToSocketAddrs {
+        TcpListener::bind(addr).map(SessionListener::new)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn accept_connection(&self) -> io::Result<Connection<P, F>> {
+        let (socket, addr) = self.listener.accept()?;
+        Ok(Connection {
+            socket,
+            addr,
+            limits: self.limits,
+            handshake_timeout: self.handshake_timeout,
+            protocol: PhantomData,
+        })
+    }
+
+    /// Get a reference to underlying listener.
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Wait for a connection and start a session over it once the peer has
+    /// passed the handshake, failing if it takes longer than the handshake
+    /// timeout. The handshake is done in the calling thread, so a silent
+    /// peer holds up the next `accept` for as long as the timeout; servers
+    /// should rather use `serve`, or `incoming` finishing the handshakes
+    /// elsewhere.
+    pub fn accept(&self) -> Result<(Chan<TcpChannel<F>, (), P>, SocketAddr), HandshakeError<F::Err>> {
+        let connection = self.accept_connection().map_err(|e| HandshakeError::Carrier(Error::Io(e)))?;
+        let addr = connection.peer_addr();
+        connection.handshake().map(|chan| (chan, addr))
+    }
+
+    /// Iterate over accepted connections, which have not passed the
+    /// handshake yet, so that slow peers never hold up the accepting loop.
+    /// Every connection should finish its handshake in the thread which is
+    /// going to serve it. Like `TcpListener::incoming`, it never ends and
+    /// yields the errors of accepting a connection, which are often
+    /// transient (e.g. a peer aborting the connection before it is accepted).
+    ///
+    /// ```
+    /// use std::thread;
+    /// use std::net::TcpStream;
+    /// use session_types_ng::{Send, End, HasDual};
+    /// use session_types_ng::stream::{self, SessionListener, Value};
+    ///
+    /// type Greeting = Send<Value<u8>, End>;
+    ///
+    /// let listener = SessionListener::<Greeting>::bind("127.0.0.1:0").unwrap();
+    /// let addr = listener.local_addr().unwrap();
+    /// // a peer which never sends its fingerprint
+    /// let _silent = TcpStream::connect(addr).unwrap();
+    /// let client = thread::spawn(move || {
+    ///     let chan = stream::connect::<<Greeting as HasDual>::Dual, _>(addr).unwrap();
+    ///     let (chan, Value(x)) = chan.recv().unwrap();
+    ///     chan.close();
+    ///     x
+    /// });
+    ///
+    /// let mut incoming = listener.incoming();
+    /// let silent = incoming.next().unwrap().unwrap();
+    /// let connection = incoming.next().unwrap().unwrap();
+    /// thread::spawn(move || {
+    ///     let chan = connection.handshake().unwrap();
+    ///     chan.send(Value(42)).unwrap().close();
+    /// });
+    /// assert_eq!(client.join().unwrap(), 42);
+    /// drop(silent);
+    /// ```
+    pub fn incoming(&self) -> Incoming<'_, P, F> {
+        Incoming { listener: self }
+    }
+
+    /// Accept connections forever, running `handler` for every session in a
+    /// thread of its own. The handshake is done in that thread too, and the
+    /// connections failing it are dropped. Returns only if the listener fails.
+    pub fn serve<H>(self, handler: H) -> io::Result<()>
+        where H: Fn(Chan<TcpChannel<F>, (), P>) + std::marker::Send + Sync + 'static,
+              P: 'static,
+              F: 'static
+    {
+        let handler = Arc::new(handler);
+        loop {
+            let connection = self.accept_connection()?;
+            let handler = handler.clone();
+            thread::spawn(move || {
+                if let Ok(chan) = connection.handshake() {
+                    handler(chan);
+                }
+            });
+        }
+    }
+}
+
+/// A connection accepted by a `SessionListener`, which has not passed the
+/// fingerprint handshake yet.
+pub struct Connection<P, F = Bincode> {
+    socket: TcpStream,
+    addr: SocketAddr,
+    limits: Limits,
+    handshake_timeout: Option<Duration>,
+    /// A connection could be handed over to another thread whatever `P` is.
+    protocol: PhantomData<fn() -> (P, F)>,
+}
+
+impl<P, F> Connection<P, F>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          F: WireFormat
+{
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Start a session once the peer has passed the handshake, failing if
+    /// it takes longer than the handshake timeout of the listener.
+    pub fn handshake(self) -> Result<Chan<TcpChannel<F>, (), P>, HandshakeError<F::Err>> {
+        let Connection { socket, addr: _addr, limits, handshake_timeout, .. } = self;
+        socket.set_read_timeout(handshake_timeout).map_err(|e| HandshakeError::Carrier(Error::Io(e)))?;
+        let chan = handshake(Channel::with_limits(socket, limits))?;
+        chan.carrier.stream.set_read_timeout(None).map_err(|e| HandshakeError::Carrier(Error::Io(e)))?;
+        #[cfg(feature = "registry")]
+        chan.set_peer_addr(_addr);
+        Ok(chan)
+    }
+}
+
+/// Iterator over connections of a `SessionListener`, see `SessionListener::incoming`.
+pub struct Incoming<'l, P, F = Bincode> {
+    listener: &'l SessionListener<P, F>,
+}
+
+impl<'l, P, F> Iterator for Incoming<'l, P, F>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          F: WireFormat
+{
+    type Item = io::Result<Connection<P, F>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept_connection())
+    }
+}
+
+/// Connect to a `SessionListener` and start a session following `P` using
+/// default wire format once the handshake has passed.
+pub fn connect<P, A>(addr: A) -> Result<Chan<TcpChannel, (), P>, HandshakeError<<Bincode as WireFormat>::Err>>
+    where P: HasDual + Fingerprint,
+          P::Dual: Fingerprint,
+          A: ToSocketAddrs
+{
+    let socket = TcpStream::connect(addr).map_err(|e| HandshakeError::Carrier(Error::Io(e)))?;
+    #[cfg(feature = "registry")]
+    let peer = socket.peer_addr().ok();
+    let chan = handshake(Channel::new(socket))?;
+    #[cfg(feature = "registry")]
+    if let Some(peer) = peer {
+        chan.set_peer_addr(peer);
+    }
+    Ok(chan)
+}
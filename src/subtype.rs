@@ -0,0 +1,107 @@
+use super::{Carrier, Chan, End, Send, Recv, Nil, Choose, Offer, Labeled, Seq, Repeat, Rec, Var, Loop, Goto, cast_chan};
+
+/// Safe subtyping relation between protocols: a channel with protocol
+/// `Self` could be used where a channel with protocol `Q` is expected.
+///
+/// A choose list could be narrowed (the trailing branches are never selected)
+/// and an offer list could be widened (the extra branches are never taken by
+/// the other side), recursively for all continuations.
+///
+/// ```
+/// use session_types_ng::{Send, Recv, Choose, Offer, End, Nil};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::subtype::SubtypeOf;
+///
+/// fn assert_subtype<P, Q>() where P: SubtypeOf<Q> { }
+///
+/// type Narrow = Choose<Send<Value<u8>, End>, Nil>;
+/// type Wide = Choose<Send<Value<u8>, End>, Choose<End, Nil>>;
+/// assert_subtype::<Wide, Narrow>();
+///
+/// type Few = Offer<Recv<Value<u8>, End>, Nil>;
+/// type Many = Offer<Recv<Value<u8>, End>, Offer<End, Nil>>;
+/// assert_subtype::<Few, Many>();
+/// ```
+///
+/// Widening a choose list is rejected, since the other side could not
+/// handle the extra branches:
+///
+/// ```compile_fail
+/// use session_types_ng::{Send, Choose, End, Nil};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::subtype::SubtypeOf;
+///
+/// fn assert_subtype<P, Q>() where P: SubtypeOf<Q> { }
+///
+/// type Narrow = Choose<Send<Value<u8>, End>, Nil>;
+/// type Wide = Choose<Send<Value<u8>, End>, Choose<End, Nil>>;
+/// assert_subtype::<Narrow, Wide>();
+/// ```
+///
+/// And so is narrowing an offer list, since the other side could select
+/// the missing branches:
+///
+/// ```compile_fail
+/// use session_types_ng::{Recv, Offer, End, Nil};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::subtype::SubtypeOf;
+///
+/// fn assert_subtype<P, Q>() where P: SubtypeOf<Q> { }
+///
+/// type Few = Offer<Recv<Value<u8>, End>, Nil>;
+/// type Many = Offer<Recv<Value<u8>, End>, Offer<End, Nil>>;
+/// assert_subtype::<Many, Few>();
+/// ```
+pub trait SubtypeOf<Q> { }
+
+/// Subtyping of choose list tails: trailing branches could be dropped.
+pub trait ChooseTail<L> { }
+
+/// Subtyping of offer list tails: trailing branches could be appended.
+pub trait OfferTail<L> { }
+
+impl SubtypeOf<End> for End { }
+
+impl<A, P, Q> SubtypeOf<Send<A, Q>> for Send<A, P> where P: SubtypeOf<Q> { }
+
+impl<A, P, Q> SubtypeOf<Recv<A, Q>> for Recv<A, P> where P: SubtypeOf<Q> { }
+
+impl<P, L, Q, M> SubtypeOf<Choose<Q, M>> for Choose<P, L> where P: SubtypeOf<Q>, L: ChooseTail<M> { }
+
+impl<P, L, Q, M> SubtypeOf<Offer<Q, M>> for Offer<P, L> where P: SubtypeOf<Q>, L: OfferTail<M> { }
+
+impl<Lbl, P, Q> SubtypeOf<Labeled<Lbl, Q>> for Labeled<Lbl, P> where P: SubtypeOf<Q> { }
+
+impl<P, Q, R, T> SubtypeOf<Seq<R, T>> for Seq<P, Q> where P: SubtypeOf<R>, Q: SubtypeOf<T> { }
+
+impl<N, P, Q, R, T> SubtypeOf<Repeat<N, R, T>> for Repeat<N, P, Q> where P: SubtypeOf<R>, Q: SubtypeOf<T> { }
+
+impl<P, Q> SubtypeOf<Rec<Q>> for Rec<P> where P: SubtypeOf<Q> { }
+
+impl<N> SubtypeOf<Var<N>> for Var<N> { }
+
+impl<Lbl, P, Q> SubtypeOf<Loop<Lbl, Q>> for Loop<Lbl, P> where P: SubtypeOf<Q> { }
+
+impl<Lbl> SubtypeOf<Goto<Lbl>> for Goto<Lbl> { }
+
+impl<L> ChooseTail<Nil> for L { }
+
+impl<P, L, Q, M> ChooseTail<Choose<Q, M>> for Choose<P, L> where P: SubtypeOf<Q>, L: ChooseTail<M> { }
+
+impl<L> OfferTail<L> for Nil { }
+
+impl<P, L, Q, M> OfferTail<Offer<Q, M>> for Offer<P, L> where P: SubtypeOf<Q>, L: OfferTail<M> { }
+
+/// Environments are related elementwise.
+impl SubtypeOf<()> for () { }
+
+impl<P, E, Q, F> SubtypeOf<(Q, F)> for (P, E) where P: SubtypeOf<Q>, E: SubtypeOf<F> { }
+
+impl<SR, E, P> Chan<SR, E, P> where SR: Carrier {
+    /// Use the channel as a channel of a supertype protocol, for instance
+    /// a client written against a narrower protocol.
+    #[must_use]
+    pub fn upcast<F, Q>(self) -> Chan<SR, F, Q> where P: SubtypeOf<Q>, E: SubtypeOf<F> {
+        cast_chan(self)
+    }
+}
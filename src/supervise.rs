@@ -0,0 +1,159 @@
+//! Supervision of sessions, in the spirit of OTP supervisors.
+//!
+//! A `Supervisor` owns a factory establishing a session, for example
+//! connecting a TCP carrier and passing the handshake, and a handler running
+//! it. When the factory or the handler fails, or the handler panics, the
+//! session is established anew after a backoff delay according to its
+//! `RestartPolicy`. A session resumed from the step it has failed at is
+//! covered by `resume` instead: the supervisor always starts from scratch.
+//!
+//! ```
+//! use std::thread;
+//! use std::time::Duration;
+//! use session_types_ng::{Chan, Recv, End, mpsc};
+//! use session_types_ng::supervise::{Supervisor, RestartPolicy};
+//!
+//! type Ticket = Recv<mpsc::Value<u32>, End>;
+//!
+//! let mut attempt = 0;
+//! let mut supervisor = Supervisor::new(
+//!     || {
+//!         attempt += 1;
+//!         let (chan, peer) = mpsc::session_channel::<Ticket>();
+//!         let number = attempt;
+//!         thread::spawn(move || peer.send(mpsc::Value(number)).unwrap().close());
+//!         Ok::<_, ()>(chan)
+//!     },
+//!     |chan: Chan<mpsc::Channel, (), Ticket>| {
+//!         let (chan, mpsc::Value(number)) = chan.recv().map_err(|_| "peer is gone")?;
+//!         chan.close();
+//!         // the first peer hands out a bad ticket
+//!         if number < 2 { Err("bad ticket") } else { Ok(number) }
+//!     },
+//!     RestartPolicy::new().max_restarts(3).backoff(Duration::from_millis(1), Duration::from_millis(10)),
+//! );
+//! assert_eq!(supervisor.run().unwrap(), 2);
+//! assert_eq!(supervisor.restarts(), 1);
+//! ```
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
+use super::Chan;
+
+/// When and how often a session is restarted.
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    pub(crate) max_restarts: Option<u32>,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    permanent: bool,
+}
+
+impl RestartPolicy {
+    /// Restart failed sessions without limit, waiting 100 milliseconds
+    /// before the first restart and twice as long before every next one,
+    /// up to 30 seconds.
+    pub fn new() -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: None,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            permanent: false,
+        }
+    }
+
+    /// Give up after `restarts` consecutive failures.
+    pub fn max_restarts(mut self, restarts: u32) -> RestartPolicy {
+        self.max_restarts = Some(restarts);
+        self
+    }
+
+    /// Wait `initial` before the first restart, doubling the delay after
+    /// every consecutive failure up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RestartPolicy {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Restart a session also when the handler has completed it, so that
+    /// the supervisor runs until it gives up.
+    pub fn permanent(mut self) -> RestartPolicy {
+        self.permanent = true;
+        self
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy::new()
+    }
+}
+
+/// Failure of a supervised session.
+#[derive(Debug)]
+pub enum Failure<CE, HE> {
+    /// The session could not be established.
+    Connect(CE),
+    /// The handler has returned an error.
+    Handler(HE),
+    /// The handler has panicked.
+    Panicked,
+}
+
+/// Supervisor of sessions made by `connect` and run by `handler`.
+pub struct Supervisor<C, H> {
+    connect: C,
+    handler: H,
+    policy: RestartPolicy,
+    restarts: u32,
+}
+
+impl<C, H> Supervisor<C, H> {
+    pub fn new(connect: C, handler: H, policy: RestartPolicy) -> Supervisor<C, H> {
+        Supervisor { connect, handler, policy, restarts: 0 }
+    }
+
+    /// Number of restarts so far.
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Establish and run sessions until the handler completes one (unless
+    /// the policy is permanent), returning its result, or until the
+    /// supervisor gives up, returning the last failure.
+    pub fn run<SR, P, T, CE, HE>(&mut self) -> Result<T, Failure<CE, HE>>
+        where C: FnMut() -> Result<Chan<SR, (), P>, CE>,
+              H: FnMut(Chan<SR, (), P>) -> Result<T, HE>
+    {
+        let mut failures = 0;
+        let mut backoff = self.policy.initial_backoff;
+        loop {
+            let failure = match (self.connect)() {
+                Ok(chan) => match panic::catch_unwind(AssertUnwindSafe(|| (self.handler)(chan))) {
+                    Ok(Ok(value)) if !self.policy.permanent =>
+                        return Ok(value),
+                    Ok(Ok(..)) => {
+                        failures = 0;
+                        backoff = self.policy.initial_backoff;
+                        continue;
+                    },
+                    Ok(Err(e)) =>
+                        Failure::Handler(e),
+                    Err(..) =>
+                        Failure::Panicked,
+                },
+                Err(e) =>
+                    Failure::Connect(e),
+            };
+            if self.policy.max_restarts.is_some_and(|max| failures >= max) {
+                return Err(failure);
+            }
+            failures += 1;
+            self.restarts += 1;
+            thread::sleep(backoff);
+            backoff = cmp::min(backoff * 2, self.policy.max_backoff);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Out-of-band copies of session traffic.
+//!
+//! A carrier wrapped into `TeeCarrier` sends a `Record` of every value and
+//! choice it transfers to a side `mpsc` channel, so that an auditing or
+//! compliance pipeline could observe the sessions without taking part in
+//! them. Values are copied with `Clone`, so only payloads implementing it
+//! could be transferred over a `TeeCarrier`. Records are dropped once the
+//! receiving side of the side channel is gone: the session goes on.
+//!
+//! ```
+//! use std::sync::mpsc::channel;
+//! use session_types_ng::{Chan, Send, Recv, End, mpsc};
+//! use session_types_ng::tee::{TeeCarrier, Record, Direction};
+//!
+//! let (audit, records) = channel();
+//! let (a, b) = mpsc::session_channel::<End>();
+//! let a: Chan<_, (), Send<mpsc::Value<String>, End>> = Chan::new(TeeCarrier::new(a.shutdown(), audit));
+//! let b: Chan<_, (), Recv<mpsc::Value<String>, End>> = Chan::new(b.shutdown());
+//! a.send(mpsc::Value("transfer 100".to_string())).unwrap().close();
+//! b.recv().unwrap().0.close();
+//!
+//! match records.recv().unwrap() {
+//!     Record::Value { direction: Direction::Sent, payload, .. } =>
+//!         assert_eq!(payload.downcast_ref::<mpsc::Value<String>>().unwrap().0, "transfer 100"),
+//!     other =>
+//!         panic!("unexpected record {:?}", other),
+//! }
+//! ```
+use std::any::{Any, type_name};
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::task::Waker;
+use std::time::Duration;
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::layer::CarrierLayer;
+
+/// Whether a record has been sent or received by the teed carrier.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A copy of a value or a choice transferred over a `TeeCarrier`. `step`
+/// is the protocol type name the session is at (`start` until the first
+/// transition).
+pub enum Record {
+    /// A value of the type `payload_type`, which could be downcast from
+    /// `payload`.
+    Value {
+        direction: Direction,
+        step: &'static str,
+        payload_type: &'static str,
+        payload: Box<dyn Any + Send>,
+    },
+    /// A choice bit.
+    Choice {
+        direction: Direction,
+        step: &'static str,
+        choice: bool,
+    },
+}
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Record::Value { direction, step, payload_type, .. } =>
+                f.debug_struct("Value")
+                    .field("direction", &direction)
+                    .field("step", &step)
+                    .field("payload_type", &payload_type)
+                    .finish_non_exhaustive(),
+            Record::Choice { direction, step, choice } =>
+                f.debug_struct("Choice")
+                    .field("direction", &direction)
+                    .field("step", &step)
+                    .field("choice", &choice)
+                    .finish(),
+        }
+    }
+}
+
+/// Carrier wrapper which copies every value and choice transferred onto a
+/// side channel.
+pub struct TeeCarrier<C> {
+    inner: C,
+    side: Sender<Record>,
+    step: &'static str,
+}
+
+impl<C> TeeCarrier<C> {
+    /// Wrap a carrier sending records to `side`.
+    pub fn new(inner: C, side: Sender<Record>) -> TeeCarrier<C> {
+        TeeCarrier {
+            inner,
+            side,
+            step: "start",
+        }
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn record(&self, record: Record) {
+        // the session goes on without an auditor
+        let _ = self.side.send(record);
+    }
+}
+
+/// Layer wrapping carriers into `TeeCarrier`s, all of them sending records
+/// to the same side channel.
+#[derive(Clone, Debug)]
+pub struct TeeLayer {
+    side: Sender<Record>,
+}
+
+impl TeeLayer {
+    pub fn new(side: Sender<Record>) -> TeeLayer {
+        TeeLayer { side }
+    }
+}
+
+impl<C> CarrierLayer<C> for TeeLayer {
+    type Carrier = TeeCarrier<C>;
+
+    fn layer(&self, inner: C) -> TeeCarrier<C> {
+        TeeCarrier::new(inner, self.side.clone())
+    }
+}
+
+impl<C> Carrier for TeeCarrier<C> where C: Carrier {
+    type SendChoiceErr = C::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.inner.send_choice(choice)?;
+        self.record(Record::Choice { direction: Direction::Sent, step: self.step, choice });
+        Ok(())
+    }
+
+    type RecvChoiceErr = C::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        let choice = self.inner.recv_choice()?;
+        self.record(Record::Choice { direction: Direction::Received, step: self.step, choice });
+        Ok(choice)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.step = protocol;
+        self.inner.step(protocol);
+    }
+}
+
+impl<C> TimeoutCarrier for TeeCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        self.inner.wait_timeout(timeout)
+    }
+}
+
+fn teed_send<T, C>(value: T, carrier: &mut TeeCarrier<C>) -> Result<(), T::Err>
+    where T: ChannelSend<C> + Clone + Send + 'static
+{
+    let copy = value.clone();
+    value.send(&mut carrier.inner)?;
+    carrier.record(Record::Value {
+        direction: Direction::Sent,
+        step: carrier.step,
+        payload_type: type_name::<T>(),
+        payload: Box::new(copy),
+    });
+    Ok(())
+}
+
+fn teed_recv<T, C>(carrier: &mut TeeCarrier<C>) -> Result<T, T::Err>
+    where T: ChannelRecv<C> + Clone + Send + 'static
+{
+    let value = T::recv(&mut carrier.inner)?;
+    carrier.record(Record::Value {
+        direction: Direction::Received,
+        step: carrier.step,
+        payload_type: type_name::<T>(),
+        payload: Box::new(value.clone()),
+    });
+    Ok(value)
+}
+
+impl<C> BatchCarrier for TeeCarrier<C> where C: BatchCarrier {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.inner.flush()
+    }
+}
+
+impl<C> CapacityCarrier for TeeCarrier<C> where C: CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+impl<C> NonBlockingCarrier for TeeCarrier<C> where C: NonBlockingCarrier {
+    type PollErr = C::PollErr;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_send()
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.inner.poll_recv()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.inner.register_waker(waker);
+    }
+}
+
+/// Cloneable values transferable over a carrier are transferable over its
+/// teed wrapper.
+impl<C, T> ChannelSend<TeeCarrier<C>> for T
+    where T: Payload + ChannelSend<C> + Clone + Send + 'static
+{
+    type Err = <T as ChannelSend<C>>::Err;
+
+    fn send(self, carrier: &mut TeeCarrier<C>) -> Result<(), Self::Err> {
+        teed_send(self, carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<TeeCarrier<C>> for T
+    where T: Payload + ChannelRecv<C> + Clone + Send + 'static
+{
+    type Err = <T as ChannelRecv<C>>::Err;
+
+    fn recv(carrier: &mut TeeCarrier<C>) -> Result<Self, Self::Err> {
+        teed_recv(carrier)
+    }
+}
+
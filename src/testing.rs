@@ -0,0 +1,522 @@
+//! Conformance testing of protocol endpoints against scripted peers.
+//!
+//! `run_against_script` runs an endpoint function over an `mpsc` channel,
+//! while the other side is driven by a `Script` of what the peer sends and
+//! chooses, and what it expects to receive. The script is followed along the
+//! runtime description of the protocol, so a script which does not fit the
+//! protocol is reported as well as a wrong message from the endpoint.
+//!
+//! With `quickcheck` feature, `RandomWalk` exercises an endpoint with random
+//! valid traversals of its protocol instead of a fixed script.
+use std::any::{Any, type_name};
+#[cfg(feature = "quickcheck")]
+use std::collections::BTreeMap;
+use std::fmt;
+use std::thread;
+#[cfg(feature = "quickcheck")]
+use quickcheck::{Arbitrary, Gen};
+use super::{Carrier, ChannelSend, ChannelRecv, Chan, HasDual, mpsc};
+use super::reflect::{ProtocolDesc, Desc, Cursor, short_name};
+
+const HUNG_UP: &str = "the endpoint has hung up";
+
+type Transfer = Box<dyn FnOnce(&mut mpsc::Channel) -> Result<(), String> + Send>;
+
+enum Action {
+    /// The peer sends a value of the given type.
+    Send(&'static str, Transfer),
+    /// The peer receives a value of the given type and checks it.
+    Expect(&'static str, Transfer),
+    /// The peer selects a branch of an offer.
+    Choose(usize),
+    /// The peer checks which branch the endpoint has selected.
+    ExpectChoice(usize),
+}
+
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Send(payload, _) => write!(f, "send {}", short_name(payload)),
+            Action::Expect(payload, _) => write!(f, "expect {}", short_name(payload)),
+            Action::Choose(branch) => write!(f, "choose branch {}", branch),
+            Action::ExpectChoice(branch) => write!(f, "expect choice of branch {}", branch),
+        }
+    }
+}
+
+/// Behaviour of the peer of a tested endpoint, step by step.
+#[derive(Default)]
+pub struct Script {
+    actions: Vec<Action>,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    /// Send `value` to the endpoint, which should be at a `Recv` step.
+    pub fn send<T>(mut self, value: T) -> Script where T: ChannelSend<mpsc::Channel> + Send + 'static {
+        self.actions.push(Action::Send(type_name::<T>(), Box::new(move |carrier| {
+            value.send(carrier).map_err(|_| HUNG_UP.to_string())
+        })));
+        self
+    }
+
+    /// Receive a value from the endpoint, which should be at a `Send` step,
+    /// and check that it is equal to `value`.
+    pub fn expect<T>(self, value: T) -> Script
+        where T: ChannelRecv<mpsc::Channel> + PartialEq + fmt::Debug + Send + 'static
+    {
+        self.expect_with(move |received: &T| if *received == value {
+            Ok(())
+        } else {
+            Err(format!("expected {:?}, received {:?}", value, received))
+        })
+    }
+
+    /// Receive a value from the endpoint, which should be at a `Send` step,
+    /// and check it with `check`.
+    pub fn expect_with<T, F>(mut self, check: F) -> Script
+        where T: ChannelRecv<mpsc::Channel> + 'static, F: FnOnce(&T) -> Result<(), String> + Send + 'static
+    {
+        self.actions.push(Action::Expect(type_name::<T>(), Box::new(move |carrier| {
+            match T::recv(carrier) {
+                Ok(received) => check(&received),
+                Err(_) => Err(HUNG_UP.to_string()),
+            }
+        })));
+        self
+    }
+
+    /// Select the branch number `branch` (starting from zero) of an offer.
+    pub fn choose(mut self, branch: usize) -> Script {
+        self.actions.push(Action::Choose(branch));
+        self
+    }
+
+    /// Check that the endpoint selects the branch number `branch` (starting
+    /// from zero) of its choice.
+    pub fn expect_choice(mut self, branch: usize) -> Script {
+        self.actions.push(Action::ExpectChoice(branch));
+        self
+    }
+}
+
+/// Failure of a conformance run.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    /// Number of the script action (starting from zero) where the run has failed.
+    pub action: usize,
+    /// The protocol of the endpoint at the failed step.
+    pub protocol: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "script action {} at `{}`: {}", self.action, self.protocol, self.reason)
+    }
+}
+
+/// Run `endpoint` against a peer following `script` until the end of the
+/// protocol, and check that the endpoint has finished without a panic.
+///
+/// ```
+/// use session_types_ng::{Chan, Send, Recv, Offer, End, Nil, mpsc};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::testing::{Script, run_against_script};
+///
+/// type Echo = Offer<Recv<Value<String>, Send<Value<String>, End>>, Offer<End, Nil>>;
+///
+/// fn echo(chan: Chan<mpsc::Channel, (), Echo>) {
+///     chan.offer()
+///         .option(|chan| {
+///             let (chan, Value(text)) = chan.recv().unwrap();
+///             chan.send(Value(text.to_uppercase())).unwrap().close();
+///         })
+///         .option(|chan| chan.close())
+///         .unwrap();
+/// }
+///
+/// let script = Script::new()
+///     .choose(0)
+///     .send(Value("hello".to_string()))
+///     .expect(Value("HELLO".to_string()));
+/// run_against_script::<Echo, _>(echo, script).unwrap();
+///
+/// let script = Script::new()
+///     .choose(0)
+///     .send(Value("hello".to_string()))
+///     .expect_with(|reply: &Value<String>| match reply.0.len() {
+///         3 => Ok(()),
+///         n => Err(format!("expected a reply of 3 chars, received {}", n)),
+///     });
+/// let mismatch = run_against_script::<Echo, _>(echo, script).unwrap_err();
+/// assert_eq!(mismatch.action, 2);
+/// assert_eq!(mismatch.reason, "expected a reply of 3 chars, received 5");
+///
+/// // the script does not fit the protocol
+/// let mismatch = run_against_script::<Echo, _>(echo, Script::new().choose(1).choose(0)).unwrap_err();
+/// assert_eq!(mismatch.action, 1);
+/// ```
+pub fn run_against_script<P, F>(endpoint: F, script: Script) -> Result<(), Mismatch>
+    where P: HasDual + ProtocolDesc + Send + 'static,
+          F: FnOnce(Chan<mpsc::Channel, (), P>) + Send + 'static
+{
+    let (chan, Chan { mut carrier, session }) = mpsc::session_channel::<P>();
+    session.finish();
+    let handle = thread::spawn(move || endpoint(chan));
+
+    let mut peer = Peer { actions: script.actions.into_iter(), next: 0 };
+    let result = peer.run(&P::desc(), &mut carrier);
+    drop(carrier);
+    match (result, handle.join()) {
+        (Ok(()), Ok(())) =>
+            Ok(()),
+        (Ok(()), Err(panic)) =>
+            Err(Mismatch { action: peer.next, protocol: Desc::End.to_string(), reason: panic_reason(panic) }),
+        (Err(ref mismatch), Err(panic)) if mismatch.reason == HUNG_UP =>
+            Err(Mismatch { reason: panic_reason(panic), ..mismatch.clone() }),
+        (Err(mismatch), _) =>
+            Err(mismatch),
+    }
+}
+
+fn panic_reason(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => format!("the endpoint has panicked: {}", message),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => format!("the endpoint has panicked: {}", message),
+            None => "the endpoint has panicked".to_string(),
+        },
+    }
+}
+
+/// Select the branch number `branch` of the offer of the endpoint.
+fn send_branch(carrier: &mut mpsc::Channel, branch: usize) -> Result<(), String> {
+    for index in 0 ..= branch {
+        carrier.send_choice(index == branch).map_err(|_| HUNG_UP.to_string())?;
+    }
+    Ok(())
+}
+
+/// Learn the number of the branch selected by the endpoint.
+fn recv_branch(carrier: &mut mpsc::Channel) -> Result<usize, String> {
+    let mut branch = 0;
+    while !carrier.recv_choice().map_err(|_| HUNG_UP.to_string())? {
+        branch += 1;
+    }
+    Ok(branch)
+}
+
+struct Peer {
+    actions: ::std::vec::IntoIter<Action>,
+    next: usize,
+}
+
+impl Peer {
+    fn run(&mut self, protocol: &Desc, carrier: &mut mpsc::Channel) -> Result<(), Mismatch> {
+        let mut cursor = Cursor::default();
+        let mut step = protocol;
+        loop {
+            step = cursor.settle(step);
+            step = match *step {
+                Desc::End =>
+                    return match self.actions.next() {
+                        None => Ok(()),
+                        Some(action) => Err(Mismatch {
+                            action: self.next,
+                            protocol: step.to_string(),
+                            reason: format!("the protocol has ended, but the script continues with {:?}", action),
+                        }),
+                    },
+                Desc::Recv(payload, ref next) => {
+                    match self.take(step)? {
+                        Action::Send(sent, transfer) if sent == payload =>
+                            transfer(carrier).map_err(|reason| self.mismatch(step, reason))?,
+                        action =>
+                            return Err(self.unexpected(step, action)),
+                    }
+                    next
+                },
+                Desc::Send(payload, ref next) => {
+                    match self.take(step)? {
+                        Action::Expect(expected, transfer) if expected == payload =>
+                            transfer(carrier).map_err(|reason| self.mismatch(step, reason))?,
+                        action =>
+                            return Err(self.unexpected(step, action)),
+                    }
+                    next
+                },
+                Desc::Offer(ref branches) =>
+                    match self.take(step)? {
+                        Action::Choose(branch) if branch < branches.len() => {
+                            send_branch(carrier, branch).map_err(|reason| self.mismatch(step, reason))?;
+                            &branches[branch]
+                        },
+                        action =>
+                            return Err(self.unexpected(step, action)),
+                    },
+                Desc::Choose(ref branches) =>
+                    match self.take(step)? {
+                        Action::ExpectChoice(expected) if expected < branches.len() => {
+                            let branch = recv_branch(carrier).map_err(|reason| self.mismatch(step, reason))?;
+                            if branch != expected {
+                                return Err(self.mismatch(step, format!("expected choice of branch {}, the endpoint has chosen {}", expected, branch)));
+                            }
+                            &branches[branch]
+                        },
+                        action =>
+                            return Err(self.unexpected(step, action)),
+                    },
+                _ =>
+                    return Err(self.mismatch(step, "the step is not supported by scripted peers".to_string())),
+            };
+        }
+    }
+
+    fn take(&mut self, step: &Desc) -> Result<Action, Mismatch> {
+        match self.actions.next() {
+            Some(action) => {
+                self.next += 1;
+                Ok(action)
+            },
+            None =>
+                Err(Mismatch {
+                    action: self.next,
+                    protocol: step.to_string(),
+                    reason: "the script has ended before the protocol".to_string(),
+                }),
+        }
+    }
+
+    fn unexpected(&self, step: &Desc, action: Action) -> Mismatch {
+        self.mismatch(step, format!("the script does not fit the protocol: {:?}", action))
+    }
+
+    /// Failure of the last taken action.
+    fn mismatch(&self, step: &Desc, reason: String) -> Mismatch {
+        Mismatch {
+            action: self.next - 1,
+            protocol: step.to_string(),
+            reason,
+        }
+    }
+}
+
+/// Random valid traversals of a protocol (requires `quickcheck` feature).
+///
+/// The endpoint is run against a peer which selects random branches of its
+/// offers and sends arbitrary payloads generated with `quickcheck::Arbitrary`,
+/// which helps to reach branch specific bugs in offer heavy protocols. Every
+/// payload type of the protocol should be registered with `payload`. After
+/// `steps` steps the peer prefers branches leading out of loops.
+///
+/// ```
+/// use session_types_ng::{Chan, Send, Recv, Offer, Rec, Var, Z, End, Nil, mpsc};
+/// use session_types_ng::mpsc::Value;
+/// use session_types_ng::testing::RandomWalk;
+///
+/// type Calc = Rec<Offer<Recv<Value<u8>, Send<Value<u8>, Var<Z>>>, Offer<End, Nil>>>;
+///
+/// fn halve(chan: Chan<mpsc::Channel, (), Calc>) {
+///     let mut chan = chan.enter();
+///     loop {
+///         chan = match chan.offer().option(Ok).option(Err).unwrap() {
+///             Ok(chan) => {
+///                 let (chan, Value(n)) = chan.recv().unwrap();
+///                 assert!(n < 200, "too large");
+///                 chan.send(Value(n / 2)).unwrap().zero()
+///             },
+///             Err(chan) => return chan.close(),
+///         }
+///     }
+/// }
+///
+/// let walk = RandomWalk::new().payload::<Value<u8>>();
+/// let failure = walk.check::<Calc, _>(halve).unwrap_err();
+/// assert!(failure.reason.contains("too large"));
+/// ```
+#[cfg(feature = "quickcheck")]
+pub struct RandomWalk {
+    payloads: BTreeMap<&'static str, Payload>,
+    steps: usize,
+    tests: u64,
+    size: usize,
+}
+
+#[cfg(feature = "quickcheck")]
+struct Payload {
+    send: fn(&mut Gen, &mut mpsc::Channel) -> Result<String, String>,
+    recv: fn(&mut mpsc::Channel) -> Result<String, String>,
+}
+
+#[cfg(feature = "quickcheck")]
+fn send_arbitrary<T>(gen: &mut Gen, carrier: &mut mpsc::Channel) -> Result<String, String>
+    where T: Arbitrary + fmt::Debug + ChannelSend<mpsc::Channel>
+{
+    let value = T::arbitrary(gen);
+    let shown = format!("send {:?}", value);
+    value.send(carrier).map(|()| shown).map_err(|_| HUNG_UP.to_string())
+}
+
+#[cfg(feature = "quickcheck")]
+fn recv_any<T>(carrier: &mut mpsc::Channel) -> Result<String, String>
+    where T: fmt::Debug + ChannelRecv<mpsc::Channel>
+{
+    T::recv(carrier).map(|value| format!("receive {:?}", value)).map_err(|_| HUNG_UP.to_string())
+}
+
+#[cfg(feature = "quickcheck")]
+impl Default for RandomWalk {
+    fn default() -> RandomWalk {
+        RandomWalk { payloads: BTreeMap::new(), steps: 100, tests: 100, size: 100 }
+    }
+}
+
+#[cfg(feature = "quickcheck")]
+impl RandomWalk {
+    pub fn new() -> RandomWalk {
+        RandomWalk::default()
+    }
+
+    /// Register a payload type, generated with `Arbitrary` when sent to the endpoint.
+    pub fn payload<T>(mut self) -> RandomWalk
+        where T: Arbitrary + fmt::Debug + ChannelSend<mpsc::Channel> + ChannelRecv<mpsc::Channel>
+    {
+        self.payloads.insert(type_name::<T>(), Payload { send: send_arbitrary::<T>, recv: recv_any::<T> });
+        self
+    }
+
+    /// Number of steps after which the walk heads for the end of the protocol.
+    pub fn steps(mut self, steps: usize) -> RandomWalk {
+        self.steps = steps;
+        self
+    }
+
+    /// Number of walks made by `check`.
+    pub fn tests(mut self, tests: u64) -> RandomWalk {
+        self.tests = tests;
+        self
+    }
+
+    /// Size parameter of the `quickcheck::Gen` generating payloads.
+    pub fn size(mut self, size: usize) -> RandomWalk {
+        self.size = size;
+        self
+    }
+
+    /// Make `tests` walks with seeds from zero, stopping on the first failure.
+    pub fn check<P, F>(&self, endpoint: F) -> Result<(), Mismatch>
+        where P: HasDual + ProtocolDesc + Send + 'static,
+              F: FnOnce(Chan<mpsc::Channel, (), P>) + Clone + Send + 'static
+    {
+        (0 .. self.tests).try_for_each(|seed| self.walk::<P, F>(endpoint.clone(), seed))
+    }
+
+    /// Make a single walk, determined by `seed`. A failure is reported with
+    /// the seed and the walk so far, the number of the action is the number
+    /// of the failed step of the walk.
+    pub fn walk<P, F>(&self, endpoint: F, seed: u64) -> Result<(), Mismatch>
+        where P: HasDual + ProtocolDesc + Send + 'static,
+              F: FnOnce(Chan<mpsc::Channel, (), P>) + Send + 'static
+    {
+        let (chan, Chan { mut carrier, session }) = mpsc::session_channel::<P>();
+        session.finish();
+        let handle = thread::spawn(move || endpoint(chan));
+
+        let mut gen = Gen::from_size_and_seed(self.size, seed);
+        let mut trace = Vec::new();
+        let protocol = P::desc();
+        let result = self.run(&protocol, &mut carrier, &mut gen, &mut trace);
+        drop(carrier);
+        let result = match (result, handle.join()) {
+            (Ok(()), Ok(())) =>
+                Ok(()),
+            (Ok(()), Err(panic)) =>
+                Err((Desc::End.to_string(), panic_reason(panic))),
+            (Err((protocol, ref reason)), Err(panic)) if reason == HUNG_UP =>
+                Err((protocol, panic_reason(panic))),
+            (Err(failure), _) =>
+                Err(failure),
+        };
+        result.map_err(|(protocol, reason)| Mismatch {
+            action: trace.len(),
+            protocol,
+            reason: format!("{} (seed {}, walk: {})", reason, seed, trace.join(", ")),
+        })
+    }
+
+    fn run(&self, protocol: &Desc, carrier: &mut mpsc::Channel, gen: &mut Gen, trace: &mut Vec<String>) -> Result<(), (String, String)> {
+        let mut cursor = Cursor::default();
+        let mut step = protocol;
+        loop {
+            step = cursor.settle(step);
+            if trace.len() > self.steps * 4 {
+                return Err((step.to_string(), format!("the protocol has not ended after {} steps", trace.len())));
+            }
+            let fail = |reason: String| (step.to_string(), reason);
+            step = match *step {
+                Desc::End =>
+                    return Ok(()),
+                Desc::Recv(payload, ref next) => {
+                    let generator = self.payloads.get(payload)
+                        .ok_or_else(|| fail(format!("payload type `{}` is not registered", payload)))?;
+                    trace.push((generator.send)(gen, carrier).map_err(fail)?);
+                    next
+                },
+                Desc::Send(payload, ref next) => {
+                    let generator = self.payloads.get(payload)
+                        .ok_or_else(|| fail(format!("payload type `{}` is not registered", payload)))?;
+                    trace.push((generator.recv)(carrier).map_err(fail)?);
+                    next
+                },
+                Desc::Offer(ref branches) => {
+                    let mut candidates: Vec<_> = (0 .. branches.len()).collect();
+                    if trace.len() >= self.steps && branches.iter().any(can_exit) {
+                        candidates.retain(|&branch| can_exit(&branches[branch]));
+                    }
+                    let branch = *gen.choose(&candidates).expect("an offer has branches");
+                    send_branch(carrier, branch).map_err(fail)?;
+                    trace.push(format!("choose {}", branch));
+                    &branches[branch]
+                },
+                Desc::Choose(ref branches) => {
+                    let branch = recv_branch(carrier).map_err(fail)?;
+                    trace.push(format!("receive choice {}", branch));
+                    &branches[branch]
+                },
+                _ =>
+                    return Err(fail("the step is not supported by random walks".to_string())),
+            };
+        }
+    }
+}
+
+/// Whether the protocol could reach its end without recursion.
+#[cfg(feature = "quickcheck")]
+fn can_exit(step: &Desc) -> bool {
+    match *step {
+        Desc::Send(_, ref next) | Desc::Recv(_, ref next) | Desc::Labeled(_, ref next) |
+        Desc::Rec(ref next) | Desc::Loop(_, ref next) =>
+            can_exit(next),
+        Desc::Choose(ref branches) | Desc::Offer(ref branches) =>
+            branches.iter().any(can_exit),
+        Desc::Var(..) | Desc::Goto(..) =>
+            false,
+        _ =>
+            true,
+    }
+}
+
+#[cfg(feature = "quickcheck")]
+impl<T> Arbitrary for mpsc::Value<T> where T: Arbitrary + Send {
+    fn arbitrary(gen: &mut Gen) -> mpsc::Value<T> {
+        mpsc::Value(T::arbitrary(gen))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = mpsc::Value<T>>> {
+        Box::new(self.0.shrink().map(mpsc::Value))
+    }
+}
@@ -0,0 +1,606 @@
+use std::any::type_name;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv, HasDual, Then, Chan, Recv, Offer, Offers, cast_chan, close_chan};
+use super::reflect::{ProtocolDesc, Desc};
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::layer::CarrierLayer;
+
+/// A carrier able to wait for an incoming message with a timeout.
+pub trait TimeoutCarrier: Carrier {
+    /// Block until a message is available to receive or `timeout` elapses.
+    /// Returns `false` on timeout. The message itself is left for the next receive.
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr>;
+}
+
+/// A carrier able to drop a message of the peer which has arrived too late,
+/// without waiting for it.
+///
+/// ```
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, Send, Recv, End};
+/// use session_types_ng::stream::{self, Value};
+/// use session_types_ng::timeout::DiscardCarrier;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+/// let (socket, _) = listener.accept().unwrap();
+/// let mut server: Chan<stream::TcpChannel, (), Recv<Value<u8>, End>> = stream::session_channel(socket);
+/// // the peer sends a late value before the one awaited
+/// let client: Chan<stream::TcpChannel, (), Send<Value<u8>, Send<Value<u8>, End>>> = stream::session_channel(peer);
+///
+/// server.carrier_mut().discard_next();
+/// client.send(Value(1)).unwrap().send(Value(2)).unwrap().close();
+/// let (server, Value(v)) = server.recv().unwrap();
+/// assert_eq!(v, 2);
+/// server.close();
+/// ```
+pub trait DiscardCarrier: TimeoutCarrier {
+    /// Drop the next message of the peer, either right away if it has
+    /// arrived already, or on arrival, so that the next receive gets the
+    /// message after it.
+    fn discard_next(&mut self);
+}
+
+/// Type level timeout duration.
+pub trait Deadline {
+    fn duration() -> Duration;
+}
+
+/// Timeout of `N` milliseconds
+pub struct Millis<const N: u64>;
+
+/// Timeout of `N` seconds
+pub struct Secs<const N: u64>;
+
+impl<const N: u64> Deadline for Millis<N> {
+    fn duration() -> Duration {
+        Duration::from_millis(N)
+    }
+}
+
+impl<const N: u64> Deadline for Secs<N> {
+    fn duration() -> Duration {
+        Duration::from_secs(N)
+    }
+}
+
+/// Receive `A` within timeout `T`, then `R`. If the other side has not come
+/// to send `A` in time, both sides continue with `F` instead.
+pub struct RecvTimeout<A, T, R, F>(PhantomData<(A, T, R, F)>);
+
+/// Send `A` which the other side waits for within timeout `T`, then `R`.
+/// If the other side has given up waiting, `A` is not sent and both sides
+/// continue with `F` instead.
+pub struct SendTimeout<A, T, R, F>(PhantomData<(A, T, R, F)>);
+
+unsafe impl<A, T, R: HasDual, F: HasDual> HasDual for RecvTimeout<A, T, R, F> {
+    type Dual = SendTimeout<A, T, R::Dual, F::Dual>;
+}
+
+unsafe impl<A, T, R: HasDual, F: HasDual> HasDual for SendTimeout<A, T, R, F> {
+    type Dual = RecvTimeout<A, T, R::Dual, F::Dual>;
+}
+
+impl<A, T, R: Then<Q>, F: Then<Q>, Q> Then<Q> for RecvTimeout<A, T, R, F> {
+    type Output = RecvTimeout<A, T, R::Output, F::Output>;
+}
+
+impl<A, T, R: Then<Q>, F: Then<Q>, Q> Then<Q> for SendTimeout<A, T, R, F> {
+    type Output = SendTimeout<A, T, R::Output, F::Output>;
+}
+
+impl<A, T, R: ProtocolDesc, F: ProtocolDesc> ProtocolDesc for RecvTimeout<A, T, R, F> {
+    fn desc() -> Desc {
+        Desc::RecvTimeout {
+            payload: type_name::<A>(),
+            deadline: type_name::<T>(),
+            in_time: Box::new(R::desc()),
+            timed_out: Box::new(F::desc()),
+        }
+    }
+}
+
+impl<A, T, R: ProtocolDesc, F: ProtocolDesc> ProtocolDesc for SendTimeout<A, T, R, F> {
+    fn desc() -> Desc {
+        Desc::SendTimeout {
+            payload: type_name::<A>(),
+            deadline: type_name::<T>(),
+            in_time: Box::new(R::desc()),
+            timed_out: Box::new(F::desc()),
+        }
+    }
+}
+
+/// Outcome of a timeout annotated step.
+pub enum Timed<R, F> {
+    InTime(R),
+    TimedOut(F),
+}
+
+#[derive(Debug)]
+pub enum TimeoutError<S, R, V> {
+    SendChoice(S),
+    RecvChoice(R),
+    Value(V),
+}
+
+impl<SR, E, A, T, R, F> Chan<SR, E, RecvTimeout<A, T, R, F>>
+    where SR: DiscardCarrier, A: ChannelRecv<SR>, T: Deadline
+{
+    /// Wait at most `T` for the other side to come to send a value. It
+    /// announces itself with a mark and is told the outcome, so that the
+    /// value is only sent if it is awaited. The mark of a sender which comes
+    /// too late is dropped by the carrier on arrival, so a stalled sender
+    /// never holds up this side.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use session_types_ng::{End, mpsc};
+    /// use session_types_ng::timeout::{RecvTimeout, Secs, Timed};
+    ///
+    /// type Srv = RecvTimeout<mpsc::Value<u8>, Secs<5>, End, End>;
+    ///
+    /// let (server, client) = mpsc::session_channel::<Srv>();
+    /// let sender = thread::spawn(move || match client.send(mpsc::Value(42)) {
+    ///     Ok(Timed::InTime(client)) => client.close(),
+    ///     _ => panic!("the value is awaited"),
+    /// });
+    /// match server.recv() {
+    ///     Ok(Timed::InTime((server, mpsc::Value(v)))) => { assert_eq!(v, 42); server.close() },
+    ///     _ => panic!("the value has been sent in time"),
+    /// }
+    /// sender.join().unwrap();
+    /// ```
+    #[must_use]
+    pub fn recv(mut self) -> Result<Timed<(Chan<SR, E, R>, A), Chan<SR, E, F>>, TimeoutError<SR::SendChoiceErr, SR::RecvChoiceErr, A::Err>> {
+        let in_time = match self.carrier.wait_timeout(T::duration()) {
+            Ok(in_time) =>
+                in_time,
+            Err(e) => {
+                close_chan(self);
+                return Err(TimeoutError::RecvChoice(e));
+            },
+        };
+        if in_time {
+            if let Err(e) = self.carrier.recv_choice() {
+                close_chan(self);
+                return Err(TimeoutError::RecvChoice(e));
+            }
+        }
+        if let Err(e) = self.carrier.send_choice(in_time) {
+            close_chan(self);
+            return Err(TimeoutError::SendChoice(e));
+        }
+        if !in_time {
+            self.carrier.discard_next();
+            return Ok(Timed::TimedOut(cast_chan(self)));
+        }
+        match A::recv(&mut self.carrier) {
+            Ok(v) =>
+                Ok(Timed::InTime((cast_chan(self), v))),
+            Err(e) => {
+                close_chan(self);
+                Err(TimeoutError::Value(e))
+            },
+        }
+    }
+}
+
+impl<SR, E, A, T, R, F> Chan<SR, E, SendTimeout<A, T, R, F>>
+    where SR: Carrier, A: ChannelSend<SR>
+{
+    /// Announce the value to the other side and learn whether it is still
+    /// awaited. The value is sent only if it is, otherwise it is returned
+    /// together with the channel.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use session_types_ng::{Send, End, mpsc};
+    /// use session_types_ng::timeout::{SendTimeout, Millis, Timed};
+    ///
+    /// type Cli = SendTimeout<mpsc::Value<u8>, Millis<10>, End, Send<mpsc::Value<u8>, End>>;
+    ///
+    /// let (client, server) = mpsc::session_channel::<Cli>();
+    /// let sender = thread::spawn(move || {
+    ///     // a stalled sender
+    ///     thread::sleep(Duration::from_millis(100));
+    ///     match client.send(mpsc::Value(1)) {
+    ///         Ok(Timed::TimedOut((client, mpsc::Value(1)))) =>
+    ///             client.send(mpsc::Value(2)).unwrap().close(),
+    ///         _ => panic!("the value is not awaited anymore"),
+    ///     }
+    /// });
+    /// // the receiver gives up without waiting for the sender
+    /// let server = match server.recv() {
+    ///     Ok(Timed::TimedOut(server)) => server,
+    ///     _ => panic!("nothing has been sent in time"),
+    /// };
+    /// let (server, mpsc::Value(v)) = server.recv().unwrap();
+    /// assert_eq!(v, 2);
+    /// server.close();
+    /// sender.join().unwrap();
+    /// ```
+    #[must_use]
+    pub fn send(mut self, v: A) -> Result<Timed<Chan<SR, E, R>, (Chan<SR, E, F>, A)>, TimeoutError<SR::SendChoiceErr, SR::RecvChoiceErr, A::Err>> {
+        if let Err(e) = self.carrier.send_choice(true) {
+            close_chan(self);
+            return Err(TimeoutError::SendChoice(e));
+        }
+        match self.carrier.recv_choice() {
+            Ok(true) =>
+                (),
+            Ok(false) =>
+                return Ok(Timed::TimedOut((cast_chan(self), v))),
+            Err(e) => {
+                close_chan(self);
+                return Err(TimeoutError::RecvChoice(e));
+            },
+        }
+        match v.send(&mut self.carrier) {
+            Ok(()) =>
+                Ok(Timed::InTime(cast_chan(self))),
+            Err(e) => {
+                close_chan(self);
+                Err(TimeoutError::Value(e))
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TryRecvError<C, W, V> {
+    /// Nothing has arrived (in time), the channel is returned in the same protocol state.
+    Empty(C),
+    /// Carrier has failed while waiting.
+    Wait(W),
+    /// A value has arrived but could not be received.
+    Value(V),
+}
+
+impl<SR, E, A, R> Chan<SR, E, Recv<A, R>> where SR: TimeoutCarrier, A: ChannelRecv<SR> {
+    /// Receive a value only if it is already available, without blocking.
+    ///
+    /// ```
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::timeout::TryRecvError;
+    ///
+    /// let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let rx = match rx.try_recv() {
+    ///     Err(TryRecvError::Empty(rx)) => rx,
+    ///     _ => panic!("nothing has been sent yet"),
+    /// };
+    /// tx.send(mpsc::Value(42)).unwrap().close();
+    /// match rx.try_recv() {
+    ///     Ok((rx, mpsc::Value(v))) => { assert_eq!(v, 42); rx.close() },
+    ///     _ => panic!("value should be available"),
+    /// }
+    /// ```
+    #[must_use]
+    pub fn try_recv(self) -> Result<(Chan<SR, E, R>, A), TryRecvError<Chan<SR, E, Recv<A, R>>, SR::RecvChoiceErr, A::Err>> {
+        self.recv_timeout(Duration::from_secs(0))
+    }
+
+    /// Receive a value, waiting for it at most `timeout`.
+    #[must_use]
+    pub fn recv_timeout(mut self, timeout: Duration) -> Result<(Chan<SR, E, R>, A), TryRecvError<Chan<SR, E, Recv<A, R>>, SR::RecvChoiceErr, A::Err>> {
+        match self.carrier.wait_timeout(timeout) {
+            Ok(true) =>
+                (),
+            Ok(false) =>
+                return Err(TryRecvError::Empty(self)),
+            Err(e) => {
+                close_chan(self);
+                return Err(TryRecvError::Wait(e));
+            },
+        }
+        match A::recv(&mut self.carrier) {
+            Ok(v) =>
+                Ok((cast_chan(self), v)),
+            Err(e) => {
+                close_chan(self);
+                Err(TryRecvError::Value(e))
+            },
+        }
+    }
+}
+
+impl<SR, E, P, L> Chan<SR, E, Offer<P, L>> where SR: TimeoutCarrier {
+    /// Passive choice, waiting for the other side to choose at most `timeout`.
+    #[must_use]
+    pub fn offer_timeout<T>(mut self, timeout: Duration) -> Result<Offers<SR, E, Offer<P, L>, T>, TryRecvError<Chan<SR, E, Offer<P, L>>, SR::RecvChoiceErr, Infallible>> {
+        match self.carrier.wait_timeout(timeout) {
+            Ok(true) =>
+                Ok(self.offer()),
+            Ok(false) =>
+                Err(TryRecvError::Empty(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(TryRecvError::Wait(e))
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TrySendError<C, A, V> {
+    /// The carrier has no capacity (in time), the channel is returned in the
+    /// same protocol state together with the value.
+    Full(C, A),
+    /// The value could not be sent.
+    Value(V),
+}
+
+impl<SR, E, A, P> Chan<SR, E, super::Send<A, P>> where SR: CapacityCarrier, A: ChannelSend<SR> {
+    /// Send a value only if the carrier has capacity for it, without
+    /// blocking, so that a producer lagged behind by its peer could back off.
+    ///
+    /// ```
+    /// use session_types_ng::{Send, End, mpsc};
+    /// use session_types_ng::timeout::TrySendError;
+    ///
+    /// type Producer = Send<mpsc::Value<u8>, Send<mpsc::Value<u8>, End>>;
+    ///
+    /// let (tx, rx) = mpsc::bounded_session_channel::<Producer>(1);
+    /// let tx = tx.try_send(mpsc::Value(1)).ok().unwrap();
+    /// let (tx, value) = match tx.try_send(mpsc::Value(2)) {
+    ///     Err(TrySendError::Full(tx, value)) => (tx, value),
+    ///     _ => panic!("the buffer is full"),
+    /// };
+    /// let (rx, mpsc::Value(first)) = rx.recv().unwrap();
+    /// assert_eq!(first, 1);
+    /// tx.try_send(value).ok().unwrap().close();
+    /// let (rx, mpsc::Value(second)) = rx.recv().unwrap();
+    /// assert_eq!(second, 2);
+    /// rx.close();
+    /// ```
+    #[must_use]
+    pub fn try_send(self, v: A) -> Result<Chan<SR, E, P>, TrySendError<Chan<SR, E, super::Send<A, P>>, A, A::Err>> {
+        self.send_timeout(v, Duration::from_secs(0))
+    }
+
+    /// Send a value, waiting for the carrier to have capacity at most
+    /// `timeout`, see `CapacityCarrier::wait_capacity`.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use session_types_ng::{Send, End, mpsc};
+    ///
+    /// type Producer = Send<mpsc::Value<u8>, Send<mpsc::Value<u8>, End>>;
+    ///
+    /// let (tx, rx) = mpsc::bounded_session_channel::<Producer>(1);
+    /// let tx = tx.try_send(mpsc::Value(1)).ok().unwrap();
+    /// let consumer = thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(20));
+    ///     let (rx, _) = rx.recv().unwrap();
+    ///     let (rx, mpsc::Value(second)) = rx.recv().unwrap();
+    ///     rx.close();
+    ///     second
+    /// });
+    /// // woken up as soon as the consumer takes the first value
+    /// tx.send_timeout(mpsc::Value(2), Duration::from_secs(10)).ok().unwrap().close();
+    /// assert_eq!(consumer.join().unwrap(), 2);
+    /// ```
+    #[must_use]
+    pub fn send_timeout(mut self, v: A, timeout: Duration) -> Result<Chan<SR, E, P>, TrySendError<Chan<SR, E, super::Send<A, P>>, A, A::Err>> {
+        if !self.carrier.wait_capacity(timeout) {
+            return Err(TrySendError::Full(self, v));
+        }
+        match v.send(&mut self.carrier) {
+            Ok(()) =>
+                Ok(cast_chan(self)),
+            Err(e) => {
+                close_chan(self);
+                Err(TrySendError::Value(e))
+            },
+        }
+    }
+}
+
+/// Carrier wrapper enforcing a deadline on a whole session: every send and
+/// choice fails once the deadline has passed, and every receive waits for
+/// the peer at most until the deadline.
+pub struct DeadlineCarrier<C> {
+    inner: C,
+    deadline: Instant,
+}
+
+/// Error of an operation over `DeadlineCarrier`.
+#[derive(Debug)]
+pub enum DeadlineError<E, W = Infallible> {
+    /// The session deadline has passed.
+    Expired,
+    /// Waiting for the peer has failed.
+    Wait(W),
+    /// The operation on the underlying carrier has failed.
+    Carrier(E),
+}
+
+impl<C> DeadlineCarrier<C> where C: TimeoutCarrier {
+    pub fn new(inner: C, deadline: Instant) -> DeadlineCarrier<C> {
+        DeadlineCarrier { inner, deadline }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn check<E, W>(&self) -> Result<(), DeadlineError<E, W>> {
+        if Instant::now() < self.deadline {
+            Ok(())
+        } else {
+            Err(DeadlineError::Expired)
+        }
+    }
+
+    fn wait<E>(&mut self) -> Result<(), DeadlineError<E, C::RecvChoiceErr>> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        match self.inner.wait_timeout(remaining) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(DeadlineError::Expired),
+            Err(e) => Err(DeadlineError::Wait(e)),
+        }
+    }
+}
+
+/// Layer wrapping carriers into `DeadlineCarrier`s, giving every session
+/// `budget` time from the moment its carrier is wrapped.
+#[derive(Clone, Copy, Debug)]
+pub struct DeadlineLayer {
+    budget: Duration,
+}
+
+impl DeadlineLayer {
+    pub fn new(budget: Duration) -> DeadlineLayer {
+        DeadlineLayer { budget }
+    }
+}
+
+impl<C> CarrierLayer<C> for DeadlineLayer where C: TimeoutCarrier {
+    type Carrier = DeadlineCarrier<C>;
+
+    fn layer(&self, inner: C) -> DeadlineCarrier<C> {
+        DeadlineCarrier::new(inner, Instant::now() + self.budget)
+    }
+}
+
+impl<C> Carrier for DeadlineCarrier<C> where C: TimeoutCarrier {
+    type SendChoiceErr = DeadlineError<C::SendChoiceErr>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        self.check()?;
+        self.inner.send_choice(choice).map_err(DeadlineError::Carrier)
+    }
+
+    type RecvChoiceErr = DeadlineError<C::RecvChoiceErr, C::RecvChoiceErr>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        self.wait()?;
+        self.inner.recv_choice().map_err(DeadlineError::Carrier)
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        self.inner.step(protocol);
+    }
+}
+
+impl<C> DiscardCarrier for DeadlineCarrier<C> where C: DiscardCarrier {
+    fn discard_next(&mut self) {
+        self.inner.discard_next();
+    }
+}
+
+/// Explicit timeouts are shortened to the session deadline.
+impl<C> TimeoutCarrier for DeadlineCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        self.inner.wait_timeout(timeout.min(remaining)).map_err(DeadlineError::Wait)
+    }
+}
+
+impl<C> BatchCarrier for DeadlineCarrier<C> where C: TimeoutCarrier + BatchCarrier {
+    type FlushErr = DeadlineError<C::FlushErr>;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        self.inner.set_batching(enabled).map_err(DeadlineError::Carrier)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        self.check()?;
+        self.inner.flush().map_err(DeadlineError::Carrier)
+    }
+}
+
+impl<C> CapacityCarrier for DeadlineCarrier<C> where C: TimeoutCarrier + CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+/// Polling fails with `Expired` once the deadline has passed.
+impl<C> NonBlockingCarrier for DeadlineCarrier<C> where C: TimeoutCarrier + NonBlockingCarrier {
+    type PollErr = DeadlineError<C::PollErr>;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.check().map_err(PollError::Carrier)?;
+        self.inner.poll_send().map_err(|e| e.map(DeadlineError::Carrier))
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        self.check().map_err(PollError::Carrier)?;
+        self.inner.poll_recv().map_err(|e| e.map(DeadlineError::Carrier))
+    }
+}
+
+/// Values transferable over a carrier are transferable before its deadline.
+impl<C, T> ChannelSend<DeadlineCarrier<C>> for T where T: Payload + ChannelSend<C>, C: TimeoutCarrier {
+    type Err = DeadlineError<<T as ChannelSend<C>>::Err>;
+
+    fn send(self, carrier: &mut DeadlineCarrier<C>) -> Result<(), Self::Err> {
+        carrier.check()?;
+        self.send(&mut carrier.inner).map_err(DeadlineError::Carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<DeadlineCarrier<C>> for T where T: Payload + ChannelRecv<C>, C: TimeoutCarrier {
+    type Err = DeadlineError<<T as ChannelRecv<C>>::Err, C::RecvChoiceErr>;
+
+    fn recv(carrier: &mut DeadlineCarrier<C>) -> Result<Self, Self::Err> {
+        carrier.wait()?;
+        <T>::recv(&mut carrier.inner).map_err(DeadlineError::Carrier)
+    }
+}
+
+impl<SR, E, P> Chan<SR, E, P> where SR: TimeoutCarrier {
+    /// Apply a deadline to the rest of the session. All subsequent
+    /// operations fail with `DeadlineError::Expired` once it has passed,
+    /// including receives blocked waiting for the peer.
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use session_types_ng::{Recv, End, mpsc};
+    /// use session_types_ng::timeout::DeadlineError;
+    ///
+    /// let (rx, tx) = mpsc::session_channel::<Recv<mpsc::Value<u8>, End>>();
+    /// let rx = rx.with_deadline(Instant::now() + Duration::from_millis(10));
+    /// match rx.recv() {
+    ///     Err(DeadlineError::Expired) => (),
+    ///     _ => panic!("nothing has been sent"),
+    /// }
+    /// assert!(tx.send(mpsc::Value(1)).is_err());
+    /// ```
+    #[must_use]
+    pub fn with_deadline(self, deadline: Instant) -> Chan<DeadlineCarrier<SR>, E, P> {
+        Chan {
+            carrier: DeadlineCarrier::new(self.carrier, deadline),
+            session: self.session,
+        }
+    }
+}
+
+impl<SR, E, P> Chan<DeadlineCarrier<SR>, E, P> where SR: TimeoutCarrier {
+    /// Remove the session deadline.
+    #[must_use]
+    pub fn without_deadline(self) -> Chan<SR, E, P> {
+        Chan {
+            carrier: self.carrier.inner,
+            session: self.session,
+        }
+    }
+}
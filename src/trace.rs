@@ -0,0 +1,200 @@
+//! Carrier middleware emitting `tracing` events (enabled with `tracing` feature).
+use std::any::type_name;
+use std::task::Waker;
+use std::time::Duration;
+use tracing::{Span, debug, trace, warn};
+use super::{Carrier, Payload, BatchCarrier, ChannelSend, ChannelRecv};
+use super::timeout::TimeoutCarrier;
+use super::select::CapacityCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::layer::CarrierLayer;
+
+/// Carrier wrapper which emits an event for every value and choice
+/// transferred, within the session span. Every event has the `step` field
+/// with the protocol type name the session is at (`start` until the first
+/// transition).
+pub struct TracedCarrier<C> {
+    inner: C,
+    span: Span,
+    step: &'static str,
+}
+
+impl<C> TracedCarrier<C> {
+    /// Wrap a carrier into a new `session` span.
+    ///
+    /// ```
+    /// use session_types_ng::{Chan, Send, Recv, End, mpsc};
+    /// use session_types_ng::trace::TracedCarrier;
+    ///
+    /// let (a, b) = mpsc::session_channel::<End>();
+    /// let a: Chan<_, (), Send<mpsc::Value<u8>, End>> = Chan::new(TracedCarrier::new(a.shutdown()));
+    /// let b: Chan<_, (), Recv<mpsc::Value<u8>, End>> = Chan::new(TracedCarrier::new(b.shutdown()));
+    /// a.send(mpsc::Value(1)).unwrap().close();
+    /// b.recv().unwrap().0.close();
+    /// ```
+    pub fn new(inner: C) -> TracedCarrier<C> {
+        TracedCarrier::with_span(inner, tracing::info_span!("session"))
+    }
+
+    /// Wrap a carrier, reporting events within the given span.
+    pub fn with_span(inner: C, span: Span) -> TracedCarrier<C> {
+        TracedCarrier {
+            inner,
+            span,
+            step: "start",
+        }
+    }
+
+    /// Get back underlying carrier.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Get a mutable reference to underlying carrier.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+/// Layer wrapping carriers into `TracedCarrier`s, each one within a new
+/// `session` span.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TraceLayer;
+
+impl<C> CarrierLayer<C> for TraceLayer {
+    type Carrier = TracedCarrier<C>;
+
+    fn layer(&self, inner: C) -> TracedCarrier<C> {
+        TracedCarrier::new(inner)
+    }
+}
+
+impl<C> Carrier for TracedCarrier<C> where C: Carrier {
+    type SendChoiceErr = C::SendChoiceErr;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        let _entered = self.span.enter();
+        let result = self.inner.send_choice(choice);
+        match result {
+            Ok(()) => debug!(step = self.step, choice, "send choice"),
+            Err(_) => warn!(step = self.step, choice, "send choice failed"),
+        }
+        result
+    }
+
+    type RecvChoiceErr = C::RecvChoiceErr;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        let _entered = self.span.enter();
+        let result = self.inner.recv_choice();
+        match result {
+            Ok(choice) => debug!(step = self.step, choice, "recv choice"),
+            Err(_) => warn!(step = self.step, "recv choice failed"),
+        }
+        result
+    }
+
+    fn step(&mut self, protocol: &'static str) {
+        let _entered = self.span.enter();
+        trace!(from = self.step, to = protocol, "transition");
+        self.step = protocol;
+        self.inner.step(protocol);
+    }
+}
+
+impl<C> TimeoutCarrier for TracedCarrier<C> where C: TimeoutCarrier {
+    fn wait_timeout(&mut self, timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        let _entered = self.span.enter();
+        let result = self.inner.wait_timeout(timeout);
+        match result {
+            Ok(true) => (),
+            Ok(false) => debug!(step = self.step, ?timeout, "timed out"),
+            Err(_) => warn!(step = self.step, "wait failed"),
+        }
+        result
+    }
+}
+
+fn traced_send<T, C>(value: T, carrier: &mut TracedCarrier<C>) -> Result<(), T::Err> where T: ChannelSend<C> {
+    let _entered = carrier.span.enter();
+    let result = value.send(&mut carrier.inner);
+    match result {
+        Ok(()) => debug!(step = carrier.step, payload = type_name::<T>(), "send"),
+        Err(_) => warn!(step = carrier.step, payload = type_name::<T>(), "send failed"),
+    }
+    result
+}
+
+fn traced_recv<T, C>(carrier: &mut TracedCarrier<C>) -> Result<T, T::Err> where T: ChannelRecv<C> {
+    let _entered = carrier.span.enter();
+    let result = T::recv(&mut carrier.inner);
+    match result {
+        Ok(_) => debug!(step = carrier.step, payload = type_name::<T>(), "recv"),
+        Err(_) => warn!(step = carrier.step, payload = type_name::<T>(), "recv failed"),
+    }
+    result
+}
+
+impl<C> BatchCarrier for TracedCarrier<C> where C: BatchCarrier {
+    type FlushErr = C::FlushErr;
+
+    fn set_batching(&mut self, enabled: bool) -> Result<(), Self::FlushErr> {
+        let _entered = self.span.enter();
+        debug!(step = self.step, enabled, "set batching");
+        self.inner.set_batching(enabled)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushErr> {
+        let _entered = self.span.enter();
+        let result = self.inner.flush();
+        match result {
+            Ok(()) => debug!(step = self.step, "flush"),
+            Err(_) => warn!(step = self.step, "flush failed"),
+        }
+        result
+    }
+}
+
+impl<C> CapacityCarrier for TracedCarrier<C> where C: CapacityCarrier {
+    fn has_capacity(&mut self) -> bool {
+        self.inner.has_capacity()
+    }
+
+    fn wait_capacity(&mut self, timeout: Duration) -> bool {
+        self.inner.wait_capacity(timeout)
+    }
+}
+
+impl<C> NonBlockingCarrier for TracedCarrier<C> where C: NonBlockingCarrier {
+    type PollErr = C::PollErr;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        let _entered = self.span.enter();
+        self.inner.poll_send()
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        let _entered = self.span.enter();
+        self.inner.poll_recv()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.inner.register_waker(waker);
+    }
+}
+
+/// Values transferable over a carrier are transferable over its traced wrapper.
+impl<C, T> ChannelSend<TracedCarrier<C>> for T where T: Payload + ChannelSend<C> {
+    type Err = <T as ChannelSend<C>>::Err;
+
+    fn send(self, carrier: &mut TracedCarrier<C>) -> Result<(), Self::Err> {
+        traced_send(self, carrier)
+    }
+}
+
+impl<C, T> ChannelRecv<TracedCarrier<C>> for T where T: Payload + ChannelRecv<C> {
+    type Err = <T as ChannelRecv<C>>::Err;
+
+    fn recv(carrier: &mut TracedCarrier<C>) -> Result<Self, Self::Err> {
+        traced_recv(carrier)
+    }
+}
+
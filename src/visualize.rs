@@ -0,0 +1,151 @@
+//! Rendering of protocols as state machine graphs in Graphviz DOT format.
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+use super::reflect::{ProtocolDesc, Desc, short_name};
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<&'static str>,
+    edges: Vec<(usize, usize, String, bool)>,
+    loops: Vec<(Option<&'static str>, usize)>,
+}
+
+impl Graph {
+    fn node(&mut self, shape: &'static str) -> usize {
+        self.nodes.push(shape);
+        self.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: String) {
+        self.edges.push((from, to, label, false));
+    }
+
+    fn silent_edge(&mut self, from: usize, to: usize) {
+        self.edges.push((from, to, String::new(), true));
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::from("digraph protocol {\n    rankdir=LR;\n    node [label=\"\"];\n");
+        for (i, shape) in self.nodes.iter().enumerate() {
+            out.push_str(&format!("    n{} [shape={}];\n", i, shape));
+        }
+        for &(from, to, ref label, silent) in &self.edges {
+            let style = if silent { ", style=dashed" } else { "" };
+            out.push_str(&format!("    n{} -> n{} [label=\"{}\"{}];\n", from, to, label.replace('"', "\\\""), style));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Render protocol `P` in Graphviz DOT format: states are nodes and
+/// transitions are edges labeled with `!T` for sends, `?T` for receives,
+/// `+branch` for choices and `&branch` for offers. Recursion is shown with
+/// dashed edges back to the loop entry.
+///
+/// ```
+/// use session_types_ng::{Send, Recv, End, mpsc::Value};
+/// use session_types_ng::visualize::to_dot;
+///
+/// let dot = to_dot::<Send<Value<u32>, Recv<Value<bool>, End>>>();
+/// assert!(dot.contains("[label=\"!Value<u32>\"]"));
+/// assert!(dot.contains("[label=\"?Value<bool>\"]"));
+/// ```
+pub fn to_dot<P>() -> String where P: ProtocolDesc {
+    let mut graph = Graph::default();
+    visit(&mut graph, &P::desc());
+    graph.render()
+}
+
+/// Add states of the protocol to the graph, returns the initial state.
+fn visit(graph: &mut Graph, desc: &Desc) -> usize {
+    match *desc {
+        Desc::End =>
+            graph.node("doublecircle"),
+        Desc::Send(payload, ref next) | Desc::Bcast(payload, ref next) | Desc::Scatter(payload, ref next) =>
+            step(graph, format!("!{}", short_name(payload)), next),
+        Desc::Recv(payload, ref next) | Desc::Gather(payload, ref next) =>
+            step(graph, format!("?{}", short_name(payload)), next),
+        Desc::Choose(ref branches) =>
+            choice(graph, '+', branches),
+        Desc::Offer(ref branches) =>
+            choice(graph, '&', branches),
+        Desc::Labeled(_, ref next) =>
+            visit(graph, next),
+        Desc::Rec(ref body) =>
+            enter(graph, None, body),
+        Desc::Var(n) => {
+            let depth = graph.loops.len() - 1 - n;
+            graph.loops[depth].1
+        },
+        Desc::Loop(label, ref body) =>
+            enter(graph, Some(label), body),
+        Desc::Goto(label) =>
+            graph.loops.iter().rev()
+            .find(|&&(loop_label, _)| loop_label == Some(label))
+            .map(|&(_, state)| state)
+            .expect("goto label is not found in enclosing loops"),
+        Desc::RecvTimeout { payload, deadline, ref in_time, ref timed_out } =>
+            outcome(graph, format!("?{}", short_name(payload)), in_time, format!("timeout {}", short_name(deadline)), timed_out),
+        Desc::SendTimeout { payload, deadline, ref in_time, ref timed_out } =>
+            outcome(graph, format!("!{}", short_name(payload)), in_time, format!("timeout {}", short_name(deadline)), timed_out),
+        Desc::Guarded(ref next, ref abort) => {
+            let state = visit(graph, next);
+            let abort = visit(graph, abort);
+            graph.edge(state, abort, "+abort".to_string());
+            state
+        },
+        Desc::Checked(ref next, ref abort) => {
+            let state = visit(graph, next);
+            let abort = visit(graph, abort);
+            graph.edge(state, abort, "&abort".to_string());
+            state
+        },
+        Desc::Par(ref left, ref right, ref next) => {
+            let state = graph.node("box");
+            let left = visit(graph, left);
+            let right = visit(graph, right);
+            let next = visit(graph, next);
+            graph.edge(state, left, "|0".to_string());
+            graph.edge(state, right, "|1".to_string());
+            graph.edge(state, next, "join".to_string());
+            state
+        },
+    }
+}
+
+fn step(graph: &mut Graph, label: String, next: &Desc) -> usize {
+    let state = graph.node("circle");
+    let next = visit(graph, next);
+    graph.edge(state, next, label);
+    state
+}
+
+fn choice(graph: &mut Graph, mark: char, branches: &[Desc]) -> usize {
+    let state = graph.node("diamond");
+    for (index, branch) in branches.iter().enumerate() {
+        let to = visit(graph, branch);
+        let label = match branch.label() {
+            Some(label) => format!("{}{}", mark, short_name(label)),
+            None => format!("{}{}", mark, index),
+        };
+        graph.edge(state, to, label);
+    }
+    state
+}
+
+fn outcome(graph: &mut Graph, label: String, in_time: &Desc, timeout: String, timed_out: &Desc) -> usize {
+    let state = step(graph, label, in_time);
+    let timed_out = visit(graph, timed_out);
+    graph.edge(state, timed_out, timeout);
+    state
+}
+
+fn enter(graph: &mut Graph, label: Option<&'static str>, body: &Desc) -> usize {
+    let state = graph.node("point");
+    graph.loops.push((label, state));
+    let body = visit(graph, body);
+    graph.loops.pop();
+    graph.silent_edge(state, body);
+    state
+}
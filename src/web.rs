@@ -0,0 +1,257 @@
+//! Carrier over `MessagePort`s for sessions between a page and a web worker
+//! or an iframe (enabled with `web` feature, for `wasm32-unknown-unknown`).
+//!
+//! Values are encoded with a `WireFormat` and posted as `Uint8Array`s, while
+//! a selection of a branch is posted as a single message with the number of
+//! skipped branches. Arriving messages are queued by the `onmessage` handler
+//! of the port.
+//!
+//! The browser event loop can't be blocked, so a receive fails with
+//! `Error::Empty` if nothing has arrived yet. Sessions are resumed from the
+//! callback registered with `on_message` using `try_recv` and
+//! `offer_timeout` (timeouts are not waited for):
+//!
+//! ```ignore
+//! let (chan, port) = web::session_pair::<Proto>()?;
+//! worker.post_message_with_transfer(&port, &js_sys::Array::of1(&port))?;
+//!
+//! let chan = Rc::new(RefCell::new(Some(chan.send(web::Value(42))?)));
+//! let waiting = chan.clone();
+//! chan.borrow_mut().as_mut().unwrap().on_message(move || {
+//!     match waiting.borrow_mut().take().unwrap().try_recv() {
+//!         Ok((chan, web::Value(reply))) => { chan.close(); /* ... */ },
+//!         Err(TryRecvError::Empty(chan)) => *waiting.borrow_mut() = Some(chan),
+//!         Err(e) => { /* ... */ },
+//!     }
+//! });
+//! ```
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::Duration;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use js_sys::Uint8Array;
+use web_sys::{MessageChannel, MessageEvent, MessagePort};
+use super::{ChannelSend, ChannelRecv, Payload, Carrier, Chan, bytes};
+use super::timeout::TimeoutCarrier;
+use super::poll::{NonBlockingCarrier, PollError};
+use super::wire::{WireFormat, Bincode};
+
+enum Message {
+    Choice(bool),
+    Frame(Vec<u8>),
+    /// Neither a selection nor a frame.
+    Unknown(JsValue),
+}
+
+#[derive(Default)]
+struct Inbox {
+    messages: VecDeque<Message>,
+    wakeup: Option<Box<dyn FnMut()>>,
+}
+
+/// Carrier over a `MessagePort`, values are encoded with the wire format `F`.
+pub struct Channel<F = Bincode> {
+    port: MessagePort,
+    inbox: Rc<RefCell<Inbox>>,
+    /// False choices not posted yet: a selection is posted when it is complete.
+    skipped: u32,
+    format: PhantomData<F>,
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Nothing has arrived yet.
+    Empty,
+    /// `postMessage` has failed.
+    Post(JsValue),
+    /// A message of unexpected kind has arrived: the peers are out of sync.
+    Unexpected(JsValue),
+    Format(E),
+}
+
+#[derive(Clone, Debug)]
+pub struct Value<T>(pub T);
+
+impl<T> Payload for Value<T> { }
+
+impl<F> Channel<F> where F: WireFormat {
+    /// Start listening to the port. Its `onmessage` handler is replaced.
+    pub fn new(port: MessagePort) -> Channel<F> {
+        let inbox = Rc::new(RefCell::new(Inbox::default()));
+        let queue = inbox.clone();
+        let listener = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let data = event.data();
+            let wakeup = {
+                let mut inbox = queue.borrow_mut();
+                if let Some(skipped) = data.as_f64() {
+                    for _ in 0 .. skipped as u32 {
+                        inbox.messages.push_back(Message::Choice(false));
+                    }
+                    inbox.messages.push_back(Message::Choice(true));
+                } else if data.is_instance_of::<Uint8Array>() {
+                    inbox.messages.push_back(Message::Frame(Uint8Array::new(&data).to_vec()));
+                } else {
+                    inbox.messages.push_back(Message::Unknown(data));
+                }
+                inbox.wakeup.take()
+            };
+            // the callback is free to borrow the channel and to replace itself
+            if let Some(mut wakeup) = wakeup {
+                wakeup();
+                queue.borrow_mut().wakeup.get_or_insert(wakeup);
+            }
+        });
+        // owned by the port rather than the channel, as the channel could be
+        // dropped from the callback while the handler is running
+        let listener = listener.into_js_value();
+        port.set_onmessage(Some(listener.unchecked_ref()));
+        Channel {
+            port,
+            inbox,
+            skipped: 0,
+            format: PhantomData,
+        }
+    }
+
+    /// Get a reference to underlying port.
+    pub fn get_ref(&self) -> &MessagePort {
+        &self.port
+    }
+
+    /// Invoke `callback` every time a message arrives, after it is queued.
+    pub fn on_message<C>(&mut self, callback: C) where C: FnMut() + 'static {
+        self.inbox.borrow_mut().wakeup = Some(Box::new(callback));
+    }
+
+    fn post(&self, message: &JsValue) -> Result<(), Error<F::Err>> {
+        self.port.post_message(message).map_err(Error::Post)
+    }
+
+    fn send_frame(&self, bytes: &[u8]) -> Result<(), Error<F::Err>> {
+        self.post(&Uint8Array::from(bytes))
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8>, Error<F::Err>> {
+        match self.inbox.borrow_mut().messages.pop_front() {
+            Some(Message::Frame(bytes)) => Ok(bytes),
+            Some(Message::Choice(choice)) => Err(Error::Unexpected(JsValue::from_bool(choice))),
+            Some(Message::Unknown(data)) => Err(Error::Unexpected(data)),
+            None => Err(Error::Empty),
+        }
+    }
+}
+
+impl<F> Drop for Channel<F> {
+    fn drop(&mut self) {
+        self.port.set_onmessage(None);
+        self.port.close();
+    }
+}
+
+impl<T, F> ChannelSend<Channel<F>> for Value<T> where T: Serialize, F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn send(self, carrier: &mut Channel<F>) -> Result<(), Self::Err> {
+        let bytes = F::encode(&self.0).map_err(Error::Format)?;
+        carrier.send_frame(&bytes)
+    }
+}
+
+impl<T, F> ChannelRecv<Channel<F>> for Value<T> where T: DeserializeOwned, F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn recv(carrier: &mut Channel<F>) -> Result<Self, Self::Err> {
+        let bytes = carrier.recv_frame()?;
+        F::decode(&bytes).map(Value).map_err(Error::Format)
+    }
+}
+
+/// Binary payloads are posted as they are, bypassing the wire format.
+impl<F> ChannelSend<Channel<F>> for bytes::Bytes where F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn send(self, carrier: &mut Channel<F>) -> Result<(), Self::Err> {
+        carrier.send_frame(&self)
+    }
+}
+
+impl<F> ChannelRecv<Channel<F>> for bytes::Bytes where F: WireFormat {
+    type Err = Error<F::Err>;
+
+    fn recv(carrier: &mut Channel<F>) -> Result<Self, Self::Err> {
+        carrier.recv_frame().map(bytes::Bytes::new)
+    }
+}
+
+impl<F> Carrier for Channel<F> where F: WireFormat {
+    type SendChoiceErr = Error<F::Err>;
+    fn send_choice(&mut self, choice: bool) -> Result<(), Self::SendChoiceErr> {
+        if !choice {
+            self.skipped += 1;
+            return Ok(());
+        }
+        let skipped = self.skipped;
+        self.skipped = 0;
+        self.post(&JsValue::from(skipped))
+    }
+
+    type RecvChoiceErr = Error<F::Err>;
+    fn recv_choice(&mut self) -> Result<bool, Self::RecvChoiceErr> {
+        match self.inbox.borrow_mut().messages.pop_front() {
+            Some(Message::Choice(choice)) => Ok(choice),
+            Some(Message::Frame(bytes)) => Err(Error::Unexpected(Uint8Array::from(&bytes[..]).into())),
+            Some(Message::Unknown(data)) => Err(Error::Unexpected(data)),
+            None => Err(Error::Empty),
+        }
+    }
+}
+
+/// Only checks whether a message has arrived: the timeout is not waited for.
+impl<F> TimeoutCarrier for Channel<F> where F: WireFormat {
+    fn wait_timeout(&mut self, _timeout: Duration) -> Result<bool, Self::RecvChoiceErr> {
+        Ok(!self.inbox.borrow().messages.is_empty())
+    }
+}
+
+/// Posting never blocks, a receive is ready once a message has arrived.
+impl<F> NonBlockingCarrier for Channel<F> where F: WireFormat {
+    type PollErr = Error<F::Err>;
+
+    fn poll_send(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        Ok(())
+    }
+
+    fn poll_recv(&mut self) -> Result<(), PollError<Self::PollErr>> {
+        if self.inbox.borrow().messages.is_empty() {
+            Err(PollError::WouldBlock)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<F, E, P> Chan<Channel<F>, E, P> where F: WireFormat {
+    /// Invoke `callback` every time a message arrives for the session.
+    pub fn on_message<C>(&mut self, callback: C) where C: FnMut() + 'static {
+        self.carrier.on_message(callback);
+    }
+}
+
+/// Returns a session channel over the port using default wire format.
+#[must_use]
+pub fn session_channel<P>(port: MessagePort) -> Chan<Channel, (), P> {
+    Chan::new(Channel::new(port))
+}
+
+/// Create a `MessageChannel` and return a session channel over its first
+/// port together with the second one, to be transferred to the peer (for
+/// example with `Worker::post_message_with_transfer`).
+pub fn session_pair<P>() -> Result<(Chan<Channel, (), P>, MessagePort), JsValue> {
+    let channel = MessageChannel::new()?;
+    Ok((session_channel(channel.port1()), channel.port2()))
+}
@@ -0,0 +1,188 @@
+use std::fmt::Debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Encoding used by byte-stream carriers to put values on the wire.
+///
+/// A format is chosen per carrier (see `stream::Channel`), so protocol
+/// types remain unaware of the actual encoding.
+///
+/// Values and choices of a session round-trip in every format:
+///
+/// ```
+/// use std::net::{TcpListener, TcpStream};
+/// use session_types_ng::{Chan, HasDual, Send, Choose, End, Nil};
+/// use session_types_ng::stream::{Channel, Value};
+/// use session_types_ng::wire::{self, WireFormat};
+///
+/// type Greet = Send<Value<(u32, String)>, Choose<End, Choose<End, Nil>>>;
+///
+/// fn round_trip<F>() where F: WireFormat {
+///     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+///     let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+///     let (server, _) = listener.accept().unwrap();
+///     let client: Chan<Channel<TcpStream, F>, (), Greet> = Chan::new(Channel::new(client));
+///     let server: Chan<Channel<TcpStream, F>, (), <Greet as HasDual>::Dual> = Chan::new(Channel::new(server));
+///
+///     client.send(Value((42, "hello".to_string()))).unwrap().second().unwrap().close();
+///     let (server, Value(greeting)) = server.recv().unwrap();
+///     assert_eq!(greeting, (42, "hello".to_string()));
+///     let branch = server.offer()
+///         .option(|chan| { chan.close(); "first" })
+///         .option(|chan| { chan.close(); "second" })
+///         .unwrap();
+///     assert_eq!(branch, "second");
+/// }
+///
+/// round_trip::<wire::Bincode>();
+/// #[cfg(feature = "json")]
+/// round_trip::<wire::Json>();
+/// #[cfg(feature = "cbor")]
+/// round_trip::<wire::Cbor>();
+/// #[cfg(feature = "msgpack")]
+/// round_trip::<wire::MsgPack>();
+/// ```
+pub trait WireFormat {
+    type Err: Debug;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>, Self::Err> where T: Serialize;
+    fn decode<T>(bytes: &[u8]) -> Result<T, Self::Err> where T: DeserializeOwned;
+}
+
+/// Compact binary encoding via `bincode`. This is the default wire format.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+impl WireFormat for Bincode {
+    type Err = bincode::Error;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>, Self::Err> where T: Serialize {
+        bincode::serialize(value)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T, Self::Err> where T: DeserializeOwned {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// Human readable encoding via `serde_json`, handy for inspecting sessions
+/// in packet captures and logs during development.
+///
+/// ```
+/// extern crate serde_json;
+/// # extern crate session_types_ng;
+/// use session_types_ng::wire::{WireFormat, Json};
+///
+/// # fn main() {
+/// let bytes = Json::encode(&(42u32, "hello")).unwrap();
+/// assert_eq!(bytes, br#"[42,"hello"]"#);
+/// assert!(serde_json::from_slice::<serde_json::Value>(&bytes).is_ok());
+/// # }
+/// ```
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl WireFormat for Json {
+    type Err = serde_json::Error;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>, Self::Err> where T: Serialize {
+        serde_json::to_vec(value)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T, Self::Err> where T: DeserializeOwned {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// Compact schema-less encoding via `ciborium` (CBOR), suitable for
+/// constrained links.
+///
+/// ```
+/// extern crate ciborium;
+/// # extern crate session_types_ng;
+/// use ciborium::Value;
+/// use session_types_ng::wire::{WireFormat, Cbor};
+///
+/// # fn main() {
+/// let bytes = Cbor::encode(&(42u32, "hello")).unwrap();
+/// // an array of two: an unsigned integer and a text string
+/// assert_eq!(bytes, b"\x82\x18\x2ae\x68ello");
+/// let value: Value = ciborium::from_reader(&bytes[..]).unwrap();
+/// assert_eq!(value, Value::Array(vec![Value::Integer(42.into()), Value::Text("hello".into())]));
+/// # }
+/// ```
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+#[derive(Debug)]
+pub enum CborError {
+    Encode(ciborium::ser::Error<std::io::Error>),
+    Decode(ciborium::de::Error<std::io::Error>),
+}
+
+#[cfg(feature = "cbor")]
+impl WireFormat for Cbor {
+    type Err = CborError;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>, Self::Err> where T: Serialize {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(CborError::Encode)?;
+        Ok(bytes)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T, Self::Err> where T: DeserializeOwned {
+        ciborium::from_reader(bytes).map_err(CborError::Decode)
+    }
+}
+
+/// MessagePack encoding via `rmp-serde`. Structs are encoded as maps with
+/// field names, for interoperability with other msgpack speaking services.
+///
+/// ```
+/// extern crate rmp_serde;
+/// #[macro_use] extern crate serde_derive;
+/// # extern crate session_types_ng;
+/// use std::collections::BTreeMap;
+/// use session_types_ng::wire::{WireFormat, MsgPack};
+///
+/// #[derive(Serialize)]
+/// struct Point {
+///     x: u8,
+///     y: u8,
+/// }
+///
+/// # fn main() {
+/// let bytes = MsgPack::encode(&Point { x: 1, y: 2 }).unwrap();
+/// // a map of two with string keys, not an array
+/// assert_eq!(bytes, b"\x82\xa1x\x01\xa1y\x02");
+/// let fields: BTreeMap<String, u8> = rmp_serde::from_slice(&bytes).unwrap();
+/// assert_eq!(fields, vec![("x".to_string(), 1), ("y".to_string(), 2)].into_iter().collect());
+/// # }
+/// ```
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+#[derive(Debug)]
+pub enum MsgPackError {
+    Encode(rmp_serde::encode::Error),
+    Decode(rmp_serde::decode::Error),
+}
+
+#[cfg(feature = "msgpack")]
+impl WireFormat for MsgPack {
+    type Err = MsgPackError;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>, Self::Err> where T: Serialize {
+        rmp_serde::to_vec_named(value).map_err(MsgPackError::Encode)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T, Self::Err> where T: DeserializeOwned {
+        rmp_serde::from_slice(bytes).map_err(MsgPackError::Decode)
+    }
+}